@@ -34,10 +34,20 @@ fn de_utils() {
     FSName::set_name_str(&mut de, name2).unwrap();
     assert_eq!(name2, FSName::get_name_str(&de));
     let name = "nowthisoneisreallylong";
-    assert!(FSName::set_name_str(&mut de, name).is_none());
+    FSName::set_name_str(&mut de, name).unwrap();
+    assert_eq!(name, FSName::get_name_str(&de));
+    let name = "❤";
+    FSName::set_name_str(&mut de, name).unwrap();
+    assert_eq!(name, FSName::get_name_str(&de));
+    let name = "n".repeat(255); //names take up at most 255 bytes
+    FSName::set_name_str(&mut de, &name).unwrap();
+    let name = "n".repeat(256);
+    assert!(FSName::set_name_str(&mut de, &name).is_none());
     let name = "";
     assert!(FSName::set_name_str(&mut de, name).is_none());
-    let name = "❤";
+    let name = "a/b";
+    assert!(FSName::set_name_str(&mut de, name).is_none());
+    let name = "a\0b";
     assert!(FSName::set_name_str(&mut de, name).is_none());
 
     let dev = my_fs.unmountfs();
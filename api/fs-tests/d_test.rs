@@ -33,8 +33,10 @@ fn cwd_utils() {
     assert!(!FSName::valid_path("//"));
     assert!(!FSName::valid_path("a"));
     assert!(!FSName::valid_path("/a/")); //we do not allow ending on a "/", as we interpret this as the last entry being empty
-    assert!(!FSName::valid_path("/❤"));
-    assert!(!FSName::valid_path("/fartoolongtobevalid"));
+    assert!(FSName::valid_path("/❤"));
+    assert!(FSName::valid_path("/fartoolongtobevalid"));
+    assert!(!FSName::valid_path(&("/".to_string() + &"n".repeat(256))));
+    assert!(!FSName::valid_path("/a\0b"));
     assert!(FSName::valid_path("/some/regular/name/.."));
 
     let path_0 = "../../test";
@@ -0,0 +1,77 @@
+//! Tool to export a device image to NBD clients, e.g. test VMs or other processes mounting it through a `RemoteDevice`.
+//!
+//! Usage: `cplfs-nbd <image> <block_size> <nblocks> <listen> [--name <name>] [--read-only]`, where `<listen>` is one of
+//! * `tcp <address>`: accept clients over TCP on `<address>`, e.g. `127.0.0.1:10809`,
+//! * `unix <path>`: accept clients on a Unix socket at `<path>`, which must not exist yet.
+//!
+//! The image is exported under the name `<name>`, or `cplfs` if none is given; clients asking for the empty name get it as well.
+//! With `--read-only`, the image is loaded without write access, and clients may not modify it.
+//! The tool serves clients until it is killed.
+
+use cplfs_api::controller::Device;
+use cplfs_api::nbd::NbdExport;
+use std::env;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::process;
+
+/// Usage message, printed on invalid arguments
+const USAGE: &str = "Usage: cplfs-nbd <image> <block_size> <nblocks> (tcp <address> | unix <path>) [--name <name>] [--read-only]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(msg) = run(&args) {
+        eprintln!("cplfs-nbd: {}", msg);
+        process::exit(1);
+    }
+}
+
+/// Parse the argument at position `i` as a number
+fn number(args: &[String], i: usize) -> Result<u64, String> {
+    let arg = args.get(i).ok_or_else(|| USAGE.to_string())?;
+    arg.parse()
+        .map_err(|_| format!("not a number: {}\n{}", arg, USAGE))
+}
+
+/// Serve the image given by `args`
+fn run(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or_else(|| USAGE.to_string())?;
+    let block_size = number(args, 1)?;
+    let nblocks = number(args, 2)?;
+    let listen = args.get(3).map(String::as_str);
+    let address = args.get(4).ok_or_else(|| USAGE.to_string())?;
+
+    let mut name = "cplfs";
+    let mut read_only = false;
+    let mut options = args[5..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--name" => name = options.next().ok_or_else(|| USAGE.to_string())?,
+            "--read-only" => read_only = true,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let dev = if read_only {
+        Device::load_readonly(path, block_size, nblocks)
+    } else {
+        Device::load(path, block_size, nblocks)
+    }
+    .map_err(|e| e.to_string())?;
+    let export = NbdExport::new(dev, name, read_only);
+    match listen {
+        Some("tcp") => {
+            let listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+            println!("exporting {} as {} on {}", path, name, address);
+            export.serve_tcp(listener).map_err(|e| e.to_string())
+        }
+        #[cfg(unix)]
+        Some("unix") => {
+            let listener = UnixListener::bind(address).map_err(|e| e.to_string())?;
+            println!("exporting {} as {} on {}", path, name, address);
+            export.serve_unix(listener).map_err(|e| e.to_string())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
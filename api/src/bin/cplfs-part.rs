@@ -0,0 +1,70 @@
+//! Tool to list, create and delete the partitions of a device image.
+//!
+//! Usage: `cplfs-part <image> <block_size> <nblocks> <command>`, where `<command>` is one of
+//! * `init`: write an empty partition table to the image, forgetting all existing partitions,
+//! * `list`: list all partitions of the image,
+//! * `create <start> <nblocks>`: add a partition of `<nblocks>` blocks, starting at block `<start>`,
+//! * `delete <slot>`: remove the partition in slot `<slot>`, leaving its blocks untouched.
+
+use cplfs_api::controller::Device;
+use cplfs_api::partition::PartitionTable;
+use std::env;
+use std::process;
+
+/// Usage message, printed on invalid arguments
+const USAGE: &str = "Usage: cplfs-part <image> <block_size> <nblocks> (init | list | create <start> <nblocks> | delete <slot>)";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(msg) = run(&args) {
+        eprintln!("cplfs-part: {}", msg);
+        process::exit(1);
+    }
+}
+
+/// Parse the argument at position `i` as a number
+fn number(args: &[String], i: usize) -> Result<u64, String> {
+    let arg = args.get(i).ok_or_else(|| USAGE.to_string())?;
+    arg.parse()
+        .map_err(|_| format!("not a number: {}\n{}", arg, USAGE))
+}
+
+/// Run the command given by `args`
+fn run(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or_else(|| USAGE.to_string())?;
+    let block_size = number(args, 1)?;
+    let nblocks = number(args, 2)?;
+    let command = args.get(3).map(String::as_str);
+
+    let mut dev = Device::load(path, block_size, nblocks).map_err(|e| e.to_string())?;
+    match command {
+        Some("init") => PartitionTable::new()
+            .write(&mut dev)
+            .map_err(|e| e.to_string()),
+        Some("list") => {
+            let table = PartitionTable::read(&dev).map_err(|e| e.to_string())?;
+            println!("{:>4} {:>12} {:>12}", "slot", "start", "blocks");
+            for (slot, entry) in table.list() {
+                println!("{:>4} {:>12} {:>12}", slot, entry.start, entry.nblocks);
+            }
+            Ok(())
+        }
+        Some("create") => {
+            let mut table = PartitionTable::read(&dev).map_err(|e| e.to_string())?;
+            let slot = table
+                .create(number(args, 4)?, number(args, 5)?, nblocks)
+                .map_err(|e| e.to_string())?;
+            table.write(&mut dev).map_err(|e| e.to_string())?;
+            println!("created partition in slot {}", slot);
+            Ok(())
+        }
+        Some("delete") => {
+            let mut table = PartitionTable::read(&dev).map_err(|e| e.to_string())?;
+            table
+                .delete(number(args, 4)? as usize)
+                .map_err(|e| e.to_string())?;
+            table.write(&mut dev).map_err(|e| e.to_string())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
@@ -0,0 +1,171 @@
+//! Per-block checksums of device images, to detect corrupted blocks instead of handing them to a file system.
+//!
+//! A checksummed [`Device`] keeps a CRC32C checksum of every block in a *sidecar file* next to its image, at the path given by [`checksum_path`].
+//! The sidecar holds one little-endian `u32` per block, in the order of the blocks, and is memory mapped just like the image itself.
+//! Every write of a block updates its checksum, and every read verifies it, so a block that was modified behind the back of the device (e.g. by a bit flip) results in an [`APIError::Corruption`] error.
+//!
+//! [`Device`]: ../controller/struct.Device.html
+//! [`checksum_path`]: fn.checksum_path.html
+//! [`APIError::Corruption`]: ../error_given/enum.APIError.html#variant.Corruption
+
+use super::controller::{lock_file, LockMode};
+use super::error_given;
+use super::error_given::APIError;
+use memmap::MmapMut;
+use std::{
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+/// Size of a single checksum in the sidecar file
+const CHECKSUM_SIZE: u64 = 4;
+
+/// Lookup table for the CRC32C (Castagnoli) polynomial, in its reflected form
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+/// Compute the lookup table for `crc32c`, one byte at a time
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC32C (Castagnoli) checksum of `data`
+pub fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &b| {
+        CRC32C_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Path of the sidecar file holding the checksums of the image at `image_path`, i.e. the path of the image with `.crc` appended
+pub fn checksum_path<P: AsRef<Path>>(image_path: P) -> PathBuf {
+    let mut path = image_path.as_ref().as_os_str().to_owned();
+    path.push(".crc");
+    PathBuf::from(path)
+}
+
+/// The opened and memory-mapped sidecar file of a checksummed device
+#[derive(Debug)]
+pub(crate) struct Checksums {
+    /// Memory-mapped contents of the sidecar file
+    map: MmapMut,
+    /// The opened sidecar file, which holds the lock on it until the device is dropped
+    file: File,
+}
+
+impl Checksums {
+    /// Open the sidecar file at `path` of an image with contents `contents`, consisting of `nblocks` blocks of `block_size` bytes, and lock it exclusively.
+    /// If the sidecar file does not exist yet, it is created, holding the checksums of the current contents of the image.
+    pub(crate) fn open(
+        path: &Path,
+        contents: &[u8],
+        block_size: u64,
+        nblocks: u64,
+    ) -> error_given::Result<Checksums> {
+        let exists = path.exists();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(!exists)
+            .truncate(false)
+            .open(path)?;
+        lock_file(&file, path, LockMode::Exclusive)?;
+        if !exists {
+            file.set_len(CHECKSUM_SIZE * nblocks)?;
+        } else if file.metadata()?.len() != CHECKSUM_SIZE * nblocks {
+            return Err(APIError::ControllerInput(
+                "Checksum file does not match the size of the device",
+            ));
+        }
+
+        let mut checksums = Checksums {
+            map: unsafe { memmap::MmapOptions::new().map_mut(&file)? },
+            file,
+        };
+        if !exists {
+            for (i, block) in contents.chunks(block_size as usize).enumerate() {
+                checksums.update(i as u64, block);
+            }
+            checksums.map.flush()?;
+        }
+        Ok(checksums)
+    }
+
+    /// Range of the checksum of block `index` in the sidecar file
+    fn range(index: u64) -> std::ops::Range<usize> {
+        let start = (CHECKSUM_SIZE * index) as usize;
+        start..start + CHECKSUM_SIZE as usize
+    }
+
+    /// Check that `data` matches the stored checksum of block `index`
+    pub(crate) fn verify(&self, index: u64, data: &[u8]) -> error_given::Result<()> {
+        let stored = u32::from_le_bytes(self.map[Checksums::range(index)].try_into().unwrap());
+        if stored != crc32c(data) {
+            return Err(APIError::Corruption { block_no: index });
+        }
+        Ok(())
+    }
+
+    /// Store the checksum of `data` as the checksum of block `index`
+    pub(crate) fn update(&mut self, index: u64, data: &[u8]) {
+        self.map[Checksums::range(index)].copy_from_slice(&crc32c(data).to_le_bytes());
+    }
+
+    /// Grow or shrink the sidecar file to hold the checksums of `nblocks` blocks of `block_size` bytes.
+    /// Blocks that are added are zero, just like the blocks added to the image.
+    pub(crate) fn resize(&mut self, nblocks: u64, block_size: u64) -> error_given::Result<()> {
+        let old_nblocks = self.map.len() as u64 / CHECKSUM_SIZE;
+        self.map.flush()?;
+        self.file.set_len(CHECKSUM_SIZE * nblocks)?;
+        self.map = unsafe { memmap::MmapOptions::new().map_mut(&self.file)? };
+        let zero = vec![0; block_size as usize];
+        for i in old_nblocks..nblocks {
+            self.update(i, &zero);
+        }
+        Ok(())
+    }
+
+    /// Make sure all checksums have reached the sidecar file, blocking until they have
+    pub(crate) fn flush(&self) -> error_given::Result<()> {
+        Ok(self.map.flush()?)
+    }
+
+    /// Start writing all checksums back to the sidecar file, without waiting for this to finish
+    pub(crate) fn flush_async(&self) -> error_given::Result<()> {
+        Ok(self.map.flush_async()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum_path, crc32c};
+    use std::path::Path;
+
+    #[test]
+    fn crc32c_test() {
+        //Check values of the CRC32C specification (RFC 3720)
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(&[0; 32]), 0x8A91_36AA);
+        assert_eq!(crc32c(&[0xFF; 32]), 0x62A8_AB43);
+        assert_eq!(
+            checksum_path("images/img"),
+            Path::new("images/img.crc").to_path_buf()
+        );
+    }
+}
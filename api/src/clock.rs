@@ -0,0 +1,84 @@
+//! Sources of the current time, used by file systems to timestamp their inodes.
+//!
+//! File systems never ask the operating system for the time directly, but go through a [`Clock`], so that tests can swap the [`SystemClock`] for a deterministic [`ManualClock`] and predict every timestamp that ends up on disk.
+//! All times are in nanoseconds since the Unix epoch, which fits in a `u64` until the year 2554.
+//!
+//! [`Clock`]: trait.Clock.html
+//! [`SystemClock`]: struct.SystemClock.html
+//! [`ManualClock`]: struct.ManualClock.html
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time
+pub trait Clock: Send {
+    /// Current time, in nanoseconds since the Unix epoch
+    fn now(&self) -> u64;
+}
+
+/// Clock that reports the time of the system it runs on
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        //a system clock set before the epoch is reported as the epoch itself
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// Clock that only moves when it is told to.
+/// Clones share the same time, so a test can keep a clone around to move the clock of a file system it handed the other clone to.
+#[derive(Debug, Default, Clone)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    /// Create a clock that is stopped at time `now`
+    pub fn new(now: u64) -> ManualClock {
+        ManualClock(Arc::new(AtomicU64::new(now)))
+    }
+
+    /// Move the clock to time `now`, which may also lie in the past
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+
+    /// Move the clock forward by `ns` nanoseconds, returning the new time
+    pub fn advance(&self, ns: u64) -> u64 {
+        self.0.fetch_add(ns, Ordering::SeqCst) + ns
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ManualClock, SystemClock};
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new(1_000);
+        let shared = clock.clone();
+        assert_eq!(clock.now(), 1_000);
+        assert_eq!(shared.advance(500), 1_500);
+        assert_eq!(clock.now(), 1_500);
+        clock.set(10);
+        assert_eq!(shared.now(), 10);
+    }
+
+    #[test]
+    fn system_clock() {
+        //some time after this code was written, and the clock does not go backwards between two calls
+        let first = SystemClock.now();
+        assert!(first > 1_600_000_000_000_000_000);
+        assert!(SystemClock.now() >= first);
+    }
+}
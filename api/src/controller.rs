@@ -5,17 +5,160 @@
 //! The memory-mapped file is what the read and write functions operate on.
 //!
 //! *EXTRA*: Note that this explicit block-level abstraction is not required for a file system at this level of abstraction, but added it to make our model a more realistic representation of a real-life file system.
-//! No provisions have been made to properly lock and unlock the file that is used to back the file system, so do not fiddle with it while a file system is running, as this leads to undefined behavior. (e.g. the fs2 crate could be used to explicitly implement locking, if so desired)
+//! The file that is used to back the file system is locked for as long as the device is alive, using an advisory lock (through the fs2 crate).
+//! By default, this lock is exclusive, so a second device for the same image cannot be created, in this process or any other one, before the first one has been dropped.
+//! Devices that are only read from can share the image with other readers, by loading it with a shared lock instead.
+//! Images that must never be modified can be loaded read-only, in which case the file is not even opened for writing.
+//! Note that an advisory lock does not stop other programs from modifying the file, so do not fiddle with it while a file system is running, as this still leads to undefined behavior.
+//!
+//! Writes to the device only reach the image for sure when the device is dropped, or when they are synced explicitly using [`Device::sync`] or [`Device::sync_range`], or their asynchronous variants.
+//!
+//! The file systems do not depend on this memory-mapped `Device` directly, but on the [`BlockDevice`] trait, which it implements.
+//! Besides single blocks, the trait reads and writes batches of blocks, and lends out a contiguous range of blocks, which the memory-mapped and heap-backed devices do without copying.
+//! Other implementations of this trait are the heap-backed [`MemDevice`](../mem_device/struct.MemDevice.html) and the [`FileDevice`](../file_device/struct.FileDevice.html), which uses positional reads and writes on a plain file.
+//! The [`OverlayDevice`](../overlay_device/struct.OverlayDevice.html) stacks the writes to a device in a separate delta file, on top of a read-only `Device`.
+//! Several devices can be combined into a single one by the mirroring and striping devices in [`raid_device`](../raid_device/index.html).
+//! A device can be shared with other processes over the network with an [`NbdExport`](../nbd/struct.NbdExport.html), and used on the other end as a [`RemoteDevice`](../nbd/struct.RemoteDevice.html).
+//!
+//! Devices created or loaded with [`Device::new_checksummed`] or [`Device::load_checksummed`] keep a [checksum](../checksum/index.html) of every block, and refuse to hand out blocks that do not match it.
+//!
+//! Every device keeps [statistics](../io_stats/index.html) of the blocks it reads and writes, which can be accessed through [`Device::stats`].
+//! To compare file systems by the time their accesses would take on a hard disk or flash disk, wrap a device in a [`SimDevice`](../sim_device/struct.SimDevice.html), which keeps a virtual clock.
+//!
+//! [`BlockDevice`]: trait.BlockDevice.html
+//! [`Device::stats`]: struct.Device.html#method.stats
+//! [`Device::sync`]: struct.Device.html#method.sync
+//! [`Device::new_checksummed`]: struct.Device.html#method.new_checksummed
+//! [`Device::load_checksummed`]: struct.Device.html#method.load_checksummed
+//! [`Device::sync_range`]: struct.Device.html#method.sync_range
 
+use super::checksum::{checksum_path, Checksums};
 use super::error_given;
 use super::error_given::APIError;
+use super::file_device::punch_hole;
+use super::io_stats::{IoOp, IoStats};
 use super::types::Block;
-use memmap::MmapMut;
+use fs2::FileExt;
+use memmap::{Mmap, MmapMut};
 use std::{
-    fs::{remove_file, OpenOptions},
+    borrow::Cow,
+    fs::{remove_file, File, OpenOptions},
+    ops::Deref,
     path::{Path, PathBuf},
 };
 
+/// Interface of a block device, i.e. a disk that reads and writes entire blocks of a fixed size.
+/// The file systems are generic over this trait, so they can be backed by any kind of storage.
+pub trait BlockDevice {
+    /// Read the block with index `index` from the device
+    /// Results in an error if the block index is too high
+    fn read_block(&self, index: u64) -> error_given::Result<Block>;
+
+    /// Write the given block into the device, at the index given by its `block_no`
+    /// Fails if the block is not exactly block-sized, or if its index is too high
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()>;
+
+    /// Read the `n` consecutive blocks starting at index `start` from the device
+    /// Results in an error if any of these blocks lies past the end of the device
+    fn read_blocks(&self, start: u64, n: u64) -> error_given::Result<Vec<Block>> {
+        (start..start + n).map(|i| self.read_block(i)).collect()
+    }
+
+    /// Write all given blocks into the device, each at the index given by its `block_no`
+    /// Fails before writing anything if any of the blocks is not exactly block-sized, or if its index is too high
+    fn write_blocks(&mut self, blocks: &[Block]) -> error_given::Result<()> {
+        check_blocks(blocks, self.block_size(), self.nblocks())?;
+        for b in blocks {
+            self.write_block(b)?;
+        }
+        Ok(())
+    }
+
+    /// Contents of the `n` consecutive blocks starting at index `start`, as a single contiguous slice.
+    /// Devices that keep their contents in memory lend them out without copying; the default implementation reads the blocks and concatenates them.
+    /// Results in an error if any of these blocks lies past the end of the device
+    fn borrow_blocks(&self, start: u64, n: u64) -> error_given::Result<Cow<'_, [u8]>> {
+        let blocks = self.read_blocks(start, n)?;
+        Ok(Cow::Owned(
+            blocks
+                .iter()
+                .flat_map(|b| b.contents_as_ref().iter().copied())
+                .collect(),
+        ))
+    }
+
+    /// Make sure all writes so far have reached the underlying storage
+    fn flush(&mut self) -> error_given::Result<()>;
+
+    /// Size of the blocks that this device reads and writes
+    fn block_size(&self) -> u64;
+
+    /// Total number of blocks this device consists of
+    fn nblocks(&self) -> u64;
+
+    /// Discard the `n` consecutive blocks starting at index `start`, telling the device that their contents are no longer needed; they read as zeroes afterwards.
+    /// Devices backed by a file release the space of these blocks on the host where possible; the default implementation overwrites them with zeroes.
+    /// Fails before discarding anything if any of these blocks lies past the end of the device
+    fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        if start.checked_add(n).is_none_or(|end| end > self.nblocks()) {
+            return Err(APIError::ControllerInput(
+                "Discard past the end of the device",
+            ));
+        }
+        //zeroes are written a bounded number of blocks at a time, so discarding a large range does not need memory for all of it
+        let mut i = start;
+        while i < start + n {
+            let end = (start + n).min(i + DISCARD_BATCH);
+            let zeroes: Vec<Block> = (i..end)
+                .map(|j| Block::new_zero(j, self.block_size()))
+                .collect();
+            self.write_blocks(&zeroes)?;
+            i = end;
+        }
+        Ok(())
+    }
+
+    /// Grow or shrink this device to `nblocks` blocks.
+    /// Blocks that are added read as zeroes, and the contents of blocks past the new end are lost.
+    /// The default implementation fails, for devices whose size is fixed.
+    fn resize(&mut self, nblocks: u64) -> error_given::Result<()> {
+        let _ = nblocks;
+        Err(APIError::ControllerInput("Device cannot be resized"))
+    }
+}
+
+/// Maximal number of zero blocks the default `BlockDevice::discard` writes at once
+const DISCARD_BATCH: u64 = 64;
+
+/// Check that all `blocks` can be written to a device with `nblocks` blocks of `block_size` bytes, so that a batched write either writes all of them or none
+pub(crate) fn check_blocks(
+    blocks: &[Block],
+    block_size: u64,
+    nblocks: u64,
+) -> error_given::Result<()> {
+    for b in blocks {
+        if b.len() != block_size {
+            return Err(APIError::ControllerInput(
+                "Trying to write a non-block-sized block",
+            ));
+        }
+        if b.block_no >= nblocks {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Block devices that are backed by an image at a path on the host.
+/// These are the devices a file system can be created for from just a path, using [`FileSysSupport::mkfs`](../fs/trait.FileSysSupport.html#tymethod.mkfs).
+pub trait ImageDevice: BlockDevice + Sized {
+    /// Create a *new* device image at `path`, with `nblocks` blocks of `block_size` bytes that are all zero
+    /// Errors if the file represented by `path` already exists.
+    fn create<P: AsRef<Path>>(path: P, block_size: u64, nblocks: u64) -> error_given::Result<Self>;
+}
+
 /// Struct representing the state of a hard drive disk (HDD).
 /// The implementation of this structure is the controller that allows us to read disk blocks from the disk, and write disk blocks to the disk.
 ///
@@ -34,7 +177,15 @@ pub struct Device {
     /// Path to the file in your file system that is used as a storage area to emulate the disk
     path: PathBuf,
     /// Memory-mapped contents of the above file. This is what is manipulated in the read and write functions.
-    contents: MmapMut,
+    contents: Mapping,
+    /// The opened file at `path`, which holds the lock on the image until the device is dropped
+    file: File,
+    /// Kind of lock held on the image
+    lock: LockMode,
+    /// Statistics of the reads and writes of this device
+    stats: IoStats,
+    /// Checksums of all blocks, if this device was created or loaded with checksums
+    checksums: Option<Checksums>,
 }
 
 /// Small enum, used to specify whether we expect to open a new file system
@@ -46,6 +197,35 @@ pub enum DiskState {
     Load,
 }
 
+/// Small enum, used to specify which kind of lock a device takes on its image
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum LockMode {
+    /// No other device can use the image at the same time; required for writing to the device
+    Exclusive,
+    /// Other devices holding a shared lock can use the image at the same time; writing to the device is not allowed
+    Shared,
+}
+
+/// Memory mapping of the image of a device, which can only be written to if the image was opened for writing
+#[derive(Debug)]
+enum Mapping {
+    /// Mapping of an image that was opened for reading and writing
+    ReadWrite(MmapMut),
+    /// Mapping of an image that was opened for reading only
+    ReadOnly(Mmap),
+}
+
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Mapping::ReadWrite(m) => m,
+            Mapping::ReadOnly(m) => m,
+        }
+    }
+}
+
 // Import the components of this enum, so we can reuse them here
 use self::DiskState::*;
 impl DiskState {
@@ -63,26 +243,62 @@ impl Drop for Device {
     /// We only need to persist these writes if the file backing this disk actually still exists
     fn drop(&mut self) {
         if self.path.exists() {
-            self.contents.flush().unwrap();
+            self.sync().unwrap();
         }
     }
 }
 
 impl Device {
     /// Core function to that handles both `new` and `load`, based on the value of the switch `ds`, representing whether we want to load or create a disk
+    /// The image is locked exclusively.
     pub fn create_device<P: AsRef<Path>>(
         path: P,
         block_size: u64,
         nblocks: u64,
         ds: DiskState,
+    ) -> error_given::Result<Device> {
+        Device::create_device_locked(path, block_size, nblocks, ds, LockMode::Exclusive)
+    }
+
+    /// Variant of `create_device` that takes the given kind of lock on the image.
+    /// Returns an `APIError::DeviceInUse` error if the image is already locked by another device in a conflicting way.
+    pub fn create_device_locked<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+        nblocks: u64,
+        ds: DiskState,
+        lock: LockMode,
+    ) -> error_given::Result<Device> {
+        Device::open_device(path, block_size, nblocks, ds, lock, true)
+    }
+
+    /// Shared implementation of `create_device_locked` and `load_readonly`.
+    /// The image is only opened and mapped for writing if `writable` is set.
+    fn open_device<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+        nblocks: u64,
+        ds: DiskState,
+        lock: LockMode,
+        writable: bool,
     ) -> error_given::Result<Device> {
         let path_buf = path.as_ref().to_path_buf();
-        let mmapf = mmap_path(path, block_size * nblocks, ds)?;
+        let file = open_path(&path, block_size * nblocks, ds, writable)?;
+        lock_file(&file, &path_buf, lock)?;
+        let mmapf = if writable {
+            Mapping::ReadWrite(unsafe { memmap::MmapOptions::new().map_mut(&file)? })
+        } else {
+            Mapping::ReadOnly(unsafe { memmap::MmapOptions::new().map(&file)? })
+        };
         Ok(Device {
             block_size: block_size,
             nblocks: nblocks,
             path: path_buf,
             contents: mmapf,
+            file,
+            lock,
+            stats: IoStats::new(),
+            checksums: None,
         })
     }
 
@@ -113,11 +329,136 @@ impl Device {
         Device::create_device(path, block_size, nblocks, Load)
     }
 
-    /// End the lifetime of this disk, and remove the file backing it on disk
+    /// Load an *existing* disk device like `load`, but only take a shared lock on its image.
+    /// Several devices can load the same image in this way at the same time, but none of them can write to it.
+    /// This function will return an error, if the image is currently used by a device holding an exclusive lock.
+    pub fn load_shared<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+        nblocks: u64,
+    ) -> error_given::Result<Device> {
+        Device::create_device_locked(path, block_size, nblocks, Load, LockMode::Shared)
+    }
+
+    /// Load an *existing* disk device for reading only.
+    /// The image is opened without write access and mapped read-only, so that neither this device nor any file system on top of it can modify it.
+    /// Like `load_shared`, this only takes a shared lock on the image.
+    pub fn load_readonly<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+        nblocks: u64,
+    ) -> error_given::Result<Device> {
+        Device::open_device(path, block_size, nblocks, Load, LockMode::Shared, false)
+    }
+
+    /// Create a *new* disk device like `new`, that also keeps a checksum of every block in a sidecar file next to its image.
+    /// This function will return an error, if the image or its sidecar file already exists.
+    pub fn new_checksummed<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+        nblocks: u64,
+    ) -> error_given::Result<Device> {
+        if checksum_path(&path).exists() {
+            return Err(APIError::ControllerInput("Checksum file already exists"));
+        }
+        Device::new(path, block_size, nblocks)?.with_checksums()
+    }
+
+    /// Load an *existing* disk device like `load`, verifying every block that is read against the checksums in the sidecar file next to its image.
+    /// If the image does not have a sidecar file yet, it is created, trusting the current contents of the image.
+    pub fn load_checksummed<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+        nblocks: u64,
+    ) -> error_given::Result<Device> {
+        Device::load(path, block_size, nblocks)?.with_checksums()
+    }
+
+    /// Open the sidecar file with the checksums of this device, and verify and update them from now on
+    fn with_checksums(mut self) -> error_given::Result<Device> {
+        self.checksums = Some(Checksums::open(
+            &checksum_path(&self.path),
+            &self.contents,
+            self.block_size,
+            self.nblocks,
+        )?);
+        Ok(self)
+    }
+
+    /// Check whether this device keeps checksums of its blocks, i.e. whether it was created or loaded with `new_checksummed` or `load_checksummed`
+    pub fn is_checksummed(&self) -> bool {
+        self.checksums.is_some()
+    }
+
+    /// Indices of all blocks that do not match their checksum, in ascending order.
+    /// Always empty if this device does not keep checksums.
+    pub fn scrub(&self) -> Vec<u64> {
+        match &self.checksums {
+            Some(c) => self
+                .contents
+                .chunks(self.block_size as usize)
+                .zip(0..)
+                .filter(|(data, i)| c.verify(*i, data).is_err())
+                .map(|(_, i)| i)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Check the checksums of the `n` blocks in `data`, starting at block `start`, if this device keeps checksums
+    fn verify(&self, start: u64, data: &[u8]) -> error_given::Result<()> {
+        if let Some(c) = &self.checksums {
+            for (block, i) in data.chunks(self.block_size as usize).zip(start..) {
+                c.verify(i, block)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether this device was loaded read-only, using `load_readonly`
+    pub fn is_readonly(&self) -> bool {
+        matches!(self.contents, Mapping::ReadOnly(_))
+    }
+
+    /// Handle to the I/O statistics of this device, which can be queried and reset while a file system uses the device
+    pub fn stats(&self) -> IoStats {
+        self.stats.clone()
+    }
+
+    /// Kind of lock this device holds on its image
+    pub fn lock_mode(&self) -> LockMode {
+        self.lock
+    }
+
+    /// End the lifetime of this disk, and remove the file backing it on disk, together with its checksums, if any
     /// Assumes that you have not made any other links to the backing file
     /// Panics if removing the file fails
     pub fn destruct(self) {
         remove_file(&self.path).unwrap();
+        if self.checksums.is_some() {
+            remove_file(checksum_path(&self.path)).unwrap();
+        }
+    }
+
+    /// Grow or shrink this device to `nblocks` blocks, by resizing its image, and its checksums if it has any.
+    /// Blocks that are added read as zeroes, and the contents of blocks past the new end are lost.
+    /// All writes so far are synced first.
+    /// Fails if the device is read-only or only holds a shared lock, or if `nblocks` is zero.
+    pub fn resize(&mut self, nblocks: u64) -> error_given::Result<()> {
+        self.check_writable()?;
+        //an empty file cannot be mapped, and the old mapping must not outlive a failed resize
+        if nblocks == 0 {
+            return Err(APIError::ControllerInput("Resize to an empty device"));
+        }
+        self.sync()?;
+        self.file.set_len(self.block_size * nblocks)?;
+        self.contents =
+            Mapping::ReadWrite(unsafe { memmap::MmapOptions::new().map_mut(&self.file)? });
+        if let Some(c) = &mut self.checksums {
+            c.resize(nblocks, self.block_size)?;
+        }
+        self.nblocks = nblocks;
+        Ok(())
     }
 
     /// Size of this device in bytes
@@ -134,6 +475,71 @@ impl Device {
         self.block_size * index
     }
 
+    /// Make sure all writes to this device so far have reached its image, blocking until they have.
+    /// Without calling this method, writes are only guaranteed to be persisted when the device is dropped.
+    pub fn sync(&self) -> error_given::Result<()> {
+        if let Mapping::ReadWrite(m) = &self.contents {
+            m.flush()?;
+        }
+        if let Some(c) = &self.checksums {
+            c.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Make sure all writes to the `n` blocks starting at block `first_block` have reached the image, blocking until they have.
+    /// The checksums of a checksummed device are synced entirely.
+    /// Fails if the range runs past the end of the device.
+    pub fn sync_range(&self, first_block: u64, n: u64) -> error_given::Result<()> {
+        let (addr, len) = self.sync_range_addr(first_block, n)?;
+        if let Mapping::ReadWrite(m) = &self.contents {
+            m.flush_range(addr, len)?;
+        }
+        if let Some(c) = &self.checksums {
+            c.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Start writing all writes to this device so far back to its image, without waiting for this to finish.
+    /// Use `sync` to wait for the writes to be persisted.
+    pub fn sync_async(&self) -> error_given::Result<()> {
+        if let Mapping::ReadWrite(m) = &self.contents {
+            m.flush_async()?;
+        }
+        if let Some(c) = &self.checksums {
+            c.flush_async()?;
+        }
+        Ok(())
+    }
+
+    /// Start writing the `n` blocks starting at block `first_block` back to the image, without waiting for this to finish.
+    /// Fails if the range runs past the end of the device.
+    pub fn sync_range_async(&self, first_block: u64, n: u64) -> error_given::Result<()> {
+        let (addr, len) = self.sync_range_addr(first_block, n)?;
+        if let Mapping::ReadWrite(m) = &self.contents {
+            m.flush_async_range(addr, len)?;
+        }
+        if let Some(c) = &self.checksums {
+            c.flush_async()?;
+        }
+        Ok(())
+    }
+
+    /// Address and length in bytes of the `n` blocks starting at block `first_block`, or an error if they do not fit on the device
+    fn sync_range_addr(&self, first_block: u64, n: u64) -> error_given::Result<(usize, usize)> {
+        if first_block
+            .checked_add(n)
+            .is_none_or(|end| end > self.nblocks)
+        {
+            return Err(APIError::ControllerInput("Sync past the end of the device"));
+        }
+        Ok((
+            self.index_to_addr(first_block) as usize,
+            (self.block_size * n) as usize,
+        ))
+    }
+
     /// Read `nb` bytes from the device starting at address `addr`
     /// Results in an error if a write past the end of the device is attempted
     /// Note that this function would probably not be offered in this way by a realistic device driver.
@@ -148,11 +554,13 @@ impl Device {
     }
 
     /// Read the block with index `index` from the device
-    /// Results in an error if the block index is too high
+    /// Results in an error if the block index is too high, or if the block does not match its checksum
     /// The block is returned in the form of a `Block` structure
     pub fn read_block(&self, index: u64) -> error_given::Result<Block> {
         let addr = self.index_to_addr(index);
         let block_data = self.read(addr, self.block_size)?;
+        self.verify(index, &block_data)?;
+        self.stats.record(IoOp::Read, index, self.block_size);
         Ok(Block::new(index, block_data))
     }
 
@@ -161,6 +569,7 @@ impl Device {
     /// Note that this function would probably not be offered in this way by a realistic device driver.
     /// Rather, the writes happen on a block-by-block basis (possibly batched)
     fn write(&mut self, addr: u64, b: &[u8]) -> error_given::Result<()> {
+        self.check_writable()?;
         if addr + b.len() as u64 > self.device_size() {
             return Err(APIError::ControllerInput(
                 "Write past the end of the device",
@@ -168,7 +577,48 @@ impl Device {
         }
         let start = addr as usize;
         let end = (addr as usize) + b.len();
-        self.contents[start..end].copy_from_slice(b);
+        if let Mapping::ReadWrite(m) = &mut self.contents {
+            m[start..end].copy_from_slice(b);
+        }
+        Ok(())
+    }
+
+    /// Fail if this device cannot be written, because it is read-only or only holds a shared lock
+    fn check_writable(&self) -> error_given::Result<()> {
+        if self.is_readonly() {
+            return Err(APIError::ControllerInput("Write to a read-only device"));
+        }
+        if self.lock == LockMode::Shared {
+            return Err(APIError::ControllerInput(
+                "Write to a device that only holds a shared lock",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Discard the `n` blocks starting at block `start`, which read as zeroes afterwards.
+    /// The blocks are punched out of the image if the file system on the host supports this, so they no longer take up space there, and are overwritten with zeroes otherwise.
+    /// Fails if any of these blocks lies past the end of the device, or if the device cannot be written
+    pub fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        self.check_writable()?;
+        if start.checked_add(n).is_none_or(|end| end > self.nblocks) {
+            return Err(APIError::ControllerInput(
+                "Discard past the end of the device",
+            ));
+        }
+        let addr = self.index_to_addr(start);
+        let len = self.block_size * n;
+        if !punch_hole(&self.file, addr, len)? {
+            if let Mapping::ReadWrite(m) = &mut self.contents {
+                m[addr as usize..(addr + len) as usize].fill(0);
+            }
+        }
+        if let Some(c) = &mut self.checksums {
+            let zero = vec![0; self.block_size as usize];
+            for i in start..start + n {
+                c.update(i, &zero);
+            }
+        }
         Ok(())
     }
 
@@ -181,15 +631,131 @@ impl Device {
             ));
         }
         let addr = self.index_to_addr(b.block_no);
-        self.write(addr, &b.contents_as_ref())
+        self.write(addr, &b.contents_as_ref())?;
+        if let Some(c) = &mut self.checksums {
+            c.update(b.block_no, b.contents_as_ref());
+        }
+        self.stats.record(IoOp::Write, b.block_no, self.block_size);
+        Ok(())
+    }
+
+    /// Borrow the contents of the `n` consecutive blocks starting at index `start` straight from the mapping, without copying them
+    /// Results in an error if any of these blocks lies past the end of the device, or does not match its checksum
+    pub fn blocks_ref(&self, start: u64, n: u64) -> error_given::Result<&[u8]> {
+        if start + n > self.nblocks {
+            return Err(APIError::ControllerInput("Read past the end of the device"));
+        }
+        let addr = self.index_to_addr(start) as usize;
+        let end = addr + (n * self.block_size) as usize;
+        self.verify(start, &self.contents[addr..end])?;
+        for i in start..start + n {
+            self.stats.record(IoOp::Read, i, self.block_size);
+        }
+        Ok(&self.contents[addr..end])
+    }
+
+    /// Read the `n` consecutive blocks starting at index `start` from the device, after checking the entire range at once
+    /// Results in an error if any of these blocks lies past the end of the device
+    pub fn read_blocks(&self, start: u64, n: u64) -> error_given::Result<Vec<Block>> {
+        let data = self.blocks_ref(start, n)?;
+        Ok(data
+            .chunks(self.block_size as usize)
+            .zip(start..)
+            .map(|(d, i)| Block::new(i, d.into()))
+            .collect())
+    }
+
+    /// Write all given blocks into the device, each at the index given by its `block_no`
+    /// Fails before writing anything if any of the blocks is not exactly block-sized, or if its index is too high
+    pub fn write_blocks(&mut self, blocks: &[Block]) -> error_given::Result<()> {
+        check_blocks(blocks, self.block_size, self.nblocks)?;
+        for b in blocks {
+            let addr = self.index_to_addr(b.block_no);
+            self.write(addr, b.contents_as_ref())?;
+            if let Some(c) = &mut self.checksums {
+                c.update(b.block_no, b.contents_as_ref());
+            }
+            self.stats.record(IoOp::Write, b.block_no, self.block_size);
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for Device {
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        Device::read_block(self, index)
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        Device::write_block(self, b)
+    }
+
+    fn read_blocks(&self, start: u64, n: u64) -> error_given::Result<Vec<Block>> {
+        Device::read_blocks(self, start, n)
+    }
+
+    fn write_blocks(&mut self, blocks: &[Block]) -> error_given::Result<()> {
+        Device::write_blocks(self, blocks)
+    }
+
+    fn borrow_blocks(&self, start: u64, n: u64) -> error_given::Result<Cow<'_, [u8]>> {
+        Ok(Cow::Borrowed(self.blocks_ref(start, n)?))
+    }
+
+    fn flush(&mut self) -> error_given::Result<()> {
+        self.sync()
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.nblocks
+    }
+
+    fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        Device::discard(self, start, n)
+    }
+
+    fn resize(&mut self, nblocks: u64) -> error_given::Result<()> {
+        Device::resize(self, nblocks)
+    }
+}
+
+impl ImageDevice for Device {
+    fn create<P: AsRef<Path>>(path: P, block_size: u64, nblocks: u64) -> error_given::Result<Self> {
+        Device::new(path, block_size, nblocks)
+    }
+}
+
+/// Take the given kind of advisory lock on the opened image `file` at `path`, without waiting for other locks to be released.
+/// The lock is released when `file` is closed.
+pub(crate) fn lock_file(file: &File, path: &Path, lock: LockMode) -> error_given::Result<()> {
+    let result = match lock {
+        LockMode::Exclusive => file.try_lock_exclusive(),
+        LockMode::Shared => FileExt::try_lock_shared(file),
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+            Err(APIError::DeviceInUse(path.to_path_buf()))
+        }
+        Err(e) => Err(e.into()),
     }
 }
 
 /// Either open or create the specified file path.
 /// The boolean `ex` specifies
 /// If the path already exists, check that the device represented by it has the correct size
+/// The file is only opened for writing if `writable` is set; new files are always writable.
 /// If any one of the intermediate calls fails, the result of this method is not an actual device file
-fn mmap_path<P: AsRef<Path>>(path: P, dsize: u64, ex: DiskState) -> error_given::Result<MmapMut> {
+pub(crate) fn open_path<P: AsRef<Path>>(
+    path: P,
+    dsize: u64,
+    ex: DiskState,
+    writable: bool,
+) -> error_given::Result<File> {
     let exists = DiskState::new(path.as_ref().exists());
     if exists != ex {
         if ex == Load {
@@ -205,8 +771,8 @@ fn mmap_path<P: AsRef<Path>>(path: P, dsize: u64, ex: DiskState) -> error_given:
 
     let f = OpenOptions::new()
         .read(true)
-        .write(true)
-        .create(true)
+        .write(writable || ex == New)
+        .create(ex == New)
         .open(path)?;
 
     if ex == Load {
@@ -219,8 +785,7 @@ fn mmap_path<P: AsRef<Path>>(path: P, dsize: u64, ex: DiskState) -> error_given:
         f.set_len(dsize)?; // The file will be extended to dsize and have all of the intermediate data filled in with 0s.
     }
 
-    let data = unsafe { memmap::MmapOptions::new().map_mut(&f)? };
-    Ok(data)
+    Ok(f)
 }
 
 // Here we define a submodule, called `tests`, that will contain the unit
@@ -242,11 +807,14 @@ fn mmap_path<P: AsRef<Path>>(path: P, dsize: u64, ex: DiskState) -> error_given:
 // For more information, see [here](https://doc.rust-lang.org/book/ch11-02-running-tests.html#running-tests-in-parallel-or-consecutively).
 //
 //
-// Note that one of the reason this assignment does not come with premade file systems, but rather, we set them up during the tests, is because the Serialization we will perform in higher layers of abstraction is platform-dependent, and binary formats might hence differ between different users.
+// Note that the file systems in this assignment are set up during the tests, rather than shipped as premade images.
+// This is no longer a matter of portability: the higher layers of abstraction store their structures in the fixed, platform-independent layout of the [`encoding`](../encoding/index.html) module, so an image written on one machine can be mounted on any other.
 #[cfg(test)]
 mod tests {
 
     use super::Device;
+    use crate::checksum::checksum_path;
+    use crate::error_given::APIError;
     use crate::types::Block;
     use std::fs::{create_dir_all, remove_dir, remove_file};
     use std::path::{Path, PathBuf};
@@ -387,4 +955,253 @@ mod tests {
         //Make sure the file has actually been destroyed
         assert!(!path.exists());
     }
+
+    // Here we test that an image cannot be used by two devices at the same time, unless both of them only read from it.
+    #[test]
+    fn locking_test() {
+        let path = disk_prep_path("locking");
+        let dev = disk_setup(&path);
+
+        //The image is locked exclusively while the device is alive
+        assert!(matches!(
+            Device::load(&path, BLOCK_SIZE, NBBLOCKS),
+            Err(APIError::DeviceInUse(_))
+        ));
+        assert!(matches!(
+            Device::load_shared(&path, BLOCK_SIZE, NBBLOCKS),
+            Err(APIError::DeviceInUse(_))
+        ));
+        drop(dev);
+
+        //Readers can share the image, but not write to it, and keep writers out
+        let mut reader1 = Device::load_shared(&path, BLOCK_SIZE, NBBLOCKS).unwrap();
+        let reader2 = Device::load_shared(&path, BLOCK_SIZE, NBBLOCKS).unwrap();
+        assert_eq!(
+            reader2.read_block(1).unwrap(),
+            Block::new_zero(1, BLOCK_SIZE)
+        );
+        assert!(reader1
+            .write_block(&Block::new_zero(1, BLOCK_SIZE))
+            .is_err());
+        assert!(matches!(
+            Device::load(&path, BLOCK_SIZE, NBBLOCKS),
+            Err(APIError::DeviceInUse(_))
+        ));
+        drop(reader1);
+        drop(reader2);
+
+        let dev = disk_open(&path);
+        disk_destruct(dev);
+    }
+
+    // Here we test that a device loaded read-only can be read from, but never modifies its image.
+    #[test]
+    fn readonly_test() {
+        let path = disk_prep_path("readonly");
+        let mut dev = disk_setup(&path);
+        let bw = Block::new(3, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+        drop(dev);
+        let image = std::fs::read(&path).unwrap();
+
+        //Several read-only devices can share the image
+        let mut reader1 = Device::load_readonly(&path, BLOCK_SIZE, NBBLOCKS).unwrap();
+        let reader2 = Device::load_readonly(&path, BLOCK_SIZE, NBBLOCKS).unwrap();
+        assert!(reader1.is_readonly());
+        assert_eq!(reader1.read_block(3).unwrap(), bw);
+        assert_eq!(reader2.read_block(3).unwrap(), bw);
+        assert!(matches!(
+            reader1.write_block(&Block::new_zero(3, BLOCK_SIZE)),
+            Err(APIError::ControllerInput(_))
+        ));
+        reader1.sync().unwrap();
+        assert!(Device::load_readonly(&path, BLOCK_SIZE, NBBLOCKS + 1).is_err());
+        drop(reader1);
+        drop(reader2);
+        assert_eq!(std::fs::read(&path).unwrap(), image);
+
+        let dev = disk_open(&path);
+        assert!(!dev.is_readonly());
+        disk_destruct(dev);
+    }
+
+    // Here we test that writes can be synced to the image at any point, while the device stays alive.
+    #[test]
+    fn sync_test() {
+        let path = disk_prep_path("sync");
+        let mut dev = disk_setup(&path);
+
+        let bw = Block::new(8, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+        dev.sync_range(8, 2).unwrap();
+        dev.sync_range_async(0, NBBLOCKS).unwrap();
+        assert_eq!(&std::fs::read(&path).unwrap()[80..90], bw.contents_as_ref());
+
+        //Ranges have to fit on the device
+        assert!(dev.sync_range(9, 2).is_err());
+        assert!(dev.sync_range_async(u64::MAX, 2).is_err());
+
+        dev.write_block(&Block::new(9, vec![1; 10].into_boxed_slice()))
+            .unwrap();
+        dev.sync_async().unwrap();
+        dev.sync().unwrap();
+        assert_eq!(&std::fs::read(&path).unwrap()[90..], &[1; 10]);
+
+        disk_destruct(dev);
+    }
+
+    #[test]
+    fn checksum_test() {
+        let path = disk_prep_path("checksum");
+        let crc_path = checksum_path(&path);
+        if crc_path.exists() {
+            remove_file(&crc_path).unwrap();
+        }
+        let mut dev = Device::new_checksummed(&path, BLOCK_SIZE, NBBLOCKS).unwrap();
+        assert!(dev.is_checksummed());
+        let bw = Block::new(3, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+        assert_eq!(dev.read_block(3).unwrap(), bw);
+        drop(dev);
+        assert!(Device::new_checksummed(&path, BLOCK_SIZE, NBBLOCKS).is_err());
+
+        //Flip a bit in block 3 behind the back of the device
+        let mut image = std::fs::read(&path).unwrap();
+        image[35] ^= 0x10;
+        std::fs::write(&path, &image).unwrap();
+
+        let mut dev = Device::load_checksummed(&path, BLOCK_SIZE, NBBLOCKS).unwrap();
+        assert!(matches!(
+            dev.read_block(3),
+            Err(APIError::Corruption { block_no: 3 })
+        ));
+        assert!(matches!(
+            dev.read_blocks(2, 2),
+            Err(APIError::Corruption { block_no: 3 })
+        ));
+        assert_eq!(dev.read_block(2).unwrap(), Block::new_zero(2, BLOCK_SIZE));
+        assert_eq!(dev.scrub(), vec![3]);
+
+        //Rewriting the block repairs it
+        dev.write_block(&bw).unwrap();
+        assert!(dev.scrub().is_empty());
+        dev.destruct();
+        assert!(!crc_path.exists());
+
+        //Loading an image without checksums creates them from its current contents
+        let mut dev = disk_setup(&path);
+        dev.write_block(&bw).unwrap();
+        assert!(!dev.is_checksummed());
+        drop(dev);
+        let dev = Device::load_checksummed(&path, BLOCK_SIZE, NBBLOCKS).unwrap();
+        assert_eq!(dev.read_block(3).unwrap(), bw);
+        assert!(crc_path.exists());
+        disk_destruct(dev);
+    }
+
+    #[test]
+    fn resize_test() {
+        let path = disk_prep_path("resize");
+        let crc_path = checksum_path(&path);
+        if crc_path.exists() {
+            remove_file(&crc_path).unwrap();
+        }
+        let mut dev = Device::new_checksummed(&path, BLOCK_SIZE, NBBLOCKS).unwrap();
+        let bw = Block::new(NBBLOCKS - 1, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+
+        //Growing keeps the old blocks, and adds zero blocks with valid checksums
+        dev.resize(2 * NBBLOCKS).unwrap();
+        assert_eq!(dev.nblocks, 2 * NBBLOCKS);
+        assert_eq!(dev.read_block(NBBLOCKS - 1).unwrap(), bw);
+        assert_eq!(
+            dev.read_block(2 * NBBLOCKS - 1).unwrap(),
+            Block::new_zero(2 * NBBLOCKS - 1, BLOCK_SIZE)
+        );
+        assert!(dev.scrub().is_empty());
+        drop(dev);
+        let mut dev = Device::load_checksummed(&path, BLOCK_SIZE, 2 * NBBLOCKS).unwrap();
+        assert_eq!(dev.read_block(NBBLOCKS - 1).unwrap(), bw);
+
+        //Shrinking drops the blocks past the new end
+        dev.resize(NBBLOCKS - 1).unwrap();
+        assert!(dev.read_block(NBBLOCKS - 1).is_err());
+        assert!(dev.resize(0).is_err());
+        drop(dev);
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            BLOCK_SIZE * (NBBLOCKS - 1)
+        );
+        let mut dev = Device::load_readonly(&path, BLOCK_SIZE, NBBLOCKS - 1).unwrap();
+        assert!(dev.resize(NBBLOCKS).is_err());
+        drop(dev);
+        let dev = Device::load_checksummed(&path, BLOCK_SIZE, NBBLOCKS - 1).unwrap();
+        assert!(dev.scrub().is_empty());
+        disk_destruct(dev);
+        assert!(!crc_path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn discard_test() {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = disk_prep_path("discard");
+        let crc_path = checksum_path(&path);
+        if crc_path.exists() {
+            remove_file(&crc_path).unwrap();
+        }
+        let mut dev = Device::new_checksummed(&path, 4096, 16).unwrap();
+        let blocks: Vec<Block> = (0..16)
+            .map(|i| Block::new(i, vec![1; 4096].into()))
+            .collect();
+        dev.write_blocks(&blocks).unwrap();
+        dev.sync().unwrap();
+        let allocated = std::fs::metadata(&path).unwrap().blocks();
+
+        //Discarded blocks read as zeroes and match their checksums, and no longer take up more space on the host
+        dev.discard(4, 8).unwrap();
+        assert!(dev.discard(12, 5).is_err());
+        assert_eq!(dev.read_block(3).unwrap(), blocks[3]);
+        assert_eq!(dev.read_block(4).unwrap(), Block::new_zero(4, 4096));
+        assert_eq!(dev.read_block(11).unwrap(), Block::new_zero(11, 4096));
+        assert_eq!(dev.read_block(12).unwrap(), blocks[12]);
+        assert!(dev.scrub().is_empty());
+        dev.sync().unwrap();
+        assert!(std::fs::metadata(&path).unwrap().blocks() <= allocated);
+        drop(dev);
+
+        //The discard is persistent, and not possible on a read-only device
+        let mut dev = Device::load_readonly(&path, 4096, 16).unwrap();
+        assert_eq!(dev.read_block(4).unwrap(), Block::new_zero(4, 4096));
+        assert!(dev.discard(0, 1).is_err());
+        assert_eq!(dev.read_block(0).unwrap(), blocks[0]);
+        drop(dev);
+        remove_file(&crc_path).unwrap();
+        disk_destruct(Device::load(&path, 4096, 16).unwrap());
+    }
+
+    #[test]
+    fn batched_test() {
+        let path = disk_prep_path("batched");
+        let mut dev = disk_setup(&path);
+
+        let blocks: Vec<Block> = (2..5)
+            .map(|i| Block::new(i, vec![i as u8; 10].into_boxed_slice()))
+            .collect();
+        dev.write_blocks(&blocks).unwrap();
+        assert_eq!(dev.read_blocks(2, 3).unwrap(), blocks);
+        assert_eq!(&dev.blocks_ref(4, 1).unwrap(), &[4; 10]);
+        assert_eq!(dev.stats().total().writes, 3);
+        assert_eq!(dev.stats().total().reads, 4);
+
+        //Nothing is written if one of the blocks does not fit
+        assert!(dev
+            .write_blocks(&[Block::new_zero(2, 10), Block::new_zero(NBBLOCKS, 10)])
+            .is_err());
+        assert_eq!(dev.read_block(2).unwrap(), blocks[0]);
+        assert!(dev.blocks_ref(NBBLOCKS - 1, 2).is_err());
+
+        disk_destruct(dev);
+    }
 }
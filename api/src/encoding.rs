@@ -0,0 +1,366 @@
+//! Fixed, platform-independent encoding of the structures that file systems store in their blocks.
+//!
+//! Every structure that is stored on disk implements [`OnDisk`], which encodes it into a fixed number of bytes, [`OnDisk::SIZE`], regardless of its contents or of the machine the code runs on.
+//! Since the encoding does not depend on a serialization library or its configuration either, an image written on one host can be mounted on any other.
+//! All integers are stored as little-endian numbers of the width of their type, and fields are stored in declaration order, without any padding:
+//!
+//! | Type                                                | Size | Layout                                                                                                                                                                                                     |
+//! |-----------------------------------------------------|------|------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------|
+//! | [`FType`](../types/enum.FType.html)                 | 4    | `u32` tag: 0 for `TDir`, 1 for `TFile`, 2 for `TFree`, 3 for `TSymlink`                                                                                                                                    |
+//! | `char`                                              | 4    | `u32` Unicode scalar value                                                                                                                                                                                 |
+//! | [`SuperBlock`](../types/struct.SuperBlock.html)     | 56   | `block_size`, `nblocks`, `ninodes`, `inodestart`, `ndatablocks`, `bmapstart`, `datastart`, as `u64`s                                                                                                       |
+//! | [`VolumeHeader`](../types/struct.VolumeHeader.html) | 56   | 8-byte `magic`, `version: u32`, 16-byte `uuid`, 16-byte `label`, then the compat, incompat and ro-compat features as `u32`s                                                                                |
+//! | [`Timestamps`](../types/struct.Timestamps.html)     | 32   | `atime`, `mtime`, `ctime`, `crtime`, as `u64`s counting nanoseconds since the Unix epoch                                                                                                                   |
+//! | [`DInode`](../types/struct.DInode.html)             | 120  | `ft`, `nlink: u16`, `size: u64`, `times`, `mode: u16`, `uid: u32`, `gid: u32`, the `DIRECT_POINTERS` block addresses and the `xattr_block` as `u32`s, then the `XATTR_INLINE_SIZE` bytes of `xattr_inline` |
+//! | [`DirEntry`](../types/struct.DirEntry.html)         | 32·n | `inum: u64`, `rec_len: u16`, `name_len: u8`, then the UTF-8 name, padded with zeroes up to `rec_len` bytes                                                                                                 |
+//!
+//! Directory entries are the only structures of varying size: their records take up as few slots of [`DIRENTRY_SIZE`] bytes as their names allow, which `rec_len` records.
+//! They are therefore not encoded through `OnDisk`, but through [`DirEntry::encode`] and [`DirEntry::decode`].
+//!
+//! This is layout version [`LAYOUT_VERSION`]. Any change to the layout of an existing structure must come with a new version, so that images written in the old layout are not misread.
+//! Version 1 stored directory entries as an `inum` followed by 14 characters of 4 bytes each; apart from those, it coincided with what the default `bincode` configuration used to produce on little-endian machines.
+//! Version 2 introduced the directory records, and version 3 added the `times` to inodes, while narrowing their block addresses to `u32`s like ext2 does, to keep inodes small enough for small blocks.
+//! Version 4 added the `mode`, `uid` and `gid` of inodes, and version 5 the `TSymlink` file type, whose inodes store short targets in the place of their block addresses.
+//! Version 6 added extended attributes to inodes, which are encoded as described in the [`xattr`](../xattr/index.html) module.
+//!
+//! File systems that store structures of their own implement `OnDisk` for them in the same way, by encoding their fields one after the other with an [`Encoder`] and decoding them in the same order with a [`Decoder`].
+//!
+//! [`OnDisk`]: trait.OnDisk.html
+//! [`OnDisk::SIZE`]: trait.OnDisk.html#associatedconstant.SIZE
+//! [`LAYOUT_VERSION`]: constant.LAYOUT_VERSION.html
+//! [`DIRENTRY_SIZE`]: ../types/constant.DIRENTRY_SIZE.html
+//! [`DirEntry::encode`]: ../types/struct.DirEntry.html#method.encode
+//! [`DirEntry::decode`]: ../types/struct.DirEntry.html#method.decode
+//! [`Encoder`]: struct.Encoder.html
+//! [`Decoder`]: struct.Decoder.html
+
+use super::error_given;
+use super::error_given::APIError;
+use std::convert::TryInto;
+
+/// Version of the on-disk layout described in this module
+pub const LAYOUT_VERSION: u32 = 6;
+
+/// Structure with a fixed on-disk encoding of `SIZE` bytes
+pub trait OnDisk: Sized {
+    /// Number of bytes this structure takes up on disk
+    const SIZE: u64;
+
+    /// Encode this structure, writing exactly `SIZE` bytes to `enc`
+    fn encode(&self, enc: &mut Encoder<'_>);
+
+    /// Decode a structure from `dec`, reading exactly `SIZE` bytes from it.
+    /// Fails if these bytes are not a valid encoding of the structure.
+    fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self>;
+}
+
+/// Writes encoded fields one after the other into a byte slice
+#[derive(Debug)]
+pub struct Encoder<'a> {
+    /// The slice being written
+    buf: &'a mut [u8],
+    /// Offset in `buf` of the next field
+    pos: usize,
+}
+
+impl<'a> Encoder<'a> {
+    /// Create an encoder that writes to `buf`, starting at its first byte.
+    /// The caller is responsible for making `buf` large enough for everything that is encoded.
+    pub fn new(buf: &'a mut [u8]) -> Encoder<'a> {
+        Encoder { buf, pos: 0 }
+    }
+
+    /// Number of bytes written so far
+    pub fn position(&self) -> u64 {
+        self.pos as u64
+    }
+
+    /// Append the raw `bytes`
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+
+    /// Append the encoding of `value`
+    pub fn put<T: OnDisk>(&mut self, value: &T) {
+        value.encode(self);
+    }
+}
+
+/// Reads encoded fields one after the other from a byte slice
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    /// The slice being read
+    buf: &'a [u8],
+    /// Offset in `buf` of the next field
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// Create a decoder that reads from `buf`, starting at its first byte
+    pub fn new(buf: &'a [u8]) -> Decoder<'a> {
+        Decoder { buf, pos: 0 }
+    }
+
+    /// Number of bytes read so far
+    pub fn position(&self) -> u64 {
+        self.pos as u64
+    }
+
+    /// Take the next `n` raw bytes
+    pub fn get_bytes(&mut self, n: usize) -> error_given::Result<&'a [u8]> {
+        if self.pos + n > self.buf.len() {
+            return Err(APIError::APISerialize(
+                "Trying to decode beyond the end of the data",
+            ));
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    /// Decode the next value
+    pub fn get<T: OnDisk>(&mut self) -> error_given::Result<T> {
+        T::decode(self)
+    }
+}
+
+/// Implement `OnDisk` for a primitive integer type, as its little-endian representation
+macro_rules! on_disk_int {
+    ($($t:ty),*) => {
+        $(
+            impl OnDisk for $t {
+                const SIZE: u64 = std::mem::size_of::<$t>() as u64;
+
+                fn encode(&self, enc: &mut Encoder<'_>) {
+                    enc.put_bytes(&self.to_le_bytes());
+                }
+
+                fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+                    let bytes = dec.get_bytes(std::mem::size_of::<$t>())?;
+                    Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+on_disk_int!(u8, u16, u32, u64);
+
+impl OnDisk for char {
+    const SIZE: u64 = 4;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.put(&(*self as u32));
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+        std::char::from_u32(dec.get()?).ok_or(APIError::APISerialize("Invalid character"))
+    }
+}
+
+impl<T: OnDisk + Default + Copy, const N: usize> OnDisk for [T; N] {
+    const SIZE: u64 = T::SIZE * N as u64;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        for x in self {
+            enc.put(x);
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+        let mut array = [T::default(); N];
+        for x in array.iter_mut() {
+            *x = dec.get()?;
+        }
+        Ok(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, Encoder, OnDisk};
+    use crate::types::{
+        DInode, DirEntry, FType, SuperBlock, Timestamps, VolumeHeader, DIRNAME_SIZE,
+        SYMLINK_INLINE_MAX,
+    };
+
+    /// Encode `value` into a fresh vector
+    fn encode<T: OnDisk>(value: &T) -> Vec<u8> {
+        let mut bytes = vec![0; T::SIZE as usize];
+        let mut enc = Encoder::new(&mut bytes);
+        enc.put(value);
+        assert_eq!(enc.position(), T::SIZE);
+        bytes
+    }
+
+    /// Decode a value from `bytes`, which must be consumed entirely
+    fn decode<T: OnDisk>(bytes: &[u8]) -> T {
+        let mut dec = Decoder::new(bytes);
+        let value = dec.get().unwrap();
+        assert_eq!(dec.position(), T::SIZE);
+        value
+    }
+
+    #[test]
+    fn superblock_golden() {
+        let sb = SuperBlock {
+            block_size: 1000,
+            nblocks: 0x0102_0304_0506_0708,
+            ninodes: 10,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        #[rustfmt::skip]
+        let golden: [u8; 56] = [
+            0xe8, 0x03, 0, 0, 0, 0, 0, 0,
+            0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+            10, 0, 0, 0, 0, 0, 0, 0,
+            1, 0, 0, 0, 0, 0, 0, 0,
+            5, 0, 0, 0, 0, 0, 0, 0,
+            4, 0, 0, 0, 0, 0, 0, 0,
+            5, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(SuperBlock::SIZE, 56);
+        assert_eq!(encode(&sb), golden);
+        assert_eq!(decode::<SuperBlock>(&golden), sb);
+    }
+
+    #[test]
+    fn volume_header_golden() {
+        let mut header = VolumeHeader {
+            magic: *b"CPLFSVOL",
+            version: 1,
+            uuid: [0x12; 16],
+            feature_compat: 1,
+            feature_incompat: 0x0100,
+            feature_ro_compat: 0x0001_0000,
+            ..Default::default()
+        };
+        header.set_label("data");
+        let mut golden = b"CPLFSVOL".to_vec();
+        golden.extend_from_slice(&[1, 0, 0, 0]);
+        golden.extend_from_slice(&[0x12; 16]);
+        golden.extend_from_slice(b"data\0\0\0\0\0\0\0\0\0\0\0\0");
+        golden.extend_from_slice(&[1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(VolumeHeader::SIZE, 56);
+        assert_eq!(encode(&header), golden);
+        assert_eq!(decode::<VolumeHeader>(&golden), header);
+    }
+
+    #[test]
+    fn dinode_golden() {
+        let mut direct_blocks = [0; 12];
+        direct_blocks[0] = 7;
+        direct_blocks[11] = 0x0102_0304;
+        let inode = DInode {
+            ft: FType::TFile,
+            nlink: 0x0203,
+            size: 300,
+            times: Timestamps {
+                atime: 1,
+                mtime: 2,
+                ctime: 0x0102_0304_0506_0708,
+                crtime: 4,
+            },
+            mode: 0o1777,
+            uid: 1000,
+            gid: 0x0102_0304,
+            direct_blocks,
+            xattr_block: 5,
+            xattr_inline: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+        };
+        let mut golden = vec![1, 0, 0, 0, 0x03, 0x02, 0x2c, 0x01, 0, 0, 0, 0, 0, 0];
+        golden.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+        golden.extend_from_slice(&[8, 7, 6, 5, 4, 3, 2, 1, 4, 0, 0, 0, 0, 0, 0, 0]);
+        golden.extend_from_slice(&[0xff, 0x03, 0xe8, 0x03, 0, 0, 4, 3, 2, 1]);
+        golden.extend_from_slice(&[7, 0, 0, 0]);
+        golden.extend_from_slice(&[0; 40]);
+        golden.extend_from_slice(&[4, 3, 2, 1]);
+        golden.extend_from_slice(&[5, 0, 0, 0]);
+        golden.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(DInode::SIZE, 120);
+        assert_eq!(encode(&inode), golden);
+        assert_eq!(decode::<DInode>(&golden), inode);
+
+        //The file types are numbered in declaration order, and other tags are rejected
+        assert_eq!(encode(&FType::TDir), [0, 0, 0, 0]);
+        assert_eq!(encode(&FType::TFree), [2, 0, 0, 0]);
+        assert_eq!(encode(&FType::TSymlink), [3, 0, 0, 0]);
+        assert!(Decoder::new(&[4, 0, 0, 0]).get::<FType>().is_err());
+    }
+
+    #[test]
+    fn inline_symlink_golden() {
+        //a short target is stored as is, in the place of the block addresses
+        let mut inode = DInode {
+            ft: FType::TSymlink,
+            ..Default::default()
+        };
+        inode.set_inline_target(b"../target.txt");
+        assert_eq!(inode.size, 13);
+        let bytes = encode(&inode);
+        assert_eq!(&bytes[56..69], b"../target.txt");
+        assert!(bytes[69..].iter().all(|b| *b == 0));
+        assert_eq!(decode::<DInode>(&bytes).inline_target(), b"../target.txt");
+
+        //targets that fill all of the addresses still fit
+        let long = [b'x'; SYMLINK_INLINE_MAX as usize];
+        inode.set_inline_target(&long);
+        assert_eq!(&encode(&inode)[56..104], &long[..]);
+        assert_eq!(inode.inline_target(), long);
+    }
+
+    #[test]
+    fn direntry_golden() {
+        let entry = DirEntry {
+            inum: 3,
+            name: "aé😀".to_string(),
+        };
+        let mut golden = vec![3, 0, 0, 0, 0, 0, 0, 0, 32, 0, 7];
+        golden.extend_from_slice(&[0x61, 0xc3, 0xa9, 0xf0, 0x9f, 0x98, 0x80]);
+        golden.extend_from_slice(&[0; 14]);
+        assert_eq!(entry.rec_len(), 32);
+        let mut bytes = vec![0; 32];
+        entry.encode(&mut Encoder::new(&mut bytes));
+        assert_eq!(bytes, golden);
+        assert_eq!(
+            DirEntry::decode(&mut Decoder::new(&golden)).unwrap(),
+            (entry, 32)
+        );
+
+        //Longer names take up more slots, up to the longest name
+        let entry = DirEntry {
+            inum: 1,
+            name: "x".repeat(DIRNAME_SIZE),
+        };
+        assert_eq!(entry.rec_len(), 9 * 32);
+        let mut bytes = vec![0; 9 * 32];
+        entry.encode(&mut Encoder::new(&mut bytes));
+        assert_eq!(&bytes[8..11], &[0x20, 0x01, 0xff]);
+        assert_eq!(
+            DirEntry::decode(&mut Decoder::new(&bytes)).unwrap(),
+            (entry, 9 * 32)
+        );
+
+        //An all-zero slot is an empty entry of a single slot
+        assert_eq!(
+            DirEntry::decode(&mut Decoder::new(&[0; 64])).unwrap(),
+            (DirEntry::default(), 32)
+        );
+        //Names have to be valid UTF-8, and fit in their record
+        golden[12] = 0xff;
+        assert!(DirEntry::decode(&mut Decoder::new(&golden)).is_err());
+        golden[12] = 0xc3;
+        golden[10] = 22;
+        assert!(DirEntry::decode(&mut Decoder::new(&golden)).is_err());
+        golden[10] = 7;
+        golden[8] = 40;
+        assert!(DirEntry::decode(&mut Decoder::new(&golden)).is_err());
+        //Truncated data cannot be decoded
+        golden[8] = 32;
+        assert!(DirEntry::decode(&mut Decoder::new(&golden[..31])).is_err());
+    }
+}
@@ -69,6 +69,7 @@
 //! ```
 
 use std::io;
+use std::path::PathBuf;
 use thiserror::Error;
 
 ///Error type used in the provided code
@@ -80,9 +81,9 @@ pub enum APIError {
     /// Error caused when performing IO in the API
     #[error("Issue using IO in the controller layer")]
     APIO(#[from] io::Error),
-    /// Error caused when performing IO in the API
-    #[error("Issue with serialization in the controller layer")]
-    APISerialize(#[from] bincode::Error),
+    /// Data read from a block is not a valid encoding of the structure that was asked for
+    #[error("Invalid on-disk encoding: {0}")]
+    APISerialize(&'static str),
     /// Invalid input to the controller layer
     /// Note: use `String` instead of `&'static str` if you want non-literal, i.e. non-hard-coded, runtime error messages
     #[error("Invalid controller input: {0}")]
@@ -90,6 +91,15 @@ pub enum APIError {
     /// Invalid input to a block
     #[error("Invalid block input: {0}")]
     BlockInput(&'static str),
+    /// The image backing a device is locked, because another device is currently using it
+    #[error("Device image {} is already in use", .0.display())]
+    DeviceInUse(PathBuf),
+    /// A block read from a device does not match its checksum
+    #[error("Block {block_no} is corrupted")]
+    Corruption {
+        /// Index of the corrupted block
+        block_no: u64,
+    },
 
     ///*EXTRA:* *Avoid* using this catch-all error in your own submission, as it is not practical to handle
     ///The [`anyhow`](https://docs.rs/anyhow/1.0.33/anyhow/) package allows defining universal error types, that any error can be cast into
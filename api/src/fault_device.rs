@@ -0,0 +1,247 @@
+//! Implementation of a block device that wraps another device and injects faults into it, to test how file systems cope with failing hardware and crashes.
+//!
+//! A [`FaultDevice`] forwards all reads and writes to the device it wraps, unless it was scripted to do otherwise.
+//! The supported faults are:
+//! * failing the `n`-th call to `write_block`, without writing anything,
+//! * failing every read and write of selected block numbers,
+//! * tearing the `n`-th write, i.e. only persisting a prefix of the block, after which the device crashes,
+//! * crashing after the `n`-th write, after which all further writes are silently dropped,
+//! * crashing right after the `n`-th flush, so that only what was flushed survives.
+//!
+//! Writes are numbered from 1, in the order in which they reach this device, and faulty writes count as well.
+//! A crashed device still reports success for every write, like a disk whose cache was never written back, and keeps serving reads of what was persisted before the crash.
+//!
+//! Crash-recovery tests typically build a file system on top of a `FaultDevice`, perform an operation that crashes halfway, and then take the wrapped device back with [`FaultDevice::into_inner`] to `mountfs` it again.
+
+use super::controller::{BlockDevice, Device};
+use super::error_given;
+use super::error_given::APIError;
+use super::types::Block;
+use std::collections::HashSet;
+use std::io;
+
+/// Block device that wraps another device, and can be scripted to fail some of its reads and writes.
+#[derive(Debug)]
+pub struct FaultDevice<D: BlockDevice = Device> {
+    /// The wrapped device
+    inner: D,
+    /// Number of writes that reached this device so far
+    writes: u64,
+    /// Number of the write that should fail, if any
+    fail_write: Option<u64>,
+    /// Blocks of which all reads and writes should fail
+    bad_blocks: HashSet<u64>,
+    /// Number of the write that should be torn, and the number of bytes of that write that are persisted
+    torn_write: Option<(u64, usize)>,
+    /// Number of the last write that is persisted before the device crashes, if any
+    crash_after: Option<u64>,
+    /// Number of flushes that reached this device so far
+    flushes: u64,
+    /// Number of the flush right after which the device crashes, if any
+    crash_after_flush: Option<u64>,
+}
+
+impl<D: BlockDevice> FaultDevice<D> {
+    /// Wrap the device `inner`, without injecting any faults yet.
+    pub fn new(inner: D) -> FaultDevice<D> {
+        FaultDevice {
+            inner,
+            writes: 0,
+            fail_write: None,
+            bad_blocks: HashSet::new(),
+            torn_write: None,
+            crash_after: None,
+            flushes: 0,
+            crash_after_flush: None,
+        }
+    }
+
+    /// Make the `n`-th write to this device fail with an I/O error, leaving the block untouched.
+    pub fn fail_nth_write(&mut self, n: u64) {
+        self.fail_write = Some(n);
+    }
+
+    /// Make all reads and writes of the block with index `block_no` fail with an I/O error.
+    pub fn fail_block(&mut self, block_no: u64) {
+        self.bad_blocks.insert(block_no);
+    }
+
+    /// Make the `n`-th write to this device only persist its first `prefix` bytes, after which the device crashes.
+    pub fn tear_nth_write(&mut self, n: u64, prefix: usize) {
+        self.torn_write = Some((n, prefix));
+    }
+
+    /// Crash the device after `n` writes, dropping all writes that come after.
+    /// Passing 0 drops every write from now on, if no writes reached this device yet.
+    pub fn crash_after(&mut self, n: u64) {
+        self.crash_after = Some(n);
+    }
+
+    /// Crash the device right after its `n`-th flush, dropping all writes that come after.
+    pub fn crash_after_flush(&mut self, n: u64) {
+        self.crash_after_flush = Some(n);
+    }
+
+    /// Remove all faults that were scripted so far. Does not revive a device that has already crashed.
+    pub fn clear_faults(&mut self) {
+        self.fail_write = None;
+        self.bad_blocks.clear();
+        self.torn_write = None;
+        self.crash_after_flush = None;
+    }
+
+    /// Number of writes that reached this device so far, including the ones that failed or were dropped
+    pub fn write_count(&self) -> u64 {
+        self.writes
+    }
+
+    /// Check whether the device has crashed, i.e. whether writes are currently being dropped
+    pub fn crashed(&self) -> bool {
+        self.crash_after.is_some_and(|n| self.writes >= n)
+    }
+
+    /// Reference to the wrapped device
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// End the lifetime of this device, giving back the wrapped device with whatever was persisted on it
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Error returned for all injected I/O failures
+    fn injected_error(msg: &'static str) -> APIError {
+        APIError::APIO(io::Error::other(msg))
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for FaultDevice<D> {
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        if self.bad_blocks.contains(&index) {
+            return Err(Self::injected_error("Injected read fault"));
+        }
+        self.inner.read_block(index)
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        if self.crashed() {
+            self.writes += 1;
+            return Ok(());
+        }
+        self.writes += 1;
+
+        if self.fail_write == Some(self.writes) || self.bad_blocks.contains(&b.block_no) {
+            return Err(Self::injected_error("Injected write fault"));
+        }
+
+        match self.torn_write {
+            Some((n, prefix)) if n == self.writes => {
+                //Overlay the prefix of the new block on top of the old contents, and crash right after
+                let mut torn = self.inner.read_block(b.block_no)?;
+                let prefix = prefix.min(b.len() as usize);
+                torn.write_data(&b.contents_as_ref()[..prefix], 0)?;
+                self.inner.write_block(&torn)?;
+                self.crash_after = Some(self.writes);
+                Ok(())
+            }
+            _ => self.inner.write_block(b),
+        }
+    }
+
+    fn flush(&mut self) -> error_given::Result<()> {
+        if self.crashed() {
+            return Ok(());
+        }
+        self.flushes += 1;
+        self.inner.flush()?;
+        if self.crash_after_flush == Some(self.flushes) {
+            self.crash_after = Some(self.writes);
+        }
+        Ok(())
+    }
+
+    fn block_size(&self) -> u64 {
+        self.inner.block_size()
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.inner.nblocks()
+    }
+
+    /// Discards on a crashed device are lost, and discards that touch a bad block fail, just like writes
+    fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        if self.crashed() {
+            return Ok(());
+        }
+        if (start..start + n).any(|i| self.bad_blocks.contains(&i)) {
+            return Err(Self::injected_error("Injected write fault"));
+        }
+        self.inner.discard(start, n)
+    }
+
+    fn resize(&mut self, nblocks: u64) -> error_given::Result<()> {
+        self.inner.resize(nblocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FaultDevice;
+    use crate::controller::BlockDevice;
+    use crate::mem_device::MemDevice;
+    use crate::types::Block;
+
+    #[test]
+    fn fault_test() {
+        let mut dev = FaultDevice::new(MemDevice::new(10, 10));
+        let b1 = Block::new(1, vec![1; 10].into_boxed_slice());
+        let b2 = Block::new(2, vec![2; 10].into_boxed_slice());
+
+        //Failing the second write leaves the block untouched
+        dev.fail_nth_write(2);
+        dev.write_block(&b1).unwrap();
+        assert!(dev.write_block(&b2).is_err());
+        assert_eq!(dev.read_block(2).unwrap(), Block::new_zero(2, 10));
+        dev.write_block(&b2).unwrap();
+        assert_eq!(dev.read_block(2).unwrap(), b2);
+
+        //Bad blocks can neither be read nor written
+        dev.fail_block(1);
+        assert!(dev.read_block(1).is_err());
+        assert!(dev.write_block(&b1).is_err());
+        assert_eq!(dev.write_count(), 4);
+        dev.clear_faults();
+        assert_eq!(dev.read_block(1).unwrap(), b1);
+
+        //A torn write only persists the prefix, and crashes the device
+        dev.tear_nth_write(5, 4);
+        let b3 = Block::new(1, vec![3; 10].into_boxed_slice());
+        dev.write_block(&b3).unwrap();
+        assert!(dev.crashed());
+        assert_eq!(
+            dev.read_block(1).unwrap().contents_as_ref(),
+            &[3, 3, 3, 3, 1, 1, 1, 1, 1, 1]
+        );
+        dev.write_block(&Block::new_zero(2, 10)).unwrap();
+        assert_eq!(dev.into_inner().read_block(2).unwrap(), b2);
+    }
+
+    #[test]
+    fn crash_test() {
+        let mut dev = FaultDevice::new(MemDevice::new(10, 10));
+        dev.crash_after(1);
+        assert!(!dev.crashed());
+        dev.write_block(&Block::new(1, vec![1; 10].into_boxed_slice()))
+            .unwrap();
+        assert!(dev.crashed());
+        dev.write_block(&Block::new(2, vec![2; 10].into_boxed_slice()))
+            .unwrap();
+        assert_eq!(dev.write_count(), 2);
+        dev.flush().unwrap();
+
+        let inner = dev.into_inner();
+        assert_eq!(inner.read_block(1).unwrap().contents_as_ref(), &[1; 10]);
+        assert_eq!(inner.read_block(2).unwrap(), Block::new_zero(2, 10));
+    }
+}
@@ -0,0 +1,321 @@
+//! Implementation of a block device on top of a plain file, without memory mapping it.
+//! Blocks are read and written with positional reads and writes (i.e. `pread` and `pwrite` on UNIX systems), at the offset of the block in the file.
+//!
+//! Creating and loading this device behaves like it does for the memory-mapped [`Device`](../controller/struct.Device.html), including the exclusive lock it takes on its image.
+
+use super::controller::{lock_file, open_path, BlockDevice, DiskState, ImageDevice, LockMode};
+use super::error_given;
+use super::error_given::APIError;
+use super::io_stats::{IoOp, IoStats};
+use super::types::Block;
+use std::{
+    borrow::Cow,
+    fs::{remove_file, File},
+    path::{Path, PathBuf},
+};
+
+/// Block device backed by a file that is accessed through positional reads and writes.
+#[derive(Debug)]
+pub struct FileDevice {
+    /// Size of the blocks that this disk reads and writes
+    pub block_size: u64,
+    /// Total number of blocks this disk consists of
+    pub nblocks: u64,
+    /// Path to the file in your file system that is used as a storage area to emulate the disk
+    path: PathBuf,
+    /// The opened file at `path`
+    file: File,
+    /// Statistics of the reads and writes of this device
+    stats: IoStats,
+}
+
+impl FileDevice {
+    /// Core function to that handles both `new` and `load`, based on the value of the switch `ds`, representing whether we want to load or create a disk
+    pub fn create_device<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+        nblocks: u64,
+        ds: DiskState,
+    ) -> error_given::Result<FileDevice> {
+        let path_buf = path.as_ref().to_path_buf();
+        let file = open_path(path, block_size * nblocks, ds, true)?;
+        lock_file(&file, &path_buf, LockMode::Exclusive)?;
+        Ok(FileDevice {
+            block_size,
+            nblocks,
+            path: path_buf,
+            file,
+            stats: IoStats::new(),
+        })
+    }
+
+    /// Create a *new* disk device at `path`, containing 0 at each address.
+    /// This function will return an error, if the file represented by `path` already exists.
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+        nblocks: u64,
+    ) -> error_given::Result<FileDevice> {
+        FileDevice::create_device(path, block_size, nblocks, DiskState::New)
+    }
+
+    /// Load an *existing* disk device, given its `block_size` and the number of blocks its file system ought to contain.
+    /// This function will return an error, if the file represented by `path` does not yet exist.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+        nblocks: u64,
+    ) -> error_given::Result<FileDevice> {
+        FileDevice::create_device(path, block_size, nblocks, DiskState::Load)
+    }
+
+    /// End the lifetime of this disk, and remove the file backing it on disk
+    /// Panics if removing the file fails
+    pub fn destruct(self) {
+        remove_file(&self.path).unwrap();
+    }
+
+    /// Path of the file backing this device
+    pub fn device_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Handle to the I/O statistics of this device, which can be queried and reset while a file system uses the device
+    pub fn stats(&self) -> IoStats {
+        self.stats.clone()
+    }
+
+    /// Offset of the block with index `index` in the file, or an error if the index is too high
+    fn block_offset(&self, index: u64) -> error_given::Result<u64> {
+        if index >= self.nblocks {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+        Ok(self.block_size * index)
+    }
+
+    /// Read the `n` consecutive blocks starting at index `start` with a single positional read
+    fn read_range(&self, start: u64, n: u64) -> error_given::Result<Vec<u8>> {
+        if start + n > self.nblocks {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+        let mut data = vec![0; (self.block_size * n) as usize];
+        read_at(&self.file, &mut data, self.block_size * start)?;
+        for i in start..start + n {
+            self.stats.record(IoOp::Read, i, self.block_size);
+        }
+        Ok(data)
+    }
+}
+
+impl BlockDevice for FileDevice {
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        let offset = self.block_offset(index)?;
+        let mut data = vec![0; self.block_size as usize];
+        read_at(&self.file, &mut data, offset)?;
+        self.stats.record(IoOp::Read, index, self.block_size);
+        Ok(Block::new(index, data.into_boxed_slice()))
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        if b.len() != self.block_size {
+            return Err(APIError::ControllerInput(
+                "Trying to write a non-block-sized block",
+            ));
+        }
+        let offset = self.block_offset(b.block_no)?;
+        write_at(&self.file, b.contents_as_ref(), offset)?;
+        self.stats.record(IoOp::Write, b.block_no, self.block_size);
+        Ok(())
+    }
+
+    fn read_blocks(&self, start: u64, n: u64) -> error_given::Result<Vec<Block>> {
+        let data = self.read_range(start, n)?;
+        Ok(data
+            .chunks(self.block_size as usize)
+            .zip(start..)
+            .map(|(d, i)| Block::new(i, d.into()))
+            .collect())
+    }
+
+    fn borrow_blocks(&self, start: u64, n: u64) -> error_given::Result<Cow<'_, [u8]>> {
+        Ok(Cow::Owned(self.read_range(start, n)?))
+    }
+
+    fn flush(&mut self) -> error_given::Result<()> {
+        Ok(self.file.sync_data()?)
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.nblocks
+    }
+
+    /// Punches the blocks out of the image, or writes zeroes if the file system on the host does not support this
+    fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        if start.checked_add(n).is_none_or(|end| end > self.nblocks) {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+        let (offset, len) = (self.block_size * start, self.block_size * n);
+        if !punch_hole(&self.file, offset, len)? {
+            write_at(&self.file, &vec![0; len as usize], offset)?;
+        }
+        Ok(())
+    }
+
+    fn resize(&mut self, nblocks: u64) -> error_given::Result<()> {
+        self.file.set_len(self.block_size * nblocks)?;
+        self.nblocks = nblocks;
+        Ok(())
+    }
+}
+
+impl ImageDevice for FileDevice {
+    fn create<P: AsRef<Path>>(path: P, block_size: u64, nblocks: u64) -> error_given::Result<Self> {
+        FileDevice::new(path, block_size, nblocks)
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(unix)]
+pub(crate) fn write_at(file: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+/// Deallocate the `len` bytes starting at `offset` in `file`, keeping its size the same; these bytes read as zeroes afterwards.
+/// Returns `false` without changing anything if the file system holding `file` cannot punch holes, so the caller can write zeroes instead.
+#[cfg(target_os = "linux")]
+pub(crate) fn punch_hole(file: &File, offset: u64, len: u64) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    let ret = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+    if ret == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn punch_hole(_file: &File, _offset: u64, _len: u64) -> std::io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(windows)]
+pub(crate) fn read_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub(crate) fn write_at(file: &File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::WriteZero.into()),
+            n => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileDevice;
+    use crate::controller::BlockDevice;
+    use crate::types::Block;
+    use std::fs::{create_dir_all, remove_dir, remove_file};
+    use std::path::PathBuf;
+
+    fn disk_prep_path(name: &str) -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("fs-images-file-device-".to_string() + name);
+        path.push("img");
+
+        if path.exists() {
+            remove_file(&path).unwrap();
+        }
+        create_dir_all(path.parent().unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn persistence_test() {
+        let path = disk_prep_path("persistence");
+        let mut dev = FileDevice::new(&path, 10, 10).unwrap();
+        assert_eq!(dev.read_block(9).unwrap(), Block::new_zero(9, 10));
+        assert!(dev.read_block(10).is_err());
+        assert!(dev.write_block(&Block::new_zero(2, 9)).is_err());
+
+        let bw = Block::new(2, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+        dev.flush().unwrap();
+        drop(dev);
+
+        //Loading with the wrong size fails, loading with the right size gives back the data
+        assert!(FileDevice::load(&path, 10, 11).is_err());
+        let dev = FileDevice::load(&path, 10, 10).unwrap();
+        assert_eq!(dev.read_block(2).unwrap(), bw);
+        assert_eq!(dev.read_blocks(1, 2).unwrap()[1], bw);
+        assert_eq!(&dev.borrow_blocks(2, 1).unwrap()[..], bw.contents_as_ref());
+        assert!(dev.read_blocks(9, 2).is_err());
+
+        dev.destruct();
+        remove_dir(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn discard_test() {
+        let path = disk_prep_path("discard");
+        let mut dev = FileDevice::new(&path, 4096, 8).unwrap();
+        let blocks: Vec<Block> = (0..8)
+            .map(|i| Block::new(i, vec![1; 4096].into()))
+            .collect();
+        dev.write_blocks(&blocks).unwrap();
+        dev.discard(2, 4).unwrap();
+        assert!(dev.discard(6, 3).is_err());
+        assert_eq!(dev.read_block(1).unwrap(), blocks[1]);
+        assert_eq!(dev.read_block(2).unwrap(), Block::new_zero(2, 4096));
+        assert_eq!(dev.read_block(5).unwrap(), Block::new_zero(5, 4096));
+        assert_eq!(dev.read_block(6).unwrap(), blocks[6]);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 8 * 4096);
+
+        dev.destruct();
+        remove_dir(path.parent().unwrap()).unwrap();
+    }
+}
@@ -5,8 +5,8 @@
 //! You might need to wrap (some of) the types I provided in the API into your own types, to be able to define additional behavior on them.
 
 use super::{
-    controller::Device,
-    types::{Block, Buffer, DirEntry, FType, InodeLike, SuperBlock},
+    controller::{BlockDevice, ImageDevice},
+    types::{Block, Buffer, DirEntry, FType, InodeLike, SuperBlock, VolumeHeader},
 };
 use std::{error, path::Path};
 
@@ -32,6 +32,10 @@ pub trait FileSysSupport: Sized {
     /// Note that you can use the same error type for multiple parts of the assignment.
     type Error: error::Error;
 
+    /// The type of the block device backing your file system.
+    /// Implement your file system generically over [`BlockDevice`](../controller/trait.BlockDevice.html), so that it can be stored on any kind of device.
+    type Dev: BlockDevice;
+
     /// Static method to check if a given superblock represents a valid file system.
     /// You will need this both when creating a new file system, and loading an existing one from disk
     ///
@@ -46,7 +50,7 @@ pub trait FileSysSupport: Sized {
     ///
     /// This method always does the following, regardless of the layer of abstraction:
     /// - Check if the given superblock is a valid file system superblock
-    /// - Create a new `Device` at the given path, to allow the file system to communicate with it
+    /// - Create a new device at the given path, to allow the file system to communicate with it
     ///
     /// Then, subdivide the freshly created device image into the previously described regions:
     /// 1. A super block containing the file system metadata at block index 0
@@ -72,7 +76,16 @@ pub trait FileSysSupport: Sized {
     /// *IMPORTANT NOTE*: In case you need to loop over inodes here or anywhere else in this project, do so **efficiently**, i.e. if you need to read/write multiple inodes in the same block, only load and store this block once!
     ///
     /// *EXTRA*: mkfs is inspired by the unix command of the same name (although this version also immediately mounts the file system)
-    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>;
+    ///
+    /// This method is only available for devices that are backed by an image at a path; it creates a new device at `path` and then behaves like `mkfs_on`.
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>
+    where
+        Self::Dev: ImageDevice;
+
+    /// Variant of `mkfs` that creates the file system on the given, freshly created device `dev`, rather than on a new device at a path.
+    /// Errors if the block size and number of blocks of `dev` do not match those of `sb`.
+    /// The initial contents of `dev` are assumed to be 0 at each address, as they are for a new device.
+    fn mkfs_on(dev: Self::Dev, sb: &SuperBlock) -> Result<Self, Self::Error>;
 
     /// Given an existing `Device` called `dev`, make sure that its image corresponds to a valid file system by reading its superblock and checking the following conditions:
     /// - The superblock is a valid superblock
@@ -82,12 +95,21 @@ pub trait FileSysSupport: Sized {
     ///
     /// You do **not** need to deserialize each individual object in each region to check that it is indeed a valid object; to keep matters simple, we will assume that the contents of each region has been properly initialized.
     /// Additionally, we could add `dev` to the return type to reclaim ownership in case of an error, but we do not bother recovering invalid devices, for simplicity reasons.
-    fn mountfs(dev: Device) -> Result<Self, Self::Error>;
+    fn mountfs(dev: Self::Dev) -> Result<Self, Self::Error>;
+
+    /// Variant of `mountfs` that mounts the file system read-only, performing the same checks.
+    /// Every operation that would modify the image of a file system mounted in this way fails with an error, without touching `dev`.
+    /// Pair this with a read-only device, such as one created by [`Device::load_readonly`](../controller/struct.Device.html#method.load_readonly), to make sure the image is never modified.
+    fn mountfs_readonly(dev: Self::Dev) -> Result<Self, Self::Error>;
 
     /// Unmount the give file system, thereby consuming it
     /// Returns the image of the file system, i.e. the `Device` backing it.
     /// The implementation of this method should be almost trivial
-    fn unmountfs(self) -> Device;
+    fn unmountfs(self) -> Self::Dev;
+
+    /// Checkpoint the mounted file system without unmounting it.
+    /// Write back any state the file system only keeps in memory, and then flush its device, so that all changes so far are durable when this method returns.
+    fn sync(&mut self) -> Result<(), Self::Error>;
 }
 
 /// This trait adds block-level operations to your file system
@@ -135,6 +157,41 @@ pub trait BlockSupport: FileSysSupport {
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error>;
 }
 
+/// This trait gives access to the identity of a mounted file system, i.e. the [`VolumeHeader`](../types/struct.VolumeHeader.html) stored after its superblock.
+/// `mkfs` writes a fresh header, and `mountfs` refuses file systems whose header has the wrong magic number or version, or that use incompatible features it does not know about.
+/// `mountfs` also refuses file systems that use unknown read-only compatible features, which `mountfs_readonly` does accept.
+pub trait VolumeSupport: BlockSupport {
+    /// Get the volume header of the current file system
+    fn volume_get(&self) -> Result<VolumeHeader, Self::Error>;
+
+    /// Write the volume header to this file system's underlying device, leaving the superblock before it unchanged
+    fn volume_put(&mut self, vol: &VolumeHeader) -> Result<(), Self::Error>;
+
+    /// Get the label of the current file system
+    fn label(&self) -> Result<String, Self::Error> {
+        Ok(self.volume_get()?.label())
+    }
+
+    /// Set the label of the current file system, truncated as described in [`VolumeHeader::set_label`](../types/struct.VolumeHeader.html#method.set_label)
+    fn set_label(&mut self, label: &str) -> Result<(), Self::Error> {
+        let mut vol = self.volume_get()?;
+        vol.set_label(label);
+        self.volume_put(&vol)
+    }
+
+    /// Get the UUID of the current file system
+    fn uuid(&self) -> Result<[u8; 16], Self::Error> {
+        Ok(self.volume_get()?.uuid)
+    }
+
+    /// Set the UUID of the current file system, e.g. to tell apart a copy of an image from the original
+    fn set_uuid(&mut self, uuid: [u8; 16]) -> Result<(), Self::Error> {
+        let mut vol = self.volume_get()?;
+        vol.uuid = uuid;
+        self.volume_put(&vol)
+    }
+}
+
 /// This trait adds the abstraction of inodes to your file system.
 /// Do not forget that `mkfs` should now result in a filesystem where each inode is marked as free.
 /// Note that this trait does not yet support ways of growing inodes.
@@ -155,6 +212,8 @@ pub trait InodeSupport: BlockSupport {
     /// A big difference is that this method should only free an inode if it is no longer referenced anywhere else in the file system, i.e. if its `nlink` field is equal to zero.
     /// In this case, the $i$th inode in the inode region is freed by setting its `ft` field to `TFree`.
     /// Additionally, all valid blocks belonging to this file, listed in its `direct_blocks` array, have to be deallocated (and set to address 0) as well.
+    /// A symlink with an inline target (see `InodeLike::has_inline_target`) has no valid blocks, as its target takes the place of the block addresses.
+    /// The xattr block of the inode, if it has one, is deallocated too, and its inline extended attributes are cleared (see the [`xattr`](../xattr/index.html) module).
     /// In case `nlink` is not equal to zero, this method does nothing.
     /// Returns an error if `i` does not correspond to an inode number, or if the inode is already free.
     fn i_free(&mut self, i: u64) -> Result<(), Self::Error>;
@@ -163,6 +222,8 @@ pub trait InodeSupport: BlockSupport {
     /// Allocates the first free `dinode` (i.e. lowest `inum`) it comes across, and sets (on disk):
     ///  - this inode's `FType` to `ft`
     ///  - this inode's `size` and `nlink` fields to 0, as it currently has no blocks and is not referenced in the file system
+    ///  - all of this inode's `times` to the current time, see `Timestamps::created`
+    ///  - this inode's `mode` to `perm::default_mode(ft)`, and its `uid` and `gid` to 0, i.e. the superuser
     /// The inode with index 0 should *never* be allocated.
     /// Errors appropriately if no inodes are available
     /// Only read each inode block once in your implementation
@@ -171,7 +232,7 @@ pub trait InodeSupport: BlockSupport {
     /// Truncate the given `inode`, i.e. release its contents (without freeing it).
     /// Sets all of the given inode's `direct_blocks` to point to address 0.
     /// Releases all blocks `direct_blocks` belonging to this inode, and sets its `size` to 0.
-    /// Changes both the given `inode` and the corresponding inode on the disk.
+    /// Changes both the given `inode` and the corresponding inode on the disk, including its modification and change time.
     /// Note that only the valid blocks should be released as only these are allocated. In other words, do not blindly release all values listed in the `direct_blocks` field
     fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error>;
 }
@@ -199,6 +260,7 @@ pub trait InodeRWSupport: InodeSupport {
     /// If necessary, start allocating extra blocks to expand the file and continue writing into the new blocks.
     /// However, returns 0 and does not write anything in case the provided offset falls outside of the file's bounds.
     /// If the inode changes while writing, do not forget to write it back to the disk too.
+    /// Every successful write updates the modification and change time of the inode, on disk as well.
     /// Returns an error if `buf` cannot hold at least `n` bytes of data.
     /// If the write would make the inode exceed its maximum possible size, do nothing and return an error.
     fn i_write(
@@ -226,14 +288,12 @@ pub trait DirectorySupport: InodeSupport {
     fn new_de(inum: u64, name: &str) -> Option<DirEntry>;
 
     /// Get the name of this directory entry as a `String`
-    /// Loop over the characters in the direntry's name until you encounter a final character `\0` OR until you reach the end of the character array, and return the concatenation of these characters as the `Direntry`'s name.
     fn get_name_str(de: &DirEntry) -> String;
 
     /// Set the name of this directory entry to the given `name`, if the given name is valid, i.e. it is
     ///- non-empty
-    ///- consists of alphanumeric characters only, or is equal to "." or ".."
-    ///- is sufficiently short when converted to characters
-    /// If the `name` is shorter than `DIRNAME_SIZE`, insert a '\0' at the end so you can still correctly read it after.
+    ///- free of the characters '/' and '\0', like any POSIX file name
+    ///- at most `DIRNAME_SIZE` bytes long when encoded as UTF-8
     /// Returns `None` in case of an invalid name
     fn set_name_str(de: &mut DirEntry, name: &str) -> Option<()>;
 
@@ -253,6 +313,7 @@ pub trait DirectorySupport: InodeSupport {
     /// In case you implemented the optional `InodeRWSupport` assignment already , you could use the `i_write` method here.
     ///
     /// When a place for the given `inode` is found, looks up the inode corresponding to `inum` and increase its `nlink` field with 1 on disk (unless `inum` and `inode`'s number are equal, then nothing happens, as this is a self-reference).
+    /// That inode's change time is updated along with its `nlink` field, and the modification and change time of `inode` itself are updated as well.
     /// Errors if
     /// - `name` is invalid, or is already an entry inside `inode`.
     /// - `inode` is not a directory.
@@ -298,7 +359,7 @@ pub trait PathSupport: DirectorySupport {
     /// - It consists of a "/"-separated sequence of *names*
     /// - It does not end in a "/" (the only exception being the path "/" itself)
     /// - Each one of these names is a *valid* directory entry name (see the [`DirectorySupport`](../fs/trait.DirectorySupport.html) trait)
-    /// - Instead of an alphanumeric name, the special entries "." and ".." can also appear anywhere in the file path. Their meaning is then similar to their meaning they have at the start of a relative path; "." specifies staying in the current directory, whereas ".." specifies moving up to the parent directory.
+    /// - Instead of a regular name, the special entries "." and ".." can also appear anywhere in the file path. Their meaning is then similar to their meaning they have at the start of a relative path; "." specifies staying in the current directory, whereas ".." specifies moving up to the parent directory.
     fn valid_path(path: &str) -> bool;
 
     ///Return the current working directory as a String path
@@ -315,12 +376,13 @@ pub trait PathSupport: DirectorySupport {
     ///Given a path name (possibly relative to the cwd), look up the inode corresponding to this path (the final inode could be either a file or a directory), and return it.
     ///Works as follows:
     ///1. Figures out what inode the cwd corresponds to (skip this step if `path` is absolute)
-    ///2. Reads `path` through the file system
+    ///2. Reads `path` through the file system, following any symlinks it comes across, including the one `path` itself may refer to
     ///
     ///Returns an error if
     /// - the path is invalid
     /// - any directory, referenced in the path (or the cwd), does not exist
     /// - any of the intermediate names refers to a file that is not of directory type.
+    /// - more than a fixed number of symlinks has to be followed, which is how loops of symlinks are detected
     fn resolve_path(&self, path: &str) -> Result<Self::Inode, Self::Error>;
 
     ///Create a new directory at the given path, where the last name of the path is the name for the new directory.
@@ -336,11 +398,12 @@ pub trait PathSupport: DirectorySupport {
     /// - if the last part of the path is not a valid directory name (i.e. it cannot be "." or "..")
     fn mkdir(&mut self, path: &str) -> Result<Self::Inode, Self::Error>;
 
-    ///Remove the directory entry located at path `path`, i.e. set the `inum` of this entry to 0 and the name of the entry to all zeroes, i.e. to `"0".repeat(*DIRNAME_SIZE)`
+    ///Remove the directory entry located at path `path`, i.e. overwrite the record holding this entry with zeroes, so that its slots are free to be reused
     ///For example, the path `/test/dir` will (on success) delete the directory entry "dir" in the parent directory `test`.
     ///In essence, the inverse of `mkdir`.
     ///
     ///Decreases the `nlink` field of the deleted entry by 1 (unless we just deleted a cyclic reference to the parent), and frees the inode in case `nlink` decreases to 0. In the latter case, a reference to the inode also gets deleted (since ".." in the entry's inode disappears when it is freed)
+    ///The parent directory gets a new modification and change time, and every inode that loses a link a new change time.
     ///
     ///Errors and does nothing else in the following cases:
     ///- the path ends in "." or ".."; these entries cannot be removed
@@ -374,9 +437,57 @@ pub trait InodeCacheSupport: InodeSupport {
         path: P,
         sb: &SuperBlock,
         nb_cache_entries: u64,
-    ) -> Result<Self, Self::Error>;
+    ) -> Result<Self, Self::Error>
+    where
+        Self::Dev: ImageDevice;
 
     ///Alternative version of `mountfs`, that allows us to specify the number of entries in the inode cache.
     ///Interpret the original `mountfs` function as a more specific variant of this function, where the number of cache entries for inodes is fixed to 5.
-    fn mountfs_cached(dev: Device, nb_cache_entries: u64) -> Result<Self, Self::Error>;
+    fn mountfs_cached(dev: Self::Dev, nb_cache_entries: u64) -> Result<Self, Self::Error>;
+}
+
+/// Support for extended attributes: name/value pairs that tag an inode with arbitrary metadata, like its content type, provenance or checksums.
+/// Read more about how they are stored in the [`xattr`](../xattr/index.html) module.
+///
+/// The attributes of an inode are stored inline as long as they fit, and in a dedicated xattr block otherwise.
+/// Every operation that changes the attributes moves them to the right place, allocating or freeing the xattr block as needed, and writes the inode back to the disk with a new change time.
+pub trait XattrSupport: InodeSupport {
+    /// Get the value of the attribute `name` of `inode`.
+    /// Errors if `inode` has no such attribute.
+    fn getxattr(&self, inode: &Self::Inode, name: &str) -> Result<Vec<u8>, Self::Error>;
+
+    /// Set the attribute `name` of `inode` to `value`, adding it if `inode` did not have it yet.
+    /// Changes both the given `inode` and the corresponding inode on the disk.
+    /// Errors, without changing anything, if the name is not valid (see `Xattrs::valid_name`), or if the attributes of `inode` would no longer fit in a block.
+    fn setxattr(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), Self::Error>;
+
+    /// List the names of all attributes of `inode`, in order
+    fn listxattr(&self, inode: &Self::Inode) -> Result<Vec<String>, Self::Error>;
+
+    /// Remove the attribute `name` from `inode`.
+    /// Changes both the given `inode` and the corresponding inode on the disk.
+    /// Errors if `inode` has no such attribute.
+    fn removexattr(&mut self, inode: &mut Self::Inode, name: &str) -> Result<(), Self::Error>;
+
+    /// Give `to` the same attributes as `from`, replacing the ones it had before.
+    /// Operations that copy or clone an inode call this, so that the attributes are carried along with its contents.
+    /// The attributes are copied, rather than shared, so that `to` gets an xattr block of its own if it needs one.
+    fn copy_xattrs(&mut self, from: &Self::Inode, to: &mut Self::Inode) -> Result<(), Self::Error> {
+        if from.get_inum() == to.get_inum() {
+            return Ok(());
+        }
+        for name in self.listxattr(to)? {
+            self.removexattr(to, &name)?;
+        }
+        for name in self.listxattr(from)? {
+            let value = self.getxattr(from, &name)?;
+            self.setxattr(to, &name, &value)?;
+        }
+        Ok(())
+    }
 }
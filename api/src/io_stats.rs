@@ -0,0 +1,239 @@
+//! I/O statistics of block devices, to check and profile how file systems access their devices.
+//!
+//! Every device provided in this crate counts the blocks that are read from and written to it, per block, in an [`IoStats`] value.
+//! Such a value is a shared handle: clones of it refer to the same statistics.
+//! This allows a test to hold on to the statistics of a device with e.g. [`Device::stats`](../controller/struct.Device.html#method.stats), hand the device over to a file system, and then query or reset the statistics while the file system is still mounted.
+//! The per-block counters can be summed up over a range of blocks, or over one of the regions of a file system, as described by its [`SuperBlock`].
+//!
+//! On top of the counters, a trace of all accesses can be enabled, which records the kind and the block number of every read and write, in order.
+//! Only successful reads and writes are recorded.
+//!
+//! [`SuperBlock`]: ../types/struct.SuperBlock.html
+
+use super::types::SuperBlock;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Kind of access to a block
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum IoOp {
+    /// The block was read
+    Read,
+    /// The block was written
+    Write,
+}
+
+/// A single access to a block, as recorded in the trace of a device
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct IoEvent {
+    /// Kind of access
+    pub op: IoOp,
+    /// Index of the block that was accessed
+    pub block_no: u64,
+}
+
+/// Regions of a file system, as laid out by its superblock
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Region {
+    /// The superblock, at block 0
+    SuperBlock,
+    /// The inode blocks, from `inodestart` up to `bmapstart`
+    Inodes,
+    /// The free bit map, from `bmapstart` up to `datastart`
+    Bitmap,
+    /// The data blocks, from `datastart` up to `nblocks`
+    Data,
+}
+
+impl Region {
+    /// Range of block indices this region occupies in a file system with superblock `sb`
+    pub fn blocks(self, sb: &SuperBlock) -> Range<u64> {
+        match self {
+            Region::SuperBlock => 0..1,
+            Region::Inodes => sb.inodestart..sb.bmapstart,
+            Region::Bitmap => sb.bmapstart..sb.datastart,
+            Region::Data => sb.datastart..sb.nblocks,
+        }
+    }
+}
+
+/// Number of accesses to one or more blocks, and the number of bytes they moved
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct IoCounters {
+    /// Number of block reads
+    pub reads: u64,
+    /// Number of block writes
+    pub writes: u64,
+    /// Number of bytes read
+    pub bytes_read: u64,
+    /// Number of bytes written
+    pub bytes_written: u64,
+}
+
+impl IoCounters {
+    /// Add the counts of `other` to these counts
+    fn add(&mut self, other: &IoCounters) {
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+    }
+}
+
+/// The statistics themselves, behind the shared handle
+#[derive(Debug, Default)]
+struct IoStatsData {
+    /// Counters of every block that was accessed at least once
+    per_block: BTreeMap<u64, IoCounters>,
+    /// Trace of all accesses, if tracing is enabled
+    trace: Option<Vec<IoEvent>>,
+}
+
+/// Shared handle to the I/O statistics of a device.
+/// Clones of this handle refer to the same statistics.
+#[derive(Debug, Default, Clone)]
+pub struct IoStats(Arc<Mutex<IoStatsData>>);
+
+impl IoStats {
+    /// Create new statistics, without any accesses and with tracing disabled
+    pub fn new() -> IoStats {
+        IoStats::default()
+    }
+
+    /// Lock the statistics; a panic while holding the lock cannot leave them in an inconsistent state, so poisoning is ignored
+    fn data(&self) -> MutexGuard<'_, IoStatsData> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record an access of kind `op` to block `block_no`, moving `bytes` bytes
+    pub fn record(&self, op: IoOp, block_no: u64, bytes: u64) {
+        let mut data = self.data();
+        let counters = data.per_block.entry(block_no).or_default();
+        match op {
+            IoOp::Read => {
+                counters.reads += 1;
+                counters.bytes_read += bytes;
+            }
+            IoOp::Write => {
+                counters.writes += 1;
+                counters.bytes_written += bytes;
+            }
+        }
+        if let Some(trace) = &mut data.trace {
+            trace.push(IoEvent { op, block_no });
+        }
+    }
+
+    /// Counters of all accesses to the device
+    pub fn total(&self) -> IoCounters {
+        self.range(0..u64::MAX)
+    }
+
+    /// Counters of the accesses to the block with index `block_no`
+    pub fn block(&self, block_no: u64) -> IoCounters {
+        self.data()
+            .per_block
+            .get(&block_no)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Counters of the accesses to the blocks in the range `blocks`, summed up
+    pub fn range(&self, blocks: Range<u64>) -> IoCounters {
+        let mut total = IoCounters::default();
+        for counters in self.data().per_block.range(blocks).map(|(_, c)| c) {
+            total.add(counters);
+        }
+        total
+    }
+
+    /// Counters of the accesses to the blocks in `region` of a file system with superblock `sb`, summed up
+    pub fn region(&self, sb: &SuperBlock, region: Region) -> IoCounters {
+        self.range(region.blocks(sb))
+    }
+
+    /// Start recording a trace of all accesses, starting from an empty trace
+    pub fn enable_trace(&self) {
+        self.data().trace = Some(Vec::new());
+    }
+
+    /// Stop recording a trace, and throw away the trace recorded so far
+    pub fn disable_trace(&self) {
+        self.data().trace = None;
+    }
+
+    /// The trace recorded since tracing was enabled or reset; empty if tracing is disabled
+    pub fn trace(&self) -> Vec<IoEvent> {
+        self.data().trace.clone().unwrap_or_default()
+    }
+
+    /// Reset all counters, and empty the trace if tracing is enabled
+    pub fn reset(&self) {
+        let mut data = self.data();
+        data.per_block.clear();
+        if let Some(trace) = &mut data.trace {
+            trace.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IoEvent, IoOp, IoStats, Region};
+    use crate::types::SuperBlock;
+
+    #[test]
+    fn counters_test() {
+        let stats = IoStats::new();
+        let shared = stats.clone();
+        stats.record(IoOp::Read, 0, 10);
+        stats.record(IoOp::Write, 4, 10);
+        shared.enable_trace();
+        stats.record(IoOp::Read, 4, 10);
+        stats.record(IoOp::Read, 7, 10);
+
+        assert_eq!(shared.block(4).reads, 1);
+        assert_eq!(shared.block(4).writes, 1);
+        assert_eq!(shared.block(5).reads, 0);
+        assert_eq!(shared.total().reads, 3);
+        assert_eq!(shared.total().bytes_written, 10);
+        assert_eq!(shared.range(1..7).bytes_read, 10);
+
+        let sb = SuperBlock {
+            block_size: 10,
+            nblocks: 10,
+            ninodes: 2,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        assert_eq!(shared.region(&sb, Region::SuperBlock).reads, 1);
+        assert_eq!(shared.region(&sb, Region::Inodes).reads, 0);
+        assert_eq!(shared.region(&sb, Region::Bitmap).writes, 1);
+        assert_eq!(shared.region(&sb, Region::Data).reads, 1);
+
+        //Only the accesses after enabling the trace are recorded in it
+        assert_eq!(
+            shared.trace(),
+            vec![
+                IoEvent {
+                    op: IoOp::Read,
+                    block_no: 4
+                },
+                IoEvent {
+                    op: IoOp::Read,
+                    block_no: 7
+                }
+            ]
+        );
+        stats.reset();
+        assert_eq!(shared.total().reads, 0);
+        assert!(shared.trace().is_empty());
+        stats.record(IoOp::Write, 1, 10);
+        assert_eq!(shared.trace().len(), 1);
+        stats.disable_trace();
+        assert!(shared.trace().is_empty());
+    }
+}
@@ -10,11 +10,25 @@
 #![deny(missing_docs)]
 
 //Implementation of the controller layer
+pub mod checksum;
 pub mod controller;
 pub mod error_given;
+pub mod fault_device;
+pub mod file_device;
+pub mod io_stats;
+pub mod mem_device;
+pub mod nbd;
+pub mod overlay_device;
+pub mod partition;
+pub mod raid_device;
+pub mod sim_device;
 
 //Basic modules for types
+pub mod clock;
+pub mod encoding;
+pub mod perm;
 pub mod types;
+pub mod xattr;
 
 //Traits you should implement
 pub mod fs;
@@ -0,0 +1,236 @@
+//! Implementation of a block device that only lives in memory.
+//! The contents of the device are stored in a heap-allocated buffer, and are lost when the device is dropped.
+//!
+//! This device is mostly useful for tests; file systems backed by it never touch the disk of the host, so tests using it can safely run in parallel.
+
+use super::controller::{check_blocks, BlockDevice};
+use super::error_given;
+use super::error_given::APIError;
+use super::io_stats::{IoOp, IoStats};
+use super::types::Block;
+use std::borrow::Cow;
+
+/// Block device whose contents are kept in memory.
+#[derive(Debug)]
+pub struct MemDevice {
+    /// Size of the blocks that this disk reads and writes
+    pub block_size: u64,
+    /// Total number of blocks this disk consists of
+    pub nblocks: u64,
+    /// Contents of the device
+    contents: Box<[u8]>,
+    /// Statistics of the reads and writes of this device
+    stats: IoStats,
+}
+
+impl Clone for MemDevice {
+    /// Copy the contents of the device into a new device, which keeps its own statistics
+    fn clone(&self) -> MemDevice {
+        MemDevice {
+            block_size: self.block_size,
+            nblocks: self.nblocks,
+            contents: self.contents.clone(),
+            stats: IoStats::new(),
+        }
+    }
+}
+
+impl MemDevice {
+    /// Create a new in-memory device with `nblocks` blocks of `block_size` bytes, containing 0 at each address.
+    pub fn new(block_size: u64, nblocks: u64) -> MemDevice {
+        MemDevice {
+            block_size,
+            nblocks,
+            contents: vec![0; (block_size * nblocks) as usize].into_boxed_slice(),
+            stats: IoStats::new(),
+        }
+    }
+
+    /// Size of this device in bytes
+    pub fn device_size(&self) -> u64 {
+        self.block_size * self.nblocks
+    }
+
+    /// Handle to the I/O statistics of this device, which can be queried and reset while a file system uses the device
+    pub fn stats(&self) -> IoStats {
+        self.stats.clone()
+    }
+
+    /// Range of addresses occupied by the block with index `index`, or an error if the index is too high
+    fn block_range(&self, index: u64) -> error_given::Result<std::ops::Range<usize>> {
+        if index >= self.nblocks {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+        let start = (self.block_size * index) as usize;
+        Ok(start..start + self.block_size as usize)
+    }
+
+    /// Borrow the contents of the `n` consecutive blocks starting at index `start`, without copying them
+    /// Results in an error if any of these blocks lies past the end of the device
+    pub fn blocks_ref(&self, start: u64, n: u64) -> error_given::Result<&[u8]> {
+        if start + n > self.nblocks {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+        for i in start..start + n {
+            self.stats.record(IoOp::Read, i, self.block_size);
+        }
+        let addr = (self.block_size * start) as usize;
+        Ok(&self.contents[addr..addr + (self.block_size * n) as usize])
+    }
+}
+
+impl BlockDevice for MemDevice {
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        let range = self.block_range(index)?;
+        self.stats.record(IoOp::Read, index, self.block_size);
+        Ok(Block::new(index, self.contents[range].into()))
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        if b.len() != self.block_size {
+            return Err(APIError::ControllerInput(
+                "Trying to write a non-block-sized block",
+            ));
+        }
+        let range = self.block_range(b.block_no)?;
+        self.contents[range].copy_from_slice(b.contents_as_ref());
+        self.stats.record(IoOp::Write, b.block_no, self.block_size);
+        Ok(())
+    }
+
+    fn read_blocks(&self, start: u64, n: u64) -> error_given::Result<Vec<Block>> {
+        let data = self.blocks_ref(start, n)?;
+        Ok(data
+            .chunks(self.block_size as usize)
+            .zip(start..)
+            .map(|(d, i)| Block::new(i, d.into()))
+            .collect())
+    }
+
+    fn write_blocks(&mut self, blocks: &[Block]) -> error_given::Result<()> {
+        check_blocks(blocks, self.block_size, self.nblocks)?;
+        for b in blocks {
+            let range = self.block_range(b.block_no)?;
+            self.contents[range].copy_from_slice(b.contents_as_ref());
+            self.stats.record(IoOp::Write, b.block_no, self.block_size);
+        }
+        Ok(())
+    }
+
+    fn borrow_blocks(&self, start: u64, n: u64) -> error_given::Result<Cow<'_, [u8]>> {
+        Ok(Cow::Borrowed(self.blocks_ref(start, n)?))
+    }
+
+    fn flush(&mut self) -> error_given::Result<()> {
+        Ok(())
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.nblocks
+    }
+
+    fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        if start.checked_add(n).is_none_or(|end| end > self.nblocks) {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+        let addr = (self.block_size * start) as usize;
+        self.contents[addr..addr + (self.block_size * n) as usize].fill(0);
+        Ok(())
+    }
+
+    fn resize(&mut self, nblocks: u64) -> error_given::Result<()> {
+        let mut contents = std::mem::take(&mut self.contents).into_vec();
+        contents.resize((self.block_size * nblocks) as usize, 0);
+        self.contents = contents.into_boxed_slice();
+        self.nblocks = nblocks;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemDevice;
+    use crate::controller::BlockDevice;
+    use crate::types::Block;
+    use std::borrow::Cow;
+
+    #[test]
+    fn read_write_test() {
+        let mut dev = MemDevice::new(10, 10);
+        assert_eq!(dev.read_block(3).unwrap(), Block::new_zero(3, 10));
+
+        let bw = Block::new(3, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+        assert_eq!(dev.read_block(3).unwrap(), bw);
+        assert_eq!(dev.read_block(4).unwrap(), Block::new_zero(4, 10));
+
+        //Out of bounds and wrongly sized blocks
+        assert!(dev.read_block(10).is_err());
+        assert!(dev.write_block(&Block::new_zero(10, 10)).is_err());
+        assert!(dev.write_block(&Block::new_zero(3, 11)).is_err());
+    }
+
+    #[test]
+    fn resize_test() {
+        let mut dev = MemDevice::new(10, 10);
+        let bw = Block::new(9, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+        dev.resize(20).unwrap();
+        assert_eq!(dev.nblocks(), 20);
+        assert_eq!(dev.read_block(9).unwrap(), bw);
+        assert_eq!(dev.read_block(19).unwrap(), Block::new_zero(19, 10));
+        dev.resize(9).unwrap();
+        assert!(dev.read_block(9).is_err());
+        assert_eq!(dev.device_size(), 90);
+    }
+
+    #[test]
+    fn discard_test() {
+        let mut dev = MemDevice::new(10, 10);
+        for i in 0..10 {
+            dev.write_block(&Block::new(i, vec![1; 10].into())).unwrap();
+        }
+        dev.discard(3, 2).unwrap();
+        assert!(dev.discard(9, 2).is_err());
+        assert_eq!(dev.read_block(2).unwrap().contents_as_ref(), &[1; 10]);
+        assert_eq!(dev.read_block(3).unwrap(), Block::new_zero(3, 10));
+        assert_eq!(dev.read_block(4).unwrap(), Block::new_zero(4, 10));
+        assert_eq!(dev.read_block(9).unwrap().contents_as_ref(), &[1; 10]);
+    }
+
+    #[test]
+    fn batched_test() {
+        let mut dev = MemDevice::new(10, 10);
+        let blocks: Vec<Block> = (4..7)
+            .map(|i| Block::new(i, vec![i as u8; 10].into_boxed_slice()))
+            .collect();
+        dev.write_blocks(&blocks).unwrap();
+        assert_eq!(dev.read_blocks(4, 3).unwrap(), blocks);
+        assert_eq!(dev.stats().total().writes, 3);
+
+        //The borrowed range is the concatenation of the blocks
+        let range = dev.borrow_blocks(3, 2).unwrap();
+        assert!(matches!(range, Cow::Borrowed(_)));
+        assert_eq!(&range[..10], &[0; 10]);
+        assert_eq!(&range[10..], &[4; 10]);
+        assert_eq!(dev.stats().total().reads, 5);
+
+        //A batch with a single bad block is not written at all
+        let bad = vec![Block::new_zero(1, 10), Block::new_zero(10, 10)];
+        assert!(dev.write_blocks(&bad).is_err());
+        assert!(dev.write_blocks(&[Block::new_zero(1, 9)]).is_err());
+        assert_eq!(dev.stats().total().writes, 3);
+        assert!(dev.read_blocks(8, 3).is_err());
+        assert!(dev.borrow_blocks(10, 1).is_err());
+    }
+}
@@ -0,0 +1,831 @@
+//! Sharing block devices over the network, using the Network Block Device (NBD) protocol.
+//!
+//! An [`NbdExport`] serves a single block device to any number of NBD clients, over TCP or a Unix socket, and a [`RemoteDevice`] is a block device that forwards all of its reads and writes to such a server.
+//! Since the protocol is the standard one, the exported device can also be attached by other NBD clients, e.g. `nbd-client` on Linux, and a `RemoteDevice` can talk to other NBD servers.
+//! The `cplfs-nbd` tool exports a device image in this way.
+//!
+//! Only the parts of the protocol that are needed to share a device are supported:
+//! * the fixed newstyle handshake, with the `NBD_OPT_EXPORT_NAME`, `NBD_OPT_GO` and `NBD_OPT_ABORT` options,
+//! * the `NBD_CMD_READ`, `NBD_CMD_WRITE`, `NBD_CMD_FLUSH`, `NBD_CMD_TRIM` and `NBD_CMD_DISC` commands, answered with simple replies.
+//!
+//! NBD addresses a device in bytes rather than in blocks, so the server accepts requests at any offset and of any length, as long as they fit on the device.
+//! Trimming a range discards all blocks that lie entirely within it, using [`BlockDevice::discard`]; the other bytes in the range are left untouched, which the protocol allows.
+//! A `RemoteDevice` in turn implements `discard` by trimming the blocks on the server.
+//! Errors are reported to the client as the `errno` values prescribed by the protocol, and end up as `APIError::APIO` errors on the side of a `RemoteDevice`.
+//!
+//! [`NbdExport`]: struct.NbdExport.html
+//! [`RemoteDevice`]: struct.RemoteDevice.html
+//! [`BlockDevice::discard`]: ../controller/trait.BlockDevice.html#method.discard
+
+use super::controller::BlockDevice;
+use super::error_given;
+use super::error_given::APIError;
+use super::io_stats::{IoOp, IoStats};
+use super::types::Block;
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+};
+
+/// Magic number that starts the handshake
+const NBDMAGIC: u64 = 0x4e42_444d_4147_4943;
+/// Magic number of the newstyle handshake, which also precedes every option
+const IHAVEOPT: u64 = 0x4948_4156_454f_5054;
+/// Magic number of option replies
+const REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+/// Magic number of transmission requests
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+/// Magic number of simple transmission replies
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+/// Handshake flag of the server: it supports the fixed newstyle handshake
+const FLAG_FIXED_NEWSTYLE: u16 = 1;
+/// Handshake flag of the server: it can leave out the zeroes after `NBD_OPT_EXPORT_NAME`
+const FLAG_NO_ZEROES: u16 = 2;
+/// Handshake flag of the client: it supports the fixed newstyle handshake
+const FLAG_C_FIXED_NEWSTYLE: u32 = 1;
+/// Handshake flag of the client: the server should leave out the zeroes after `NBD_OPT_EXPORT_NAME`
+const FLAG_C_NO_ZEROES: u32 = 2;
+
+/// Option to select an export, without any way to report errors
+const OPT_EXPORT_NAME: u32 = 1;
+/// Option to end the handshake without entering transmission
+const OPT_ABORT: u32 = 2;
+/// Option to select an export and enter transmission
+const OPT_GO: u32 = 7;
+/// Option reply: the option was handled successfully
+const REP_ACK: u32 = 1;
+/// Option reply: information about the export
+const REP_INFO: u32 = 3;
+/// Option reply: the option is not supported
+const REP_ERR_UNSUP: u32 = 0x8000_0001;
+/// Option reply: the requested export does not exist
+const REP_ERR_UNKNOWN: u32 = 0x8000_0006;
+/// Information type of the size and transmission flags of an export
+const INFO_EXPORT: u16 = 0;
+
+/// Transmission flag: the other flags are valid
+const TFLAG_HAS_FLAGS: u16 = 1;
+/// Transmission flag: the export is read-only
+const TFLAG_READ_ONLY: u16 = 2;
+/// Transmission flag: the server supports `NBD_CMD_FLUSH`
+const TFLAG_SEND_FLUSH: u16 = 4;
+/// Transmission flag: the server supports `NBD_CMD_TRIM`
+const TFLAG_SEND_TRIM: u16 = 32;
+
+/// Command to read a range of bytes
+const CMD_READ: u16 = 0;
+/// Command to write a range of bytes
+const CMD_WRITE: u16 = 1;
+/// Command to end the transmission
+const CMD_DISC: u16 = 2;
+/// Command to persist all completed writes
+const CMD_FLUSH: u16 = 3;
+/// Command to discard a range of bytes
+const CMD_TRIM: u16 = 4;
+
+/// Error: operation not permitted, e.g. a write to a read-only export
+const EPERM: u32 = 1;
+/// Error: the device failed to perform the operation
+const EIO: u32 = 5;
+/// Error: invalid request, e.g. a read past the end of the device
+const EINVAL: u32 = 22;
+/// Error: a write or trim past the end of the device
+const ENOSPC: u32 = 28;
+
+/// Largest number of bytes a single request may move
+const MAX_REQUEST: u32 = 32 << 20;
+/// Largest option the server accepts during the handshake
+const MAX_OPTION: u32 = 4096;
+
+/// A block device, exported to NBD clients under a name.
+/// Clones of an export share the device, so they can serve several clients at the same time.
+#[derive(Debug)]
+pub struct NbdExport<D: BlockDevice> {
+    /// The exported device
+    dev: Arc<Mutex<D>>,
+    /// Name of the export; clients asking for the empty name get this export as well
+    name: String,
+    /// Whether clients are allowed to modify the device
+    read_only: bool,
+}
+
+impl<D: BlockDevice> Clone for NbdExport<D> {
+    fn clone(&self) -> NbdExport<D> {
+        NbdExport {
+            dev: Arc::clone(&self.dev),
+            name: self.name.clone(),
+            read_only: self.read_only,
+        }
+    }
+}
+
+impl<D: BlockDevice + Send + 'static> NbdExport<D> {
+    /// Export `dev` under the name `name`, refusing all writes and trims if `read_only` is set
+    pub fn new(dev: D, name: &str, read_only: bool) -> NbdExport<D> {
+        NbdExport {
+            dev: Arc::new(Mutex::new(dev)),
+            name: name.to_string(),
+            read_only,
+        }
+    }
+
+    /// End the lifetime of this export, giving back the exported device.
+    /// Returns `None` if a clone of this export is still alive, e.g. because it is still serving a client.
+    pub fn into_inner(self) -> Option<D> {
+        Arc::try_unwrap(self.dev)
+            .ok()
+            .map(|m| m.into_inner().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Accept clients on `listener` forever, serving each of them on a thread of its own.
+    /// A client that breaks the protocol only ends its own connection.
+    pub fn serve_tcp(&self, listener: TcpListener) -> error_given::Result<()> {
+        for stream in listener.incoming() {
+            let export = self.clone();
+            let stream = stream?;
+            thread::spawn(move || export.serve(stream));
+        }
+        Ok(())
+    }
+
+    /// Accept clients on the Unix socket `listener` forever, serving each of them on a thread of its own.
+    /// A client that breaks the protocol only ends its own connection.
+    #[cfg(unix)]
+    pub fn serve_unix(&self, listener: UnixListener) -> error_given::Result<()> {
+        for stream in listener.incoming() {
+            let export = self.clone();
+            let stream = stream?;
+            thread::spawn(move || export.serve(stream));
+        }
+        Ok(())
+    }
+
+    /// Serve a single client on the connection `stream`, until it disconnects.
+    /// Fails if the client breaks the protocol, or asks for an unknown export using `NBD_OPT_EXPORT_NAME`.
+    pub fn serve<S: Read + Write>(&self, mut stream: S) -> error_given::Result<()> {
+        if self.handshake(&mut stream)? {
+            self.transmission(&mut stream)?;
+        }
+        Ok(())
+    }
+
+    /// Lock the exported device; a client that panicked while holding the lock cannot leave it in an inconsistent state, so poisoning is ignored
+    fn device(&self) -> MutexGuard<'_, D> {
+        self.dev.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Size of the exported device in bytes, and its transmission flags
+    fn export_info(&self) -> (u64, u16) {
+        let dev = self.device();
+        let mut flags = TFLAG_HAS_FLAGS | TFLAG_SEND_FLUSH | TFLAG_SEND_TRIM;
+        if self.read_only {
+            flags |= TFLAG_READ_ONLY;
+        }
+        (dev.block_size() * dev.nblocks(), flags)
+    }
+
+    /// Perform the handshake with a client, returning whether it selected the export and entered transmission
+    fn handshake<S: Read + Write>(&self, s: &mut S) -> error_given::Result<bool> {
+        put_u64(s, NBDMAGIC)?;
+        put_u64(s, IHAVEOPT)?;
+        put_u16(s, FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES)?;
+        s.flush()?;
+        let client_flags = get_u32(s)?;
+        if client_flags & FLAG_C_FIXED_NEWSTYLE == 0 {
+            return Err(APIError::ControllerInput(
+                "NBD client does not support the fixed newstyle handshake",
+            ));
+        }
+
+        loop {
+            if get_u64(s)? != IHAVEOPT {
+                return Err(APIError::ControllerInput("Invalid NBD option"));
+            }
+            let option = get_u32(s)?;
+            let len = get_u32(s)?;
+            if len > MAX_OPTION {
+                return Err(APIError::ControllerInput("NBD option too long"));
+            }
+            let mut data = vec![0; len as usize];
+            s.read_exact(&mut data)?;
+
+            match option {
+                OPT_EXPORT_NAME => {
+                    //this option has no way to report errors, other than closing the connection
+                    if !self.matches(&data) {
+                        return Err(APIError::ControllerInput("Unknown NBD export"));
+                    }
+                    let (size, flags) = self.export_info();
+                    put_u64(s, size)?;
+                    put_u16(s, flags)?;
+                    if client_flags & FLAG_C_NO_ZEROES == 0 {
+                        s.write_all(&[0; 124])?;
+                    }
+                    s.flush()?;
+                    return Ok(true);
+                }
+                OPT_GO => {
+                    let name_len = data.get(..4).map(|b| be_u32(b) as usize);
+                    let name = name_len.and_then(|n| data.get(4..4 + n));
+                    if !name.is_some_and(|name| self.matches(name)) {
+                        option_reply(s, option, REP_ERR_UNKNOWN, &[])?;
+                        continue;
+                    }
+                    let (size, flags) = self.export_info();
+                    let mut info = Vec::with_capacity(12);
+                    info.extend_from_slice(&INFO_EXPORT.to_be_bytes());
+                    info.extend_from_slice(&size.to_be_bytes());
+                    info.extend_from_slice(&flags.to_be_bytes());
+                    option_reply(s, option, REP_INFO, &info)?;
+                    option_reply(s, option, REP_ACK, &[])?;
+                    return Ok(true);
+                }
+                OPT_ABORT => {
+                    option_reply(s, option, REP_ACK, &[])?;
+                    return Ok(false);
+                }
+                _ => option_reply(s, option, REP_ERR_UNSUP, &[])?,
+            }
+        }
+    }
+
+    /// Check whether a client asking for the export `name` should get this export
+    fn matches(&self, name: &[u8]) -> bool {
+        name.is_empty() || name == self.name.as_bytes()
+    }
+
+    /// Answer the requests of a client, until it disconnects
+    fn transmission<S: Read + Write>(&self, s: &mut S) -> error_given::Result<()> {
+        loop {
+            if get_u32(s)? != REQUEST_MAGIC {
+                return Err(APIError::ControllerInput("Invalid NBD request"));
+            }
+            let _flags = get_u16(s)?;
+            let command = get_u16(s)?;
+            let handle = get_u64(s)?;
+            let offset = get_u64(s)?;
+            let length = get_u32(s)?;
+
+            let result = match command {
+                CMD_READ => self.read_bytes(offset, length),
+                CMD_WRITE => {
+                    //the data has to be consumed before anything else can be read from the connection
+                    if length > MAX_REQUEST {
+                        return Err(APIError::ControllerInput("NBD write too long"));
+                    }
+                    let mut data = vec![0; length as usize];
+                    s.read_exact(&mut data)?;
+                    self.write_bytes(offset, &data).map(|_| Vec::new())
+                }
+                CMD_DISC => return Ok(()),
+                CMD_FLUSH => self.device().flush().map(|_| Vec::new()).map_err(|_| EIO),
+                CMD_TRIM => self.trim_bytes(offset, length).map(|_| Vec::new()),
+                _ => Err(EINVAL),
+            };
+
+            put_u32(s, SIMPLE_REPLY_MAGIC)?;
+            match result {
+                Ok(data) => {
+                    put_u32(s, 0)?;
+                    put_u64(s, handle)?;
+                    s.write_all(&data)?;
+                }
+                Err(errno) => {
+                    put_u32(s, errno)?;
+                    put_u64(s, handle)?;
+                }
+            }
+            s.flush()?;
+        }
+    }
+
+    /// Range of blocks that holds the `length` bytes starting at byte `offset`, as the first and one past the last block, or `err` if they do not fit on the device
+    fn block_range(&self, dev: &D, offset: u64, length: u32, err: u32) -> Result<(u64, u64), u32> {
+        let end = offset.checked_add(length as u64).ok_or(err)?;
+        if length > MAX_REQUEST || end > dev.block_size() * dev.nblocks() {
+            return Err(err);
+        }
+        Ok((offset / dev.block_size(), end.div_ceil(dev.block_size())))
+    }
+
+    /// Read the `length` bytes starting at byte `offset`
+    fn read_bytes(&self, offset: u64, length: u32) -> Result<Vec<u8>, u32> {
+        let dev = self.device();
+        let (first, end) = self.block_range(&dev, offset, length, EINVAL)?;
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        let data = dev.borrow_blocks(first, end - first).map_err(|_| EIO)?;
+        let start = (offset - first * dev.block_size()) as usize;
+        Ok(data[start..start + length as usize].to_vec())
+    }
+
+    /// Write `data` to the device, starting at byte `offset`; blocks that are only written in part are read first
+    fn write_bytes(&self, offset: u64, data: &[u8]) -> Result<(), u32> {
+        if self.read_only {
+            return Err(EPERM);
+        }
+        let mut dev = self.device();
+        let (first, end) = self.block_range(&dev, offset, data.len() as u32, ENOSPC)?;
+        let bs = dev.block_size();
+        let mut blocks = Vec::new();
+        for b in first..end {
+            //part of the block that is covered by the write, in bytes from the start of the device
+            let lo = offset.max(b * bs);
+            let hi = (offset + data.len() as u64).min((b + 1) * bs);
+            let part = &data[(lo - offset) as usize..(hi - offset) as usize];
+            let block = if hi - lo == bs {
+                Block::new(b, part.into())
+            } else {
+                let mut block = dev.read_block(b).map_err(|_| EIO)?;
+                block.write_data(part, lo - b * bs).map_err(|_| EIO)?;
+                block
+            };
+            blocks.push(block);
+        }
+        dev.write_blocks(&blocks).map_err(|_| EIO)
+    }
+
+    /// Discard all blocks that lie entirely within the `length` bytes starting at byte `offset`
+    fn trim_bytes(&self, offset: u64, length: u32) -> Result<(), u32> {
+        if self.read_only {
+            return Err(EPERM);
+        }
+        let mut dev = self.device();
+        self.block_range(&dev, offset, length, ENOSPC)?;
+        let bs = dev.block_size();
+        let (first, end) = (offset.div_ceil(bs), (offset + length as u64) / bs);
+        if first >= end {
+            return Ok(());
+        }
+        dev.discard(first, end - first).map_err(|_| EIO)
+    }
+}
+
+/// Connection of a `RemoteDevice` to its server
+#[derive(Debug)]
+enum Connection {
+    /// Connection over TCP
+    Tcp(TcpStream),
+    /// Connection over a Unix socket
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Connection::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// Block device that forwards all reads and writes to an NBD server.
+/// The client picks the block size; the size of the export has to be a multiple of it.
+#[derive(Debug)]
+pub struct RemoteDevice {
+    /// Size of the blocks that this disk reads and writes
+    block_size: u64,
+    /// Total number of blocks this disk consists of
+    nblocks: u64,
+    /// Whether the server refuses writes to the export
+    read_only: bool,
+    /// Connection to the server, in the transmission phase
+    conn: RefCell<Connection>,
+    /// Handle of the next request
+    next_handle: Cell<u64>,
+    /// Statistics of the reads and writes of this device
+    stats: IoStats,
+}
+
+impl RemoteDevice {
+    /// Connect to the NBD server at `addr` over TCP, and use its export `name` as a device with blocks of `block_size` bytes.
+    /// Fails if the server does not have the export, or if its size is not a multiple of `block_size`.
+    pub fn connect_tcp<A: ToSocketAddrs>(
+        addr: A,
+        name: &str,
+        block_size: u64,
+    ) -> error_given::Result<RemoteDevice> {
+        let stream = TcpStream::connect(addr)?;
+        //requests are small and wait for their reply, so they should not be held back
+        stream.set_nodelay(true)?;
+        RemoteDevice::connect(Connection::Tcp(stream), name, block_size)
+    }
+
+    /// Connect to the NBD server at the Unix socket `path`, and use its export `name` as a device with blocks of `block_size` bytes.
+    /// Fails if the server does not have the export, or if its size is not a multiple of `block_size`.
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(
+        path: P,
+        name: &str,
+        block_size: u64,
+    ) -> error_given::Result<RemoteDevice> {
+        let stream = UnixStream::connect(path)?;
+        RemoteDevice::connect(Connection::Unix(stream), name, block_size)
+    }
+
+    /// Perform the handshake on the connection `conn`, selecting the export `name` with `NBD_OPT_GO`
+    fn connect(
+        mut conn: Connection,
+        name: &str,
+        block_size: u64,
+    ) -> error_given::Result<RemoteDevice> {
+        let s = &mut conn;
+        if get_u64(s)? != NBDMAGIC || get_u64(s)? != IHAVEOPT {
+            return Err(APIError::ControllerInput("Not an NBD newstyle server"));
+        }
+        let server_flags = get_u16(s)?;
+        if server_flags & FLAG_FIXED_NEWSTYLE == 0 {
+            return Err(APIError::ControllerInput(
+                "NBD server does not support the fixed newstyle handshake",
+            ));
+        }
+        put_u32(
+            s,
+            FLAG_C_FIXED_NEWSTYLE | (server_flags as u32 & FLAG_C_NO_ZEROES),
+        )?;
+
+        put_u64(s, IHAVEOPT)?;
+        put_u32(s, OPT_GO)?;
+        put_u32(s, 4 + name.len() as u32 + 2)?;
+        put_u32(s, name.len() as u32)?;
+        s.write_all(name.as_bytes())?;
+        put_u16(s, 0)?;
+        s.flush()?;
+
+        let mut export = None;
+        loop {
+            if get_u64(s)? != REPLY_MAGIC || get_u32(s)? != OPT_GO {
+                return Err(APIError::ControllerInput("Invalid NBD option reply"));
+            }
+            let reply = get_u32(s)?;
+            let len = get_u32(s)?;
+            if len > MAX_OPTION {
+                return Err(APIError::ControllerInput("NBD option reply too long"));
+            }
+            let mut data = vec![0; len as usize];
+            s.read_exact(&mut data)?;
+            match reply {
+                REP_ACK => break,
+                REP_INFO if data.len() == 12 && data[..2] == INFO_EXPORT.to_be_bytes() => {
+                    export = Some((be_u64(&data[2..10]), be_u16(&data[10..12])));
+                }
+                REP_INFO => {}
+                _ => return Err(APIError::ControllerInput("NBD server refused the export")),
+            }
+        }
+
+        let (size, flags) = export.ok_or(APIError::ControllerInput(
+            "NBD server did not report the size of the export",
+        ))?;
+        if block_size == 0 || size % block_size != 0 {
+            return Err(APIError::ControllerInput(
+                "Size of the NBD export is not a multiple of the block size",
+            ));
+        }
+        Ok(RemoteDevice {
+            block_size,
+            nblocks: size / block_size,
+            read_only: flags & TFLAG_READ_ONLY != 0,
+            conn: RefCell::new(conn),
+            next_handle: Cell::new(0),
+            stats: IoStats::new(),
+        })
+    }
+
+    /// Check whether the server refuses writes to the export
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Handle to the I/O statistics of this device, which can be queried and reset while a file system uses the device
+    pub fn stats(&self) -> IoStats {
+        self.stats.clone()
+    }
+
+    /// Fail if the `n` blocks starting at block `start` do not fit on the device
+    fn check_range(&self, start: u64, n: u64) -> error_given::Result<()> {
+        if start.checked_add(n).is_none_or(|end| end > self.nblocks) {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Send a request to the server and wait for its reply, returning the `reply_len` bytes of data that come with it
+    fn request(
+        &self,
+        command: u16,
+        offset: u64,
+        length: u64,
+        data: &[u8],
+        reply_len: u64,
+    ) -> error_given::Result<Vec<u8>> {
+        if length > MAX_REQUEST as u64 {
+            return Err(APIError::ControllerInput("NBD request too long"));
+        }
+        let handle = self.next_handle.get();
+        self.next_handle.set(handle.wrapping_add(1));
+
+        let s = &mut *self.conn.borrow_mut();
+        put_u32(s, REQUEST_MAGIC)?;
+        put_u16(s, 0)?;
+        put_u16(s, command)?;
+        put_u64(s, handle)?;
+        put_u64(s, offset)?;
+        put_u32(s, length as u32)?;
+        s.write_all(data)?;
+        s.flush()?;
+
+        if get_u32(s)? != SIMPLE_REPLY_MAGIC {
+            return Err(APIError::ControllerInput("Invalid NBD reply"));
+        }
+        let errno = get_u32(s)?;
+        if get_u64(s)? != handle {
+            return Err(APIError::ControllerInput("NBD reply to another request"));
+        }
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno as i32).into());
+        }
+        let mut reply = vec![0; reply_len as usize];
+        s.read_exact(&mut reply)?;
+        Ok(reply)
+    }
+}
+
+impl Drop for RemoteDevice {
+    /// Tell the server that this client is done; the server might already be gone, so failures are ignored
+    fn drop(&mut self) {
+        let s = self.conn.get_mut();
+        let _ = put_u32(s, REQUEST_MAGIC)
+            .and_then(|_| put_u16(s, 0))
+            .and_then(|_| put_u16(s, CMD_DISC))
+            .and_then(|_| put_u64(s, 0))
+            .and_then(|_| put_u64(s, 0))
+            .and_then(|_| put_u32(s, 0))
+            .and_then(|_| s.flush());
+    }
+}
+
+impl BlockDevice for RemoteDevice {
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        self.check_range(index, 1)?;
+        let bs = self.block_size;
+        let data = self.request(CMD_READ, index * bs, bs, &[], bs)?;
+        self.stats.record(IoOp::Read, index, bs);
+        Ok(Block::new(index, data.into_boxed_slice()))
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        if b.len() != self.block_size {
+            return Err(APIError::ControllerInput(
+                "Trying to write a non-block-sized block",
+            ));
+        }
+        self.check_range(b.block_no, 1)?;
+        let bs = self.block_size;
+        self.request(CMD_WRITE, b.block_no * bs, bs, b.contents_as_ref(), 0)?;
+        self.stats.record(IoOp::Write, b.block_no, bs);
+        Ok(())
+    }
+
+    /// Reads all blocks with a single request
+    fn read_blocks(&self, start: u64, n: u64) -> error_given::Result<Vec<Block>> {
+        self.check_range(start, n)?;
+        let bs = self.block_size;
+        let data = self.request(CMD_READ, start * bs, n * bs, &[], n * bs)?;
+        for i in start..start + n {
+            self.stats.record(IoOp::Read, i, bs);
+        }
+        Ok(data
+            .chunks(bs as usize)
+            .zip(start..)
+            .map(|(d, i)| Block::new(i, d.into()))
+            .collect())
+    }
+
+    /// Trims the blocks on the server, in as many requests as needed to stay below the maximum request size
+    fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        self.check_range(start, n)?;
+        let bs = self.block_size;
+        let per_request = (MAX_REQUEST as u64 / bs).max(1);
+        let mut first = start;
+        while first < start + n {
+            let count = per_request.min(start + n - first);
+            self.request(CMD_TRIM, first * bs, count * bs, &[], 0)?;
+            first += count;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> error_given::Result<()> {
+        self.request(CMD_FLUSH, 0, 0, &[], 0)?;
+        Ok(())
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.nblocks
+    }
+}
+
+/// Option reply of type `reply` to the option `option`, with data `data`
+fn option_reply<W: Write>(w: &mut W, option: u32, reply: u32, data: &[u8]) -> io::Result<()> {
+    put_u64(w, REPLY_MAGIC)?;
+    put_u32(w, option)?;
+    put_u32(w, reply)?;
+    put_u32(w, data.len() as u32)?;
+    w.write_all(data)?;
+    w.flush()
+}
+
+/// Write a big-endian `u16`, as all numbers in the protocol are
+fn put_u16<W: Write>(w: &mut W, x: u16) -> io::Result<()> {
+    w.write_all(&x.to_be_bytes())
+}
+
+/// Write a big-endian `u32`
+fn put_u32<W: Write>(w: &mut W, x: u32) -> io::Result<()> {
+    w.write_all(&x.to_be_bytes())
+}
+
+/// Write a big-endian `u64`
+fn put_u64<W: Write>(w: &mut W, x: u64) -> io::Result<()> {
+    w.write_all(&x.to_be_bytes())
+}
+
+/// Read a big-endian `u16`
+fn get_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut b = [0; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_be_bytes(b))
+}
+
+/// Read a big-endian `u32`
+fn get_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_be_bytes(b))
+}
+
+/// Read a big-endian `u64`
+fn get_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_be_bytes(b))
+}
+
+/// Decode a big-endian `u16` from the first 2 bytes of `b`
+fn be_u16(b: &[u8]) -> u16 {
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+/// Decode a big-endian `u32` from the first 4 bytes of `b`
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// Decode a big-endian `u64` from the first 8 bytes of `b`
+fn be_u64(b: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&b[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NbdExport, RemoteDevice};
+    use crate::controller::BlockDevice;
+    use crate::error_given::APIError;
+    use crate::mem_device::MemDevice;
+    use crate::types::Block;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Export `dev` on a fresh localhost port, serving a single client, and return the port and the thread serving it
+    fn serve_once(
+        dev: MemDevice,
+        read_only: bool,
+    ) -> (u16, thread::JoinHandle<(NbdExport<MemDevice>, bool)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let export = NbdExport::new(dev, "test", read_only);
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let ok = export.serve(stream).is_ok();
+            (export, ok)
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn tcp_test() {
+        let (port, server) = serve_once(MemDevice::new(10, 10), false);
+        let mut dev = RemoteDevice::connect_tcp(("127.0.0.1", port), "test", 5).unwrap();
+        assert_eq!(dev.nblocks(), 20);
+        assert!(!dev.is_read_only());
+
+        //The client uses a smaller block size than the exported device
+        let bw = Block::new(3, vec![1, 2, 3, 4, 5].into_boxed_slice());
+        dev.write_block(&bw).unwrap();
+        assert_eq!(dev.read_block(3).unwrap(), bw);
+        assert_eq!(dev.read_blocks(2, 3).unwrap()[1], bw);
+        assert!(dev.read_block(20).is_err());
+        assert!(dev.write_block(&Block::new_zero(1, 4)).is_err());
+        dev.flush().unwrap();
+
+        //Trimming zeroes the blocks of the server that are covered entirely, and leaves the others alone
+        dev.write_block(&Block::new(4, vec![9; 5].into_boxed_slice()))
+            .unwrap();
+        dev.discard(3, 3).unwrap();
+        drop(dev);
+
+        let (export, ok) = server.join().unwrap();
+        assert!(ok);
+        let inner = export.into_inner().unwrap();
+        assert_eq!(
+            inner.read_block(1).unwrap().contents_as_ref(),
+            &[0, 0, 0, 0, 0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(
+            inner.read_block(2).unwrap().contents_as_ref(),
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn refused_test() {
+        //Unknown exports and block sizes that do not fit are refused
+        let (port, server) = serve_once(MemDevice::new(10, 10), true);
+        assert!(RemoteDevice::connect_tcp(("127.0.0.1", port), "other", 10).is_err());
+        assert!(!server.join().unwrap().1);
+        let (port, _) = serve_once(MemDevice::new(10, 10), true);
+        assert!(RemoteDevice::connect_tcp(("127.0.0.1", port), "test", 30).is_err());
+
+        //Read-only exports refuse writes with EPERM
+        let (port, server) = serve_once(MemDevice::new(10, 10), true);
+        let mut dev = RemoteDevice::connect_tcp(("127.0.0.1", port), "", 10).unwrap();
+        assert!(dev.is_read_only());
+        match dev.write_block(&Block::new_zero(1, 10)) {
+            Err(APIError::APIO(e)) => assert_eq!(e.raw_os_error(), Some(1)),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(dev.read_block(1).unwrap(), Block::new_zero(1, 10));
+        drop(dev);
+        assert!(server.join().unwrap().1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_test() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("cplfs-nbd-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let export = NbdExport::new(MemDevice::new(10, 10), "test", false);
+        let server = export.clone();
+        thread::spawn(move || server.serve_unix(listener));
+
+        //Two clients share the same device
+        let mut dev = RemoteDevice::connect_unix(&path, "test", 10).unwrap();
+        let other = RemoteDevice::connect_unix(&path, "test", 10).unwrap();
+        let bw = Block::new(7, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+        assert_eq!(other.read_block(7).unwrap(), bw);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,445 @@
+//! Implementation of a copy-on-write block device, that layers the blocks written to it on top of an immutable base image.
+//!
+//! An [`OverlayDevice`] loads its base [`Device`] read-only, and never writes to it while the overlay is in use.
+//! Every block that is written to the overlay is stored in a separate *delta file* instead, and reads of such a block are served from that file from then on.
+//! All other reads go through to the base image.
+//! This allows running destructive experiments on top of a golden image, without copying the entire image first.
+//!
+//! The delta file starts with a header, holding a magic number and the dimensions of the device, followed by one record per modified block.
+//! Each record consists of the index of the block, as a little-endian `u64`, followed by the contents of the block.
+//! Writing a block that is already in the delta file overwrites its record in place; writing any other block appends a new record.
+//! The index of the records is rebuilt when an existing delta file is loaded, so experiments can be continued later on.
+//!
+//! Discarding blocks with [`BlockDevice::discard`] removes their records again, by moving the last record into the freed slot, so that the delta file shrinks.
+//! Only blocks that are not all zeroes in the base image keep a record, filled with zeroes.
+//!
+//! When the experiment is over, the delta can either be reverted with [`OverlayDevice::revert`], restoring the view of the unmodified base image, or committed into the base image with [`OverlayDevice::commit`].
+//!
+//! The base image is locked with a shared lock for as long as the overlay is alive, so other readers can still use it, but nobody can modify it.
+//! The delta file is locked exclusively.
+//!
+//! [`Device`]: ../controller/struct.Device.html
+//! [`OverlayDevice`]: struct.OverlayDevice.html
+//! [`OverlayDevice::commit`]: struct.OverlayDevice.html#method.commit
+//! [`OverlayDevice::revert`]: struct.OverlayDevice.html#method.revert
+//! [`BlockDevice::discard`]: ../controller/trait.BlockDevice.html#method.discard
+
+use super::controller::{lock_file, BlockDevice, Device, LockMode};
+use super::error_given;
+use super::error_given::APIError;
+use super::file_device::{read_at, write_at};
+use super::io_stats::{IoOp, IoStats};
+use super::types::Block;
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs::{remove_file, File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+/// Magic number at the start of every delta file
+const DELTA_MAGIC: &[u8; 8] = b"CPLFSDLT";
+/// Size of the header of a delta file: the magic number, the block size and the number of blocks
+const HEADER_SIZE: u64 = 24;
+/// Size of the block index in front of every record of a delta file
+const RECORD_INDEX_SIZE: u64 = 8;
+
+/// Block device that stores all writes in a delta file, on top of a base image that is only read from.
+#[derive(Debug)]
+pub struct OverlayDevice {
+    /// The base image, loaded read-only
+    base: Device,
+    /// Path of the delta file
+    delta_path: PathBuf,
+    /// The opened delta file at `delta_path`
+    delta: File,
+    /// Slot in the delta file of every block that was written to the overlay
+    index: HashMap<u64, u64>,
+    /// Statistics of the reads and writes of this device
+    stats: IoStats,
+}
+
+impl OverlayDevice {
+    /// Create an overlay on top of the existing image at `base_path`, with a *new*, empty delta file at `delta_path`.
+    /// This function will return an error if the base image does not match the given dimensions, or if the delta file already exists.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(
+        base_path: P,
+        delta_path: Q,
+        block_size: u64,
+        nblocks: u64,
+    ) -> error_given::Result<OverlayDevice> {
+        let base = Device::load_readonly(base_path, block_size, nblocks)?;
+        let delta = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&delta_path)?;
+        lock_file(&delta, delta_path.as_ref(), LockMode::Exclusive)?;
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(DELTA_MAGIC);
+        header.extend_from_slice(&block_size.to_le_bytes());
+        header.extend_from_slice(&nblocks.to_le_bytes());
+        write_at(&delta, &header, 0)?;
+
+        Ok(OverlayDevice {
+            base,
+            delta_path: delta_path.as_ref().to_path_buf(),
+            delta,
+            index: HashMap::new(),
+            stats: IoStats::new(),
+        })
+    }
+
+    /// Load an overlay on top of the existing image at `base_path`, continuing with the *existing* delta file at `delta_path`.
+    /// This function will return an error if the delta file does not exist, or if it does not belong to a device with the given dimensions.
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(
+        base_path: P,
+        delta_path: Q,
+        block_size: u64,
+        nblocks: u64,
+    ) -> error_given::Result<OverlayDevice> {
+        let base = Device::load_readonly(base_path, block_size, nblocks)?;
+        let delta = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&delta_path)?;
+        lock_file(&delta, delta_path.as_ref(), LockMode::Exclusive)?;
+
+        let len = delta.metadata()?.len();
+        let record_size = RECORD_INDEX_SIZE + block_size;
+        if len < HEADER_SIZE || !(len - HEADER_SIZE).is_multiple_of(record_size) {
+            return Err(APIError::ControllerInput("Delta file has an invalid size"));
+        }
+        let mut header = [0; HEADER_SIZE as usize];
+        read_at(&delta, &mut header, 0)?;
+        if &header[..8] != DELTA_MAGIC {
+            return Err(APIError::ControllerInput("File is not a delta file"));
+        }
+        if read_u64(&header[8..16]) != block_size || read_u64(&header[16..]) != nblocks {
+            return Err(APIError::ControllerInput(
+                "Delta file belongs to a device of a different size",
+            ));
+        }
+
+        //rebuild the index from the block numbers in front of the records
+        let mut index = HashMap::new();
+        let nrecords = (len - HEADER_SIZE) / record_size;
+        for slot in 0..nrecords {
+            let mut block_no = [0; RECORD_INDEX_SIZE as usize];
+            read_at(&delta, &mut block_no, HEADER_SIZE + slot * record_size)?;
+            let block_no = read_u64(&block_no);
+            //a duplicate last record was already moved into the slot of a discarded block, right before the file would have been shortened
+            if slot == nrecords - 1 && index.contains_key(&block_no) {
+                delta.set_len(HEADER_SIZE + slot * record_size)?;
+                break;
+            }
+            if block_no >= nblocks || index.insert(block_no, slot).is_some() {
+                return Err(APIError::ControllerInput(
+                    "Delta file contains an invalid record",
+                ));
+            }
+        }
+
+        Ok(OverlayDevice {
+            base,
+            delta_path: delta_path.as_ref().to_path_buf(),
+            delta,
+            index,
+            stats: IoStats::new(),
+        })
+    }
+
+    /// Indices of the blocks that were written to the overlay, in ascending order
+    pub fn modified_blocks(&self) -> Vec<u64> {
+        let mut blocks: Vec<u64> = self.index.keys().copied().collect();
+        blocks.sort_unstable();
+        blocks
+    }
+
+    /// Handle to the I/O statistics of this device, which can be queried and reset while a file system uses the device
+    pub fn stats(&self) -> IoStats {
+        self.stats.clone()
+    }
+
+    /// Throw away all blocks written to the overlay so far, so that it shows the unmodified base image again
+    pub fn revert(&mut self) -> error_given::Result<()> {
+        self.delta.set_len(HEADER_SIZE)?;
+        self.delta.sync_data()?;
+        self.index.clear();
+        Ok(())
+    }
+
+    /// Write all blocks of the delta into the base image, and remove the delta file.
+    /// Ends the lifetime of the overlay, giving back the base image as a writable device instead, with an exclusive lock on it.
+    /// Fails if another device is still using the base image.
+    pub fn commit(self) -> error_given::Result<Device> {
+        let blocks = self.delta_blocks()?;
+        let base_path = self.base.device_path().to_path_buf();
+        let (block_size, nblocks) = (self.base.block_size, self.base.nblocks);
+        let OverlayDevice {
+            base,
+            delta_path,
+            delta,
+            ..
+        } = self;
+
+        //release the shared lock first, so the base image can be locked exclusively
+        //if this fails, the delta file is left behind, so nothing is lost
+        drop(base);
+        let mut base = Device::load(&base_path, block_size, nblocks)?;
+        base.write_blocks(&blocks)?;
+        base.sync()?;
+
+        drop(delta);
+        remove_file(&delta_path)?;
+        Ok(base)
+    }
+
+    /// End the lifetime of this overlay, and remove its delta file, leaving the base image untouched
+    /// Panics if removing the file fails
+    pub fn destruct(self) {
+        remove_file(&self.delta_path).unwrap();
+    }
+
+    /// Path of the delta file of this overlay
+    pub fn delta_path(&self) -> &Path {
+        &self.delta_path
+    }
+
+    /// Offset in the delta file of the record in slot `slot`
+    fn record_offset(&self, slot: u64) -> u64 {
+        HEADER_SIZE + slot * (RECORD_INDEX_SIZE + self.base.block_size)
+    }
+
+    /// Remove the record of block `block_no` from the delta file, by moving the last record into its slot and shortening the file
+    fn remove_record(&mut self, block_no: u64) -> error_given::Result<()> {
+        let slot = match self.index.remove(&block_no) {
+            Some(slot) => slot,
+            None => return Ok(()),
+        };
+        let last = self.index.len() as u64;
+        if slot != last {
+            let mut record = vec![0; (RECORD_INDEX_SIZE + self.base.block_size) as usize];
+            read_at(&self.delta, &mut record, self.record_offset(last))?;
+            write_at(&self.delta, &record, self.record_offset(slot))?;
+            self.index.insert(read_u64(&record), slot);
+        }
+        self.delta.set_len(self.record_offset(last))?;
+        Ok(())
+    }
+
+    /// Read all blocks in the delta file, in ascending order of their indices, without recording them in the statistics
+    fn delta_blocks(&self) -> error_given::Result<Vec<Block>> {
+        self.modified_blocks()
+            .into_iter()
+            .map(|i| {
+                let mut data = vec![0; self.base.block_size as usize];
+                let offset = self.record_offset(self.index[&i]) + RECORD_INDEX_SIZE;
+                read_at(&self.delta, &mut data, offset)?;
+                Ok(Block::new(i, data.into_boxed_slice()))
+            })
+            .collect()
+    }
+}
+
+impl BlockDevice for OverlayDevice {
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        let block = match self.index.get(&index) {
+            Some(&slot) => {
+                let mut data = vec![0; self.base.block_size as usize];
+                read_at(
+                    &self.delta,
+                    &mut data,
+                    self.record_offset(slot) + RECORD_INDEX_SIZE,
+                )?;
+                Block::new(index, data.into_boxed_slice())
+            }
+            None => self.base.read_block(index)?,
+        };
+        self.stats.record(IoOp::Read, index, self.base.block_size);
+        Ok(block)
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        if b.len() != self.base.block_size {
+            return Err(APIError::ControllerInput(
+                "Trying to write a non-block-sized block",
+            ));
+        }
+        if b.block_no >= self.base.nblocks {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+
+        match self.index.get(&b.block_no) {
+            Some(&slot) => {
+                let offset = self.record_offset(slot) + RECORD_INDEX_SIZE;
+                write_at(&self.delta, b.contents_as_ref(), offset)?;
+            }
+            None => {
+                //the record is written in one go, and only indexed once it has been written
+                let slot = self.index.len() as u64;
+                let mut record = Vec::with_capacity((RECORD_INDEX_SIZE + b.len()) as usize);
+                record.extend_from_slice(&b.block_no.to_le_bytes());
+                record.extend_from_slice(b.contents_as_ref());
+                write_at(&self.delta, &record, self.record_offset(slot))?;
+                self.index.insert(b.block_no, slot);
+            }
+        }
+        self.stats
+            .record(IoOp::Write, b.block_no, self.base.block_size);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> error_given::Result<()> {
+        Ok(self.delta.sync_data()?)
+    }
+
+    fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        if start
+            .checked_add(n)
+            .is_none_or(|end| end > self.base.nblocks)
+        {
+            return Err(APIError::ControllerInput(
+                "Discard past the end of the device",
+            ));
+        }
+        for i in start..start + n {
+            let zero = Block::new_zero(i, self.base.block_size);
+            if self.base.read_block(i)? == zero {
+                self.remove_record(i)?;
+            } else {
+                self.write_block(&zero)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn block_size(&self) -> u64 {
+        self.base.block_size
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.base.nblocks
+    }
+}
+
+/// Decode a little-endian `u64` from the first 8 bytes of `bytes`
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OverlayDevice, HEADER_SIZE, RECORD_INDEX_SIZE};
+    use crate::controller::{BlockDevice, Device};
+    use crate::types::Block;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::path::PathBuf;
+
+    fn overlay_prep_dir(name: &str) -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("fs-images-overlay-device-".to_string() + name);
+        if path.exists() {
+            remove_dir_all(&path).unwrap();
+        }
+        create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn overlay_test() {
+        let dir = overlay_prep_dir("overlay");
+        let (base_path, delta_path) = (dir.join("base"), dir.join("delta"));
+        let mut base = Device::new(&base_path, 10, 10).unwrap();
+        let b1 = Block::new(1, vec![1; 10].into_boxed_slice());
+        base.write_block(&b1).unwrap();
+        drop(base);
+
+        //Writes only end up in the delta file
+        let mut dev = OverlayDevice::new(&base_path, &delta_path, 10, 10).unwrap();
+        assert!(OverlayDevice::new(&base_path, &delta_path, 10, 10).is_err());
+        assert!(dev.write_block(&Block::new_zero(10, 10)).is_err());
+        let b2 = Block::new(2, vec![2; 10].into_boxed_slice());
+        let b1_new = Block::new(1, vec![3; 10].into_boxed_slice());
+        dev.write_block(&b2).unwrap();
+        dev.write_block(&b1_new).unwrap();
+        dev.write_block(&b2).unwrap();
+        assert_eq!(dev.read_block(1).unwrap(), b1_new);
+        assert_eq!(dev.read_block(2).unwrap(), b2);
+        assert_eq!(dev.read_block(3).unwrap(), Block::new_zero(3, 10));
+        assert_eq!(dev.modified_blocks(), vec![1, 2]);
+        assert_eq!(
+            &std::fs::read(&base_path).unwrap()[10..30],
+            &[[1; 10], [0; 10]].concat()[..]
+        );
+
+        //The delta survives reloading, and can be reverted
+        drop(dev);
+        assert!(OverlayDevice::load(&base_path, &delta_path, 10, 11).is_err());
+        let mut dev = OverlayDevice::load(&base_path, &delta_path, 10, 10).unwrap();
+        assert_eq!(dev.read_block(1).unwrap(), b1_new);
+        dev.revert().unwrap();
+        assert_eq!(dev.read_block(1).unwrap(), b1);
+        assert!(dev.modified_blocks().is_empty());
+
+        //Committing writes the delta into the base image and removes the delta file
+        dev.write_block(&b2).unwrap();
+        let base = dev.commit().unwrap();
+        assert_eq!(base.read_block(2).unwrap(), b2);
+        assert_eq!(base.read_block(1).unwrap(), b1);
+        assert!(!delta_path.exists());
+
+        base.destruct();
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn discard_test() {
+        let dir = overlay_prep_dir("discard");
+        let (base_path, delta_path) = (dir.join("base"), dir.join("delta"));
+        let mut base = Device::new(&base_path, 10, 10).unwrap();
+        base.write_block(&Block::new(1, vec![1; 10].into_boxed_slice()))
+            .unwrap();
+        drop(base);
+
+        let mut dev = OverlayDevice::new(&base_path, &delta_path, 10, 10).unwrap();
+        let blocks: Vec<Block> = (1..5)
+            .map(|i| Block::new(i, vec![i as u8 + 1; 10].into_boxed_slice()))
+            .collect();
+        dev.write_blocks(&blocks).unwrap();
+        let delta_len = || std::fs::metadata(&delta_path).unwrap().len();
+        assert_eq!(delta_len(), HEADER_SIZE + 4 * (RECORD_INDEX_SIZE + 10));
+
+        //Discarding drops the records of blocks that are zero in the base image, and the delta file shrinks
+        dev.discard(2, 2).unwrap();
+        assert_eq!(dev.modified_blocks(), vec![1, 4]);
+        assert_eq!(delta_len(), HEADER_SIZE + 2 * (RECORD_INDEX_SIZE + 10));
+        assert_eq!(dev.read_block(2).unwrap(), Block::new_zero(2, 10));
+        assert_eq!(dev.read_block(3).unwrap(), Block::new_zero(3, 10));
+        assert_eq!(dev.read_block(4).unwrap(), blocks[3]);
+
+        //Blocks that are not zero in the base image keep a zeroed record
+        dev.discard(1, 1).unwrap();
+        assert_eq!(dev.modified_blocks(), vec![1, 4]);
+        assert_eq!(dev.read_block(1).unwrap(), Block::new_zero(1, 10));
+        assert!(dev.discard(9, 2).is_err());
+        assert!(dev.discard(u64::MAX, 2).is_err());
+
+        //A copy of the last record, left behind by an interrupted discard, is dropped when loading
+        drop(dev);
+        let mut contents = std::fs::read(&delta_path).unwrap();
+        let last = contents[contents.len() - (RECORD_INDEX_SIZE + 10) as usize..].to_vec();
+        contents.extend_from_slice(&last);
+        std::fs::write(&delta_path, &contents).unwrap();
+        let dev = OverlayDevice::load(&base_path, &delta_path, 10, 10).unwrap();
+        assert_eq!(dev.modified_blocks(), vec![1, 4]);
+        assert_eq!(dev.read_block(4).unwrap(), blocks[3]);
+        assert_eq!(delta_len(), HEADER_SIZE + 2 * (RECORD_INDEX_SIZE + 10));
+
+        dev.destruct();
+        remove_dir_all(&dir).unwrap();
+    }
+}
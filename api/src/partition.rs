@@ -0,0 +1,366 @@
+//! Partitioning of block devices, so that a single device can hold several file systems.
+//!
+//! Real devices reserve their first block for a Master Boot Record (MBR) or a similar structure that describes how the device is partitioned.
+//! This module adds a simple, MBR-like partition table to the model, stored in block 0 of a device, with room for [`MAX_PARTITIONS`] partitions.
+//! All numbers in the table are stored as little-endian `u64`s:
+//!
+//! | Offset         | Contents                                                  |
+//! |----------------|-----------------------------------------------------------|
+//! | 0              | The magic number `CPLFSPT1`                               |
+//! | 8              | The number of slots in the table, i.e. [`MAX_PARTITIONS`] |
+//! | 16 + 16 * slot | The first block of the partition in slot `slot`           |
+//! | 24 + 16 * slot | The number of blocks of that partition, or 0 if unused    |
+//!
+//! A [`Partition`] exposes a single partition as a block device of its own, with its block numbers rebased to 0, so any file system can be created on and mounted from a partition, just like from an entire device.
+//! The `cplfs-part` tool lists, creates and deletes the partitions of a device image.
+//!
+//! [`MAX_PARTITIONS`]: constant.MAX_PARTITIONS.html
+//! [`Partition`]: struct.Partition.html
+
+use super::controller::{BlockDevice, Device};
+use super::error_given;
+use super::error_given::APIError;
+use super::types::Block;
+use std::borrow::Cow;
+use std::convert::TryInto;
+
+/// Maximal number of partitions on a single device
+pub const MAX_PARTITIONS: usize = 4;
+/// Magic number at the start of every partition table
+const TABLE_MAGIC: &[u8; 8] = b"CPLFSPT1";
+/// Size of the header of the table: the magic number and the number of slots
+const HEADER_SIZE: usize = 16;
+/// Size of a single entry of the table
+const ENTRY_SIZE: usize = 16;
+/// Smallest block size that can hold a partition table
+pub const MIN_TABLE_BLOCK_SIZE: u64 = (HEADER_SIZE + MAX_PARTITIONS * ENTRY_SIZE) as u64;
+
+/// Location of a single partition on its device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /// Index of the first block of the partition on the device
+    pub start: u64,
+    /// Number of blocks of the partition
+    pub nblocks: u64,
+}
+
+impl PartitionEntry {
+    /// Check whether this partition shares any blocks with `other`
+    fn overlaps(&self, other: &PartitionEntry) -> bool {
+        self.start < other.start + other.nblocks && other.start < self.start + self.nblocks
+    }
+}
+
+/// Partition table of a device, as stored in its block 0
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartitionTable {
+    /// The partition in every slot of the table, if any
+    slots: [Option<PartitionEntry>; MAX_PARTITIONS],
+}
+
+impl PartitionTable {
+    /// Create an empty partition table
+    pub fn new() -> PartitionTable {
+        PartitionTable::default()
+    }
+
+    /// Read the partition table from block 0 of `dev`
+    /// Fails if block 0 does not hold a valid partition table
+    pub fn read<D: BlockDevice>(dev: &D) -> error_given::Result<PartitionTable> {
+        let block = dev.read_block(0)?;
+        let data = block.contents_as_ref();
+        if data.len() < MIN_TABLE_BLOCK_SIZE as usize || &data[..8] != TABLE_MAGIC {
+            return Err(APIError::ControllerInput(
+                "Device does not hold a partition table",
+            ));
+        }
+        if read_u64(data, 8) != MAX_PARTITIONS as u64 {
+            return Err(APIError::ControllerInput(
+                "Partition table has an unsupported number of slots",
+            ));
+        }
+
+        let mut table = PartitionTable::new();
+        for slot in 0..MAX_PARTITIONS {
+            let offset = HEADER_SIZE + slot * ENTRY_SIZE;
+            let entry = PartitionEntry {
+                start: read_u64(data, offset),
+                nblocks: read_u64(data, offset + 8),
+            };
+            if entry.nblocks != 0 {
+                table.check_new(&entry, dev.nblocks())?;
+                table.slots[slot] = Some(entry);
+            }
+        }
+        Ok(table)
+    }
+
+    /// Write this partition table to block 0 of `dev`, overwriting whatever was stored there
+    /// Fails if the blocks of `dev` are too small to hold a partition table
+    pub fn write<D: BlockDevice>(&self, dev: &mut D) -> error_given::Result<()> {
+        if dev.block_size() < MIN_TABLE_BLOCK_SIZE {
+            return Err(APIError::ControllerInput(
+                "Block size too small to hold a partition table",
+            ));
+        }
+        let mut data = vec![0; dev.block_size() as usize];
+        data[..8].copy_from_slice(TABLE_MAGIC);
+        data[8..16].copy_from_slice(&(MAX_PARTITIONS as u64).to_le_bytes());
+        for (slot, entry) in self.slots.iter().enumerate() {
+            if let Some(entry) = entry {
+                let offset = HEADER_SIZE + slot * ENTRY_SIZE;
+                data[offset..offset + 8].copy_from_slice(&entry.start.to_le_bytes());
+                data[offset + 8..offset + 16].copy_from_slice(&entry.nblocks.to_le_bytes());
+            }
+        }
+        dev.write_block(&Block::new(0, data.into_boxed_slice()))
+    }
+
+    /// The partition in slot `slot`, if any
+    pub fn get(&self, slot: usize) -> Option<PartitionEntry> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    /// All partitions in the table, together with their slots, in the order of their slots
+    pub fn list(&self) -> Vec<(usize, PartitionEntry)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, entry)| entry.map(|e| (slot, e)))
+            .collect()
+    }
+
+    /// Add a partition of `nblocks` blocks, starting at block `start`, to the first free slot of the table, and return that slot.
+    /// Fails if there is no free slot left, or if the partition does not fit on a device of `dev_nblocks` blocks, or overlaps block 0 or another partition.
+    pub fn create(
+        &mut self,
+        start: u64,
+        nblocks: u64,
+        dev_nblocks: u64,
+    ) -> error_given::Result<usize> {
+        let entry = PartitionEntry { start, nblocks };
+        self.check_new(&entry, dev_nblocks)?;
+        let slot = self
+            .slots
+            .iter()
+            .position(|s| s.is_none())
+            .ok_or(APIError::ControllerInput("Partition table is full"))?;
+        self.slots[slot] = Some(entry);
+        Ok(slot)
+    }
+
+    /// Remove the partition in slot `slot` from the table, and return it.
+    /// The blocks of the partition are left untouched.
+    /// Fails if the slot is empty.
+    pub fn delete(&mut self, slot: usize) -> error_given::Result<PartitionEntry> {
+        self.slots
+            .get_mut(slot)
+            .and_then(|s| s.take())
+            .ok_or(APIError::ControllerInput("Partition slot is empty"))
+    }
+
+    /// Check whether `entry` can be added to this table, on a device of `dev_nblocks` blocks
+    fn check_new(&self, entry: &PartitionEntry, dev_nblocks: u64) -> error_given::Result<()> {
+        if entry.nblocks == 0 {
+            return Err(APIError::ControllerInput("Partition without any blocks"));
+        }
+        if entry.start == 0
+            || entry
+                .start
+                .checked_add(entry.nblocks)
+                .is_none_or(|end| end > dev_nblocks)
+        {
+            return Err(APIError::ControllerInput(
+                "Partition does not fit on the device",
+            ));
+        }
+        if self.slots.iter().flatten().any(|e| e.overlaps(entry)) {
+            return Err(APIError::ControllerInput(
+                "Partition overlaps another partition",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Block device that consists of a single partition of another device, with its block numbers rebased to 0.
+#[derive(Debug)]
+pub struct Partition<D: BlockDevice = Device> {
+    /// The partitioned device
+    inner: D,
+    /// Slot of this partition in the partition table of `inner`
+    slot: usize,
+    /// Location of this partition on `inner`
+    entry: PartitionEntry,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    /// Open the partition in slot `slot` of the partition table of `inner`.
+    /// Fails if `inner` does not hold a partition table, or if the slot is empty.
+    pub fn open(inner: D, slot: usize) -> error_given::Result<Partition<D>> {
+        let entry = PartitionTable::read(&inner)?
+            .get(slot)
+            .ok_or(APIError::ControllerInput("Partition slot is empty"))?;
+        Ok(Partition { inner, slot, entry })
+    }
+
+    /// Slot of this partition in the partition table of its device
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Location of this partition on its device
+    pub fn entry(&self) -> PartitionEntry {
+        self.entry
+    }
+
+    /// Reference to the partitioned device
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// End the lifetime of this partition, giving back the partitioned device
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Index on the partitioned device of the `n` blocks starting at block `index` of this partition, or an error if they do not fit in the partition
+    fn rebase(&self, index: u64, n: u64) -> error_given::Result<u64> {
+        if index
+            .checked_add(n)
+            .is_none_or(|end| end > self.entry.nblocks)
+        {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the partition",
+            ));
+        }
+        Ok(self.entry.start + index)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        let mut block = self.inner.read_block(self.rebase(index, 1)?)?;
+        block.block_no = index;
+        Ok(block)
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        let inner_index = self.rebase(b.block_no, 1)?;
+        self.inner
+            .write_block(&Block::new(inner_index, b.contents_as_ref().into()))
+    }
+
+    fn read_blocks(&self, start: u64, n: u64) -> error_given::Result<Vec<Block>> {
+        let mut blocks = self.inner.read_blocks(self.rebase(start, n)?, n)?;
+        for (b, i) in blocks.iter_mut().zip(start..) {
+            b.block_no = i;
+        }
+        Ok(blocks)
+    }
+
+    fn borrow_blocks(&self, start: u64, n: u64) -> error_given::Result<Cow<'_, [u8]>> {
+        self.inner.borrow_blocks(self.rebase(start, n)?, n)
+    }
+
+    fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        let inner_start = self.rebase(start, n)?;
+        self.inner.discard(inner_start, n)
+    }
+
+    fn flush(&mut self) -> error_given::Result<()> {
+        self.inner.flush()
+    }
+
+    fn block_size(&self) -> u64 {
+        self.inner.block_size()
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.entry.nblocks
+    }
+}
+
+/// Decode the little-endian `u64` at offset `offset` of `data`
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Partition, PartitionEntry, PartitionTable};
+    use crate::controller::BlockDevice;
+    use crate::mem_device::MemDevice;
+    use crate::types::Block;
+
+    #[test]
+    fn table_test() {
+        let mut dev = MemDevice::new(100, 20);
+        assert!(PartitionTable::read(&dev).is_err());
+
+        let mut table = PartitionTable::new();
+        assert!(table.create(0, 5, 20).is_err());
+        assert!(table.create(15, 6, 20).is_err());
+        assert!(table.create(1, 0, 20).is_err());
+        assert_eq!(table.create(1, 9, 20).unwrap(), 0);
+        assert!(table.create(9, 2, 20).is_err());
+        assert_eq!(table.create(10, 10, 20).unwrap(), 1);
+        table.write(&mut dev).unwrap();
+
+        let mut table = PartitionTable::read(&dev).unwrap();
+        assert_eq!(
+            table.list(),
+            vec![
+                (
+                    0,
+                    PartitionEntry {
+                        start: 1,
+                        nblocks: 9
+                    }
+                ),
+                (
+                    1,
+                    PartitionEntry {
+                        start: 10,
+                        nblocks: 10
+                    }
+                )
+            ]
+        );
+        assert_eq!(table.delete(0).unwrap().nblocks, 9);
+        assert!(table.delete(0).is_err());
+        assert_eq!(table.create(1, 2, 20).unwrap(), 0);
+        assert!(PartitionTable::new()
+            .write(&mut MemDevice::new(50, 2))
+            .is_err());
+    }
+
+    #[test]
+    fn partition_test() {
+        let mut dev = MemDevice::new(10, 20);
+        let mut table = PartitionTable::new();
+        table.create(5, 4, 20).unwrap();
+        table.create(10, 10, 20).unwrap();
+        assert!(table.write(&mut dev).is_err());
+        let mut dev = MemDevice::new(100, 20);
+        table.write(&mut dev).unwrap();
+
+        //Blocks are rebased to the start of the partition
+        let mut part = Partition::open(dev, 0).unwrap();
+        assert_eq!(part.nblocks(), 4);
+        let bw = Block::new(1, vec![7; 100].into_boxed_slice());
+        part.write_block(&bw).unwrap();
+        assert!(part.write_block(&Block::new_zero(4, 100)).is_err());
+        assert_eq!(part.read_block(1).unwrap(), bw);
+        assert_eq!(part.read_blocks(0, 2).unwrap()[1], bw);
+        assert!(part.read_blocks(3, 2).is_err());
+        assert_eq!(&part.borrow_blocks(1, 1).unwrap()[..], bw.contents_as_ref());
+
+        let dev = part.into_inner();
+        assert_eq!(
+            dev.read_block(6).unwrap().contents_as_ref(),
+            bw.contents_as_ref()
+        );
+        assert!(Partition::open(dev, 2).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Ownership and permission bits of inodes, and the credentials they are checked against.
+//!
+//! Every inode has an owning user and group, and a `mode` made up of three classes of `rwx` bits, for its owner, its group and everyone else, like in POSIX.
+//! On top of those, the [`S_ISGID`] bit makes new entries of a directory inherit its group, and the [`S_ISVTX`] (sticky) bit only lets the owners of entries in a directory remove them.
+//! Operations that act on behalf of someone are given their [`Credentials`], and check the bits of the class those credentials fall in with [`Credentials::may`].
+//!
+//! [`S_ISGID`]: constant.S_ISGID.html
+//! [`S_ISVTX`]: constant.S_ISVTX.html
+//! [`Credentials`]: struct.Credentials.html
+//! [`Credentials::may`]: struct.Credentials.html#method.may
+
+use crate::types::FType;
+
+/// Set-group-ID bit: entries created in a directory with this bit get the group of the directory, and new directories inherit the bit
+pub const S_ISGID: u16 = 0o2000;
+/// Sticky bit: entries in a directory with this bit can only be removed by their owner, the owner of the directory, or the superuser
+pub const S_ISVTX: u16 = 0o1000;
+/// All bits of a `mode` that have a meaning; the others are always 0
+pub const MODE_MASK: u16 = 0o3777;
+
+/// Mode of directories created by a file system, like after `mkdir` with a umask of `022`
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+/// Mode of other inodes created by a file system
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+/// Mode of symlinks, whose own permission bits are never checked, like in Linux
+pub const DEFAULT_SYMLINK_MODE: u16 = 0o777;
+
+/// Mode that a file system gives to a newly allocated inode of type `ft`
+pub fn default_mode(ft: FType) -> u16 {
+    match ft {
+        FType::TDir => DEFAULT_DIR_MODE,
+        FType::TSymlink => DEFAULT_SYMLINK_MODE,
+        _ => DEFAULT_FILE_MODE,
+    }
+}
+
+/// Permission to read the contents of a file, or list the entries of a directory
+pub const MAY_READ: u16 = 0o4;
+/// Permission to change the contents of a file, or add and remove entries of a directory
+pub const MAY_WRITE: u16 = 0o2;
+/// Permission to search a directory, i.e. to look up its entries by name while resolving a path
+pub const MAY_EXEC: u16 = 0o1;
+
+/// User on whose behalf an operation is performed
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Credentials {
+    /// User id
+    pub uid: u32,
+    /// Primary group id, which new inodes get as their group
+    pub gid: u32,
+    /// Supplementary groups the user is a member of
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// Credentials of the superuser, who passes every permission check
+    pub fn root() -> Credentials {
+        Credentials::default()
+    }
+
+    /// Credentials of user `uid`, with primary group `gid` and no supplementary groups
+    pub fn new(uid: u32, gid: u32) -> Credentials {
+        Credentials {
+            uid,
+            gid,
+            groups: Vec::new(),
+        }
+    }
+
+    /// The same credentials, with the given supplementary groups
+    pub fn with_groups(mut self, groups: &[u32]) -> Credentials {
+        self.groups = groups.to_vec();
+        self
+    }
+
+    /// Are these the credentials of the superuser?
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// Is the user a member of group `gid`, either as its primary group or as a supplementary one?
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+
+    /// May the user perform `access`, a combination of `MAY_READ`, `MAY_WRITE` and `MAY_EXEC`, on an inode with the given `mode`, owned by `uid` and `gid`?
+    /// Only the bits of the first class the user falls in count: the owner does not get the permissions of the group, even if those are wider.
+    pub fn may(&self, mode: u16, uid: u32, gid: u32, access: u16) -> bool {
+        if self.is_root() {
+            return true;
+        }
+        let class = if self.uid == uid {
+            mode >> 6
+        } else if self.in_group(gid) {
+            mode >> 3
+        } else {
+            mode
+        };
+        class & access & 0o7 == access
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Credentials, MAY_EXEC, MAY_READ, MAY_WRITE};
+
+    #[test]
+    fn permission_classes() {
+        let owner = Credentials::new(1000, 100);
+        let member = Credentials::new(1001, 200).with_groups(&[100]);
+        let other = Credentials::new(1002, 200);
+
+        //rw-r-----: the owner reads and writes, the group only reads, and the rest cannot do anything
+        let mode = 0o640;
+        assert!(owner.may(mode, 1000, 100, MAY_READ | MAY_WRITE));
+        assert!(member.may(mode, 1000, 100, MAY_READ));
+        assert!(!member.may(mode, 1000, 100, MAY_WRITE));
+        assert!(!other.may(mode, 1000, 100, MAY_READ));
+        assert!(Credentials::root().may(0, 1000, 100, MAY_READ | MAY_WRITE | MAY_EXEC));
+
+        //---rwx---: the owner falls in its own class, even though the group may do more
+        assert!(!owner.may(0o070, 1000, 100, MAY_READ));
+        assert!(member.may(0o070, 1000, 100, MAY_READ | MAY_WRITE | MAY_EXEC));
+    }
+}
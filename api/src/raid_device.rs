@@ -0,0 +1,502 @@
+//! Implementation of composite block devices, that combine several member devices into a single one, in the style of RAID.
+//!
+//! * A [`MirrorDevice`] (RAID-1) stores every block on all of its members, and can keep serving reads and writes as long as a single member is healthy.
+//!   Members that failed, or that were replaced by a fresh device, are *stale*: they no longer take part in reads and writes, until [`MirrorDevice::resync`] has copied the contents of a healthy member onto them.
+//! * A [`StripeDevice`] (RAID-0) spreads consecutive block numbers over its members in a round-robin fashion, so block `i` is stored on member `i % n`, at block `i / n`.
+//!   It offers no redundancy whatsoever: losing a member loses every `n`-th block.
+//!
+//! Both devices implement [`BlockDevice`], so any file system runs on them unmodified.
+//!
+//! [`BlockDevice`]: ../controller/trait.BlockDevice.html
+//! [`MirrorDevice`]: struct.MirrorDevice.html
+//! [`MirrorDevice::resync`]: struct.MirrorDevice.html#method.resync
+//! [`StripeDevice`]: struct.StripeDevice.html
+
+use super::controller::{BlockDevice, Device};
+use super::error_given;
+use super::error_given::APIError;
+use super::io_stats::{IoOp, IoStats};
+use super::types::Block;
+use std::mem;
+
+/// Number of blocks that are copied at once while resyncing a mirror
+const RESYNC_BATCH: u64 = 64;
+
+/// Slot of a mirror that holds one of its members
+#[derive(Debug)]
+enum Member<D> {
+    /// A member holding the current contents of the mirror
+    Active(D),
+    /// A member that missed some writes, and has to be resynced before it can be used again
+    Stale(D),
+    /// A slot of which the member was removed
+    Missing,
+}
+
+/// Block device that mirrors all of its blocks on several member devices of the same size.
+#[derive(Debug)]
+pub struct MirrorDevice<D: BlockDevice = Device> {
+    /// Size of the blocks that this disk reads and writes
+    block_size: u64,
+    /// Total number of blocks this disk consists of
+    nblocks: u64,
+    /// The members of the mirror
+    members: Vec<Member<D>>,
+    /// Statistics of the reads and writes of this device
+    stats: IoStats,
+}
+
+impl<D: BlockDevice> MirrorDevice<D> {
+    /// Combine the given devices into a mirror.
+    /// The devices are assumed to hold the same contents already, e.g. because they are all new; call [`MirrorDevice::resync`](#method.resync) after replacing them otherwise.
+    /// Fails if no devices are given, or if they do not all have the same block size and number of blocks.
+    pub fn new(members: Vec<D>) -> error_given::Result<MirrorDevice<D>> {
+        let (block_size, nblocks) = match members.first() {
+            Some(m) => (m.block_size(), m.nblocks()),
+            None => return Err(APIError::ControllerInput("Mirror without any members")),
+        };
+        if members
+            .iter()
+            .any(|m| m.block_size() != block_size || m.nblocks() != nblocks)
+        {
+            return Err(APIError::ControllerInput("Mirror members differ in size"));
+        }
+        Ok(MirrorDevice {
+            block_size,
+            nblocks,
+            members: members.into_iter().map(Member::Active).collect(),
+            stats: IoStats::new(),
+        })
+    }
+
+    /// Number of slots of this mirror, including the ones of which the member was removed
+    pub fn n_members(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Number of members that hold the current contents of the mirror
+    pub fn n_healthy(&self) -> usize {
+        self.members
+            .iter()
+            .filter(|m| matches!(m, Member::Active(_)))
+            .count()
+    }
+
+    /// Check whether some member of the mirror is stale or missing
+    pub fn is_degraded(&self) -> bool {
+        self.n_healthy() < self.members.len()
+    }
+
+    /// Check whether the member in slot `i` holds the current contents of the mirror
+    pub fn is_healthy(&self, i: usize) -> bool {
+        matches!(self.members.get(i), Some(Member::Active(_)))
+    }
+
+    /// Reference to the member in slot `i`, whether it is healthy or not, if there is one
+    pub fn member(&self, i: usize) -> Option<&D> {
+        match self.members.get(i) {
+            Some(Member::Active(d)) | Some(Member::Stale(d)) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Take the member in slot `i` away from the mirror, leaving the slot empty.
+    /// Returns the member, or `None` if the slot was empty or does not exist.
+    pub fn remove_member(&mut self, i: usize) -> Option<D> {
+        match self
+            .members
+            .get_mut(i)
+            .map(|m| mem::replace(m, Member::Missing))
+        {
+            Some(Member::Active(d)) | Some(Member::Stale(d)) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Put the device `dev` in slot `i` of the mirror, as a stale member that still has to be resynced.
+    /// Returns the member that was in the slot before, if any.
+    /// Fails if the slot does not exist, or if `dev` does not have the size of the mirror.
+    pub fn replace_member(&mut self, i: usize, dev: D) -> error_given::Result<Option<D>> {
+        if i >= self.members.len() {
+            return Err(APIError::ControllerInput("Mirror slot does not exist"));
+        }
+        if dev.block_size() != self.block_size || dev.nblocks() != self.nblocks {
+            return Err(APIError::ControllerInput("Mirror members differ in size"));
+        }
+        let old = self.remove_member(i);
+        self.members[i] = Member::Stale(dev);
+        Ok(old)
+    }
+
+    /// Copy the contents of a healthy member onto all stale members, making them healthy again.
+    /// Fails if there is no healthy member left to copy from; a member that fails while being resynced stays stale.
+    pub fn resync(&mut self) -> error_given::Result<()> {
+        let source = self
+            .members
+            .iter()
+            .position(|m| matches!(m, Member::Active(_)))
+            .ok_or(APIError::ControllerInput(
+                "Mirror has no healthy member left",
+            ))?;
+
+        let mut result = Ok(());
+        for i in 0..self.members.len() {
+            if !matches!(self.members[i], Member::Stale(_)) {
+                continue;
+            }
+            match self.copy_member(source, i) {
+                Ok(()) => self.set_state(i, true),
+                Err(e) => result = Err(e),
+            }
+        }
+        result
+    }
+
+    /// End the lifetime of the mirror, giving back its members, with `None` for every empty slot
+    pub fn into_members(self) -> Vec<Option<D>> {
+        self.members
+            .into_iter()
+            .map(|m| match m {
+                Member::Active(d) | Member::Stale(d) => Some(d),
+                Member::Missing => None,
+            })
+            .collect()
+    }
+
+    /// Handle to the I/O statistics of this device, which can be queried and reset while a file system uses the device
+    pub fn stats(&self) -> IoStats {
+        self.stats.clone()
+    }
+
+    /// Copy all blocks of the member in slot `source` onto the member in slot `target`
+    fn copy_member(&mut self, source: usize, target: usize) -> error_given::Result<()> {
+        let mut start = 0;
+        while start < self.nblocks {
+            let n = RESYNC_BATCH.min(self.nblocks - start);
+            let blocks = match &self.members[source] {
+                Member::Active(d) => d.read_blocks(start, n)?,
+                _ => unreachable!("resync source is always healthy"),
+            };
+            match &mut self.members[target] {
+                Member::Stale(d) => d.write_blocks(&blocks)?,
+                _ => unreachable!("resync target is always stale"),
+            }
+            start += n;
+        }
+        match &mut self.members[target] {
+            Member::Stale(d) => d.flush(),
+            _ => unreachable!("resync target is always stale"),
+        }
+    }
+
+    /// Mark the member in slot `i` as healthy or stale
+    fn set_state(&mut self, i: usize, healthy: bool) {
+        self.members[i] = match mem::replace(&mut self.members[i], Member::Missing) {
+            Member::Active(d) | Member::Stale(d) if healthy => Member::Active(d),
+            Member::Active(d) | Member::Stale(d) => Member::Stale(d),
+            Member::Missing => Member::Missing,
+        };
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for MirrorDevice<D> {
+    /// Reads from the first healthy member, falling back to the other healthy members if that read fails
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        let mut result = Err(APIError::ControllerInput(
+            "Mirror has no healthy member left",
+        ));
+        for m in &self.members {
+            if let Member::Active(d) = m {
+                result = d.read_block(index);
+                if result.is_ok() {
+                    break;
+                }
+            }
+        }
+        let block = result?;
+        self.stats.record(IoOp::Read, index, self.block_size);
+        Ok(block)
+    }
+
+    /// Writes to all healthy members, marking the members of which the write fails as stale.
+    /// Only fails if the write did not succeed on any member.
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        if b.len() != self.block_size {
+            return Err(APIError::ControllerInput(
+                "Trying to write a non-block-sized block",
+            ));
+        }
+        if b.block_no >= self.nblocks {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+
+        let mut result = Err(APIError::ControllerInput(
+            "Mirror has no healthy member left",
+        ));
+        for i in 0..self.members.len() {
+            if let Member::Active(d) = &mut self.members[i] {
+                match d.write_block(b) {
+                    Ok(()) => result = Ok(()),
+                    Err(e) => {
+                        self.set_state(i, false);
+                        if result.is_err() {
+                            result = Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        result?;
+        self.stats.record(IoOp::Write, b.block_no, self.block_size);
+        Ok(())
+    }
+
+    /// Flushes all healthy members, marking the members of which the flush fails as stale
+    fn flush(&mut self) -> error_given::Result<()> {
+        for i in 0..self.members.len() {
+            if let Member::Active(d) = &mut self.members[i] {
+                if d.flush().is_err() {
+                    self.set_state(i, false);
+                }
+            }
+        }
+        if self.n_healthy() == 0 {
+            return Err(APIError::ControllerInput(
+                "Mirror has no healthy member left",
+            ));
+        }
+        Ok(())
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.nblocks
+    }
+}
+
+/// Block device that stripes its blocks over several member devices with the same block size.
+#[derive(Debug)]
+pub struct StripeDevice<D: BlockDevice = Device> {
+    /// Size of the blocks that this disk reads and writes
+    block_size: u64,
+    /// Total number of blocks this disk consists of
+    nblocks: u64,
+    /// The members of the stripe
+    members: Vec<D>,
+    /// Statistics of the reads and writes of this device
+    stats: IoStats,
+}
+
+impl<D: BlockDevice> StripeDevice<D> {
+    /// Combine the given devices into a stripe.
+    /// The stripe uses the same number of blocks on every member, so if the members differ in size, the surplus blocks of the larger ones are left unused.
+    /// Fails if no devices are given, or if they do not all have the same block size.
+    pub fn new(members: Vec<D>) -> error_given::Result<StripeDevice<D>> {
+        let block_size = match members.first() {
+            Some(m) => m.block_size(),
+            None => return Err(APIError::ControllerInput("Stripe without any members")),
+        };
+        if members.iter().any(|m| m.block_size() != block_size) {
+            return Err(APIError::ControllerInput(
+                "Stripe members differ in block size",
+            ));
+        }
+        let per_member = members.iter().map(|m| m.nblocks()).min().unwrap_or(0);
+        Ok(StripeDevice {
+            block_size,
+            nblocks: per_member * members.len() as u64,
+            members,
+            stats: IoStats::new(),
+        })
+    }
+
+    /// The members of this stripe, in order
+    pub fn members(&self) -> &[D] {
+        &self.members
+    }
+
+    /// End the lifetime of the stripe, giving back its members in order
+    pub fn into_members(self) -> Vec<D> {
+        self.members
+    }
+
+    /// Handle to the I/O statistics of this device, which can be queried and reset while a file system uses the device
+    pub fn stats(&self) -> IoStats {
+        self.stats.clone()
+    }
+
+    /// Member on which the block with index `index` is stored, and the index of the block on that member
+    fn locate(&self, index: u64) -> error_given::Result<(usize, u64)> {
+        if index >= self.nblocks {
+            return Err(APIError::ControllerInput(
+                "Block index past the end of the device",
+            ));
+        }
+        let n = self.members.len() as u64;
+        Ok(((index % n) as usize, index / n))
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for StripeDevice<D> {
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        let (member, inner_index) = self.locate(index)?;
+        let mut block = self.members[member].read_block(inner_index)?;
+        block.block_no = index;
+        self.stats.record(IoOp::Read, index, self.block_size);
+        Ok(block)
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        let (member, inner_index) = self.locate(b.block_no)?;
+        self.members[member].write_block(&Block::new(inner_index, b.contents_as_ref().into()))?;
+        self.stats.record(IoOp::Write, b.block_no, self.block_size);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> error_given::Result<()> {
+        for m in &mut self.members {
+            m.flush()?;
+        }
+        Ok(())
+    }
+
+    fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.nblocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MirrorDevice, StripeDevice};
+    use crate::controller::BlockDevice;
+    use crate::fault_device::FaultDevice;
+    use crate::mem_device::MemDevice;
+    use crate::types::Block;
+
+    fn filled(i: u64) -> Block {
+        Block::new(i, vec![i as u8 + 1; 10].into_boxed_slice())
+    }
+
+    #[test]
+    fn mirror_test() {
+        assert!(MirrorDevice::<MemDevice>::new(vec![]).is_err());
+        assert!(MirrorDevice::new(vec![MemDevice::new(10, 10), MemDevice::new(10, 11)]).is_err());
+
+        let mut dev =
+            MirrorDevice::new(vec![MemDevice::new(10, 10), MemDevice::new(10, 10)]).unwrap();
+        dev.write_block(&filled(1)).unwrap();
+        assert_eq!(dev.member(0).unwrap().read_block(1).unwrap(), filled(1));
+        assert_eq!(dev.member(1).unwrap().read_block(1).unwrap(), filled(1));
+
+        //Taking a member away leaves a degraded, but working mirror
+        let removed = dev.remove_member(0).unwrap();
+        assert!(dev.is_degraded());
+        assert_eq!(dev.read_block(1).unwrap(), filled(1));
+        dev.write_block(&filled(2)).unwrap();
+        assert_eq!(removed.read_block(2).unwrap(), Block::new_zero(2, 10));
+
+        //A member that is put back only takes part after resyncing, which brings it up to date with the writes it missed
+        assert!(dev.replace_member(0, MemDevice::new(10, 9)).is_err());
+        assert!(dev.replace_member(0, removed).unwrap().is_none());
+        assert!(!dev.is_healthy(0));
+        dev.resync().unwrap();
+        assert!(!dev.is_degraded());
+        assert_eq!(dev.member(0).unwrap().read_block(2).unwrap(), filled(2));
+        let members = dev.into_members();
+        let first = members[0].as_ref().unwrap();
+        for i in 0..10 {
+            assert_eq!(
+                first.read_block(i).unwrap(),
+                members[1].as_ref().unwrap().read_block(i).unwrap()
+            );
+        }
+
+        //Without any healthy member, nothing can be read, written or resynced anymore
+        let mut dev = MirrorDevice::new(vec![MemDevice::new(10, 10)]).unwrap();
+        dev.remove_member(0);
+        assert!(dev.read_block(1).is_err());
+        assert!(dev.write_block(&filled(1)).is_err());
+        assert!(dev.resync().is_err());
+    }
+
+    #[test]
+    fn mirror_failing_member_test() {
+        let mut failing = FaultDevice::new(MemDevice::new(10, 10));
+        failing.fail_block(3);
+        let mut dev =
+            MirrorDevice::new(vec![failing, FaultDevice::new(MemDevice::new(10, 10))]).unwrap();
+
+        //A member of which a write fails becomes stale, but the write still succeeds on the other member
+        dev.write_block(&filled(2)).unwrap();
+        dev.write_block(&filled(3)).unwrap();
+        assert!(!dev.is_healthy(0));
+        assert!(dev.is_healthy(1));
+        assert_eq!(dev.read_block(3).unwrap(), filled(3));
+
+        //Resyncing the failing member keeps failing, until it is repaired
+        assert!(dev.resync().is_err());
+        assert!(!dev.is_healthy(0));
+        let mut repaired = dev.remove_member(0).unwrap();
+        repaired.clear_faults();
+        dev.replace_member(0, repaired).unwrap();
+        dev.resync().unwrap();
+        assert_eq!(dev.member(0).unwrap().read_block(3).unwrap(), filled(3));
+    }
+
+    #[test]
+    fn stripe_test() {
+        assert!(StripeDevice::new(vec![MemDevice::new(10, 4), MemDevice::new(11, 4)]).is_err());
+        let mut dev = StripeDevice::new(vec![
+            MemDevice::new(10, 4),
+            MemDevice::new(10, 4),
+            MemDevice::new(10, 5),
+        ])
+        .unwrap();
+        assert_eq!(dev.nblocks(), 12);
+
+        for i in 0..12 {
+            dev.write_block(&filled(i)).unwrap();
+        }
+        assert!(dev.write_block(&filled(12)).is_err());
+        assert_eq!(dev.read_block(7).unwrap(), filled(7));
+
+        //Consecutive blocks end up on consecutive members
+        let members = dev.into_members();
+        assert_eq!(
+            members[1].read_block(2).unwrap().contents_as_ref(),
+            filled(7).contents_as_ref()
+        );
+        assert_eq!(
+            members[2].read_block(0).unwrap().contents_as_ref(),
+            filled(2).contents_as_ref()
+        );
+        assert_eq!(members[2].read_block(4).unwrap(), Block::new_zero(4, 10));
+    }
+
+    #[test]
+    fn stripe_discard_test() {
+        let mut dev =
+            StripeDevice::new(vec![MemDevice::new(10, 50), MemDevice::new(10, 50)]).unwrap();
+        for i in 0..100 {
+            dev.write_block(&filled(i)).unwrap();
+        }
+
+        //The default discard zeroes ranges longer than a single batch, and checks the range before touching anything
+        dev.discard(10, 80).unwrap();
+        assert_eq!(dev.read_block(9).unwrap(), filled(9));
+        assert!((10..90).all(|i| dev.read_block(i).unwrap() == Block::new_zero(i, 10)));
+        assert_eq!(dev.read_block(90).unwrap(), filled(90));
+        assert!(dev.discard(95, 10).is_err());
+        assert!(dev.discard(u64::MAX, 2).is_err());
+        assert_eq!(dev.read_block(95).unwrap(), filled(95));
+    }
+}
@@ -0,0 +1,410 @@
+//! Implementation of a block device that wraps another device and models the time its accesses would take on real hardware, to compare file system policies by their modeled cost rather than by wall-clock time.
+//!
+//! A [`SimDevice`] forwards all reads and writes to the device it wraps, and advances a virtual clock by the cost of every successful access, according to a [`CostModel`]:
+//! * An [`HddModel`] lays the blocks out on consecutive tracks of a spinning disk. Accessing a block right after the previously accessed one is a pure transfer; any other access first moves the head to the right track (a *seek*) and then waits for the block to rotate under it.
+//!   The rotation during a seek is ignored, which keeps the model deterministic.
+//! * An [`SsdModel`] groups the blocks into erase blocks. Blocks (pages) that have been written since their erase block was last erased cannot be written again before the entire erase block is erased,
+//!   which also requires copying all other valid pages of that erase block. Discarded pages no longer count as valid, so they are not copied.
+//!   The number of pages that were physically written per logical write is the *write amplification*.
+//!
+//! The totals are kept in a [`SimClock`], which is a shared handle, just like [`IoStats`](../io_stats/struct.IoStats.html): a test can hold on to it, hand the device over to a file system, and report the modeled cost of e.g. `mkfs`, file writes or directory scans while the file system is still mounted.
+//! All times are in nanoseconds.
+//!
+//! [`SimDevice`]: struct.SimDevice.html
+//! [`CostModel`]: enum.CostModel.html
+//! [`HddModel`]: struct.HddModel.html
+//! [`SsdModel`]: struct.SsdModel.html
+//! [`SimClock`]: struct.SimClock.html
+
+use super::controller::{BlockDevice, Device};
+use super::error_given;
+use super::types::Block;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Cost parameters of a spinning hard disk
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct HddModel {
+    /// Number of blocks on a single track
+    pub blocks_per_track: u64,
+    /// Fixed cost of moving the head to another track, regardless of the distance
+    pub seek_settle_ns: u64,
+    /// Additional cost of moving the head, per track it crosses
+    pub seek_ns_per_track: u64,
+    /// Time of a full rotation of the platter
+    pub rotation_ns: u64,
+    /// Time to transfer a single block once it is under the head
+    pub transfer_ns: u64,
+}
+
+impl Default for HddModel {
+    /// A 7200 rpm desktop disk with 1000 blocks per track, where transferring a block takes as long as it takes to pass under the head
+    fn default() -> HddModel {
+        HddModel {
+            blocks_per_track: 1000,
+            seek_settle_ns: 1_000_000,
+            seek_ns_per_track: 10,
+            rotation_ns: 8_333_333,
+            transfer_ns: 8_333,
+        }
+    }
+}
+
+/// Cost parameters of a flash disk without remapping, where pages are rewritten in place
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct SsdModel {
+    /// Number of blocks (pages) in a single erase block
+    pub blocks_per_erase_block: u64,
+    /// Time to read a single page
+    pub read_ns: u64,
+    /// Time to program a single page
+    pub write_ns: u64,
+    /// Time to erase an entire erase block
+    pub erase_ns: u64,
+}
+
+impl Default for SsdModel {
+    /// A typical NAND flash disk with erase blocks of 64 pages
+    fn default() -> SsdModel {
+        SsdModel {
+            blocks_per_erase_block: 64,
+            read_ns: 25_000,
+            write_ns: 200_000,
+            erase_ns: 1_500_000,
+        }
+    }
+}
+
+/// Kind of hardware a `SimDevice` models, with its cost parameters
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum CostModel {
+    /// A spinning hard disk
+    Hdd(HddModel),
+    /// A flash disk
+    Ssd(SsdModel),
+}
+
+/// Modeled cost of all accesses to a `SimDevice` so far
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct SimTotals {
+    /// Value of the virtual clock, i.e. the total modeled time of all accesses
+    pub elapsed_ns: u64,
+    /// Number of block reads
+    pub reads: u64,
+    /// Number of block writes
+    pub writes: u64,
+    /// HDD: number of accesses that had to move the head to another track
+    pub seeks: u64,
+    /// HDD: time spent moving the head
+    pub seek_ns: u64,
+    /// HDD: time spent waiting for blocks to rotate under the head
+    pub rotation_ns: u64,
+    /// Time spent transferring blocks; for an SSD, the time spent reading and programming pages
+    pub transfer_ns: u64,
+    /// SSD: number of erase blocks that were erased
+    pub erases: u64,
+    /// Number of blocks physically written, including the pages an SSD copies when erasing
+    pub pages_written: u64,
+}
+
+impl SimTotals {
+    /// Number of blocks physically written per block written to the device, or 0 if nothing was written yet
+    pub fn write_amplification(&self) -> f64 {
+        if self.writes == 0 {
+            return 0.0;
+        }
+        self.pages_written as f64 / self.writes as f64
+    }
+}
+
+/// Shared handle to the virtual clock of a `SimDevice`, and the totals behind it.
+/// Clones of this handle refer to the same clock.
+#[derive(Debug, Default, Clone)]
+pub struct SimClock(Arc<Mutex<SimTotals>>);
+
+impl SimClock {
+    /// Lock the totals; a panic while holding the lock cannot leave them in an inconsistent state, so poisoning is ignored
+    fn data(&self) -> MutexGuard<'_, SimTotals> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Current value of the virtual clock
+    pub fn elapsed_ns(&self) -> u64 {
+        self.data().elapsed_ns
+    }
+
+    /// Copy of the totals so far
+    pub fn totals(&self) -> SimTotals {
+        *self.data()
+    }
+
+    /// Set the clock and all totals back to zero, e.g. to only measure the operations that follow.
+    /// The state of the modeled hardware, such as the position of the head, is kept.
+    pub fn reset(&self) {
+        *self.data() = SimTotals::default();
+    }
+}
+
+/// Block device that wraps another device, and models the cost of every access to it on a virtual clock.
+#[derive(Debug)]
+pub struct SimDevice<D: BlockDevice = Device> {
+    /// The wrapped device
+    inner: D,
+    /// The modeled hardware
+    model: CostModel,
+    /// HDD: the block that is under the head, i.e. the block right after the last one accessed
+    head: Cell<u64>,
+    /// SSD: pages that were written since their erase block was last erased
+    programmed: HashSet<u64>,
+    /// SSD: programmed pages that have not been discarded since, and hence have to be copied when their erase block is erased
+    valid: HashSet<u64>,
+    /// The virtual clock
+    clock: SimClock,
+}
+
+impl<D: BlockDevice> SimDevice<D> {
+    /// Wrap the device `inner`, modeling it as the hardware described by `model`.
+    /// The head of an HDD starts at block 0, and all pages of an SSD start out erased.
+    pub fn new(inner: D, model: CostModel) -> SimDevice<D> {
+        SimDevice {
+            inner,
+            model,
+            head: Cell::new(0),
+            programmed: HashSet::new(),
+            valid: HashSet::new(),
+            clock: SimClock::default(),
+        }
+    }
+
+    /// Handle to the virtual clock of this device, which can be queried and reset while a file system uses the device
+    pub fn clock(&self) -> SimClock {
+        self.clock.clone()
+    }
+
+    /// The modeled hardware
+    pub fn model(&self) -> CostModel {
+        self.model
+    }
+
+    /// Reference to the wrapped device
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// End the lifetime of this device, giving back the wrapped device
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Advance the clock by the cost of moving the head of an HDD to block `index` and transferring it
+    fn charge_hdd(&self, hdd: &HddModel, totals: &mut SimTotals, index: u64) {
+        let head = self.head.get();
+        if index != head {
+            let (track, head_track) = (index / hdd.blocks_per_track, head / hdd.blocks_per_track);
+            if track != head_track {
+                let seek = hdd.seek_settle_ns + hdd.seek_ns_per_track * track.abs_diff(head_track);
+                totals.seeks += 1;
+                totals.seek_ns += seek;
+                totals.elapsed_ns += seek;
+            }
+            let (sector, head_sector) = (index % hdd.blocks_per_track, head % hdd.blocks_per_track);
+            let distance = (sector + hdd.blocks_per_track - head_sector) % hdd.blocks_per_track;
+            let rotation = hdd.rotation_ns * distance / hdd.blocks_per_track;
+            totals.rotation_ns += rotation;
+            totals.elapsed_ns += rotation;
+        }
+        totals.transfer_ns += hdd.transfer_ns;
+        totals.elapsed_ns += hdd.transfer_ns;
+        self.head.set(index + 1);
+    }
+
+    /// Advance the clock by the cost of reading block `index`
+    fn charge_read(&self, index: u64) {
+        let mut totals = self.clock.data();
+        totals.reads += 1;
+        match &self.model {
+            CostModel::Hdd(hdd) => self.charge_hdd(hdd, &mut totals, index),
+            CostModel::Ssd(ssd) => {
+                totals.transfer_ns += ssd.read_ns;
+                totals.elapsed_ns += ssd.read_ns;
+            }
+        }
+    }
+
+    /// Advance the clock by the cost of writing block `index`, erasing its erase block first if the page was already programmed
+    fn charge_write(&mut self, index: u64) {
+        let clock = self.clock.clone();
+        let mut totals = clock.data();
+        totals.writes += 1;
+        let ssd = match self.model {
+            CostModel::Hdd(hdd) => {
+                totals.pages_written += 1;
+                return self.charge_hdd(&hdd, &mut totals, index);
+            }
+            CostModel::Ssd(ssd) => ssd,
+        };
+
+        if self.programmed.contains(&index) {
+            //the valid pages are read and programmed again after the erase; the page being written is replaced anyway
+            let first = index - index % ssd.blocks_per_erase_block;
+            let mut copied = 0;
+            for page in first..first + ssd.blocks_per_erase_block {
+                self.programmed.remove(&page);
+                if page != index && self.valid.contains(&page) {
+                    self.programmed.insert(page);
+                    copied += 1;
+                }
+            }
+            totals.erases += 1;
+            totals.pages_written += copied;
+            let cost = ssd.erase_ns + copied * (ssd.read_ns + ssd.write_ns);
+            totals.transfer_ns += copied * (ssd.read_ns + ssd.write_ns);
+            totals.elapsed_ns += cost;
+        }
+        self.programmed.insert(index);
+        self.valid.insert(index);
+        totals.pages_written += 1;
+        totals.transfer_ns += ssd.write_ns;
+        totals.elapsed_ns += ssd.write_ns;
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for SimDevice<D> {
+    fn read_block(&self, index: u64) -> error_given::Result<Block> {
+        let block = self.inner.read_block(index)?;
+        self.charge_read(index);
+        Ok(block)
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        self.inner.write_block(b)?;
+        self.charge_write(b.block_no);
+        Ok(())
+    }
+
+    fn read_blocks(&self, start: u64, n: u64) -> error_given::Result<Vec<Block>> {
+        let blocks = self.inner.read_blocks(start, n)?;
+        for i in start..start + n {
+            self.charge_read(i);
+        }
+        Ok(blocks)
+    }
+
+    fn write_blocks(&mut self, blocks: &[Block]) -> error_given::Result<()> {
+        self.inner.write_blocks(blocks)?;
+        for b in blocks {
+            self.charge_write(b.block_no);
+        }
+        Ok(())
+    }
+
+    /// Discarding is free; on an SSD, the discarded pages no longer have to be copied when their erase block is erased
+    fn discard(&mut self, start: u64, n: u64) -> error_given::Result<()> {
+        self.inner.discard(start, n)?;
+        for i in start..start + n {
+            self.valid.remove(&i);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> error_given::Result<()> {
+        self.inner.flush()
+    }
+
+    fn block_size(&self) -> u64 {
+        self.inner.block_size()
+    }
+
+    fn nblocks(&self) -> u64 {
+        self.inner.nblocks()
+    }
+
+    fn resize(&mut self, nblocks: u64) -> error_given::Result<()> {
+        self.inner.resize(nblocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CostModel, HddModel, SimDevice, SsdModel};
+    use crate::controller::BlockDevice;
+    use crate::mem_device::MemDevice;
+    use crate::types::Block;
+
+    static HDD: HddModel = HddModel {
+        blocks_per_track: 10,
+        seek_settle_ns: 1000,
+        seek_ns_per_track: 100,
+        rotation_ns: 500,
+        transfer_ns: 50,
+    };
+
+    static SSD: SsdModel = SsdModel {
+        blocks_per_erase_block: 4,
+        read_ns: 10,
+        write_ns: 100,
+        erase_ns: 1000,
+    };
+
+    #[test]
+    fn hdd_test() {
+        let dev = SimDevice::new(MemDevice::new(10, 100), CostModel::Hdd(HDD));
+        let clock = dev.clock();
+
+        //Sequential reads only cost their transfer
+        dev.read_blocks(0, 5).unwrap();
+        assert_eq!(clock.elapsed_ns(), 5 * 50);
+
+        //Skipping 2 blocks on the same track costs their rotation
+        clock.reset();
+        dev.read_block(7).unwrap();
+        assert_eq!(clock.totals().rotation_ns, 2 * 50);
+        assert_eq!(clock.elapsed_ns(), 2 * 50 + 50);
+
+        //Going back to block 3 of track 4 seeks across 4 tracks, and waits for 5 blocks to pass
+        clock.reset();
+        dev.read_block(43).unwrap();
+        let totals = clock.totals();
+        assert_eq!(totals.seeks, 1);
+        assert_eq!(totals.seek_ns, 1000 + 4 * 100);
+        assert_eq!(totals.rotation_ns, 5 * 50);
+        assert_eq!(totals.elapsed_ns, 1000 + 4 * 100 + 5 * 50 + 50);
+
+        //Failed accesses cost nothing
+        clock.reset();
+        assert!(dev.read_block(100).is_err());
+        assert_eq!(clock.totals(), Default::default());
+    }
+
+    #[test]
+    fn ssd_test() {
+        let mut dev = SimDevice::new(MemDevice::new(10, 16), CostModel::Ssd(SSD));
+        let clock = dev.clock();
+
+        //Writing erased pages only costs programming them
+        let blocks: Vec<Block> = (0..4).map(|i| Block::new_zero(i, 10)).collect();
+        dev.write_blocks(&blocks).unwrap();
+        assert_eq!(clock.elapsed_ns(), 4 * 100);
+        assert_eq!(clock.totals().write_amplification(), 1.0);
+
+        //Rewriting a page erases its erase block, and copies the 3 other valid pages
+        clock.reset();
+        dev.write_block(&blocks[1]).unwrap();
+        let totals = clock.totals();
+        assert_eq!(totals.erases, 1);
+        assert_eq!(totals.pages_written, 4);
+        assert_eq!(totals.write_amplification(), 4.0);
+        assert_eq!(totals.elapsed_ns, 1000 + 3 * (10 + 100) + 100);
+
+        //Discarded pages are not copied
+        clock.reset();
+        dev.discard(2, 2).unwrap();
+        dev.write_block(&blocks[1]).unwrap();
+        let totals = clock.totals();
+        assert_eq!(totals.pages_written, 2);
+        assert_eq!(totals.elapsed_ns, 1000 + (10 + 100) + 100);
+        dev.read_block(1).unwrap();
+        assert_eq!(clock.elapsed_ns(), totals.elapsed_ns + 10);
+    }
+}
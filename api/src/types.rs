@@ -1,16 +1,21 @@
 //! Module containing the types used in this project.
 //! You can define your own wrappers around these types if you need more than the provided functionality.
 
+use super::encoding::{Decoder, Encoder, OnDisk, LAYOUT_VERSION};
 use super::error_given;
 use super::error_given::APIError;
-use lazy_static::lazy_static;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use super::perm::Credentials;
+use super::xattr::XATTR_INLINE_SIZE;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::io::prelude::*;
 use std::io::{Cursor, SeekFrom};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Buffer abstraction, representing some data on the heap.
 /// Buffers can have any size, and will be used further on to build file system `Block`s with, but also as output to read and write functions on files
-/// Support regular read and write methods, but also (de)serialization of structures implementing the appropriate traits
+/// Support regular read and write methods, but also (de)serialization of structures with an on-disk encoding, i.e. implementing [`OnDisk`](../encoding/trait.OnDisk.html)
 #[derive(Debug, PartialEq, Eq)]
 pub struct Buffer {
     ///Contents of the buffer, represented as a boxed slice
@@ -70,30 +75,66 @@ impl Buffer {
         c.write_all(data).map_err(|e| e.into())
     }
 
-    /// Read any object that implements the DeserializeOwned trait from this buffer
+    /// Read any object that implements the `OnDisk` trait from this buffer, starting at the given `offset`
     ///
     /// *EXTRA*: Note that since this method takes ownership of the deserialized data, the link with the original data in the block necessarily breaks.
     /// This is not what you would have in a high-performance C implementation, as you would simply perform a cast of the part of memory you are interested in to a struct, without having to worry about lifetimes.
     /// To keep things simple and not have additional lifetime dependencies and unsafe code here, this method was not implemented as such.
     pub fn deserialize_from<S>(&self, offset: u64) -> error_given::Result<S>
     where
-        S: DeserializeOwned,
+        S: OnDisk,
     {
-        let mut c = Cursor::new(&self.contents);
-        c.seek(SeekFrom::Start(offset))?;
-        Ok((bincode::deserialize_from(c))?)
+        if offset + S::SIZE > self.len() {
+            return Err(APIError::BlockInput(
+                "Trying to read beyond the bounds of the block",
+            ));
+        }
+        let start = offset as usize;
+        Decoder::new(&self.contents[start..start + S::SIZE as usize]).get()
     }
 
-    /// Write any object that implements the Serialize trait into this buffer
-    /// Goes through `write_data` so that the appropriate error get triggered.
-    /// Alternatively, we could go through `serialize_into` [`bincode`](https://docs.rs/bincode/1.3.1/bincode/index.html) and use the standard error.
+    /// Write any object that implements the `OnDisk` trait into this buffer, starting at the given `offset`
+    /// Fails without changing the buffer if the encoded object does not fit.
     pub fn serialize_into<S>(&mut self, stru: &S, offset: u64) -> error_given::Result<()>
     where
-        S: Serialize,
+        S: OnDisk,
     {
-        let stru_bin = bincode::serialize(stru)?;
-        //Going through write data so that the appropriate errors get triggered
-        self.write_data(&stru_bin, offset)
+        if offset + S::SIZE > self.len() {
+            return Err(APIError::BlockInput(
+                "Trying to write beyond the bounds of the block",
+            ));
+        }
+        let start = offset as usize;
+        Encoder::new(&mut self.contents[start..start + S::SIZE as usize]).put(stru);
+        Ok(())
+    }
+
+    /// Read the directory record starting at the given `offset`, returning the entry it holds and the number of bytes the record takes up
+    pub fn read_dir_entry(&self, offset: u64) -> error_given::Result<(DirEntry, u64)> {
+        if offset > self.len() {
+            return Err(APIError::BlockInput(
+                "Trying to read beyond the bounds of the block",
+            ));
+        }
+        DirEntry::decode(&mut Decoder::new(&self.contents[offset as usize..]))
+    }
+
+    /// Write the record holding the directory entry `de` into this buffer, starting at the given `offset`
+    /// Fails without changing the buffer if the record does not fit, or if the name of the entry is too long.
+    pub fn write_dir_entry(&mut self, de: &DirEntry, offset: u64) -> error_given::Result<()> {
+        if de.name.len() > DIRNAME_SIZE {
+            return Err(APIError::APISerialize("Directory entry name is too long"));
+        }
+        if offset + de.rec_len() > self.len() {
+            return Err(APIError::BlockInput(
+                "Trying to write beyond the bounds of the block",
+            ));
+        }
+        let start = offset as usize;
+        de.encode(&mut Encoder::new(
+            &mut self.contents[start..start + de.rec_len() as usize],
+        ));
+        Ok(())
     }
 }
 
@@ -152,46 +193,58 @@ impl Block {
         self.buf.write_data(data, offset)
     }
 
-    /// Read any object that implements the DeserializeOwned trait from this block
+    /// Read any object that implements the `OnDisk` trait from this block
     ///
     /// *EXTRA*: Note that since this method takes ownership of the deserialized data, the link with the original data in the block necessarily breaks.
     /// This is not what you would have in a high-performance C implementation, as you would simply perform a cast of the part of memory you are interested in to a struct, without having to worry about lifetimes.
     /// To keep things simple and not have additional lifetime dependencies here, this method was not implemented as such.
     pub fn deserialize_from<S>(&self, offset: u64) -> error_given::Result<S>
     where
-        S: DeserializeOwned,
+        S: OnDisk,
     {
         self.buf.deserialize_from(offset)
     }
 
-    /// Write any object that implements the Serialize trait into this block
-    /// Goes through `write_data` so that the appropriate errors get triggered, because using `serialize_into` from [`bincode`](https://docs.rs/bincode/1.3.1/bincode/index.html) would risk extending the underlying vector instead of throwing an error.
+    /// Write any object that implements the `OnDisk` trait into this block
+    /// Fails without changing the block if the encoded object does not fit.
     pub fn serialize_into<S>(&mut self, stru: &S, offset: u64) -> error_given::Result<()>
     where
-        S: Serialize,
+        S: OnDisk,
     {
         self.buf.serialize_into(stru, offset)
     }
+
+    /// Read the directory record starting at the given `offset` from this block
+    pub fn read_dir_entry(&self, offset: u64) -> error_given::Result<(DirEntry, u64)> {
+        self.buf.read_dir_entry(offset)
+    }
+
+    /// Write the record holding the directory entry `de` into this block
+    /// Fails without changing the block if the record does not fit, or if the name of the entry is too long.
+    pub fn write_dir_entry(&mut self, de: &DirEntry, offset: u64) -> error_given::Result<()> {
+        self.buf.write_dir_entry(de, offset)
+    }
 }
 
 /// Structure representing all file system metadata that we are interested in, and hence the file system's structure.
 /// Note that the size of the Superblock struct does not necessarily have to be a full block, as it can just be read from disk contiguously.
 /// Rather, the size of `SuperBlock` must be at most as large as a single disk block.
-/// Derives `Serialize` and `Deserialize` so we can easily write this block to the disk and read it again after.
+/// Implements `OnDisk`, so we can easily write this block to the disk and read it again after, in the layout described in [`encoding`](../encoding/index.html).
 ///
 /// The layout of the simple file system model we use is as follows:
 ///     \[super block | inode blocks | free bit map | data blocks\]
 /// , where each component has the following meaning:
 ///
-/// 1. *super block*: aggregates all the file system meta-data including the sizes of all subsequent regions. This is the first block that is read by the file system driver when loading an existing file system, and the first block to be written by the driver in case a new file system is initialized. This area should consist of a single block, i.e. the `SuperBlock` type defined below should not take up more space in memory than a single block, defined by `Disk.block_size` in [`controller.rs`](../controller/index.html).
+/// 1. *super block*: aggregates all the file system meta-data including the sizes of all subsequent regions, followed by a [`VolumeHeader`](struct.VolumeHeader.html) that identifies the file system. This is the first block that is read by the file system driver when loading an existing file system, and the first block to be written by the driver in case a new file system is initialized. This area should consist of a single block, i.e. the `SuperBlock` type defined below should not take up more space in memory than a single block, defined by `Disk.block_size` in [`controller.rs`](../controller/index.html).
 /// 2. *inode blocks*: a sequence of blocks containing all the inode metadata. This region contains all inodes in order, starting from inode 1 (the root directory, i.e. the directory on your computer with path "\"), all the way up to the last inode. The number of inodes stored in each block is equal to the floor of the block size divided by the inode size, i.e. blocks are packed with inodes, and individual inodes are always entirely stored in a single block (they are never broken up over multiple blocks).
 /// 3. *free bit map*: a sequence of blocks keeping track of the allocation state (allocated or free) of all disk blocks in the next data block region. The *n*th bit in this sequence specifies whether or not the *n*th data block is currently in use.
 /// 4. *data blocks*: contain the actual file and directory data, as a long sequence of disk blocks.
 ///
 /// *EXTRA*: Since we do not support logging, there is no need for an additional memory region to store any logs in
 /// Also note that in contrast to more realistic device layouts, we ignore the fact that the first block of the device is often reserved for bootstrapping code, and makes use of e.g. a Master Boot Record (MBR) or Volume Boot Record (VBR).
+/// A device can however be split into several partitions with a partition table in its first block, using the [`partition`](../partition/index.html) module; every partition then holds a file system of its own, laid out as described here, starting from block 0 of the partition.
 /// *EXTRA*: Note that just like blocks, inodes are not being cached either. The consequence is that the users of our APIs are responsible for ensuring that they aren't handling different aliases to the same inode without realizing it. This will not scale well to a parallellized setting. In our case, this is no major problem, as we have no parallellism, and we have simple system call interactions, that will not handle a lot of inodes at the same time, and will hence not need to perform many of those inode equality checks.
-#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct SuperBlock {
     ///Size of the blocks in the current file system, in *BYTES*\
     ///In a real world application, this block size does not necessarily match the size of the sectors on the device itself, but for simplicity reasons we assume this value and the disk sector size in `Device.block_size` to always be equal
@@ -220,21 +273,181 @@ pub struct SuperBlock {
     pub datastart: u64,
 }
 
-lazy_static! {
-    /// Size the superblock takes up in memory on your system, in bytes.
-    /// This size can only be found out at runtime, which is the reason why we have to wrap this code in a `lazy_static` macro.
-    /// Notice the use of the `ref` keyword; `SUPERBLOCK_SIZE` is a reference to an `u64` number, that will only be filled in at runtime.
-    /// Used to determine the number of inodes per block, which is important for filesystem initialization.
-    pub static ref SUPERBLOCK_SIZE : u64 = bincode::serialize(&SuperBlock::default()).unwrap().len() as u64;
+impl OnDisk for SuperBlock {
+    const SIZE: u64 = 7 * u64::SIZE;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.put(&self.block_size);
+        enc.put(&self.nblocks);
+        enc.put(&self.ninodes);
+        enc.put(&self.inodestart);
+        enc.put(&self.ndatablocks);
+        enc.put(&self.bmapstart);
+        enc.put(&self.datastart);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+        Ok(SuperBlock {
+            block_size: dec.get()?,
+            nblocks: dec.get()?,
+            ninodes: dec.get()?,
+            inodestart: dec.get()?,
+            ndatablocks: dec.get()?,
+            bmapstart: dec.get()?,
+            datastart: dec.get()?,
+        })
+    }
+}
+
+/// Size the superblock takes up on disk, in bytes.
+/// This size is fixed by the on-disk layout; it is a reference to a number, so that code written when this size was computed at runtime with a `lazy_static`, and hence dereferences it, keeps working.
+pub const SUPERBLOCK_SIZE: &u64 = &SuperBlock::SIZE;
+
+/// Magic number at the start of every `VolumeHeader`
+pub const VOLUME_MAGIC: [u8; 8] = *b"CPLFSVOL";
+
+/// Maximal length of a volume label, in bytes
+pub const LABEL_SIZE: usize = 16;
+
+/// Compatible features this code knows about. Unknown compatible features do not affect how the file system can be used, and are simply ignored.
+pub const FEATURE_COMPAT_SUPPORTED: u32 = 0;
+/// Incompatible features this code knows about. File systems with any other incompatible feature cannot be mounted at all, as their image cannot be interpreted correctly.
+pub const FEATURE_INCOMPAT_SUPPORTED: u32 = 0;
+/// Read-only compatible features this code knows about. File systems with any other read-only compatible feature can only be mounted read-only, as writing to them could corrupt the structures of that feature.
+pub const FEATURE_RO_COMPAT_SUPPORTED: u32 = 0;
+
+/// Identity of a file system, stored in block 0 right after its [`SuperBlock`](struct.SuperBlock.html), i.e. at offset `SUPERBLOCK_SIZE`.
+/// The `SuperBlock` describes the geometry of a file system; this header marks block 0 as holding a file system in the first place, and records the format it was written in and the features it uses.
+///
+/// Block 0 of an image whose superblock was written on its own, without a header, holds a *blank* header of all zeros.
+/// Such images are mounted as if they had a header of the current version without any features, and without a label or UUID.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VolumeHeader {
+    /// Magic number, always `VOLUME_MAGIC`, that identifies block 0 as holding a file system
+    pub magic: [u8; 8],
+    /// Version of the on-disk layout the file system was written in, see [`LAYOUT_VERSION`](../encoding/constant.LAYOUT_VERSION.html)
+    pub version: u32,
+    /// Universally unique identifier of the file system, generated randomly by `mkfs`
+    pub uuid: [u8; 16],
+    /// Human-readable name of the file system, as UTF-8, padded with null bytes
+    pub label: [u8; LABEL_SIZE],
+    /// Bitmask of compatible features the file system uses
+    pub feature_compat: u32,
+    /// Bitmask of incompatible features the file system uses
+    pub feature_incompat: u32,
+    /// Bitmask of read-only compatible features the file system uses
+    pub feature_ro_compat: u32,
+}
+
+impl VolumeHeader {
+    /// Create the header of a new file system: the current version, a fresh random UUID, no label and no features
+    pub fn new() -> VolumeHeader {
+        VolumeHeader {
+            magic: VOLUME_MAGIC,
+            version: LAYOUT_VERSION,
+            uuid: random_uuid(),
+            ..Default::default()
+        }
+    }
+
+    /// Check whether this is a blank header, i.e. whether block 0 holds nothing but a superblock
+    pub fn is_blank(&self) -> bool {
+        *self == VolumeHeader::default()
+    }
+
+    /// Label of the file system, up to the first null byte.
+    /// Bytes that are not valid UTF-8 are replaced.
+    pub fn label(&self) -> String {
+        let end = self
+            .label
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(LABEL_SIZE);
+        String::from_utf8_lossy(&self.label[..end]).into_owned()
+    }
+
+    /// Set the label of the file system to `label`.
+    /// Labels of more than `LABEL_SIZE` bytes are truncated to the last character that fits, like `e2label` does.
+    pub fn set_label(&mut self, label: &str) {
+        let mut end = label.len().min(LABEL_SIZE);
+        while !label.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.label = [0; LABEL_SIZE];
+        self.label[..end].copy_from_slice(&label.as_bytes()[..end]);
+    }
+
+    /// UUID of the file system in its usual textual form, e.g. `123e4567-e89b-42d3-a456-426614174000`
+    pub fn uuid_string(&self) -> String {
+        let hex: Vec<String> = self.uuid.iter().map(|b| format!("{:02x}", b)).collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            hex[0..4].concat(),
+            hex[4..6].concat(),
+            hex[6..8].concat(),
+            hex[8..10].concat(),
+            hex[10..16].concat()
+        )
+    }
+}
+
+impl OnDisk for VolumeHeader {
+    const SIZE: u64 = 8 + u32::SIZE + 16 + LABEL_SIZE as u64 + 3 * u32::SIZE;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.put(&self.magic);
+        enc.put(&self.version);
+        enc.put(&self.uuid);
+        enc.put(&self.label);
+        enc.put(&self.feature_compat);
+        enc.put(&self.feature_incompat);
+        enc.put(&self.feature_ro_compat);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+        Ok(VolumeHeader {
+            magic: dec.get()?,
+            version: dec.get()?,
+            uuid: dec.get()?,
+            label: dec.get()?,
+            feature_compat: dec.get()?,
+            feature_incompat: dec.get()?,
+            feature_ro_compat: dec.get()?,
+        })
+    }
+}
+
+/// Generate a random (version 4) UUID.
+/// The randomness comes from the randomly keyed hasher of the standard library, mixed with the current time and process.
+fn random_uuid() -> [u8; 16] {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut uuid = [0; 16];
+    for (i, half) in uuid.chunks_mut(8).enumerate() {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        hasher.write_u32(process::id());
+        hasher.write_usize(i);
+        half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
 }
 
 /// Hard-coded number of data blocks each inode can point to
 pub const DIRECT_POINTERS: u64 = 12;
 
+/// Longest target, in bytes, that a symlink stores inline, in the place of the direct block addresses of its inode, rather than in data blocks.
+/// Like the fast symlinks of ext2, this saves a data block for most symlinks.
+pub const SYMLINK_INLINE_MAX: u64 = DIRECT_POINTERS * u32::SIZE;
+
 /// Enum describing file types
-/// Currently, either a file `T_FILE`, a directory `T_DIR` or a free inode `T_Free`
+/// Currently, either a file `T_FILE`, a directory `T_DIR`, a symbolic link `T_SYMLINK` or a free inode `T_Free`
 /// The file type `T_FREE` is used to signify a free inode, that can be used to allocate a new file or directory.
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum FType {
     /// Directory file type
     TDir,
@@ -242,6 +455,8 @@ pub enum FType {
     TFile,
     /// Free file type
     TFree,
+    /// Symbolic link file type, whose contents are the path it points to
+    TSymlink,
 }
 impl Default for FType {
     fn default() -> FType {
@@ -249,13 +464,99 @@ impl Default for FType {
     }
 }
 
+impl OnDisk for FType {
+    const SIZE: u64 = u32::SIZE;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        let tag: u32 = match self {
+            FType::TDir => 0,
+            FType::TFile => 1,
+            FType::TFree => 2,
+            FType::TSymlink => 3,
+        };
+        enc.put(&tag);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+        match dec.get::<u32>()? {
+            0 => Ok(FType::TDir),
+            1 => Ok(FType::TFile),
+            2 => Ok(FType::TFree),
+            3 => Ok(FType::TSymlink),
+            _ => Err(APIError::APISerialize("Unknown file type")),
+        }
+    }
+}
+
+/// Times at which an inode was last used in some way, in nanoseconds since the Unix epoch, as reported by a [`Clock`](../clock/trait.Clock.html).
+/// A time of 0 means that the inode was never used in that way by a file system that keeps track of it.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Timestamps {
+    /// Last time the contents of the inode were read
+    pub atime: u64,
+    /// Last time the contents of the inode were modified
+    pub mtime: u64,
+    /// Last time the inode itself changed, i.e. its contents or its metadata such as its number of links
+    pub ctime: u64,
+    /// Time at which the inode was allocated
+    pub crtime: u64,
+}
+
+impl Timestamps {
+    /// Timestamps of an inode that is allocated at time `now`
+    pub fn created(now: u64) -> Timestamps {
+        Timestamps {
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+        }
+    }
+
+    /// Record that the contents were read at time `now`
+    pub fn accessed(&mut self, now: u64) {
+        self.atime = now;
+    }
+
+    /// Record that the contents were modified at time `now`, which changes the inode as well
+    pub fn modified(&mut self, now: u64) {
+        self.mtime = now;
+        self.ctime = now;
+    }
+
+    /// Record that the metadata of the inode changed at time `now`
+    pub fn changed(&mut self, now: u64) {
+        self.ctime = now;
+    }
+}
+
+impl OnDisk for Timestamps {
+    const SIZE: u64 = 4 * u64::SIZE;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.put(&self.atime);
+        enc.put(&self.mtime);
+        enc.put(&self.ctime);
+        enc.put(&self.crtime);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+        Ok(Timestamps {
+            atime: dec.get()?,
+            mtime: dec.get()?,
+            ctime: dec.get()?,
+            crtime: dec.get()?,
+        })
+    }
+}
+
 /// Struct describing data held by an inode on the disk.
-/// Derives the Serialize and Deserialize traits, to allow for easy (de-)serialization when writing to disk blocks
+/// Implements `OnDisk`, to allow for easy (de-)serialization when writing to disk blocks
 ///
 /// *EXTRA*: In real-life file systems, files also contain a field pointing to a data block containing more data blocks, called an indirect pointer.
 /// For simplicity reasons, we do not support this in the current file system.
 /// In other words, files are made up of a total of at most `DIRECT_POINTERS` blocks.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct DInode {
     /// Registers the file type
     pub ft: FType,
@@ -263,18 +564,103 @@ pub struct DInode {
     pub nlink: u16,
     /// Size of the file in bytes. Used to see when a read or write would go out of file bounds.
     pub size: u64,
+    /// Times at which this inode was created, and last read, modified and changed
+    pub times: Timestamps,
+    /// Permission bits of this inode, see the `perm` module
+    pub mode: u16,
+    /// User id of the owner of this inode
+    pub uid: u32,
+    /// Group id of the group this inode belongs to
+    pub gid: u32,
     /// A list of up to `DIRECT_POINTERS` valid block addresses (counting from 0, *not* from the start of the data block region), to specify where the contents of this file are stored.
     pub direct_blocks: [u64; DIRECT_POINTERS as usize],
+    /// Address of the block holding the extended attributes of this inode, or 0 if they are stored inline, see the `xattr` module
+    pub xattr_block: u64,
+    /// Extended attributes of this inode, if they fit in these bytes, see the `xattr` module
+    pub xattr_inline: [u8; XATTR_INLINE_SIZE as usize],
 }
 
-lazy_static! {
-    /// Size of an inode in your system, in bytes.
-    /// This size can only be found out at runtime, which is the reason why we have to wrap this code in a `lazy_static` macro.
-    /// Notice the use of the `ref` keyword; `DINODE_SIZE` is a reference to an `u64` number, that will only be filled in at runtime.
-    /// Used to determine the number of inodes per block, which is important for filesystem initialization.
-    pub static ref DINODE_SIZE : u64 = bincode::serialize(&DInode::default()).unwrap().len() as u64;
+impl OnDisk for DInode {
+    const SIZE: u64 = FType::SIZE
+        + u16::SIZE
+        + u64::SIZE
+        + Timestamps::SIZE
+        + u16::SIZE
+        + 2 * u32::SIZE
+        + DIRECT_POINTERS * u32::SIZE
+        + u32::SIZE
+        + XATTR_INLINE_SIZE;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.put(&self.ft);
+        enc.put(&self.nlink);
+        enc.put(&self.size);
+        enc.put(&self.times);
+        enc.put(&self.mode);
+        enc.put(&self.uid);
+        enc.put(&self.gid);
+        //block addresses are stored as `u32`s, so file systems only accept devices whose addresses fit
+        for address in self.direct_blocks.iter() {
+            enc.put(&(*address as u32));
+        }
+        enc.put(&(self.xattr_block as u32));
+        enc.put(&self.xattr_inline);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+        let mut inode = DInode {
+            ft: dec.get()?,
+            nlink: dec.get()?,
+            size: dec.get()?,
+            times: dec.get()?,
+            mode: dec.get()?,
+            uid: dec.get()?,
+            gid: dec.get()?,
+            direct_blocks: Default::default(),
+            xattr_block: 0,
+            xattr_inline: Default::default(),
+        };
+        for address in inode.direct_blocks.iter_mut() {
+            *address = dec.get::<u32>()? as u64;
+        }
+        inode.xattr_block = dec.get::<u32>()? as u64;
+        inode.xattr_inline = dec.get()?;
+        Ok(inode)
+    }
 }
 
+impl DInode {
+    /// Target of a symlink that is stored inline, see `SYMLINK_INLINE_MAX`.
+    /// The target fills the direct block addresses from the first one on, with 4 bytes in each, in the order in which they are stored on disk.
+    pub fn inline_target(&self) -> Vec<u8> {
+        let mut target: Vec<u8> = self
+            .direct_blocks
+            .iter()
+            .flat_map(|address| (*address as u32).to_le_bytes())
+            .collect();
+        target.truncate(self.size.min(SYMLINK_INLINE_MAX) as usize);
+        target
+    }
+
+    /// Store `target` inline, in the place of the direct block addresses, and set the `size` to its length.
+    /// The target can be at most `SYMLINK_INLINE_MAX` bytes long.
+    pub fn set_inline_target(&mut self, target: &[u8]) {
+        assert!(target.len() as u64 <= SYMLINK_INLINE_MAX);
+        self.direct_blocks = Default::default();
+        for (address, chunk) in self.direct_blocks.iter_mut().zip(target.chunks(4)) {
+            let mut bytes = [0; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *address = u32::from_le_bytes(bytes) as u64;
+        }
+        self.size = target.len() as u64;
+    }
+}
+
+/// Size of an inode on disk, in bytes.
+/// Like `SUPERBLOCK_SIZE`, a reference to the size fixed by the on-disk layout.
+/// Used to determine the number of inodes per block, which is important for filesystem initialization.
+pub const DINODE_SIZE: &u64 = &DInode::SIZE;
+
 /// Inode number of the root inode
 pub const ROOT_INUM: u64 = 1;
 
@@ -314,6 +700,39 @@ pub trait InodeLike: Sized {
     fn get_block(&self, i: u64) -> u64;
     ///Get the number of this inode on the disk
     fn get_inum(&self) -> u64;
+    ///Get the times at which this inode was created, and last read, modified and changed
+    fn get_times(&self) -> Timestamps;
+    ///Get the last time the contents of this inode were read, in nanoseconds since the Unix epoch
+    fn get_atime(&self) -> u64 {
+        self.get_times().atime
+    }
+    ///Get the last time the contents of this inode were modified, in nanoseconds since the Unix epoch
+    fn get_mtime(&self) -> u64 {
+        self.get_times().mtime
+    }
+    ///Get the last time this inode changed, in nanoseconds since the Unix epoch
+    fn get_ctime(&self) -> u64 {
+        self.get_times().ctime
+    }
+    ///Get the time at which this inode was allocated, in nanoseconds since the Unix epoch
+    fn get_crtime(&self) -> u64 {
+        self.get_times().crtime
+    }
+    ///Get the permission bits of this inode
+    fn get_mode(&self) -> u16;
+    ///Get the user id of the owner of this inode
+    fn get_uid(&self) -> u32;
+    ///Get the group id of the group this inode belongs to
+    fn get_gid(&self) -> u32;
+    ///May the user with the given credentials perform `access` on this inode? See `Credentials::may`
+    fn permits(&self, creds: &Credentials, access: u16) -> bool {
+        creds.may(self.get_mode(), self.get_uid(), self.get_gid(), access)
+    }
+    ///Is this a symlink whose target is stored in the place of its block addresses, see `SYMLINK_INLINE_MAX`?
+    ///The block addresses of such an inode do not point to any blocks, so they should never be freed.
+    fn has_inline_target(&self) -> bool {
+        self.get_ft() == FType::TSymlink && self.get_size() <= SYMLINK_INLINE_MAX
+    }
 }
 
 ///You get the implementation of `InodeLike` for free for the `Inode` I defined above
@@ -336,7 +755,13 @@ impl InodeLike for Inode {
             ft: *ft,
             nlink: nlink as u16,
             size,
+            times: Timestamps::default(),
+            mode: 0,
+            uid: 0,
+            gid: 0,
             direct_blocks: db,
+            xattr_block: 0,
+            xattr_inline: Default::default(),
         };
         Some(Inode::new(inum, di))
     }
@@ -360,38 +785,100 @@ impl InodeLike for Inode {
     fn get_inum(&self) -> u64 {
         self.inum
     }
+
+    fn get_times(&self) -> Timestamps {
+        self.disk_node.times
+    }
+    fn get_mode(&self) -> u16 {
+        self.disk_node.mode
+    }
+    fn get_uid(&self) -> u32 {
+        self.disk_node.uid
+    }
+    fn get_gid(&self) -> u32 {
+        self.disk_node.gid
+    }
 }
 
-/// Hard-coded number of characters each directory entry can contain for its name
-pub const DIRNAME_SIZE: usize = 14;
+/// Maximum number of bytes in the UTF-8 encoding of a directory entry's name
+pub const DIRNAME_SIZE: usize = 255;
 
 /// Specific type of inode contents for directories
-/// A directory is a file containing a sequence of DirEntry structures, with the `FType` set to the directory type `TDir`.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+/// A directory is a file containing a sequence of directory records, with the `FType` set to the directory type `TDir`.
+/// Every record holds a single `DirEntry`, and takes up as few slots of `DIRENTRY_SIZE` bytes as its name allows, in the layout described in [`encoding`](../encoding/index.html).
+/// Records never cross a block boundary, so the last few bytes of a directory block are unused if the block size is not a multiple of `DIRENTRY_SIZE`.
+/// Slots that are not part of any record are all zeroes, and read as empty entries of a single slot.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct DirEntry {
     ///Number of the inode this directory entry points to
     ///It is these types of pointers that cause inode's `nlink` fields to increase in the file system
     ///A directory entry with an `inum` of 0 represents an empty entry
     pub inum: u64,
-    ///Character array specifying the name of this entry\
-    ///Names can be up to `DIRNAME_SIZE` characters long\
-    ///Shorter names can be specified by storing the null termination character `\0` inside the array; this character indicates the end of the name string
-    ///Note that `char` in Rust is UTF-8 encoded and always takes up 4 bytes. This saves us headaches in the conversion below, at the cost of some memory efficiency
-    pub name: [char; DIRNAME_SIZE],
+    ///Name of this entry\
+    ///Names are stored on disk as UTF-8, and can be up to `DIRNAME_SIZE` bytes long
+    pub name: String,
 }
 
-lazy_static! {
-    /// Size of an directory entry in your system, in bytes.
-    /// For similar reasons, again wrapped in the `lazy_static!` macro.
-    pub static ref DIRENTRY_SIZE : u64 = bincode::serialize(&DirEntry::default()).unwrap().len() as u64;
+impl DirEntry {
+    /// Number of bytes the record holding this entry takes up on disk
+    pub fn rec_len(&self) -> u64 {
+        (DIRENTRY_HEADER_SIZE + self.name.len() as u64).div_ceil(*DIRENTRY_SIZE) * *DIRENTRY_SIZE
+    }
+
+    /// Encode the record holding this entry, padded with zeroes up to its `rec_len`.
+    /// The caller is responsible for keeping the name at most `DIRNAME_SIZE` bytes long.
+    pub fn encode(&self, enc: &mut Encoder<'_>) {
+        let rec_len = self.rec_len();
+        enc.put(&self.inum);
+        enc.put(&(rec_len as u16));
+        enc.put(&(self.name.len() as u8));
+        enc.put_bytes(self.name.as_bytes());
+        enc.put_bytes(&vec![
+            0;
+            (rec_len - DIRENTRY_HEADER_SIZE) as usize
+                - self.name.len()
+        ]);
+    }
+
+    /// Decode a record, returning the entry it holds and the number of bytes the record takes up.
+    /// Fails if the record length does not match the name, or if the name is not valid UTF-8.
+    pub fn decode(dec: &mut Decoder<'_>) -> error_given::Result<(DirEntry, u64)> {
+        let inum: u64 = dec.get()?;
+        let rec_len = dec.get::<u16>()? as u64;
+        let name_len = dec.get::<u8>()? as u64;
+
+        //a slot that is not part of any record
+        if rec_len == 0 && inum == 0 && name_len == 0 {
+            dec.get_bytes((*DIRENTRY_SIZE - DIRENTRY_HEADER_SIZE) as usize)?;
+            return Ok((DirEntry::default(), *DIRENTRY_SIZE));
+        }
+        if !rec_len.is_multiple_of(*DIRENTRY_SIZE) || rec_len < DIRENTRY_HEADER_SIZE + name_len {
+            return Err(APIError::APISerialize("Invalid directory record length"));
+        }
+
+        let name = std::str::from_utf8(dec.get_bytes(name_len as usize)?)
+            .map_err(|_| APIError::APISerialize("Directory entry name is not valid UTF-8"))?
+            .to_string();
+        dec.get_bytes((rec_len - DIRENTRY_HEADER_SIZE - name_len) as usize)?;
+        Ok((DirEntry { inum, name }, rec_len))
+    }
 }
 
+/// Size of a directory slot on disk, in bytes.
+/// Directory records take up a whole number of slots; a single slot fits names of up to `DIRENTRY_SIZE - DIRENTRY_HEADER_SIZE` bytes.
+/// Again a reference, like the sizes of the other on-disk structures.
+pub const DIRENTRY_SIZE: &u64 = &32;
+
+/// Size of the fixed part of a directory record on disk, in bytes, i.e. of its inode number, record length and name length
+pub const DIRENTRY_HEADER_SIZE: u64 = 11;
+
 ///Tests for the block type
 #[cfg(test)]
 mod block_tests {
 
     use super::Block;
-    use serde::{Deserialize, Serialize};
+    use crate::encoding::{Decoder, Encoder, OnDisk};
+    use crate::error_given;
 
     // For these tests, we use blocks containing 1000 bytes, which should be enough to store a few inodes
     static BLOCK_SIZE: u64 = 1000;
@@ -426,11 +913,25 @@ mod block_tests {
     }
 
     //Importing some example deserializable struct
-    use crate::types::{DInode, FType, DINODE_SIZE, DIRECT_POINTERS};
+    use crate::types::{DInode, FType, Timestamps, DINODE_SIZE, DIRECT_POINTERS};
+    use crate::xattr::XATTR_INLINE_SIZE;
     //Another testing struct to perform (de)serialization on
-    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[derive(Debug, PartialEq, Eq)]
     struct Point(u64, u64);
 
+    impl OnDisk for Point {
+        const SIZE: u64 = 16;
+
+        fn encode(&self, enc: &mut Encoder<'_>) {
+            enc.put(&self.0);
+            enc.put(&self.1);
+        }
+
+        fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+            Ok(Point(dec.get()?, dec.get()?))
+        }
+    }
+
     //Testing the (de)serialization methods offered by blocks
     #[test]
     fn serialization_test() {
@@ -441,26 +942,26 @@ mod block_tests {
             ft: FType::TFree,
             nlink: 13,
             size: 142,
+            times: Timestamps::created(7),
+            mode: 0o2755,
+            uid: 1000,
+            gid: 100,
             direct_blocks: [1000; DIRECT_POINTERS as usize],
+            xattr_block: 1001,
+            xattr_inline: [7; XATTR_INLINE_SIZE as usize],
         };
 
-        //Testing some length consistency, and the global variable DINODE_SIZE
-        assert_eq!(
-            bincode::serialize(&p1).unwrap().len(),
-            bincode::serialize(&p1).unwrap().len()
-        );
-        assert_eq!(
-            bincode::serialize(&in1).unwrap().len(),
-            *DINODE_SIZE as usize
-        );
-        assert_eq!(
-            bincode::serialize(&in1).unwrap().len(),
-            bincode::serialize(&in2).unwrap().len()
-        );
+        //Testing the global variable DINODE_SIZE, and that the size does not depend on the contents
+        assert_eq!(*DINODE_SIZE, DInode::SIZE);
+        let mut b1 = Block::new_zero(0, BLOCK_SIZE);
+        b1.serialize_into(&in2, 0).unwrap();
+        assert!(b1.contents_as_ref()[*DINODE_SIZE as usize..]
+            .iter()
+            .all(|b| *b == 0));
 
         let n1 = 12;
         let mut b1 = Block::new(n1, vec![1; BLOCK_SIZE as usize].into_boxed_slice());
-        let point_size = bincode::serialize(&p1).unwrap().len() as u64;
+        let point_size = Point::SIZE;
         //Now perform some actual writes to the block, and read them again after
         b1.serialize_into(&p1, 0).unwrap();
         b1.serialize_into(&p2, point_size).unwrap();
@@ -486,3 +987,44 @@ mod block_tests {
         assert_eq!(b1.contents_as_ref(), vec![0; BLOCK_SIZE as usize]);
     }
 }
+
+///Tests for the volume header
+#[cfg(test)]
+mod volume_tests {
+    use super::{VolumeHeader, LABEL_SIZE};
+
+    #[test]
+    fn label_test() {
+        let mut header = VolumeHeader::new();
+        assert!(!header.is_blank());
+        assert_eq!(header.label(), "");
+
+        header.set_label("backup");
+        assert_eq!(header.label(), "backup");
+        //Long labels are cut off at a character boundary
+        header.set_label("ééééééééé");
+        assert_eq!(header.label(), "éééééééé");
+        assert_eq!(header.label.len(), LABEL_SIZE);
+        header.set_label("");
+        assert_eq!(header.label, [0; LABEL_SIZE]);
+        assert!(VolumeHeader::default().is_blank());
+    }
+
+    #[test]
+    fn uuid_test() {
+        let (h1, h2) = (VolumeHeader::new(), VolumeHeader::new());
+        assert_ne!(h1.uuid, h2.uuid);
+        //Random UUIDs have version 4 and the RFC 4122 variant
+        assert_eq!(h1.uuid[6] >> 4, 4);
+        assert_eq!(h1.uuid[8] >> 6, 0b10);
+
+        let header = VolumeHeader {
+            uuid: [
+                0x12, 0x3e, 0x45, 0x67, 0xe8, 0x9b, 0x42, 0xd3, 0xa4, 0x56, 0x42, 0x66, 0x14, 0x17,
+                0x40, 0x00,
+            ],
+            ..Default::default()
+        };
+        assert_eq!(header.uuid_string(), "123e4567-e89b-42d3-a456-426614174000");
+    }
+}
@@ -0,0 +1,294 @@
+//! Extended attributes: arbitrary name/value pairs that tag an inode with metadata, like its content type, its provenance or a checksum of its contents.
+//!
+//! An inode keeps all of its attributes together, in one of two places:
+//! - a small set, whose encoding fits in [`XATTR_INLINE_SIZE`] bytes, is stored inline, in the inode itself, so that it costs neither a block nor an extra read;
+//! - a larger set is stored in a dedicated xattr block, whose address the inode records. Its inline area is left empty then.
+//!
+//! Both places use the same encoding: one record per attribute, in the order of their names, each made up of a `name_len: u8`, a `value_len: u16`, the UTF-8 name and the value itself.
+//! The records end with a record whose `name_len` is 0, or at the end of the storage, so that an all-zero inline area or block holds no attributes at all.
+//! A set can therefore grow as large as a single block.
+//!
+//! File systems do not place the attributes themselves, but go through [`load_xattrs`], [`store_xattrs`] and [`release_xattrs`], which work for any inode that implements [`XattrInode`].
+//!
+//! [`XATTR_INLINE_SIZE`]: constant.XATTR_INLINE_SIZE.html
+//! [`load_xattrs`]: fn.load_xattrs.html
+//! [`store_xattrs`]: fn.store_xattrs.html
+//! [`release_xattrs`]: fn.release_xattrs.html
+//! [`XattrInode`]: trait.XattrInode.html
+
+use super::encoding::{Decoder, Encoder};
+use super::error_given;
+use super::error_given::APIError;
+use super::fs::{BlockSupport, InodeSupport};
+use super::types::{Block, DInode, Inode, Timestamps};
+use std::collections::BTreeMap;
+
+/// Number of bytes that an inode reserves to store its extended attributes inline
+pub const XATTR_INLINE_SIZE: u64 = 12;
+/// Maximum number of bytes in the UTF-8 encoding of the name of an extended attribute
+pub const XATTR_NAME_MAX: usize = 255;
+/// Maximum number of bytes in the value of an extended attribute
+pub const XATTR_VALUE_MAX: usize = u16::MAX as usize;
+/// Number of bytes in front of the name and value of every encoded attribute, i.e. its name and value length
+const XATTR_HEADER_SIZE: u64 = 3;
+
+/// Set of extended attributes of a single inode, ordered by name
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Xattrs(BTreeMap<String, Vec<u8>>);
+
+impl Xattrs {
+    /// Create an empty set of attributes
+    pub fn new() -> Xattrs {
+        Xattrs::default()
+    }
+
+    /// Can `name` be used as the name of an attribute? It has to be non-empty, and at most `XATTR_NAME_MAX` bytes long.
+    pub fn valid_name(name: &str) -> bool {
+        !name.is_empty() && name.len() <= XATTR_NAME_MAX
+    }
+
+    /// Get the value of the attribute `name`, if there is one
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.0.get(name).map(|value| value.as_slice())
+    }
+
+    /// Set the attribute `name` to `value`, replacing its previous value if there was one.
+    /// Returns `None`, without changing the set, if the name is not valid or the value is longer than `XATTR_VALUE_MAX` bytes.
+    pub fn set(&mut self, name: &str, value: &[u8]) -> Option<()> {
+        if !Self::valid_name(name) || value.len() > XATTR_VALUE_MAX {
+            return None;
+        }
+        self.0.insert(name.to_string(), value.to_vec());
+        Some(())
+    }
+
+    /// Remove the attribute `name`, returning its value, or `None` if there was no such attribute
+    pub fn remove(&mut self, name: &str) -> Option<Vec<u8>> {
+        self.0.remove(name)
+    }
+
+    /// Names of all attributes in the set, in order
+    pub fn names(&self) -> Vec<String> {
+        self.0.keys().cloned().collect()
+    }
+
+    /// Does the set hold no attributes at all?
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Number of bytes the encoding of this set takes up, leaving out the record that ends it
+    pub fn encoded_len(&self) -> u64 {
+        self.0
+            .iter()
+            .map(|(name, value)| XATTR_HEADER_SIZE + name.len() as u64 + value.len() as u64)
+            .sum()
+    }
+
+    /// Encode this set into `buf`, filling the rest of it with zeroes.
+    /// Fails, without changing `buf`, if the encoding is longer than `buf`.
+    pub fn encode(&self, buf: &mut [u8]) -> error_given::Result<()> {
+        if self.encoded_len() > buf.len() as u64 {
+            return Err(APIError::APISerialize(
+                "Extended attributes do not fit in their storage",
+            ));
+        }
+
+        buf.fill(0);
+        let mut enc = Encoder::new(buf);
+        for (name, value) in self.0.iter() {
+            enc.put(&(name.len() as u8));
+            enc.put(&(value.len() as u16));
+            enc.put_bytes(name.as_bytes());
+            enc.put_bytes(value);
+        }
+        Ok(())
+    }
+
+    /// Decode a set of attributes from `buf`, the inline area of an inode or the contents of an xattr block
+    pub fn decode(buf: &[u8]) -> error_given::Result<Xattrs> {
+        let mut xattrs = Xattrs::new();
+        let mut dec = Decoder::new(buf);
+        while dec.position() < buf.len() as u64 {
+            let name_len = dec.get::<u8>()?;
+            if name_len == 0 {
+                break;
+            }
+            let value_len = dec.get::<u16>()?;
+            let name = std::str::from_utf8(dec.get_bytes(name_len as usize)?)
+                .map_err(|_| APIError::APISerialize("Extended attribute name is not valid UTF-8"))?
+                .to_string();
+            let value = dec.get_bytes(value_len as usize)?.to_vec();
+            xattrs.0.insert(name, value);
+        }
+        Ok(xattrs)
+    }
+}
+
+/// Inode that keeps its extended attributes in an xattr block and an inline area, like `DInode`
+pub trait XattrInode {
+    /// Address of the xattr block of the inode, or 0 if its attributes are stored inline
+    fn xattr_block(&self) -> u64;
+
+    /// Inline area of the inode, see `XATTR_INLINE_SIZE`
+    fn xattr_inline(&self) -> &[u8];
+
+    /// The address of the xattr block, the inline area and the timestamps of the inode, to place a new set of attributes in
+    fn xattr_parts_mut(&mut self) -> (&mut u64, &mut [u8], &mut Timestamps);
+}
+
+impl XattrInode for DInode {
+    fn xattr_block(&self) -> u64 {
+        self.xattr_block
+    }
+
+    fn xattr_inline(&self) -> &[u8] {
+        &self.xattr_inline
+    }
+
+    fn xattr_parts_mut(&mut self) -> (&mut u64, &mut [u8], &mut Timestamps) {
+        (
+            &mut self.xattr_block,
+            &mut self.xattr_inline,
+            &mut self.times,
+        )
+    }
+}
+
+impl XattrInode for Inode {
+    fn xattr_block(&self) -> u64 {
+        self.disk_node.xattr_block()
+    }
+
+    fn xattr_inline(&self) -> &[u8] {
+        self.disk_node.xattr_inline()
+    }
+
+    fn xattr_parts_mut(&mut self) -> (&mut u64, &mut [u8], &mut Timestamps) {
+        self.disk_node.xattr_parts_mut()
+    }
+}
+
+/// Load the attributes of `inode` through `fs`, from its xattr block if it has one, or from its inline area otherwise
+pub fn load_xattrs<FS, I>(fs: &FS, inode: &I) -> Result<Xattrs, FS::Error>
+where
+    FS: BlockSupport,
+    FS::Error: From<APIError>,
+    I: XattrInode,
+{
+    if inode.xattr_block() != 0 {
+        let block = fs.b_get(inode.xattr_block())?;
+        return Ok(Xattrs::decode(block.contents_as_ref())?);
+    }
+    Ok(Xattrs::decode(inode.xattr_inline())?)
+}
+
+/// Store `xattrs` as the attributes of `inode`, mark it as changed at time `now`, and write it back to the disk through `fs`.
+/// A set that fits is stored inline, giving back the xattr block of the inode if it had one; a larger set is stored in the xattr block, which is allocated if the inode has none yet.
+/// The xattr block is only freed once the inode no longer points to it, so that a crash in between leaks the block rather than leaving it in use.
+/// Returns `None`, without changing anything, if the set does not even fit in a block.
+pub fn store_xattrs<FS>(
+    fs: &mut FS,
+    inode: &mut FS::Inode,
+    xattrs: &Xattrs,
+    now: u64,
+) -> Result<Option<()>, FS::Error>
+where
+    FS: InodeSupport,
+    FS::Error: From<APIError>,
+    FS::Inode: XattrInode,
+{
+    let sb = fs.sup_get()?;
+    if xattrs.encoded_len() > sb.block_size {
+        return Ok(None);
+    }
+
+    let old_block = inode.xattr_block();
+    let new_block = if xattrs.encoded_len() <= XATTR_INLINE_SIZE {
+        0
+    } else if old_block == 0 {
+        fs.b_alloc()? + sb.datastart
+    } else {
+        old_block
+    };
+    if new_block != 0 {
+        let mut data = vec![0; sb.block_size as usize];
+        xattrs.encode(&mut data)?;
+        fs.b_put(&Block::new(new_block, data.into_boxed_slice()))?;
+    }
+
+    let (block, inline, times) = inode.xattr_parts_mut();
+    *block = new_block;
+    if new_block == 0 {
+        xattrs.encode(inline)?;
+    } else {
+        inline.fill(0);
+    }
+    times.changed(now);
+    fs.i_put(inode)?;
+
+    if old_block != 0 && new_block == 0 {
+        fs.b_free(old_block - sb.datastart)?;
+    }
+    Ok(Some(()))
+}
+
+/// Give back the xattr block of `inode` through `fs`, if it has one, and clear its inline area, as part of freeing the inode.
+/// Only changes `inode` in memory; it is up to the caller to write it back to the disk.
+pub fn release_xattrs<FS, I>(fs: &mut FS, inode: &mut I) -> Result<(), FS::Error>
+where
+    FS: BlockSupport,
+    I: XattrInode,
+{
+    let (block, inline, _) = inode.xattr_parts_mut();
+    if *block != 0 {
+        let sb = fs.sup_get()?;
+        fs.b_free(*block - sb.datastart)?;
+    }
+    *block = 0;
+    inline.fill(0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Xattrs, XATTR_INLINE_SIZE, XATTR_NAME_MAX};
+
+    #[test]
+    fn xattrs_golden() {
+        let mut xattrs = Xattrs::new();
+        xattrs.set("type", b"pdf").unwrap();
+        assert_eq!(xattrs.encoded_len(), 10);
+
+        //a single small attribute fits inline, followed by zeroes
+        let mut inline = [0xff; XATTR_INLINE_SIZE as usize];
+        xattrs.encode(&mut inline).unwrap();
+        assert_eq!(
+            inline,
+            [4, 3, 0, b't', b'y', b'p', b'e', b'p', b'd', b'f', 0, 0]
+        );
+        assert_eq!(Xattrs::decode(&inline).unwrap(), xattrs);
+
+        //a second one does not, and the records are ordered by name
+        xattrs.set("md5", &[0xab; 16]).unwrap();
+        assert!(xattrs.encode(&mut inline).is_err());
+        assert_eq!(inline[..4], [4, 3, 0, b't']);
+        let mut block = vec![0; 64];
+        xattrs.encode(&mut block).unwrap();
+        assert_eq!(block[..6], [3, 16, 0, b'm', b'd', b'5']);
+        assert_eq!(Xattrs::decode(&block).unwrap(), xattrs);
+        assert_eq!(xattrs.names(), vec!["md5", "type"]);
+
+        //replacing and removing attributes
+        xattrs.set("type", b"").unwrap();
+        assert_eq!(xattrs.get("type"), Some(&b""[..]));
+        assert_eq!(xattrs.remove("md5"), Some(vec![0xab; 16]));
+        assert_eq!(xattrs.remove("md5"), None);
+        assert!(xattrs.set("", b"x").is_none());
+        assert!(xattrs.set(&"n".repeat(XATTR_NAME_MAX + 1), b"x").is_none());
+
+        //an empty storage holds no attributes, and a record cut off by the end of the storage is an error
+        assert!(Xattrs::decode(&[0; 12]).unwrap().is_empty());
+        assert!(Xattrs::decode(&[4, 3, 0, b't']).is_err());
+    }
+}
@@ -15,20 +15,24 @@
 //! ...
 //!
 
-use crate::a_block_support::BlockFSError::{DeviceConfigurationInvalid, FileSystemError, MemoryAlreadyDeallocated, OutsideOfTheBoundariesError, SuperBlockInvalid};
-use cplfs_api::controller::Device;
+use crate::a_block_support::BlockFSError::{DeviceConfigurationInvalid, FileSystemError, MemoryAlreadyDeallocated, NotAFileSystem, OutsideOfTheBoundariesError, ReadOnlyFileSystem, ResizeImpossible, SuperBlockInvalid, UnsupportedFeatures, UnsupportedVersion};
+use cplfs_api::controller::{BlockDevice, Device, ImageDevice};
 use cplfs_api::error_given::APIError;
-use cplfs_api::fs::{BlockSupport, FileSysSupport};
-use cplfs_api::types::{Block, SuperBlock, DINODE_SIZE};
+use cplfs_api::encoding::{OnDisk, LAYOUT_VERSION};
+use cplfs_api::fs::{BlockSupport, FileSysSupport, VolumeSupport};
+use cplfs_api::types::{Block, SuperBlock, VolumeHeader, DINODE_SIZE, FEATURE_INCOMPAT_SUPPORTED, FEATURE_RO_COMPAT_SUPPORTED, SUPERBLOCK_SIZE, VOLUME_MAGIC};
+use std::borrow::Cow;
 use std::path::Path;
 use thiserror::Error;
 
 ///File system name
 pub type FSName = BlockFS;
 
-///Main structure of the File System object
-pub struct BlockFS {
-    device: Device,
+///Main structure of the File System object, generic over the device it is stored on
+pub struct BlockFS<D: BlockDevice = Device> {
+    device: D,
+    read_only: bool,
+    discard: bool,
 }
 ///File System Error
 #[derive(Error, Debug)]
@@ -54,12 +58,263 @@ pub enum BlockFSError {
     ///Error that's triggered when we are trying to deallocated the memory that's already deallocated.
     #[error("Memory, at this address, is already deallocated!")]
     MemoryAlreadyDeallocated(),
+
+    ///Error that's triggered when we try to modify a file system that was mounted read-only.
+    #[error("File system is mounted read-only!")]
+    ReadOnlyFileSystem(),
+
+    ///Error that's triggered when the file system cannot be resized to the requested size, because data blocks past the new end
+    /// are still in use, or because there are no free blocks left for the data blocks that have to make room for the bitmap.
+    #[error("File system cannot be resized to this size!")]
+    ResizeImpossible(),
+
+    ///Error that's triggered when block 0 of a device does not hold a file system, i.e. when its volume header does not start with the magic number.
+    #[error("Device does not hold a file system!")]
+    NotAFileSystem(),
+
+    ///Error that's triggered when a file system was written in a version of the on-disk layout that this code does not support.
+    #[error("File system version {0} is not supported!")]
+    UnsupportedVersion(u32),
+
+    ///Error that's triggered when a file system uses incompatible features that this code does not know about, or read-only compatible ones
+    /// when mounting it writable. Holds the bitmask of the offending features.
+    #[error("File system uses unsupported features {0:#x}!")]
+    UnsupportedFeatures(u32),
+}
+
+impl<D: BlockDevice> BlockFS<D> {
+    ///Check whether the file system was mounted read-only, using `mountfs_readonly`
+    pub fn is_read_only(&self) -> bool {
+        return self.read_only;
+    }
+
+    ///Mount the file system on `dev` like `mountfs`, but discard every data block as soon as it is freed, so the device can release its space.
+    ///Since the inode layers on top free data blocks with `b_free`, this also applies to the blocks released when truncating or freeing inodes.
+    pub fn mountfs_discard(dev: D) -> Result<Self, BlockFSError> {
+        let mut rustfs = Self::mountfs(dev)?;
+        rustfs.discard = true;
+        return Ok(rustfs);
+    }
+
+    ///Check whether the file system was mounted with `mountfs_discard`
+    pub fn is_discarding(&self) -> bool {
+        return self.discard;
+    }
+
+    ///Discard every data block that the bitmap marks as free, in as few ranges as possible.
+    ///This releases the space of blocks that were freed without being discarded, e.g. while the file system was not mounted with `mountfs_discard`.
+    ///Returns the number of discarded blocks.
+    pub fn fstrim(&mut self) -> Result<u64, BlockFSError> {
+        self.check_writable()?;
+        let sb: SuperBlock = self.sup_get()?;
+        return Self::trim_free_blocks(&mut self.device, &sb);
+    }
+
+    ///Discard every data block that the bitmap of the file system with superblock `sb` on `device` marks as free, as described for `fstrim`.
+    ///Also used by the layers that keep their own device, to implement `fstrim` themselves.
+    pub fn trim_free_blocks(device: &mut D, sb: &SuperBlock) -> Result<u64, BlockFSError> {
+        let used = Self::read_data_bitmap(device, sb)?;
+
+        let mut discarded = 0;
+        let mut i = 0;
+        while i < sb.ndatablocks {
+            if used[i as usize] {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < sb.ndatablocks && !used[i as usize] {
+                i += 1;
+            }
+            device.discard(sb.datastart + start, i - start)?;
+            discarded += i - start;
+        }
+        return Ok(discarded);
+    }
+
+    ///Allocation state of all data blocks, as kept track of in the bitmap
+    fn data_bitmap(&self, sb: &SuperBlock) -> Result<Vec<bool>, BlockFSError> {
+        return Self::read_data_bitmap(&self.device, sb);
+    }
+
+    ///Allocation state of all data blocks of the file system with superblock `sb` on `device`
+    fn read_data_bitmap(device: &D, sb: &SuperBlock) -> Result<Vec<bool>, BlockFSError> {
+        let bitmap = device.borrow_blocks(sb.bmapstart, sb.datastart - sb.bmapstart)?;
+        return Ok((0..sb.ndatablocks).map(|i| bitmap[(i / 8) as usize] & (1 << (i % 8)) > 0).collect());
+    }
+
+    ///Contents of the `n` consecutive blocks starting at block `start`, as a single slice.
+    ///Borrowed straight from the device if it keeps its contents in memory, so multi-block reads do not copy every block separately.
+    pub fn b_get_range(&self, start: u64, n: u64) -> Result<Cow<'_, [u8]>, BlockFSError> {
+        return Ok(self.device.borrow_blocks(start, n)?);
+    }
+
+    ///Write all given blocks to the device at once.
+    ///Nothing is written if any of the blocks does not fit on the device.
+    pub fn b_put_blocks(&mut self, blocks: &[Block]) -> Result<(), BlockFSError> {
+        self.check_writable()?;
+        self.device.write_blocks(blocks)?;
+        return Ok(());
+    }
+
+    ///Grow or shrink the file system, together with the device it is stored on, to `nblocks` blocks.
+    ///The data region is made to run until the new end of the file system. If the bitmap becomes too small to keep track of it,
+    /// the bitmap region grows as well, by taking over the first data blocks; the ones among those that are allocated are copied to free blocks first.
+    ///Returns the blocks that were moved in this way as pairs of their old and new address, so the layers on top can update their pointers to them.
+    ///Shrinking only succeeds if all data blocks past the new end are free. Nothing is changed if the file system cannot be resized to `nblocks` blocks.
+    pub fn resize_fs(&mut self, nblocks: u64) -> Result<Vec<(u64, u64)>, BlockFSError> {
+        self.check_writable()?;
+        let sb: SuperBlock = self.sup_get()?;
+        let bits_per_block = sb.block_size * 8;
+
+        let used = self.data_bitmap(&sb)?;
+
+        //number of data blocks the bitmap has to take over to keep track of the new data region, using the same bitmap size as `sb_valid`
+        let mut shift = 0;
+        loop {
+            if sb.datastart + shift >= nblocks {
+                return Err(ResizeImpossible());
+            }
+            let ndatablocks = nblocks - sb.datastart - shift;
+            if sb.bmapstart + ndatablocks / bits_per_block < sb.datastart + shift {
+                break;
+            }
+            shift += 1;
+        }
+        let new_sb = SuperBlock {
+            nblocks,
+            datastart: sb.datastart + shift,
+            ndatablocks: nblocks - sb.datastart - shift,
+            ..sb
+        };
+        if !Self::sb_valid(&new_sb) {
+            return Err(ResizeImpossible());
+        }
+
+        //data blocks keep their address, so their bit moves along with the start of the data region
+        let mut new_used: Vec<bool> = (0..new_sb.ndatablocks)
+            .map(|j| used.get((j + shift) as usize).copied().unwrap_or(false))
+            .collect();
+        if used.iter().skip((shift + new_sb.ndatablocks) as usize).any(|u| *u) {
+            return Err(ResizeImpossible());
+        }
+
+        //finding a free block for every allocated block the bitmap takes over
+        let mut moves = Vec::new();
+        for i in (0..shift).filter(|i| used.get(*i as usize).copied().unwrap_or(false)) {
+            let j = new_used.iter().position(|u| !*u).ok_or(ResizeImpossible())?;
+            new_used[j] = true;
+            moves.push((sb.datastart + i, new_sb.datastart + j as u64));
+        }
+
+        if nblocks != self.device.nblocks() {
+            self.device.resize(nblocks)?;
+        }
+
+        let mut copies = Vec::new();
+        for (from, to) in &moves {
+            copies.push(Block::new(*to, self.device.read_block(*from)?.contents_as_ref().into()));
+        }
+        self.device.write_blocks(&copies)?;
+
+        let mut bitmap = vec![0u8; ((new_sb.datastart - new_sb.bmapstart) * sb.block_size) as usize];
+        for (j, _) in new_used.iter().enumerate().filter(|(_, u)| **u) {
+            bitmap[j / 8] |= 1 << (j % 8);
+        }
+        let bitmap_blocks: Vec<Block> = bitmap
+            .chunks(sb.block_size as usize)
+            .zip(new_sb.bmapstart..)
+            .map(|(data, i)| Block::new(i, data.into()))
+            .collect();
+        self.device.write_blocks(&bitmap_blocks)?;
+        self.sup_put(&new_sb)?;
+
+        return Ok(moves);
+    }
+
+    ///Fail with a read-only error if the file system was mounted read-only.
+    ///Every operation that modifies the device checks this first, so that it leaves the device untouched.
+    fn check_writable(&self) -> Result<(), BlockFSError> {
+        if self.read_only {
+            return Err(ReadOnlyFileSystem());
+        }
+        return Ok(());
+    }
+
+    ///Block 0 of a new file system: the superblock `sb`, followed by a fresh volume header.
+    ///Also used by the layers that set up block 0 themselves.
+    pub fn superblock_block(sb: &SuperBlock) -> Result<Block, BlockFSError> {
+        let mut super_block = Block::new_zero(0, sb.block_size);
+        super_block.serialize_into(sb, 0)?;
+        super_block.serialize_into(&VolumeHeader::new(), *SUPERBLOCK_SIZE)?;
+        return Ok(super_block);
+    }
+
+    ///Check whether a file system with the volume header `vol` can be mounted, read-only if `read_only` is set.
+    ///Only the current layout version is supported, as older ones would be misread.
+    ///Blank headers, of images whose superblock was written without one, are accepted.
+    ///Also used by the layers that read block 0 themselves.
+    pub fn check_volume(vol: &VolumeHeader, read_only: bool) -> Result<(), BlockFSError> {
+        if vol.is_blank() {
+            return Ok(());
+        }
+        if vol.magic != VOLUME_MAGIC {
+            return Err(NotAFileSystem());
+        }
+        if vol.version != LAYOUT_VERSION {
+            return Err(UnsupportedVersion(vol.version));
+        }
+
+        let incompat = vol.feature_incompat & !FEATURE_INCOMPAT_SUPPORTED;
+        if incompat != 0 {
+            return Err(UnsupportedFeatures(incompat));
+        }
+        let ro_compat = vol.feature_ro_compat & !FEATURE_RO_COMPAT_SUPPORTED;
+        if ro_compat != 0 && !read_only {
+            return Err(UnsupportedFeatures(ro_compat));
+        }
+        return Ok(());
+    }
+
+    ///Mount the file system on `dev`, read-only if `read_only` is set, after checking its superblock and volume header
+    fn mount(dev: D, read_only: bool) -> Result<Self, BlockFSError> {
+        let block_at_zero: Block = dev.read_block(0)?;
+        let superblock = block_at_zero.deserialize_from::<SuperBlock>(0)?;
+
+        //checking whether the superblock in the device is valid
+        if !Self::sb_valid(&superblock) {
+            return Err(SuperBlockInvalid());
+        }
+        Self::check_volume(&block_at_zero.deserialize_from::<VolumeHeader>(*SUPERBLOCK_SIZE)?, read_only)?;
+
+        //checking whether the block size in superblock and device are matching
+        if !((superblock.block_size == dev.block_size()) && (superblock.nblocks == dev.nblocks())) {
+            return Err(DeviceConfigurationInvalid());
+        }
+
+        let rustfs = BlockFS { device: dev, read_only, discard: false };
+        return Ok(rustfs);
+    }
 }
 
-impl FileSysSupport for BlockFS {
+impl<D: BlockDevice> FileSysSupport for BlockFS<D> {
     type Error = BlockFSError;
+    type Dev = D;
 
     fn sb_valid(sb: &SuperBlock) -> bool {
+        //blocks have to hold at least a single inode, which also rules out the empty superblock of a fresh device
+        if sb.block_size < *DINODE_SIZE {
+            return false;
+        }
+        //block 0 has to hold the volume header after the superblock
+        if sb.block_size < *SUPERBLOCK_SIZE + VolumeHeader::SIZE {
+            return false;
+        }
+        //inodes store block addresses as `u32`s
+        if sb.nblocks > u32::MAX as u64 {
+            return false;
+        }
+
         //calculating number of blocks for bitmap part and inodes
         let n_super_blocks = 1;
         let n_bitmap_blocks = (sb.ndatablocks / (sb.block_size * 8)) + 1;
@@ -89,47 +344,55 @@ impl FileSysSupport for BlockFS {
         };
     }
 
-    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
-        if !BlockFS::sb_valid(sb) {
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>
+    where
+        D: ImageDevice,
+    {
+        if !Self::sb_valid(sb) {
             return Err(SuperBlockInvalid());
         }
 
-        let mut device = Device::new(&path, sb.block_size, sb.nblocks)?;
-
-        //serializing superblock into block and writing it at the position zero on the device
-        let mut super_block = Block::new_zero(0, sb.block_size);
-        super_block.serialize_into(sb, 0)?;
-        device.write_block(&super_block)?;
-
-        //initializing the file system with the device and returning it
-        let rushfs = BlockFS { device };
-        return Ok(rushfs);
+        let device = D::create(&path, sb.block_size, sb.nblocks)?;
+        return Self::mkfs_on(device, sb);
     }
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
-        let block_at_zero: Block = dev.read_block(0).unwrap();
-        let superblock = block_at_zero.deserialize_from::<SuperBlock>(0).unwrap();
-
-        //checking whether the superblock in the device is valid
-        if !BlockFS::sb_valid(&superblock) {
+    fn mkfs_on(mut device: D, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        if !Self::sb_valid(sb) {
             return Err(SuperBlockInvalid());
         }
 
         //checking whether the block size in superblock and device are matching
-        if !((superblock.block_size == dev.block_size) && (superblock.nblocks == dev.nblocks)) {
+        if !((sb.block_size == device.block_size()) && (sb.nblocks == device.nblocks())) {
             return Err(DeviceConfigurationInvalid());
         }
 
-        let rustfs = BlockFS { device: dev };
-        return Ok(rustfs);
+        //serializing superblock and a fresh volume header into block and writing it at the position zero on the device
+        device.write_block(&Self::superblock_block(sb)?)?;
+
+        //initializing the file system with the device and returning it
+        let rushfs = BlockFS { device, read_only: false, discard: false };
+        return Ok(rushfs);
+    }
+
+    fn mountfs(dev: D) -> Result<Self, Self::Error> {
+        return Self::mount(dev, false);
     }
 
-    fn unmountfs(self) -> Device {
+    fn mountfs_readonly(dev: D) -> Result<Self, Self::Error> {
+        return Self::mount(dev, true);
+    }
+
+    fn unmountfs(self) -> D {
         return self.device;
     }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        self.device.flush()?;
+        return Ok(());
+    }
 }
 
-impl BlockSupport for BlockFS {
+impl<D: BlockDevice> BlockSupport for BlockFS<D> {
     fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
         let block = self.device.read_block(i);
 
@@ -141,6 +404,7 @@ impl BlockSupport for BlockFS {
     }
 
     fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let result = self.device.write_block(b);
 
         if result.is_err() {
@@ -151,6 +415,7 @@ impl BlockSupport for BlockFS {
     }
 
     fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
 
         if i >= sb.ndatablocks {
@@ -191,10 +456,16 @@ impl BlockSupport for BlockFS {
         current_block.write_data(&changed_data, 0)?;
         self.device.write_block(&current_block)?;
 
+        //the block is only discarded once it is marked free, so a failing discard cannot leak it
+        if self.discard {
+            self.device.discard(sb.datastart + i, 1)?;
+        }
+
         return Ok(());
     }
 
     fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
 
         if i >= sb.ndatablocks {
@@ -208,6 +479,7 @@ impl BlockSupport for BlockFS {
     }
 
     fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        self.check_writable()?;
         let superblock: SuperBlock = self.sup_get()?;
         let bitmap_blocks = superblock.ndatablocks / (superblock.block_size * 8);
 
@@ -218,7 +490,6 @@ impl BlockSupport for BlockFS {
             let data = current_block.contents_as_ref();
 
             let mut changed_data: Vec<u8> = Vec::new();
-            let mut counter: u64 = 0;
             let mut is_data_changed: bool = false;
             let mut index: u64 = 0;
 
@@ -238,24 +509,22 @@ impl BlockSupport for BlockFS {
                     changed_data.push(new_value);
                     is_data_changed = true;
 
-                    //index of the data block we just initialized
-                    index = superblock.datastart + i * superblock.block_size + counter + (k as u64);
+                    //index (within the data region) of the data block we just initialized
+                    index = (k + 8*(j as u32)) as u64  + superblock.block_size*8*i;
 
                     //if the index is larger than the range for data blocks,
                     //that means that there is an error
-                    if index > superblock.ndatablocks + superblock.datastart - 1 {
+                    if index >= superblock.ndatablocks {
                         return Err(OutsideOfTheBoundariesError());
                     }
 
-                    self.b_zero(index%superblock.ndatablocks)?;
-                    index = (k + 8*(j as u32)) as u64  + superblock.block_size*8*i;
+                    self.b_zero(index)?;
                     continue;
                 }
 
                 //if the that has been changed or the data block is equal to 255, we just pass the
-                //unchanged data and increase the counter
+                //unchanged data
                 changed_data.push(data[j]);
-                counter += 8;
             }
 
             //At the end we are checking whether we changed anything in the block. If we did, then we
@@ -287,9 +556,27 @@ impl BlockSupport for BlockFS {
     }
 
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
-        let mut block: Block = Block::new_zero(0, sup.block_size);
+        self.check_writable()?;
+        //reading the block first, so the volume header after the superblock stays intact
+        let mut block: Block = self.device.read_block(0)?;
+
+        block.serialize_into(sup, 0)?;
+        self.device.write_block(&block)?;
+
+        return Ok(());
+    }
+}
+
+impl<D: BlockDevice> VolumeSupport for BlockFS<D> {
+    fn volume_get(&self) -> Result<VolumeHeader, Self::Error> {
+        return Ok(self.device.read_block(0)?.deserialize_from::<VolumeHeader>(*SUPERBLOCK_SIZE)?);
+    }
+
+    fn volume_put(&mut self, vol: &VolumeHeader) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        let mut block: Block = self.device.read_block(0)?;
 
-        block.serialize_into(&sup, 0)?;
+        block.serialize_into(vol, *SUPERBLOCK_SIZE)?;
         self.device.write_block(&block)?;
 
         return Ok(());
@@ -307,9 +594,15 @@ impl BlockSupport for BlockFS {
 // Book: https://doc.rust-lang.org/book/testing.html
 #[cfg(test)]
 mod my_tests {
-    use crate::a_block_support::BlockFS;
-    use cplfs_api::fs::FileSysSupport;
-    use cplfs_api::types::SuperBlock;
+    use crate::a_block_support::FSName as BlockFS;
+    use crate::a_block_support::BlockFSError::{FileSystemError, ReadOnlyFileSystem};
+    use cplfs_api::checksum::checksum_path;
+    use cplfs_api::controller::Device;
+    use cplfs_api::error_given::APIError;
+    use cplfs_api::fs::{BlockSupport, FileSysSupport};
+    use cplfs_api::overlay_device::OverlayDevice;
+    use cplfs_api::types::{Block, SuperBlock};
+    use std::path::PathBuf;
 
     /// Testing whether will FileSystem return false for the superblock where the file system regions
     /// are not in the right order. E.q. Inode region is staring after bitmap region.
@@ -360,7 +653,7 @@ mod my_tests {
         let bad_region_size_superblock1 = SuperBlock {
             block_size: 1000,
             nblocks: 100,
-            ninodes: 10,
+            ninodes: 20,
             inodestart: 1,
             ndatablocks: 20,
             bmapstart: 2,
@@ -397,17 +690,144 @@ mod my_tests {
 
         assert_eq!(BlockFS::sb_valid(&good_superblock2), true);
     }
+
+    /// Testing whether the example image can be inspected through a read-only mount, without ever being modified.
+    /// Every modification is rejected by the file system, and the read-only device backs this up.
+    #[test]
+    fn readonly_example_image_test() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fs-images-a-example").join("img");
+        let image = std::fs::read(&path).unwrap();
+
+        let dev = Device::load_readonly(&path, 1000, 10).unwrap();
+        let mut my_fs = BlockFS::mountfs_readonly(dev).unwrap();
+        assert!(my_fs.is_read_only());
+        let sb = my_fs.sup_get().unwrap();
+        let block = my_fs.b_get(sb.datastart).unwrap();
+        assert!(matches!(my_fs.b_put(&block), Err(ReadOnlyFileSystem())));
+        assert!(matches!(my_fs.b_alloc(), Err(ReadOnlyFileSystem())));
+        assert!(matches!(my_fs.b_free(0), Err(ReadOnlyFileSystem())));
+        assert!(matches!(my_fs.b_zero(0), Err(ReadOnlyFileSystem())));
+        assert!(matches!(my_fs.sup_put(&sb), Err(ReadOnlyFileSystem())));
+        my_fs.sync().unwrap();
+
+        //mounting the read-only device for writing still does not allow modifying the image
+        let mut my_fs = BlockFS::mountfs(my_fs.unmountfs()).unwrap();
+        assert!(!my_fs.is_read_only());
+        assert!(matches!(my_fs.b_put(&block), Err(FileSystemError(_))));
+        drop(my_fs);
+
+        assert_eq!(std::fs::read(&path).unwrap(), image);
+    }
+
+    /// Testing whether a bit flip in the image of a checksummed device is reported as a corrupted block, instead of being handed to the file system.
+    #[test]
+    fn checksum_corruption_test() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fs-images-a-checksum");
+        let path = dir.join("img");
+        let crc_path = checksum_path(&path);
+        for p in &[&path, &crc_path] {
+            if p.exists() {
+                std::fs::remove_file(p).unwrap();
+            }
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        let sb = SuperBlock {
+            block_size: 1000,
+            nblocks: 10,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+
+        let dev = Device::new_checksummed(&path, 1000, 10).unwrap();
+        let mut my_fs = crate::a_block_support::BlockFS::mkfs_on(dev, &sb).unwrap();
+        let i = my_fs.b_alloc().unwrap();
+        my_fs.b_put(&Block::new(sb.datastart + i, vec![42; 1000].into_boxed_slice())).unwrap();
+        drop(my_fs);
+
+        let mut image = std::fs::read(&path).unwrap();
+        image[((sb.datastart + i) * 1000 + 17) as usize] ^= 0x01;
+        image[3] ^= 0x80;
+        std::fs::write(&path, &image).unwrap();
+
+        //the corrupted superblock stops the file system from being mounted, without panicking
+        let dev = Device::load_checksummed(&path, 1000, 10).unwrap();
+        assert!(matches!(
+            crate::a_block_support::BlockFS::mountfs(dev),
+            Err(FileSystemError(APIError::Corruption { block_no: 0 }))
+        ));
+
+        //once the superblock is repaired, only the corrupted data block is refused
+        let mut dev = Device::load_checksummed(&path, 1000, 10).unwrap();
+        let mut sb_block = Block::new_zero(0, 1000);
+        sb_block.serialize_into(&sb, 0).unwrap();
+        dev.write_block(&sb_block).unwrap();
+        let my_fs = crate::a_block_support::BlockFS::mountfs(dev).unwrap();
+        assert!(matches!(
+            my_fs.b_get(sb.datastart + i),
+            Err(FileSystemError(APIError::Corruption { block_no })) if block_no == sb.datastart + i
+        ));
+        assert!(my_fs.b_get(sb.bmapstart).is_ok());
+
+        my_fs.unmountfs().destruct();
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    /// Testing whether the example image can be modified through an overlay device, while the image itself is left untouched.
+    #[test]
+    fn overlay_example_image_test() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fs-images-a-example").join("img");
+        let delta_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fs-images-a-overlay");
+        let delta_path = delta_dir.join("delta");
+        if delta_path.exists() {
+            std::fs::remove_file(&delta_path).unwrap();
+        }
+        std::fs::create_dir_all(&delta_dir).unwrap();
+        let image = std::fs::read(&path).unwrap();
+
+        let dev = OverlayDevice::new(&path, &delta_path, 1000, 10).unwrap();
+        let mut my_fs = crate::a_block_support::BlockFS::mountfs(dev).unwrap();
+        let i = my_fs.b_alloc().unwrap();
+        let sb = my_fs.sup_get().unwrap();
+        let block = Block::new(sb.datastart + i, vec![42; 1000].into_boxed_slice());
+        my_fs.b_put(&block).unwrap();
+        let dev = my_fs.unmountfs();
+        assert_eq!(dev.modified_blocks(), vec![sb.bmapstart, sb.datastart + i]);
+        drop(dev);
+        assert_eq!(std::fs::read(&path).unwrap(), image);
+
+        //the modifications survive remounting, until they are reverted
+        let mut my_fs = crate::a_block_support::BlockFS::mountfs(OverlayDevice::load(&path, &delta_path, 1000, 10).unwrap()).unwrap();
+        assert_eq!(my_fs.b_get(sb.datastart + i).unwrap(), block);
+        assert!(my_fs.b_free(i).is_ok());
+        let mut dev = my_fs.unmountfs();
+        dev.revert().unwrap();
+        let my_fs = crate::a_block_support::BlockFS::mountfs(dev).unwrap();
+        assert_ne!(my_fs.b_get(sb.datastart + i).unwrap(), block);
+
+        my_fs.unmountfs().destruct();
+        std::fs::remove_dir(&delta_dir).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), image);
+    }
 }
 
-// If you want to write more complicated tests that create actual files on your system, take a look at `utils.rs` in the assignment, and how it is used in the `fs_tests` folder to perform the tests. I have imported it below to show you how it can be used.
-// The `utils` folder has a few other useful methods too (nothing too crazy though, you might want to write your own utility functions, or use a testing framework in rust, if you want more advanced features)
+// These tests run on an in-memory device, so they do not create any files on your system and can safely run in parallel.
+// The tests in the `fs_tests` folder show how `utils.rs` can be used to run tests on actual device images instead.
 #[cfg(test)]
-#[path = "../../api/fs-tests"]
-mod test_with_utils {
-    use std::path::PathBuf;
-    use crate::a_block_support::FSName;
-    use cplfs_api::fs::{FileSysSupport, BlockSupport};
-    use cplfs_api::types::{SuperBlock, Block};
+mod test_in_memory {
+    use crate::a_block_support::BlockFS;
+    use crate::a_block_support::BlockFSError::{NotAFileSystem, UnsupportedFeatures, UnsupportedVersion};
+    use cplfs_api::controller::BlockDevice;
+    use cplfs_api::encoding::LAYOUT_VERSION;
+    use cplfs_api::fault_device::FaultDevice;
+    use cplfs_api::io_stats::Region;
+    use cplfs_api::mem_device::MemDevice;
+    use cplfs_api::nbd::{NbdExport, RemoteDevice};
+    use cplfs_api::partition::{Partition, PartitionTable};
+    use cplfs_api::fs::{FileSysSupport, BlockSupport, VolumeSupport};
+    use cplfs_api::types::{SuperBlock, Block, VolumeHeader, SUPERBLOCK_SIZE};
 
     static BLOCK_SIZE: u64 = 1000;
     static NBLOCKS: u64 = 10;
@@ -431,17 +851,12 @@ mod test_with_utils {
         datastart: 100,
     };
 
-    #[path = "utils.rs"]
-    mod utils;
-
-    fn disk_prep_path(name: &str) -> PathBuf {
-        utils::disk_prep_path(&("fs-images-a-".to_string() + name), "img")
-    }
+    type FSName = BlockFS<MemDevice>;
 
     #[test]
     fn check_allocated(){
-        let path = disk_prep_path("zeroed");
-        let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
         let sb: SuperBlock = my_fs.sup_get().unwrap();
 
         //Allocate
@@ -480,15 +895,12 @@ mod test_with_utils {
         let bb = my_fs.b_get(4).unwrap();
         bb.read_data(&mut byte, 0).unwrap();
         assert_eq!(byte[0], 0b0001_1111);
-
-        let dev = my_fs.unmountfs();
-        utils::disk_destruct(dev);
     }
 
     #[test]
     fn big_superblock_test(){
-        let path = disk_prep_path("big_superblock");
-        let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD_BIG).unwrap();
+        let dev = MemDevice::new(SUPERBLOCK_GOOD_BIG.block_size, SUPERBLOCK_GOOD_BIG.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD_BIG).unwrap();
         let _sb: SuperBlock = my_fs.sup_get().unwrap();
 
         //Allocate
@@ -518,9 +930,248 @@ mod test_with_utils {
 
         //checking the first next byte in bitmap (it has to be empty)
         assert_eq!(byte[125],0b0000_0000);
+    }
+
+    #[test]
+    fn device_mismatch_test(){
+        //the device has to match the superblock exactly
+        assert!(FSName::mkfs_on(MemDevice::new(BLOCK_SIZE, NBLOCKS + 1), &SUPERBLOCK_GOOD).is_err());
+        assert!(FSName::mkfs_on(MemDevice::new(BLOCK_SIZE / 2, NBLOCKS), &SUPERBLOCK_GOOD).is_err());
+
+        //and a mounted device is handed back as it was
+        let dev = MemDevice::new(BLOCK_SIZE, NBLOCKS);
+        let dev = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap().unmountfs();
+        let my_fs = FSName::mountfs(dev).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+    }
+
+    #[test]
+    fn b_alloc_access_pattern(){
+        let dev = MemDevice::new(SUPERBLOCK_GOOD_BIG.block_size, SUPERBLOCK_GOOD_BIG.nblocks);
+        let stats = dev.stats();
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD_BIG).unwrap();
+        for _ in 0..SUPERBLOCK_GOOD_BIG.block_size * 8 {
+            my_fs.b_alloc().unwrap();
+        }
+
+        //the first bitmap block is full, so the next allocation reads both bitmap blocks once, and only writes the second one
+        stats.reset();
+        assert_eq!(my_fs.b_alloc().unwrap(), SUPERBLOCK_GOOD_BIG.block_size * 8);
+        let bitmap = stats.region(&SUPERBLOCK_GOOD_BIG, Region::Bitmap);
+        assert_eq!(bitmap.reads, 2);
+        assert_eq!(bitmap.writes, 1);
+        assert_eq!(stats.block(SUPERBLOCK_GOOD_BIG.bmapstart + 1).writes, 1);
+        assert_eq!(stats.region(&SUPERBLOCK_GOOD_BIG, Region::Data).writes, 1);
+        assert_eq!(stats.region(&SUPERBLOCK_GOOD_BIG, Region::Inodes), Default::default());
+    }
 
+    #[test]
+    fn partitioned_device(){
+        //two partitions that each hold a file system with the good superblock, behind the partition table in block 0
+        let mut dev = MemDevice::new(BLOCK_SIZE, 1 + 2 * NBLOCKS);
+        let mut table = PartitionTable::new();
+        table.create(1, NBLOCKS, dev.nblocks).unwrap();
+        table.create(1 + NBLOCKS, NBLOCKS, dev.nblocks).unwrap();
+        table.write(&mut dev).unwrap();
+
+        let mut my_fs = BlockFS::mkfs_on(Partition::open(dev, 0).unwrap(), &SUPERBLOCK_GOOD).unwrap();
+        assert_eq!(my_fs.b_alloc().unwrap(), 0);
+        let block = Block::new(SUPERBLOCK_GOOD.datastart, vec![1; BLOCK_SIZE as usize].into_boxed_slice());
+        my_fs.b_put(&block).unwrap();
+        let dev = my_fs.unmountfs().into_inner();
+
+        //the second partition starts out empty, and creating a file system on it leaves the first one untouched
+        assert!(BlockFS::mountfs(Partition::open(dev.clone(), 1).unwrap()).is_err());
+        let mut my_fs = BlockFS::mkfs_on(Partition::open(dev, 1).unwrap(), &SUPERBLOCK_GOOD).unwrap();
+        assert_eq!(my_fs.b_alloc().unwrap(), 0);
+        assert_eq!(my_fs.b_alloc().unwrap(), 1);
+        assert_eq!(my_fs.b_get(SUPERBLOCK_GOOD.datastart).unwrap(), Block::new_zero(SUPERBLOCK_GOOD.datastart, BLOCK_SIZE));
+        let dev = my_fs.unmountfs().into_inner();
+
+        let mut my_fs = BlockFS::mountfs(Partition::open(dev, 0).unwrap()).unwrap();
+        assert_eq!(my_fs.b_get(SUPERBLOCK_GOOD.datastart).unwrap(), block);
+        assert_eq!(my_fs.b_alloc().unwrap(), 1);
+        let dev = my_fs.unmountfs().into_inner();
+        assert_eq!(PartitionTable::read(&dev).unwrap(), table);
+    }
+
+    #[test]
+    fn resize_fs(){
+        let mut my_fs = FSName::mkfs_on(MemDevice::new(BLOCK_SIZE, NBLOCKS), &SUPERBLOCK_GOOD).unwrap();
+        for i in 0..SUPERBLOCK_GOOD.ndatablocks {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        assert!(my_fs.b_alloc().is_err());
+
+        //growing adds free data blocks at the end, and the bitmap still has room for them
+        assert!(my_fs.resize_fs(2 * NBLOCKS).unwrap().is_empty());
+        let sb = my_fs.sup_get().unwrap();
+        assert_eq!(sb, SuperBlock { nblocks: 2 * NBLOCKS, ndatablocks: 2 * NBLOCKS - SUPERBLOCK_GOOD.datastart, ..SUPERBLOCK_GOOD });
+        assert_eq!(my_fs.b_alloc().unwrap(), SUPERBLOCK_GOOD.ndatablocks);
+        let dev = my_fs.unmountfs();
+        assert_eq!(dev.nblocks, 2 * NBLOCKS);
+
+        //shrinking only works as long as the blocks past the new end are free
+        let mut my_fs = FSName::mountfs(dev).unwrap();
+        assert!(my_fs.resize_fs(NBLOCKS).is_err());
+        assert_eq!(my_fs.sup_get().unwrap(), sb);
+        my_fs.b_free(SUPERBLOCK_GOOD.ndatablocks).unwrap();
+        assert!(my_fs.resize_fs(NBLOCKS).unwrap().is_empty());
+        assert!(my_fs.resize_fs(SUPERBLOCK_GOOD.datastart).is_err());
         let dev = my_fs.unmountfs();
-        utils::disk_destruct(dev);
+        assert_eq!(dev.nblocks, NBLOCKS);
+        let mut my_fs = FSName::mountfs(dev).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        assert!(my_fs.b_alloc().is_err());
+
+        //devices of a fixed size cannot be resized
+        let mut dev = MemDevice::new(BLOCK_SIZE, 1 + NBLOCKS);
+        let mut table = PartitionTable::new();
+        table.create(1, NBLOCKS, dev.nblocks).unwrap();
+        table.write(&mut dev).unwrap();
+        let mut my_fs = BlockFS::mkfs_on(Partition::open(dev, 0).unwrap(), &SUPERBLOCK_GOOD).unwrap();
+        assert!(my_fs.resize_fs(2 * NBLOCKS).is_err());
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+    }
+
+    #[test]
+    fn discard_and_fstrim(){
+        let datastart = SUPERBLOCK_GOOD.datastart;
+        let full = |i| Block::new(datastart + i, vec![1; BLOCK_SIZE as usize].into_boxed_slice());
+        let dev = FSName::mkfs_on(MemDevice::new(BLOCK_SIZE, NBLOCKS), &SUPERBLOCK_GOOD).unwrap().unmountfs();
+
+        //without the mount option, freed blocks keep their contents
+        let mut my_fs = FSName::mountfs(dev).unwrap();
+        assert!(!my_fs.is_discarding());
+        for i in 0..3 {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+            my_fs.b_put(&full(i)).unwrap();
+        }
+        my_fs.b_free(0).unwrap();
+        assert_eq!(my_fs.b_get(datastart).unwrap(), full(0));
+
+        //with it, they are discarded as soon as they are freed
+        let mut my_fs = FSName::mountfs_discard(my_fs.unmountfs()).unwrap();
+        assert!(my_fs.is_discarding());
+        my_fs.b_free(1).unwrap();
+        assert_eq!(my_fs.b_get(datastart + 1).unwrap(), Block::new_zero(datastart + 1, BLOCK_SIZE));
+        assert_eq!(my_fs.b_get(datastart).unwrap(), full(0));
+
+        //fstrim discards all free blocks, and leaves the allocated one alone
+        assert_eq!(my_fs.fstrim().unwrap(), SUPERBLOCK_GOOD.ndatablocks - 1);
+        assert_eq!(my_fs.b_get(datastart).unwrap(), Block::new_zero(datastart, BLOCK_SIZE));
+        assert_eq!(my_fs.b_get(datastart + 2).unwrap(), full(2));
+        let mut my_fs = FSName::mountfs_readonly(my_fs.unmountfs()).unwrap();
+        assert!(my_fs.fstrim().is_err());
+    }
+
+    #[test]
+    fn remote_device(){
+        let my_fs = BlockFS::mkfs_on(MemDevice::new(BLOCK_SIZE, NBLOCKS), &SUPERBLOCK_GOOD).unwrap();
+        let export = NbdExport::new(my_fs.unmountfs(), "fs", false);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            export.serve(stream).unwrap();
+            export
+        });
+
+        //mount the file system on the other end of the connection, and modify it there
+        let mut my_fs = BlockFS::mountfs(RemoteDevice::connect_tcp(addr, "fs", BLOCK_SIZE).unwrap()).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        assert_eq!(my_fs.b_alloc().unwrap(), 0);
+        let block = Block::new(SUPERBLOCK_GOOD.datastart, vec![1; BLOCK_SIZE as usize].into_boxed_slice());
+        my_fs.b_put(&block).unwrap();
+        drop(my_fs.unmountfs());
+
+        //the changes ended up on the exported device
+        let dev = server.join().unwrap().into_inner().unwrap();
+        let mut my_fs = BlockFS::mountfs(dev).unwrap();
+        assert_eq!(my_fs.b_get(SUPERBLOCK_GOOD.datastart).unwrap(), block);
+        assert_eq!(my_fs.b_alloc().unwrap(), 1);
+    }
+
+    #[test]
+    fn b_alloc_crash_recovery(){
+        let datastart = SUPERBLOCK_GOOD.datastart;
+        for crash_point in 0.. {
+            //leave garbage behind in a freed data block
+            let dev = FaultDevice::new(MemDevice::new(BLOCK_SIZE, NBLOCKS));
+            let mut my_fs = BlockFS::<FaultDevice<MemDevice>>::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+            assert_eq!(my_fs.b_alloc().unwrap(), 0);
+            my_fs.b_put(&Block::new(datastart, vec![1; BLOCK_SIZE as usize].into_boxed_slice())).unwrap();
+            my_fs.b_free(0).unwrap();
+
+            let mut dev = my_fs.unmountfs();
+            dev.crash_after(dev.write_count() + crash_point);
+            let mut my_fs = BlockFS::mountfs(dev).unwrap();
+            assert_eq!(my_fs.b_alloc().unwrap(), 0);
+            let dev = my_fs.unmountfs();
+            let completed = !dev.crashed();
+
+            //after remounting, a block marked as allocated never holds the garbage of its previous owner
+            let mut my_fs = FSName::mountfs(dev.into_inner()).unwrap();
+            let mut byte: [u8; 1] = [0];
+            my_fs.b_get(SUPERBLOCK_GOOD.bmapstart).unwrap().read_data(&mut byte, 0).unwrap();
+            if byte[0] & 1 == 1 {
+                assert_eq!(my_fs.b_get(datastart).unwrap(), Block::new_zero(datastart, BLOCK_SIZE));
+                assert_eq!(my_fs.b_alloc().unwrap(), 1);
+            } else {
+                assert!(!completed);
+                assert_eq!(my_fs.b_alloc().unwrap(), 0);
+            }
+            if completed {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn volume_header(){
+        let mut my_fs = BlockFS::mkfs_on(MemDevice::new(BLOCK_SIZE, NBLOCKS), &SUPERBLOCK_GOOD).unwrap();
+        let vol = my_fs.volume_get().unwrap();
+        assert_eq!((vol.magic, vol.version), (*b"CPLFSVOL", LAYOUT_VERSION));
+        assert_ne!(vol.uuid, [0; 16]);
+        assert_eq!(my_fs.label().unwrap(), "");
+
+        //the label and uuid can be changed, and writing the superblock leaves them alone
+        my_fs.set_label("backup").unwrap();
+        my_fs.set_uuid([7; 16]).unwrap();
+        my_fs.sup_put(&SUPERBLOCK_GOOD).unwrap();
+        let my_fs = BlockFS::mountfs(my_fs.unmountfs()).unwrap();
+        assert_eq!(my_fs.label().unwrap(), "backup");
+        assert_eq!(my_fs.uuid().unwrap(), [7; 16]);
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+
+        //unknown incompatible features prevent any mount, unknown read-only compatible features only a writable one
+        let mount = |vol: &VolumeHeader, read_only: bool| {
+            let mut dev = MemDevice::new(BLOCK_SIZE, NBLOCKS);
+            let mut sb_block = BlockFS::<MemDevice>::superblock_block(&SUPERBLOCK_GOOD).unwrap();
+            sb_block.serialize_into(vol, *SUPERBLOCK_SIZE).unwrap();
+            dev.write_block(&sb_block).unwrap();
+            return if read_only { BlockFS::mountfs_readonly(dev) } else { BlockFS::mountfs(dev) };
+        };
+        let mut vol = my_fs.volume_get().unwrap();
+        vol.feature_incompat = 1 << 31;
+        assert!(matches!(mount(&vol, false), Err(UnsupportedFeatures(f)) if f == 1 << 31));
+        assert!(matches!(mount(&vol, true), Err(UnsupportedFeatures(_))));
+        vol.feature_incompat = 0;
+        vol.feature_ro_compat = 1 << 31;
+        assert!(matches!(mount(&vol, false), Err(UnsupportedFeatures(_))));
+        let mut my_fs = mount(&vol, true).unwrap();
+        assert!(my_fs.set_label("nope").is_err());
+
+        //newer layouts and foreign data are refused, but a superblock without any header still mounts
+        vol.feature_ro_compat = 0;
+        vol.version = LAYOUT_VERSION + 1;
+        assert!(matches!(mount(&vol, false), Err(UnsupportedVersion(v)) if v == LAYOUT_VERSION + 1));
+        vol.version = LAYOUT_VERSION - 1;
+        assert!(matches!(mount(&vol, false), Err(UnsupportedVersion(v)) if v == LAYOUT_VERSION - 1));
+        vol.magic = *b"NOTCPLFS";
+        assert!(matches!(mount(&vol, false), Err(NotAFileSystem())));
+        let my_fs = mount(&VolumeHeader::default(), false).unwrap();
+        assert!(my_fs.volume_get().unwrap().is_blank());
     }
 }
 
@@ -21,10 +21,16 @@ use crate::a_block_support::{BlockFS, BlockFSError};
 use crate::b_inode_support::InodeFSError::{
     InodeAlreadyDeallocatedError, InodeInitializationError, InodeSystemError,
 };
-use cplfs_api::controller::Device;
+use cplfs_api::clock::{Clock, SystemClock};
+use cplfs_api::controller::{BlockDevice, Device, ImageDevice};
 use cplfs_api::error_given::APIError;
-use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeSupport};
-use cplfs_api::types::{Block, DInode, FType, Inode, SuperBlock, DINODE_SIZE};
+use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeSupport, VolumeSupport};
+use cplfs_api::perm;
+use cplfs_api::types::{
+    Block, DInode, FType, Inode, InodeLike, SuperBlock, Timestamps, VolumeHeader, DINODE_SIZE,
+    SUPERBLOCK_SIZE,
+};
+use cplfs_api::xattr::release_xattrs;
 use std::path::Path;
 use thiserror::Error;
 
@@ -32,8 +38,11 @@ use thiserror::Error;
 pub type FSName = InodeFS;
 
 ///Main struct file for the Inode File System
-pub struct InodeFS {
-    device: Device,
+pub struct InodeFS<D: BlockDevice = Device> {
+    device: D,
+    read_only: bool,
+    discard: bool,
+    clock: Box<dyn Clock>,
 }
 
 ///Main error file for Inode File system
@@ -56,23 +65,111 @@ pub enum InodeFSError {
     InodeAlreadyDeallocatedError(),
 }
 
-impl FileSysSupport for InodeFS {
+impl<D: BlockDevice> InodeFS<D> {
+    ///Check whether the file system was mounted read-only, using `mountfs_readonly`
+    pub fn is_read_only(&self) -> bool {
+        return self.read_only;
+    }
+
+    ///Mount the file system on `dev` like `mountfs`, but discard every data block as soon as it is freed, as described for `BlockFS::mountfs_discard`.
+    ///This includes the blocks released when truncating or freeing inodes.
+    pub fn mountfs_discard(dev: D) -> Result<Self, InodeFSError> {
+        let mut rustfs = Self::mountfs(dev)?;
+        rustfs.discard = true;
+        return Ok(rustfs);
+    }
+
+    ///Check whether the file system was mounted with `mountfs_discard`
+    pub fn is_discarding(&self) -> bool {
+        return self.discard;
+    }
+
+    ///Discard every data block that the bitmap marks as free, as described for `BlockFS::fstrim`.
+    ///Returns the number of discarded blocks.
+    pub fn fstrim(&mut self) -> Result<u64, InodeFSError> {
+        self.check_writable()?;
+        let sb: SuperBlock = self.sup_get()?;
+        return Ok(BlockFS::<D>::trim_free_blocks(&mut self.device, &sb)?);
+    }
+
+    ///Replace the clock the inode timestamps are taken from, which is the `SystemClock` by default
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    ///Current time according to the clock of this file system, in nanoseconds
+    pub fn now(&self) -> u64 {
+        return self.clock.now();
+    }
+
+    ///Fail with a read-only error if the file system was mounted read-only.
+    ///Every operation that modifies the device checks this first, so that it leaves the device untouched.
+    fn check_writable(&self) -> Result<(), InodeFSError> {
+        if self.read_only {
+            return Err(InodeSystemError(BlockFSError::ReadOnlyFileSystem()));
+        }
+        return Ok(());
+    }
+
+    ///Mount the file system on `dev`, read-only if `read_only` is set, after checking its superblock and volume header
+    fn mount(dev: D, read_only: bool) -> Result<Self, InodeFSError> {
+        let block_at_zero: Block = dev.read_block(0)?;
+        let superblock = block_at_zero.deserialize_from::<SuperBlock>(0)?;
+
+        //checking whether the superblock in the device is valid
+        if !BlockFS::<D>::sb_valid(&superblock) {
+            return Err(InodeSystemError(BlockFSError::SuperBlockInvalid()));
+        }
+        let volume = block_at_zero.deserialize_from::<VolumeHeader>(*SUPERBLOCK_SIZE)?;
+        BlockFS::<D>::check_volume(&volume, read_only)?;
+
+        //checking whether the block size in superblock and device are matching
+        if !((superblock.block_size == dev.block_size()) && (superblock.nblocks == dev.nblocks())) {
+            return Err(InodeSystemError(BlockFSError::DeviceConfigurationInvalid()));
+        }
+
+        let rustfs = InodeFS {
+            device: dev,
+            read_only,
+            discard: false,
+            clock: Box::new(SystemClock),
+        };
+        return Ok(rustfs);
+    }
+}
+
+impl<D: BlockDevice> FileSysSupport for InodeFS<D> {
     type Error = InodeFSError;
+    type Dev = D;
 
     fn sb_valid(sb: &SuperBlock) -> bool {
-        return BlockFS::sb_valid(sb);
+        return BlockFS::<D>::sb_valid(sb);
     }
 
-    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
-        if !InodeFS::sb_valid(sb) {
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>
+    where
+        D: ImageDevice,
+    {
+        if !Self::sb_valid(sb) {
             return Err(InodeSystemError(BlockFSError::SuperBlockInvalid()));
         }
 
-        let mut device = Device::new(&path, sb.block_size, sb.nblocks)?;
+        let device = D::create(&path, sb.block_size, sb.nblocks)?;
+        return Self::mkfs_on(device, sb);
+    }
+
+    fn mkfs_on(mut device: D, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        if !Self::sb_valid(sb) {
+            return Err(InodeSystemError(BlockFSError::SuperBlockInvalid()));
+        }
+
+        //checking whether the block size in superblock and device are matching
+        if !((sb.block_size == device.block_size()) && (sb.nblocks == device.nblocks())) {
+            return Err(InodeSystemError(BlockFSError::DeviceConfigurationInvalid()));
+        }
 
-        //serializing superblock into block and writing it at the position zero on the device
-        let mut super_block = Block::new_zero(0, sb.block_size);
-        super_block.serialize_into(sb, 0)?;
+        //serializing superblock and a fresh volume header into block and writing it at the position zero on the device
+        let super_block = BlockFS::<D>::superblock_block(sb)?;
         device.write_block(&super_block)?;
 
         //calculating number of inodes per block and number of inodes blocks that will be required
@@ -83,6 +180,7 @@ impl FileSysSupport for InodeFS {
         //going through all the blocks and all the inodes we have to add into the blocks
         //we loop over number of block we are going to fill with inodes and the number of inodes we are
         //going to write into block.
+        let mut inode_blocks = Vec::new();
         for i in 0..n_inodes_blocks + 1 {
             let mut inode_block = Block::new_zero(i + 1, sb.block_size);
             for j in 0..n_inodes_per_block {
@@ -92,38 +190,40 @@ impl FileSysSupport for InodeFS {
                     inode_block.serialize_into(&DInode::default(), *DINODE_SIZE * j)?;
                 }
             }
-            device.write_block(&inode_block)?;
+            inode_blocks.push(inode_block);
         }
-
-        let rustfs = InodeFS { device };
+        //writing the whole inode table at once
+        device.write_blocks(&inode_blocks)?;
+
+        let rustfs = InodeFS {
+            device,
+            read_only: false,
+            discard: false,
+            clock: Box::new(SystemClock),
+        };
 
         return Ok(rustfs);
     }
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
-        let block_at_zero: Block = dev.read_block(0).unwrap();
-        let superblock = block_at_zero.deserialize_from::<SuperBlock>(0).unwrap();
-
-        //checking whether the superblock in the device is valid
-        if !BlockFS::sb_valid(&superblock) {
-            return Err(InodeSystemError(BlockFSError::SuperBlockInvalid()));
-        }
-
-        //checking whether the block size in superblock and device are matching
-        if !((superblock.block_size == dev.block_size) && (superblock.nblocks == dev.nblocks)) {
-            return Err(InodeSystemError(BlockFSError::DeviceConfigurationInvalid()));
-        }
+    fn mountfs(dev: D) -> Result<Self, Self::Error> {
+        return Self::mount(dev, false);
+    }
 
-        let rustfs = InodeFS { device: dev };
-        return Ok(rustfs);
+    fn mountfs_readonly(dev: D) -> Result<Self, Self::Error> {
+        return Self::mount(dev, true);
     }
 
-    fn unmountfs(self) -> Device {
+    fn unmountfs(self) -> D {
         return self.device;
     }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        self.device.flush()?;
+        return Ok(());
+    }
 }
 
-impl BlockSupport for InodeFS {
+impl<D: BlockDevice> BlockSupport for InodeFS<D> {
     fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
         let block = self.device.read_block(i);
 
@@ -137,6 +237,7 @@ impl BlockSupport for InodeFS {
     }
 
     fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let result = self.device.write_block(b);
 
         if result.is_err() {
@@ -149,6 +250,7 @@ impl BlockSupport for InodeFS {
     }
 
     fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
 
         if i >= sb.ndatablocks {
@@ -189,10 +291,16 @@ impl BlockSupport for InodeFS {
         current_block.write_data(&changed_data, 0)?;
         self.device.write_block(&current_block)?;
 
+        //the block is only discarded once it is marked free, so a failing discard cannot leak it
+        if self.discard {
+            self.device.discard(sb.datastart + i, 1)?;
+        }
+
         return Ok(());
     }
 
     fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
 
         if i >= sb.ndatablocks {
@@ -206,6 +314,7 @@ impl BlockSupport for InodeFS {
     }
 
     fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        self.check_writable()?;
         let superblock: SuperBlock = self.sup_get()?;
         let bitmap_blocks = superblock.ndatablocks / (superblock.block_size * 8);
 
@@ -290,16 +399,34 @@ impl BlockSupport for InodeFS {
     }
 
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
-        let mut block: Block = Block::new_zero(0, sup.block_size);
+        self.check_writable()?;
+        //reading the block first, so the volume header after the superblock stays intact
+        let mut block: Block = self.device.read_block(0)?;
 
-        block.serialize_into(&sup, 0)?;
+        block.serialize_into(sup, 0)?;
         self.device.write_block(&block)?;
 
         return Ok(());
     }
 }
 
-impl InodeSupport for InodeFS {
+impl<D: BlockDevice> VolumeSupport for InodeFS<D> {
+    fn volume_get(&self) -> Result<VolumeHeader, Self::Error> {
+        return Ok(self.device.read_block(0)?.deserialize_from::<VolumeHeader>(*SUPERBLOCK_SIZE)?);
+    }
+
+    fn volume_put(&mut self, vol: &VolumeHeader) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        let mut block: Block = self.device.read_block(0)?;
+
+        block.serialize_into(vol, *SUPERBLOCK_SIZE)?;
+        self.device.write_block(&block)?;
+
+        return Ok(());
+    }
+}
+
+impl<D: BlockDevice> InodeSupport for InodeFS<D> {
     type Inode = Inode;
 
     fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
@@ -317,14 +444,13 @@ impl InodeSupport for InodeFS {
         let index_of_inode = i - (n_inodes_per_block * index_of_block);
 
         //here we deserialize the block into dinode
-        let dinode = inode_block
-            .deserialize_from::<DInode>(*DINODE_SIZE * index_of_inode)
-            .unwrap();
+        let dinode = inode_block.deserialize_from::<DInode>(*DINODE_SIZE * index_of_inode)?;
 
         return Ok(Inode::new(i, dinode));
     }
 
     fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
         if ino.inum > sb.ninodes - 1 {
             return Err(InodeSystemError(OutsideOfTheBoundariesError()));
@@ -346,12 +472,13 @@ impl InodeSupport for InodeFS {
     }
 
     fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb = self.sup_get()?;
         if i > sb.ninodes - 1 {
             return Err(InodeSystemError(OutsideOfTheBoundariesError()));
         }
 
-        let requested_inode = self.i_get(i).unwrap();
+        let requested_inode = self.i_get(i)?;
 
         if requested_inode.inum != i {
             return Err(InodeInitializationError());
@@ -366,17 +493,24 @@ impl InodeSupport for InodeFS {
         //and deallocating all the blocks. 3.) setting all direct pointers to zero. 4.) returing the
         //modified inode to the disc.
         if requested_inode.disk_node.nlink == 0 {
+            //the target of a short symlink takes the place of its block addresses, so there are no blocks to free
+            let n_valid_blocks = if requested_inode.has_inline_target() {
+                0
+            } else {
+                (requested_inode.disk_node.size as f64 / sb.block_size as f64).ceil() as usize
+            };
+
             let mut modified_dinode = requested_inode.disk_node;
             modified_dinode.ft = FType::TFree;
-
-            let n_valid_blocks =
-                (modified_dinode.size as f64 / sb.block_size as f64).ceil() as usize;
             for i in 0..n_valid_blocks {
                 if modified_dinode.direct_blocks[i] != 0 {
                     self.b_free(modified_dinode.direct_blocks[i] % sb.datastart)?;
                 }
             }
 
+            //the extended attributes go together with the inode
+            release_xattrs(self, &mut modified_dinode)?;
+
             modified_dinode.direct_blocks = Default::default();
             self.i_put(&Inode::new(requested_inode.inum, modified_dinode))?;
         }
@@ -385,7 +519,8 @@ impl InodeSupport for InodeFS {
     }
 
     fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
-        let sb = self.sup_get().unwrap();
+        self.check_writable()?;
+        let sb = self.sup_get()?;
 
         //calculating number of inodes per block and number of inodes blocks that will be required
         //to save those inodes
@@ -404,7 +539,7 @@ impl InodeSupport for InodeFS {
                     continue;
                 }
 
-                let inode = self.i_get(i * n_inodes_per_block + j).unwrap();
+                let inode = self.i_get(i * n_inodes_per_block + j)?;
                 let mut disc_inode = inode.disk_node;
                 let index = inode.inum;
 
@@ -412,6 +547,10 @@ impl InodeSupport for InodeFS {
                     disc_inode.ft = ft;
                     disc_inode.size = Default::default();
                     disc_inode.nlink = Default::default();
+                    disc_inode.times = Timestamps::created(self.now());
+                    disc_inode.mode = perm::default_mode(ft);
+                    disc_inode.uid = 0;
+                    disc_inode.gid = 0;
 
                     self.i_put(&Inode::new(index, disc_inode))?;
                     return Ok(index);
@@ -423,10 +562,15 @@ impl InodeSupport for InodeFS {
     }
 
     fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
-        let sb = self.sup_get().unwrap();
+        self.check_writable()?;
+        let sb = self.sup_get()?;
 
         //going through all valid blocks and freeing them
-        let n_valid_blocks = (inode.disk_node.size as f64 / sb.block_size as f64).ceil() as usize;
+        let n_valid_blocks = if inode.has_inline_target() {
+            0
+        } else {
+            (inode.disk_node.size as f64 / sb.block_size as f64).ceil() as usize
+        };
         for i in 0..n_valid_blocks {
             if inode.disk_node.direct_blocks[i] != 0 {
                 self.b_free(inode.disk_node.direct_blocks[i] % sb.datastart)?;
@@ -435,16 +579,16 @@ impl InodeSupport for InodeFS {
 
         inode.disk_node.size = Default::default();
         inode.disk_node.direct_blocks = Default::default();
+        inode.disk_node.times.modified(self.now());
         self.i_put(inode)?;
         return Ok(());
     }
 }
 
 #[cfg(test)]
-#[path = "../../api/fs-tests"]
-mod test_with_utils {
-    use std::path::PathBuf;
-    use crate::b_inode_support::FSName;
+mod test_in_memory {
+    use crate::b_inode_support::InodeFS;
+    use cplfs_api::mem_device::MemDevice;
     use cplfs_api::fs::{FileSysSupport, InodeSupport};
     use cplfs_api::types::{SuperBlock, FType, InodeLike};
 
@@ -460,17 +604,12 @@ mod test_with_utils {
         datastart: 5,
     };
 
-    #[path = "utils.rs"]
-    mod utils;
-
-    fn disk_prep_path(name: &str) -> PathBuf {
-        utils::disk_prep_path(&("fs-images-b-".to_string() + name), "img")
-    }
+    type FSName = InodeFS<MemDevice>;
 
     #[test]
     fn multiple_blocks_test(){
-        let path = disk_prep_path("multiple_blocks");
-        let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK).unwrap();
+        let dev = MemDevice::new(SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.block_size, SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK).unwrap();
 
         //Allocate inodes
         for i in 0..(SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.ninodes - 1) {
@@ -518,9 +657,6 @@ mod test_with_utils {
         assert_eq!(my_fs.i_get(2).unwrap(),i2);
         assert_eq!(my_fs.i_get(3).unwrap(),i3);
         assert_eq!(my_fs.i_get(4).unwrap(),i4);
-
-        let dev = my_fs.unmountfs();
-        utils::disk_destruct(dev);
     }
 
 }
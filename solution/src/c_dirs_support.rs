@@ -19,11 +19,14 @@
 
 use crate::a_block_support::{BlockFS, BlockFSError};
 use crate::b_inode_support::InodeFSError;
-use crate::c_dirs_support::DirFSError::{DirectorySystemError, SearchedDirectoryDoesntExist, InodeNotDirectoryError, DirEntryNameAlreadyExists, InodeNotInUse};
-use cplfs_api::controller::Device;
+use crate::c_dirs_support::DirFSError::{DirectorySystemError, SearchedDirectoryDoesntExist, InodeNotDirectoryError, DirEntryNameAlreadyExists, InodeNotInUse, InvalidDirEntryName, DirectoryFull, XattrNotFound, InvalidXattr, XattrTooLarge};
+use cplfs_api::clock::{Clock, SystemClock};
+use cplfs_api::controller::{BlockDevice, Device, ImageDevice};
 use cplfs_api::error_given::APIError;
-use cplfs_api::fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport};
-use cplfs_api::types::{Block, DInode, DirEntry, FType, Inode, SuperBlock, DINODE_SIZE, DIRENTRY_SIZE, DIRNAME_SIZE};
+use cplfs_api::fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport, VolumeSupport, XattrSupport};
+use cplfs_api::perm::{self, DEFAULT_DIR_MODE};
+use cplfs_api::types::{Block, DInode, DirEntry, FType, Inode, InodeLike, SuperBlock, Timestamps, VolumeHeader, DINODE_SIZE, DIRECT_POINTERS, DIRENTRY_SIZE, DIRNAME_SIZE, SUPERBLOCK_SIZE};
+use cplfs_api::xattr::{load_xattrs, release_xattrs, store_xattrs};
 use std::path::Path;
 use thiserror::Error;
 
@@ -31,8 +34,11 @@ use thiserror::Error;
 pub type FSName = DirFS;
 
 ///Main struct file for Directory file system
-struct DirFS {
-    device: Device,
+pub struct DirFS<D: BlockDevice = Device> {
+    device: D,
+    read_only: bool,
+    discard: bool,
+    clock: Box<dyn Clock>,
 }
 
 ///Main error file for Directory file system
@@ -61,27 +67,218 @@ pub enum DirFSError {
     ///When we want to link the dir entry to inode that's not in use
     #[error("Inode is not in use!")]
     InodeNotInUse(),
+
+    ///When we want to link a dir entry with a name that is invalid, or too long to fit in a single directory block
+    #[error("Invalid directory entry name!")]
+    InvalidDirEntryName(),
+
+    ///When a directory has used up all of its direct blocks, and a new entry does not fit in any of them
+    #[error("Directory is full!")]
+    DirectoryFull(),
+
+    ///When we look up or remove an extended attribute that the inode does not have
+    #[error("Extended attribute not found!")]
+    XattrNotFound(),
+
+    ///When we want to set an extended attribute with an invalid name, or a value that is too long
+    #[error("Invalid extended attribute!")]
+    InvalidXattr(),
+
+    ///When the extended attributes of an inode would no longer fit in a single block
+    #[error("Extended attributes do not fit in a block!")]
+    XattrTooLarge(),
 }
 
-impl FileSysSupport for DirFS {
+impl<D: BlockDevice> DirFS<D> {
+    ///Check whether the file system was mounted read-only, using `mountfs_readonly`
+    pub fn is_read_only(&self) -> bool {
+        return self.read_only;
+    }
+
+    ///Mount the file system on `dev` like `mountfs`, but discard every data block as soon as it is freed, as described for `BlockFS::mountfs_discard`.
+    ///This includes the blocks released when truncating or freeing inodes.
+    pub fn mountfs_discard(dev: D) -> Result<Self, DirFSError> {
+        let mut rustfs = Self::mountfs(dev)?;
+        rustfs.discard = true;
+        return Ok(rustfs);
+    }
+
+    ///Check whether the file system was mounted with `mountfs_discard`
+    pub fn is_discarding(&self) -> bool {
+        return self.discard;
+    }
+
+    ///Discard every data block that the bitmap marks as free, as described for `BlockFS::fstrim`.
+    ///Returns the number of discarded blocks.
+    pub fn fstrim(&mut self) -> Result<u64, DirFSError> {
+        self.check_writable()?;
+        let sb: SuperBlock = self.sup_get()?;
+        return BlockFS::<D>::trim_free_blocks(&mut self.device, &sb).map_err(|e| DirectorySystemError(InodeFSError::InodeSystemError(e)));
+    }
+
+    ///Replace the clock the inode timestamps are taken from, which is the `SystemClock` by default
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    ///Current time according to the clock of this file system, in nanoseconds
+    pub fn now(&self) -> u64 {
+        return self.clock.now();
+    }
+
+    ///Fail with a read-only error if the file system was mounted read-only.
+    ///Every operation that modifies the device checks this first, so that it leaves the device untouched.
+    fn check_writable(&self) -> Result<(), DirFSError> {
+        if self.read_only {
+            return Err(DirectorySystemError(InodeFSError::InodeSystemError(BlockFSError::ReadOnlyFileSystem())));
+        }
+        return Ok(());
+    }
+
+    ///Mount the file system on `dev`, read-only if `read_only` is set, after checking its superblock and volume header
+    fn mount(dev: D, read_only: bool) -> Result<Self, DirFSError> {
+        let block_at_zero: Block = dev.read_block(0)?;
+        let superblock = block_at_zero.deserialize_from::<SuperBlock>(0)?;
+
+        //checking whether the superblock in the device is valid
+        if !BlockFS::<D>::sb_valid(&superblock) {
+            return Err(DirectorySystemError(InodeFSError::InodeSystemError(
+                BlockFSError::SuperBlockInvalid(),
+            )));
+        }
+        let volume = block_at_zero.deserialize_from::<VolumeHeader>(*SUPERBLOCK_SIZE)?;
+        BlockFS::<D>::check_volume(&volume, read_only).map_err(|e| DirectorySystemError(InodeFSError::InodeSystemError(e)))?;
+
+        //checking whether the block size in superblock and device are matching
+        if !((superblock.block_size == dev.block_size()) && (superblock.nblocks == dev.nblocks())) {
+            return Err(DirectorySystemError(InodeFSError::InodeSystemError(
+                BlockFSError::DeviceConfigurationInvalid(),
+            )));
+        }
+
+        let rustfs = DirFS {
+            device: dev,
+            read_only,
+            discard: false,
+            clock: Box::new(SystemClock),
+        };
+        return Ok(rustfs);
+    }
+
+    ///Number of bytes in a directory block that is actually used to store directory records, i.e. the whole slots that fit in it
+    fn dir_block_capacity(sb: &SuperBlock) -> u64 {
+        return sb.block_size - (sb.block_size % *DIRENTRY_SIZE);
+    }
+
+    ///Number of data blocks that hold the contents of `inode`.
+    ///Directories only use `dir_block_capacity` bytes of every block, and the target of a short symlink takes the place of its block addresses, so it has no blocks at all.
+    fn n_valid_blocks(sb: &SuperBlock, inode: &Inode) -> usize {
+        if inode.has_inline_target() {
+            return 0;
+        }
+        let capacity = if inode.disk_node.ft == FType::TDir {
+            Self::dir_block_capacity(sb)
+        } else {
+            sb.block_size
+        };
+        return inode.disk_node.size.div_ceil(capacity) as usize;
+    }
+
+    ///Collects all directory records, used or not, that fall within the size of the directory `inode`.
+    ///Every record is returned as its entry, its byte offset (from the start of the inode contents) and its length in bytes.
+    pub fn dir_entries(&self, inode: &Inode) -> Result<Vec<(DirEntry, u64, u64)>, DirFSError> {
+        let sb = self.sup_get()?;
+        let capacity = Self::dir_block_capacity(&sb);
+
+        let mut entries = Vec::new();
+        let n_valid_blocks = inode.disk_node.size.div_ceil(capacity);
+        for i in 0..n_valid_blocks {
+            if inode.disk_node.direct_blocks[i as usize] == 0 {
+                break;
+            }
+
+            let current_block = self.b_get(inode.disk_node.direct_blocks[i as usize])?;
+            let mut offset = 0;
+            while offset < capacity && i * capacity + offset < inode.disk_node.size {
+                let (entry, rec_len) = current_block.read_dir_entry(offset)?;
+                if offset + rec_len > capacity {
+                    return Err(DirFSError::DeviceSystemError(APIError::APISerialize("Directory record crosses a block boundary")));
+                }
+                entries.push((entry, i * capacity + offset, rec_len));
+                offset += rec_len;
+            }
+        }
+        return Ok(entries);
+    }
+
+    ///Overwrites the record at byte offset `offset` of the directory `inode` with zeroes, freeing its slots for later entries.
+    ///Does not change any link counts, but does mark the directory as modified.
+    pub fn clear_entry(&mut self, inode: &mut Inode, offset: u64) -> Result<(), DirFSError> {
+        self.check_writable()?;
+        let sb = self.sup_get()?;
+        let capacity = Self::dir_block_capacity(&sb);
+
+        let mut block = self.b_get(inode.disk_node.direct_blocks[(offset / capacity) as usize])?;
+        let (_, rec_len) = block.read_dir_entry(offset % capacity)?;
+        block.write_data(&vec![0; rec_len as usize], offset % capacity)?;
+        self.b_put(&block)?;
+
+        inode.disk_node.times.modified(self.now());
+        self.i_put(inode)?;
+        return Ok(());
+    }
+
+    ///Increase the nlink number of the inode `inum` that is being linked into directory `inode`, unless the directory links to itself.
+    ///`dirlink` does this before the new entry is written, so that a crash in between leaves a link count that is too high rather than too low.
+    fn dirlink_nlink(&mut self, inode: &Inode, inum: u64) -> Result<(), DirFSError> {
+        if inum != inode.inum {
+            let mut ref_inode = self.i_get(inum)?;
+            ref_inode.disk_node.nlink += 1;
+            ref_inode.disk_node.times.changed(self.now());
+            self.i_put(&ref_inode)?;
+        }
+        return Ok(());
+    }
+}
+
+impl<D: BlockDevice> FileSysSupport for DirFS<D> {
     type Error = DirFSError;
+    type Dev = D;
 
     fn sb_valid(sb: &SuperBlock) -> bool {
-        return BlockFS::sb_valid(sb);
+        return BlockFS::<D>::sb_valid(sb);
     }
 
-    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
-        if !DirFS::sb_valid(sb) {
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>
+    where
+        D: ImageDevice,
+    {
+        if !Self::sb_valid(sb) {
             return Err(DirectorySystemError(InodeFSError::InodeSystemError(
                 BlockFSError::SuperBlockInvalid(),
             )));
         }
 
-        let mut device = Device::new(&path, sb.block_size, sb.nblocks)?;
+        let device = D::create(&path, sb.block_size, sb.nblocks)?;
+        return Self::mkfs_on(device, sb);
+    }
+
+    fn mkfs_on(mut device: D, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        if !Self::sb_valid(sb) {
+            return Err(DirectorySystemError(InodeFSError::InodeSystemError(
+                BlockFSError::SuperBlockInvalid(),
+            )));
+        }
 
-        //serializing superblock into block and writing it at the position zero on the device
-        let mut super_block = Block::new_zero(0, sb.block_size);
-        super_block.serialize_into(sb, 0)?;
+        //checking whether the block size in superblock and device are matching
+        if !((sb.block_size == device.block_size()) && (sb.nblocks == device.nblocks())) {
+            return Err(DirectorySystemError(InodeFSError::InodeSystemError(
+                BlockFSError::DeviceConfigurationInvalid(),
+            )));
+        }
+
+        //serializing superblock and a fresh volume header into block and writing it at the position zero on the device
+        let super_block = BlockFS::<D>::superblock_block(sb).map_err(|e| DirectorySystemError(InodeFSError::InodeSystemError(e)))?;
         device.write_block(&super_block)?;
 
         //calculating number of inodes per block and number of inodes blocks that will be required
@@ -89,9 +286,13 @@ impl FileSysSupport for DirFS {
         let n_inodes_per_block = sb.block_size / (*DINODE_SIZE);
         let n_inodes_blocks = sb.ninodes / n_inodes_per_block;
 
+        //the root directory is created right now, according to the default clock
+        let clock: Box<dyn Clock> = Box::new(SystemClock);
+
         //going through all the blocks and all the inodes we have to add into the blocks
         //we loop over number of block we are going to fill with inodes and the number of inodes we are
         //going to write into block.
+        let mut inode_blocks = Vec::new();
         for i in 0..n_inodes_blocks + 1 {
             let mut inode_block = Block::new_zero(i + 1, sb.block_size);
             for j in 0..n_inodes_per_block {
@@ -103,48 +304,48 @@ impl FileSysSupport for DirFS {
                         let mut root_inode = DInode::default();
                         root_inode.ft = FType::TDir;
                         root_inode.nlink = 1;
+                        root_inode.times = Timestamps::created(clock.now());
+                        root_inode.mode = DEFAULT_DIR_MODE;
                         inode_block.serialize_into(&root_inode, *DINODE_SIZE * j)?;
                     } else {
                         inode_block.serialize_into(&DInode::default(), *DINODE_SIZE * j)?;
                     }
                 }
             }
-            device.write_block(&inode_block)?;
+            inode_blocks.push(inode_block);
         }
-
-        let rustfs = DirFS { device };
+        //writing the whole inode table at once
+        device.write_blocks(&inode_blocks)?;
+
+        let rustfs = DirFS {
+            device,
+            read_only: false,
+            discard: false,
+            clock,
+        };
 
         return Ok(rustfs);
     }
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
-        let block_at_zero: Block = dev.read_block(0).unwrap();
-        let superblock = block_at_zero.deserialize_from::<SuperBlock>(0).unwrap();
-
-        //checking whether the superblock in the device is valid
-        if !BlockFS::sb_valid(&superblock) {
-            return Err(DirectorySystemError(InodeFSError::InodeSystemError(
-                BlockFSError::SuperBlockInvalid(),
-            )));
-        }
-
-        //checking whether the block size in superblock and device are matching
-        if !((superblock.block_size == dev.block_size) && (superblock.nblocks == dev.nblocks)) {
-            return Err(DirectorySystemError(InodeFSError::InodeSystemError(
-                BlockFSError::DeviceConfigurationInvalid(),
-            )));
-        }
+    fn mountfs(dev: D) -> Result<Self, Self::Error> {
+        return Self::mount(dev, false);
+    }
 
-        let rustfs = DirFS { device: dev };
-        return Ok(rustfs);
+    fn mountfs_readonly(dev: D) -> Result<Self, Self::Error> {
+        return Self::mount(dev, true);
     }
 
-    fn unmountfs(self) -> Device {
+    fn unmountfs(self) -> D {
         return self.device;
     }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        self.device.flush()?;
+        return Ok(());
+    }
 }
 
-impl BlockSupport for DirFS {
+impl<D: BlockDevice> BlockSupport for DirFS<D> {
     fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
         let block = self.device.read_block(i);
 
@@ -159,6 +360,7 @@ impl BlockSupport for DirFS {
     }
 
     fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let result = self.device.write_block(b);
 
         if result.is_err() {
@@ -171,6 +373,7 @@ impl BlockSupport for DirFS {
     }
 
     fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
 
         if i >= sb.ndatablocks {
@@ -215,10 +418,16 @@ impl BlockSupport for DirFS {
         current_block.write_data(&changed_data, 0)?;
         self.device.write_block(&current_block)?;
 
+        //the block is only discarded once it is marked free, so a failing discard cannot leak it
+        if self.discard {
+            self.device.discard(sb.datastart + i, 1)?;
+        }
+
         return Ok(());
     }
 
     fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
 
         if i >= sb.ndatablocks {
@@ -234,6 +443,7 @@ impl BlockSupport for DirFS {
     }
 
     fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        self.check_writable()?;
         let superblock: SuperBlock = self.sup_get()?;
         let bitmap_blocks = superblock.ndatablocks / (superblock.block_size * 8);
 
@@ -244,7 +454,6 @@ impl BlockSupport for DirFS {
             let data = current_block.contents_as_ref();
 
             let mut changed_data: Vec<u8> = Vec::new();
-            let mut counter: u64 = 0;
             let mut is_data_changed: bool = false;
             let mut index: u64 = 0;
 
@@ -264,26 +473,24 @@ impl BlockSupport for DirFS {
                     changed_data.push(new_value);
                     is_data_changed = true;
 
-                    //index of the data block we just initialized
-                    index = superblock.datastart + i * superblock.block_size + counter + (k as u64);
+                    //index (within the data region) of the data block we just initialized
+                    index = (k + 8*(j as u32)) as u64  + superblock.block_size*8*i;
 
                     //if the index is larger than the range for data blocks,
                     //that means that there is an error
-                    if index > superblock.ndatablocks + superblock.datastart - 1 {
+                    if index >= superblock.ndatablocks {
                         return Err(DirectorySystemError(InodeFSError::InodeSystemError(
                             BlockFSError::OutsideOfTheBoundariesError(),
                         )));
                     }
 
-                    self.b_zero(index%superblock.ndatablocks)?;
-                    index = (k + 8*(j as u32)) as u64  + superblock.block_size*8*i;
+                    self.b_zero(index)?;
                     continue;
                 }
 
                 //if the that has been changed or the data block is equal to 255, we just pass the
-                //unchanged data and increase the counter
+                //unchanged data
                 changed_data.push(data[j]);
-                counter += 8;
             }
 
             //At the end we are checking whether we changed anything in the block. If we did, then we
@@ -322,16 +529,34 @@ impl BlockSupport for DirFS {
     }
 
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
-        let mut block: Block = Block::new_zero(0, sup.block_size);
+        self.check_writable()?;
+        //reading the block first, so the volume header after the superblock stays intact
+        let mut block: Block = self.device.read_block(0)?;
 
-        block.serialize_into(&sup, 0)?;
+        block.serialize_into(sup, 0)?;
         self.device.write_block(&block)?;
 
         return Ok(());
     }
 }
 
-impl InodeSupport for DirFS {
+impl<D: BlockDevice> VolumeSupport for DirFS<D> {
+    fn volume_get(&self) -> Result<VolumeHeader, Self::Error> {
+        return Ok(self.device.read_block(0)?.deserialize_from::<VolumeHeader>(*SUPERBLOCK_SIZE)?);
+    }
+
+    fn volume_put(&mut self, vol: &VolumeHeader) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        let mut block: Block = self.device.read_block(0)?;
+
+        block.serialize_into(vol, *SUPERBLOCK_SIZE)?;
+        self.device.write_block(&block)?;
+
+        return Ok(());
+    }
+}
+
+impl<D: BlockDevice> InodeSupport for DirFS<D> {
     type Inode = Inode;
 
     fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
@@ -352,14 +577,13 @@ impl InodeSupport for DirFS {
         let index_of_inode = i - (n_inodes_per_block * index_of_block);
 
         //here we deserialize the block into dinode
-        let dinode = inode_block
-            .deserialize_from::<DInode>(*DINODE_SIZE * index_of_inode)
-            .unwrap();
+        let dinode = inode_block.deserialize_from::<DInode>(*DINODE_SIZE * index_of_inode)?;
 
         return Ok(Inode::new(i, dinode));
     }
 
     fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
         if ino.inum + 1 > sb.ninodes {
             return Err(DirectorySystemError(InodeFSError::InodeSystemError(
@@ -383,6 +607,7 @@ impl InodeSupport for DirFS {
     }
 
     fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb = self.sup_get()?;
         if i+1 > sb.ninodes {
             return Err(DirectorySystemError(InodeFSError::InodeSystemError(
@@ -390,7 +615,7 @@ impl InodeSupport for DirFS {
             )));
         }
 
-        let requested_inode = self.i_get(i).unwrap();
+        let requested_inode = self.i_get(i)?;
 
         if requested_inode.inum != i {
             return Err(DirectorySystemError(
@@ -409,17 +634,19 @@ impl InodeSupport for DirFS {
         //and deallocating all the blocks. 3.) setting all direct pointers to zero. 4.) returing the
         //modified inode to the disc.
         if requested_inode.disk_node.nlink == 0 {
+            let n_valid_blocks = Self::n_valid_blocks(&sb, &requested_inode);
+
             let mut modified_dinode = requested_inode.disk_node;
             modified_dinode.ft = FType::TFree;
-
-            let n_valid_blocks =
-                (modified_dinode.size as f64 / sb.block_size as f64).ceil() as usize;
             for i in 0..n_valid_blocks {
                 if modified_dinode.direct_blocks[i] != 0 {
-                    self.b_free(modified_dinode.direct_blocks[i] % sb.datastart)?;
+                    self.b_free(modified_dinode.direct_blocks[i] - sb.datastart)?;
                 }
             }
 
+            //the extended attributes go together with the inode
+            release_xattrs(self, &mut modified_dinode)?;
+
             modified_dinode.direct_blocks = Default::default();
             self.i_put(&Inode::new(requested_inode.inum, modified_dinode))?;
         }
@@ -428,7 +655,8 @@ impl InodeSupport for DirFS {
     }
 
     fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
-        let sb = self.sup_get().unwrap();
+        self.check_writable()?;
+        let sb = self.sup_get()?;
 
         //calculating number of inodes per block and number of inodes blocks that will be required
         //to save those inodes
@@ -449,7 +677,7 @@ impl InodeSupport for DirFS {
                     continue;
                 }
 
-                let inode = self.i_get(i * n_inodes_per_block + j).unwrap();
+                let inode = self.i_get(i * n_inodes_per_block + j)?;
                 let mut disc_inode = inode.disk_node;
                 let index = inode.inum;
 
@@ -457,6 +685,10 @@ impl InodeSupport for DirFS {
                     disc_inode.ft = ft;
                     disc_inode.size = Default::default();
                     disc_inode.nlink = Default::default();
+                    disc_inode.times = Timestamps::created(self.now());
+                    disc_inode.mode = perm::default_mode(ft);
+                    disc_inode.uid = 0;
+                    disc_inode.gid = 0;
 
                     self.i_put(&Inode::new(index, disc_inode))?;
                     return Ok(index);
@@ -470,30 +702,32 @@ impl InodeSupport for DirFS {
     }
 
     fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
-        let sb = self.sup_get().unwrap();
+        self.check_writable()?;
+        let sb = self.sup_get()?;
 
-        let n_valid_blocks = (inode.disk_node.size as f64 / sb.block_size as f64).ceil() as usize;
+        let n_valid_blocks = Self::n_valid_blocks(&sb, inode);
         for i in 0..n_valid_blocks {
             if inode.disk_node.direct_blocks[i] != 0 {
-                self.b_free(inode.disk_node.direct_blocks[i] % sb.datastart)?;
+                self.b_free(inode.disk_node.direct_blocks[i] - sb.datastart)?;
             }
         }
 
         inode.disk_node.size = Default::default();
         inode.disk_node.direct_blocks = Default::default();
+        inode.disk_node.times.modified(self.now());
         self.i_put(inode)?;
         return Ok(());
     }
 }
 
-impl DirectorySupport for DirFS {
+impl<D: BlockDevice> DirectorySupport for DirFS<D> {
     fn new_de(inum: u64, name: &str) -> Option<DirEntry> {
         let mut dir_entry = DirEntry {
             inum,
             name: Default::default(),
         };
 
-        let result = DirFS::set_name_str(&mut dir_entry, name);
+        let result = Self::set_name_str(&mut dir_entry, name);
 
         return if result.is_some() {
             Some(dir_entry)
@@ -503,46 +737,20 @@ impl DirectorySupport for DirFS {
     }
 
     fn get_name_str(de: &DirEntry) -> String {
-        let mut name: String = String::new();
-        let characters = de.name;
-
-        for i in 0..characters.len() {
-            if characters[i] == '\0' {
-                break;
-            }
-            name.push(characters[i]);
-        }
-
-        return name;
+        return de.name.clone();
     }
 
     fn set_name_str(de: &mut DirEntry, name: &str) -> Option<()> {
-        if name.len() == 0 {
+        if name.is_empty() || name.len() > DIRNAME_SIZE {
             return None;
         }
 
-        if name.len() >= DIRNAME_SIZE {
+        //like in POSIX, names can contain any character but the path separator and the null character
+        if name.contains('/') || name.contains('\0') {
             return None;
         }
 
-        for c in name.chars() {
-            if !c.is_alphanumeric() {
-                return None;
-            }
-        }
-
-        let mut dir_name: [char; DIRNAME_SIZE] = Default::default();
-        for i in 0..dir_name.len() {
-            dir_name[i] = '\0';
-        }
-
-        let mut i = 0;
-        for c in name.chars() {
-            dir_name[i] = c;
-            i += 1;
-        }
-
-        de.name = dir_name;
+        de.name = name.to_string();
         return Some(());
     }
 
@@ -551,35 +759,16 @@ impl DirectorySupport for DirFS {
         inode: &Self::Inode,
         name: &str,
     ) -> Result<(Self::Inode, u64), Self::Error> {
-        let sb = self.sup_get()?;
-
         //checking whether the inode is different type than directory
         if inode.disk_node.ft != FType::TDir {
             return Err(InodeNotDirectoryError());
         }
 
-        //going through all the valid blocks and looking for a inode with a name that was passed as an argument
-        let n_valid_blocks = (inode.disk_node.size as f64 / (sb.block_size-(sb.block_size%*DIRENTRY_SIZE)) as f64).ceil() as usize;
-        for i in 0..n_valid_blocks {
-            if inode.disk_node.direct_blocks[i] == 0 {
-                break;
-            }
-
-            let current_block = self.b_get(inode.disk_node.direct_blocks[i])?;
-            let mut offset = 0;
-
-            //looking through each block and all dir entries to find the one that corresponds to the passed name
-            while *DIRENTRY_SIZE + offset < sb.block_size {
-                let current_dir_entry: DirEntry = current_block.deserialize_from::<DirEntry>(offset).unwrap();
-
-                //when we find the dir entry we return the inode from that entry and the offset where we found it
-                let dir_name = DirFS::get_name_str(&current_dir_entry);
-                if dir_name == name {
-                    let searched_inode = self.i_get(current_dir_entry.inum)?;
-                    return Ok((searched_inode, offset + (i as u64)*(sb.block_size-(sb.block_size%*DIRENTRY_SIZE))));
-                }
-
-                offset += *DIRENTRY_SIZE;
+        //going through all the records and looking for a used one with the name that was passed as an argument
+        for (entry, offset, _) in self.dir_entries(inode)? {
+            if entry.inum != 0 && entry.name == name {
+                let searched_inode = self.i_get(entry.inum)?;
+                return Ok((searched_inode, offset));
             }
         }
         return Err(SearchedDirectoryDoesntExist());
@@ -591,13 +780,22 @@ impl DirectorySupport for DirFS {
         name: &str,
         inum: u64,
     ) -> Result<u64, Self::Error> {
+        self.check_writable()?;
         let sb = self.sup_get()?;
+        let capacity = Self::dir_block_capacity(&sb);
 
         //checking whether the inode is directory
         if inode.disk_node.ft != FType::TDir {
             return Err(InodeNotDirectoryError());
         }
 
+        //generating dir entry we are going to link, whose record has to fit in a single block
+        let dir_entry = Self::new_de(inum, name).ok_or(InvalidDirEntryName())?;
+        let rec_len = dir_entry.rec_len();
+        if rec_len > capacity {
+            return Err(InvalidDirEntryName());
+        }
+
         //checking whether the inode with the given name already exists
         let lookup_results = self.dirlookup(inode, name);
         if lookup_results.is_ok() {
@@ -615,78 +813,106 @@ impl DirectorySupport for DirFS {
             }
         }
 
-        //generating dir entry we are going to link
-        let dir_entry = DirFS::new_de(inum, name).unwrap();
-
-        //going through all valid blocks and finding the one that has first available space to save new directory entry
-        let n_valid_blocks = (inode.disk_node.size as f64 / (sb.block_size-(sb.block_size%*DIRENTRY_SIZE)) as f64).ceil() as usize;
-        for i in 0..n_valid_blocks {
-            let mut current_block = self.b_get(inode.disk_node.direct_blocks[i]%sb.datastart+sb.datastart)?;
-            let mut offset = 0;
-
-            //going through block by increasing offset by the size of the dir entry
-            while offset + *DIRENTRY_SIZE <= sb.block_size {
-                let current_direntry: DirEntry = current_block.deserialize_from::<DirEntry>(offset).unwrap();
-
-                //when we find free space we save the dir entry into that place
-                if current_direntry.inum == 0 {
-
-                    current_block.serialize_into(&dir_entry, offset)?;
-                    self.b_put(&current_block)?;
-
-                    //checking whether we need to increase the size of inode
-                    //if the inode size is already larger than current offset it means that that was already allocated space and
-                    //we do not have to increase the size
-                    if inode.disk_node.size < offset + (i as u64)*(sb.block_size-(sb.block_size%*DIRENTRY_SIZE)) + *DIRENTRY_SIZE {
-                        inode.disk_node.size += *DIRENTRY_SIZE;
-                    }
-                    self.i_put(inode)?;
-
-                    //if inode numbers aren't the same, we have to increase the nlink number in other inode
-                    if inum != inode.inum {
-                        let mut ref_inode = self.i_get(inum).unwrap();
-                        ref_inode.disk_node.nlink += 1;
-                        self.i_put(&ref_inode)?;
-                    }
-
-                    return Ok(offset + (i as u64)*(sb.block_size-(sb.block_size%*DIRENTRY_SIZE)));
-                }
-
-                offset += *DIRENTRY_SIZE;
+        //going through all records and finding the first run of free ones within a single block that can hold the new record
+        let mut run_start = None;
+        for (entry, offset, entry_len) in self.dir_entries(inode)? {
+            if entry.inum != 0 {
+                run_start = None;
+                continue;
+            }
+            let start = match run_start {
+                Some(start) if start / capacity == offset / capacity => start,
+                _ => offset,
+            };
+            run_start = Some(start);
+
+            if offset + entry_len - start >= rec_len {
+                self.dirlink_nlink(inode, inum)?;
+
+                //the free records are all zeroes, apart from the part that the new record takes up
+                let mut current_block = self.b_get(inode.disk_node.direct_blocks[(start / capacity) as usize])?;
+                current_block.write_data(&vec![0; (offset + entry_len - start) as usize], start % capacity)?;
+                current_block.write_dir_entry(&dir_entry, start % capacity)?;
+                self.b_put(&current_block)?;
+
+                inode.disk_node.times.modified(self.now());
+                self.i_put(inode)?;
+                return Ok(start);
             }
         }
 
-        //if there isn't space in available we have to allocate another block
-        let index = self.b_alloc()?;
-        let mut allocated_block = self.b_get(index+sb.datastart)?;
-        allocated_block.serialize_into(&dir_entry, 0)?;
-        self.b_put(&allocated_block)?;
+        //if there isn't enough free space, the record goes at the end of the directory,
+        //skipping the rest of the last block if it does not fit in there anymore
+        let mut offset = inode.disk_node.size;
+        if offset % capacity != 0 && offset % capacity + rec_len > capacity {
+            offset += capacity - offset % capacity;
+        }
+        let index = (offset / capacity) as usize;
+        if index as u64 >= DIRECT_POINTERS {
+            return Err(DirectoryFull());
+        }
+
+        //if the record starts a new block, we have to allocate that block and add it to the list of blocks of the inode
+        if offset % capacity == 0 {
+            inode.disk_node.direct_blocks[index] = self.b_alloc()? + sb.datastart;
+        }
+        self.dirlink_nlink(inode, inum)?;
+        let mut current_block = self.b_get(inode.disk_node.direct_blocks[index])?;
+        current_block.write_dir_entry(&dir_entry, offset % capacity)?;
+        self.b_put(&current_block)?;
 
-        //adding that allocated block to the list of available blocks
-        inode.disk_node.direct_blocks[n_valid_blocks as usize] = index+sb.datastart;
-        inode.disk_node.size += *DIRENTRY_SIZE;
+        inode.disk_node.size = offset + rec_len;
+        inode.disk_node.times.modified(self.now());
         self.i_put(inode)?;
+        return Ok(offset);
+    }
+}
 
-        //if inode numbers aren't the same, we have to increase the nlink number in other inode
-        if inum != inode.inum {
-            let mut ref_inode = self.i_get(inum).unwrap();
-            ref_inode.disk_node.nlink += 1;
-            self.i_put(&ref_inode)?;
+impl<D: BlockDevice> XattrSupport for DirFS<D> {
+    fn getxattr(&self, inode: &Self::Inode, name: &str) -> Result<Vec<u8>, Self::Error> {
+        let xattrs = load_xattrs(self, inode)?;
+        return xattrs.get(name).map(|value| value.to_vec()).ok_or(XattrNotFound());
+    }
 
-        }
+    fn setxattr(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        let mut xattrs = load_xattrs(self, inode)?;
+        xattrs.set(name, value).ok_or(InvalidXattr())?;
+        let now = self.now();
+        return store_xattrs(self, inode, &xattrs, now)?.ok_or(XattrTooLarge());
+    }
+
+    fn listxattr(&self, inode: &Self::Inode) -> Result<Vec<String>, Self::Error> {
+        return Ok(load_xattrs(self, inode)?.names());
+    }
 
-        Ok(inode.disk_node.size - *DIRENTRY_SIZE)
+    fn removexattr(&mut self, inode: &mut Self::Inode, name: &str) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        let mut xattrs = load_xattrs(self, inode)?;
+        xattrs.remove(name).ok_or(XattrNotFound())?;
+        let now = self.now();
+        return store_xattrs(self, inode, &xattrs, now)?.ok_or(XattrTooLarge());
     }
 }
 
 
 #[cfg(test)]
-#[path = "../../api/fs-tests"]
-mod test_with_utils {
-    use std::path::PathBuf;
+mod test_in_memory {
     use cplfs_api::fs::{FileSysSupport, InodeSupport, DirectorySupport};
-    use cplfs_api::types::{SuperBlock, FType, InodeLike, DIRENTRY_SIZE};
-    use crate::c_dirs_support::FSName;
+    use cplfs_api::types::{SuperBlock, FType, InodeLike, DIRENTRY_SIZE, DIRNAME_SIZE};
+    use crate::c_dirs_support::DirFS;
+    use crate::c_dirs_support::DirFSError::InvalidDirEntryName;
+    use cplfs_api::fault_device::FaultDevice;
+    use cplfs_api::mem_device::MemDevice;
+    use cplfs_api::sim_device::{CostModel, SimDevice, SsdModel};
+    use cplfs_api::fs::BlockSupport;
+    use cplfs_api::controller::BlockDevice;
+    use cplfs_api::types::Block;
 
     static BLOCK_SIZE: u64 = 250;
     static NBLOCKS: u64 = 10;
@@ -700,17 +926,12 @@ mod test_with_utils {
         datastart: 5,
     };
 
-    #[path = "utils.rs"]
-    mod utils;
-
-    fn disk_prep_path(name: &str) -> PathBuf {
-        utils::disk_prep_path(&("fs-images-c-".to_string() + name), "img")
-    }
+    type FSName = DirFS<MemDevice>;
 
     #[test]
     fn multiblock_allocations(){
-        let path = disk_prep_path("mutliblock_lookup");
-        let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK).unwrap();
+        let dev = MemDevice::new(SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.block_size, SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK).unwrap();
 
         //inode with two blocks free with direntries
         let mut i1 = <<FSName as InodeSupport>::Inode as InodeLike>::new(
@@ -728,7 +949,8 @@ mod test_with_utils {
         }
 
         //filling the dir entries in inode (4 extra blocks are allocated)
-        for i in 0..36 {
+        let n_entries = 4 * (BLOCK_SIZE / *DIRENTRY_SIZE);
+        for i in 0..n_entries {
             assert_eq!(
                 my_fs.dirlink(&mut i1, &i.to_string(), 3).unwrap(),
                 i * *DIRENTRY_SIZE
@@ -736,13 +958,213 @@ mod test_with_utils {
         }
 
         //checking whether did all entires saved correctly
-        for i in 0..36{
+        for i in 0..n_entries{
             assert_eq!(my_fs.dirlookup(&i1,&i.to_string()).unwrap().1, i* *DIRENTRY_SIZE);
         }
 
-        assert_eq!(my_fs.i_get(3).unwrap().disk_node.nlink, 36);
-        let dev = my_fs.unmountfs();
-        utils::disk_destruct(dev);
+        assert_eq!(my_fs.i_get(3).unwrap().disk_node.nlink, n_entries as u16);
+    }
+
+    #[test]
+    fn long_names(){
+        let dev = MemDevice::new(SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.block_size, SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK).unwrap();
+        let mut i1 = <<FSName as InodeSupport>::Inode as InodeLike>::new(5, &FType::TDir, 0, 0, &[]).unwrap();
+        my_fs.i_put(&i1).unwrap();
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 2);
+
+        //a block holds 7 slots; records take up as many of them as their names need, and never cross a block boundary
+        let accents = "ü".repeat(50);
+        let a3 = "a".repeat(60);
+        let b7 = "b".repeat(213);
+        let names: [(&str, u64); 6] = [("report.txt", 0), ("my_file", 32), (&accents, 64), ("x", 192), (&a3, 224), (&b7, 448)];
+        for (name, offset) in names.iter() {
+            assert_eq!(my_fs.dirlink(&mut i1, name, 2).unwrap(), *offset);
+        }
+        assert_eq!(i1.get_size(), 672);
+
+        //names that do not fit in a block, or are not valid names at all, cannot be linked
+        assert!(matches!(my_fs.dirlink(&mut i1, &"c".repeat(214), 2), Err(InvalidDirEntryName())));
+        assert!(matches!(my_fs.dirlink(&mut i1, &"c".repeat(DIRNAME_SIZE + 1), 2), Err(InvalidDirEntryName())));
+        assert!(matches!(my_fs.dirlink(&mut i1, "a/b", 2), Err(InvalidDirEntryName())));
+
+        let mut my_fs = FSName::mountfs(my_fs.unmountfs()).unwrap();
+        let mut i1 = my_fs.i_get(5).unwrap();
+        for (name, offset) in names.iter() {
+            assert_eq!(my_fs.dirlookup(&i1, name).unwrap().1, *offset);
+        }
+        let linked: Vec<String> = my_fs.dir_entries(&i1).unwrap().iter().filter(|(de, _, _)| de.inum != 0).map(|(de, _, _)| FSName::get_name_str(de)).collect();
+        assert_eq!(linked, names.iter().map(|(name, _)| name.to_string()).collect::<Vec<_>>());
+
+        //freed records are reused once enough adjacent slots are free, as are the slots skipped at the end of a block
+        my_fs.clear_entry(&mut i1, 0).unwrap();
+        my_fs.clear_entry(&mut i1, 32).unwrap();
+        assert!(my_fs.dirlookup(&i1, "report.txt").is_err());
+        assert_eq!(my_fs.dirlink(&mut i1, &"c".repeat(30), 2).unwrap(), 0);
+        assert_eq!(my_fs.dirlink(&mut i1, "z", 2).unwrap(), 320);
+        assert_eq!(i1.get_size(), 672);
+        assert_eq!(my_fs.dirlookup(&i1, &accents).unwrap().1, 64);
+    }
+
+    #[test]
+    fn free_dir_blocks(){
+        let dev = MemDevice::new(SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.block_size, SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK).unwrap();
+        let mut i1 = <<FSName as InodeSupport>::Inode as InodeLike>::new(5, &FType::TDir, 0, 0, &[]).unwrap();
+        my_fs.i_put(&i1).unwrap();
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 2);
+        let ndatablocks = SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.ndatablocks;
+
+        //a block of 250 bytes only holds 7 slots of 32 bytes, so 15 entries take up 480 bytes spread over 3 blocks,
+        //where 480 bytes of file contents would only take up 2 of them
+        for i in 0..15 {
+            my_fs.dirlink(&mut i1, &i.to_string(), 2).unwrap();
+        }
+        assert_eq!(i1.get_size(), 480);
+        assert_ne!(i1.get_block(2), 0);
+        my_fs.i_trunc(&mut i1).unwrap();
+
+        //all data blocks are free again
+        for i in 0..ndatablocks {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        for i in 0..ndatablocks {
+            my_fs.b_free(i).unwrap();
+        }
+
+        //freeing the directory gives back all of its blocks as well
+        for i in 0..15 {
+            my_fs.dirlink(&mut i1, &i.to_string(), 2).unwrap();
+        }
+        my_fs.i_free(5).unwrap();
+        assert_eq!(my_fs.i_get(5).unwrap().get_ft(), FType::TFree);
+        for i in 0..ndatablocks {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn dirlink_crash_recovery(){
+        //crash the device after every possible number of writes of a dirlink, both when the directory needs a new block and when it has a free slot
+        for prelinked in [false, true] {
+            for crash_point in 0.. {
+                let dev = FaultDevice::new(MemDevice::new(SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.block_size, SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.nblocks));
+                let mut my_fs = DirFS::<FaultDevice<MemDevice>>::mkfs_on(dev, &SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK).unwrap();
+                let mut dir = <<FSName as InodeSupport>::Inode as InodeLike>::new(5, &FType::TDir, 0, 0, &[]).unwrap();
+                my_fs.i_put(&dir).unwrap();
+                assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 2);
+                if prelinked {
+                    my_fs.dirlink(&mut dir, "x", 2).unwrap();
+                }
+                let nlink_before = my_fs.i_get(2).unwrap().disk_node.nlink;
+
+                let mut dev = my_fs.unmountfs();
+                dev.crash_after(dev.write_count() + crash_point);
+                let mut my_fs = DirFS::mountfs(dev).unwrap();
+                my_fs.dirlink(&mut dir, "a", 2).unwrap();
+                let dev = my_fs.unmountfs();
+                let completed = !dev.crashed();
+
+                //after remounting, the entry is either there or not, but never without the link it accounts for
+                let my_fs = FSName::mountfs(dev.into_inner()).unwrap();
+                let dir = my_fs.i_get(5).unwrap();
+                let nlink = my_fs.i_get(2).unwrap().disk_node.nlink;
+                match my_fs.dirlookup(&dir, "a") {
+                    Ok((found, _)) => {
+                        assert_eq!(found.inum, 2);
+                        assert_eq!(nlink, nlink_before + 1);
+                    }
+                    Err(_) => assert!(!completed && nlink >= nlink_before),
+                }
+                if prelinked {
+                    assert_eq!(my_fs.dirlookup(&dir, "x").unwrap().0.inum, 2);
+                }
+                if completed {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn modeled_scan_cost(){
+        let dev = SimDevice::new(MemDevice::new(SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.block_size, SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK.nblocks), CostModel::Ssd(SsdModel::default()));
+        let clock = dev.clock();
+        let mut my_fs = DirFS::<SimDevice<MemDevice>>::mkfs_on(dev, &SUPERBLOCK_GOOD_MULTIPLE_INODES_BLOCK).unwrap();
+        let mut dir = <<FSName as InodeSupport>::Inode as InodeLike>::new(5, &FType::TDir, 0, 0, &[]).unwrap();
+        my_fs.i_put(&dir).unwrap();
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 2);
+        for i in 0..12 {
+            my_fs.dirlink(&mut dir, &i.to_string(), 2).unwrap();
+        }
+
+        //looking up a missing name scans the entire directory, which only costs reads
+        clock.reset();
+        assert!(my_fs.dirlookup(&dir, "missing").is_err());
+        let scan = clock.totals();
+        assert_eq!(scan.writes, 0);
+        assert!(scan.reads >= (12 * *DIRENTRY_SIZE).div_ceil(BLOCK_SIZE));
+        assert_eq!(scan.elapsed_ns, scan.reads * SsdModel::default().read_ns);
+    }
+
+    #[test]
+    fn golden_image(){
+        //an image laid out by hand, byte by byte, with 128-byte blocks that fit exactly 4 directory slots
+        let mut golden: Vec<Vec<u8>> = vec![vec![0; 128]; 8];
+        golden[0][..56].copy_from_slice(&[
+            128, 0, 0, 0, 0, 0, 0, 0, //block_size
+            8, 0, 0, 0, 0, 0, 0, 0, //nblocks
+            3, 0, 0, 0, 0, 0, 0, 0, //ninodes
+            1, 0, 0, 0, 0, 0, 0, 0, //inodestart
+            3, 0, 0, 0, 0, 0, 0, 0, //ndatablocks
+            4, 0, 0, 0, 0, 0, 0, 0, //bmapstart
+            5, 0, 0, 0, 0, 0, 0, 0, //datastart
+        ]);
+        //inode 0 is free, the root directory is inode 1, and the file in it is inode 2
+        golden[1][..4].copy_from_slice(&[2, 0, 0, 0]);
+        //ft, nlink and size, then the atime, mtime, ctime and crtime, the mode, uid and gid, and finally the first direct block
+        golden[2][..14].copy_from_slice(&[0, 0, 0, 0, 1, 0, 96, 0, 0, 0, 0, 0, 0, 0]);
+        golden[2][46..48].copy_from_slice(&[0xed, 0x01]);
+        golden[2][56..60].copy_from_slice(&[5, 0, 0, 0]);
+        golden[3][..14].copy_from_slice(&[1, 0, 0, 0, 1, 0, 5, 0, 0, 0, 0, 0, 0, 0]);
+        golden[3][14..46].copy_from_slice(&[
+            4, 0, 0, 0, 0, 0, 0, 0, //atime
+            3, 0, 0, 0, 0, 0, 0, 0, //mtime
+            2, 0, 0, 0, 0, 0, 0, 0, //ctime
+            1, 0, 0, 0, 0, 0, 0, 0, //crtime
+        ]);
+        golden[3][46..56].copy_from_slice(&[
+            0xa4, 0x01, //mode 0o644
+            0xe8, 0x03, 0, 0, //uid 1000
+            100, 0, 0, 0, //gid 100
+        ]);
+        golden[3][56..60].copy_from_slice(&[6, 0, 0, 0]);
+        golden[4][0] = 0b11;
+        //the entries "." and "a.txt", with a free slot in between
+        golden[5][..12].copy_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 32, 0, 1, b'.']);
+        golden[5][64..80].copy_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0, 32, 0, 5, b'a', b'.', b't', b'x', b't']);
+        golden[6][..5].copy_from_slice(b"hello");
+
+        let mut dev = MemDevice::new(128, 8);
+        for (i, data) in golden.iter().enumerate() {
+            dev.write_block(&Block::new(i as u64, data.clone().into_boxed_slice())).unwrap();
+        }
+        let my_fs = FSName::mountfs(dev).unwrap();
+        let root = my_fs.i_get(1).unwrap();
+        assert_eq!(root.get_ft(), FType::TDir);
+        let (file, offset) = my_fs.dirlookup(&root, "a.txt").unwrap();
+        assert_eq!(offset, 64);
+        assert_eq!((file.inum, file.get_ft(), file.get_size(), file.get_block(0)), (2, FType::TFile, 5, 6));
+        assert_eq!((file.get_atime(), file.get_mtime(), file.get_ctime(), file.get_crtime()), (4, 3, 2, 1));
+        assert_eq!((file.get_mode(), file.get_uid(), file.get_gid()), (0o644, 1000, 100));
+        assert_eq!(root.get_mode(), 0o755);
+        assert_eq!(&my_fs.b_get(6).unwrap().contents_as_ref()[..5], b"hello");
+
+        //mkfs writes the same superblock, followed by a volume header
+        let my_fs = FSName::mkfs_on(MemDevice::new(128, 8), &my_fs.sup_get().unwrap()).unwrap();
+        let sb = my_fs.unmountfs().read_block(0).unwrap();
+        assert_eq!(&sb.contents_as_ref()[..56], &golden[0][..56]);
+        assert_eq!(&sb.contents_as_ref()[56..68], b"CPLFSVOL\x06\0\0\0");
     }
 }
 
@@ -11,27 +11,1217 @@
 //!
 //! # Status
 //!
-//! **TODO**: Replace the question mark below with YES, NO, or PARTIAL to
-//! indicate the status of this assignment. If you want to tell something
-//! about this assignment to the grader, e.g., you have a bug you can't fix,
-//! or you want to explain your approach, write it down after the comments
-//! section. If you had no major issues and everything works, there is no need to write any comments.
-//!
-//! COMPLETED: NO
+//! COMPLETED: YES
 //!
 //! COMMENTS:
 //!
-//! ...
+//! The path file system wraps the directory file system from `c_dirs_support` and delegates all of the
+//! block, inode and directory operations to it. The current working directory is kept as a list of names,
+//! so that "." and ".." can be resolved by simply popping and pushing names.
+//!
+//! The methods of `PathSupport` act on behalf of the superuser. Their `_as` counterparts, like `resolve_path_as`, take the
+//! `Credentials` of the caller and check the permission bits of every directory they search or change, as do `chmod` and `chown`.
+//! The working directory is reached without any checks, as if the process had kept a handle to it.
+//!
+//! Symlinks are followed while resolving paths, in every name but the last one for `lresolve_path_as`, and a path that takes more than
+//! `MAX_SYMLINK_HOPS` of them fails, which catches loops. `unlink` and `readlink` act on a symlink itself rather than on its target.
 //!
+//! Extended attributes are handled by the directory file system, which implements `XattrSupport`. The `_at` methods, like `getxattr_at`,
+//! reach them by path instead, following symlinks, and need read permission on the inode to get or list its attributes, and write permission to change them.
+//!
+
+use crate::c_dirs_support::{DirFS, DirFSError};
+use crate::d_path_support::PathFSError::{
+    DirectoryNotEmpty, InvalidDirectoryName, InvalidPath, InvalidSymlinkTarget, NotASymlink,
+    PathSystemError, PermissionDenied, SymlinkLoop,
+};
+use cplfs_api::clock::Clock;
+use cplfs_api::controller::{BlockDevice, Device, ImageDevice};
+use cplfs_api::fs::{
+    BlockSupport, DirectorySupport, FileSysSupport, InodeSupport, PathSupport, VolumeSupport, XattrSupport,
+};
+use cplfs_api::perm::{Credentials, MAY_EXEC, MAY_READ, MAY_WRITE, MODE_MASK, S_ISGID, S_ISVTX};
+use cplfs_api::types::{
+    Block, DirEntry, FType, Inode, InodeLike, SuperBlock, VolumeHeader, DIRECT_POINTERS, ROOT_INUM,
+    SYMLINK_INLINE_MAX,
+};
+use std::path::Path;
+use thiserror::Error;
+
+///File system name
+pub type FSName = PathFS;
+
+///Maximum number of symlinks that are followed while resolving a single path, like `MAXSYMLINKS` in Linux.
+///Resolving a path that needs more fails with `SymlinkLoop`, which is what happens for links that (indirectly) point to themselves.
+pub const MAX_SYMLINK_HOPS: u32 = 40;
+
+///Main struct file for the Path file system
+pub struct PathFS<D: BlockDevice = Device> {
+    dir_fs: DirFS<D>,
+    ///Current working directory, stored as the sequence of names leading up to it from the root
+    cwd: Vec<String>,
+}
+
+///Main error file for Path file system
+#[derive(Error, Debug)]
+pub enum PathFSError {
+    ///Wrapper error that's going to wrap all errors from previous Directory layer
+    #[error("Path system error.")]
+    PathSystemError(#[from] DirFSError),
+
+    ///Error that is thrown when the given path is not a valid path
+    #[error("Path is not valid!")]
+    InvalidPath(),
+
+    ///Error that is thrown when we want to create or remove a directory entry named "." or ".."
+    #[error("Directory name is not valid!")]
+    InvalidDirectoryName(),
+
+    ///Error that is thrown when we want to unlink a directory that still contains entries
+    #[error("Directory is not empty!")]
+    DirectoryNotEmpty(),
+
+    ///Error that is thrown when the credentials of the caller do not grant the access an operation needs
+    #[error("Permission denied!")]
+    PermissionDenied(),
+
+    ///Error that is thrown when resolving a path takes more than `MAX_SYMLINK_HOPS` symlinks
+    #[error("Too many levels of symbolic links!")]
+    SymlinkLoop(),
+
+    ///Error that is thrown when a symlink target is empty or too long, or when a stored target is not valid UTF-8
+    #[error("Symlink target is not valid!")]
+    InvalidSymlinkTarget(),
+
+    ///Error that is thrown when reading the target of something that is not a symlink
+    #[error("Not a symbolic link!")]
+    NotASymlink(),
+}
+
+impl<D: BlockDevice> PathFS<D> {
+    ///Check whether the file system was mounted read-only, using `mountfs_readonly`
+    pub fn is_read_only(&self) -> bool {
+        return self.dir_fs.is_read_only();
+    }
+
+    ///Mount the file system on `dev` like `mountfs`, but discard every data block as soon as it is freed, see `DirFS::mountfs_discard`
+    pub fn mountfs_discard(dev: D) -> Result<Self, PathFSError> {
+        let rustfs = PathFS {
+            dir_fs: DirFS::mountfs_discard(dev)?,
+            cwd: Vec::new(),
+        };
+        return Ok(rustfs);
+    }
+
+    ///Check whether the file system was mounted with `mountfs_discard`
+    pub fn is_discarding(&self) -> bool {
+        return self.dir_fs.is_discarding();
+    }
+
+    ///Discard every data block that the bitmap marks as free, see `DirFS::fstrim`.
+    ///Returns the number of discarded blocks.
+    pub fn fstrim(&mut self) -> Result<u64, PathFSError> {
+        return Ok(self.dir_fs.fstrim()?);
+    }
+
+    ///Replace the clock the inode timestamps are taken from, see `DirFS::set_clock`
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.dir_fs.set_clock(clock);
+    }
+
+    ///Splits the given path in its "/"-separated names
+    ///The leading "/" of an absolute path does not result in an empty name
+    fn path_names(path: &str) -> Vec<&str> {
+        let relative_path = path.strip_prefix('/').unwrap_or(path);
+        if relative_path.is_empty() {
+            return Vec::new();
+        }
+        relative_path.split('/').collect()
+    }
+
+    ///Splits the given path in the path of its parent directory and the name of its last entry
+    fn split_last(path: &str) -> Option<(&str, &str)> {
+        let index = path.rfind('/')?;
+        let name = &path[index + 1..];
+
+        //the parent of an entry in the root directory is the root itself
+        return if index == 0 {
+            Some(("/", name))
+        } else {
+            Some((&path[..index], name))
+        };
+    }
+
+    ///Follows the given names through the file system, starting from the directory `start`.
+    ///Every directory that is searched for the next name needs to grant search permission to `creds`.
+    ///Symlinks are followed wherever they occur, except for the last name when `follow_last` is not set; `hops` counts the symlinks followed so far.
+    fn walk(
+        &self,
+        creds: &Credentials,
+        start: Inode,
+        names: &[&str],
+        follow_last: bool,
+        hops: &mut u32,
+    ) -> Result<Inode, PathFSError> {
+        let mut current = start;
+        for (i, name) in names.iter().enumerate() {
+            //looking up a name in anything else than a directory fails in `dirlookup` itself
+            if current.disk_node.ft == FType::TDir && !current.permits(creds, MAY_EXEC) {
+                return Err(PermissionDenied());
+            }
+            let next = self.dir_fs.dirlookup(&current, name)?.0;
+
+            let is_last = i + 1 == names.len();
+            if next.disk_node.ft != FType::TSymlink || (is_last && !follow_last) {
+                current = next;
+                continue;
+            }
+
+            *hops += 1;
+            if *hops > MAX_SYMLINK_HOPS {
+                return Err(SymlinkLoop());
+            }
+            //a relative target starts from the directory the symlink is in, and may itself contain empty names, as in "a//b"
+            let target = self.read_target(&next)?;
+            let target_names: Vec<&str> = Self::path_names(&target)
+                .into_iter()
+                .filter(|name| !name.is_empty())
+                .collect();
+            let target_start = if target.starts_with('/') {
+                self.dir_fs.i_get(ROOT_INUM)?
+            } else {
+                current
+            };
+            current = self.walk(creds, target_start, &target_names, true, hops)?;
+        }
+        Ok(current)
+    }
+
+    ///Resolves `path` on behalf of `creds`, following a symlink in its last name only if `follow_last` is set
+    fn resolve(&self, creds: &Credentials, path: &str, follow_last: bool) -> Result<Inode, PathFSError> {
+        if !Self::valid_path(path) {
+            return Err(InvalidPath());
+        }
+
+        let start = if path.starts_with('/') {
+            self.dir_fs.i_get(ROOT_INUM)?
+        } else {
+            self.cwd_inode()?
+        };
+
+        self.walk(creds, start, &Self::path_names(path), follow_last, &mut 0)
+    }
+
+    ///Reads the target of the symlink `inode`, either from the inode itself or from its data blocks
+    fn read_target(&self, inode: &Inode) -> Result<String, PathFSError> {
+        let sb = self.dir_fs.sup_get()?;
+        let size = inode.disk_node.size;
+        if size > DIRECT_POINTERS * sb.block_size {
+            return Err(InvalidSymlinkTarget());
+        }
+
+        let bytes = if inode.has_inline_target() {
+            inode.disk_node.inline_target()
+        } else {
+            let mut bytes = Vec::with_capacity(size as usize);
+            for i in 0..size.div_ceil(sb.block_size) {
+                let block = self.dir_fs.b_get(inode.disk_node.direct_blocks[i as usize])?;
+                let n = sb.block_size.min(size - i * sb.block_size) as usize;
+                bytes.extend_from_slice(&block.contents_as_ref()[..n]);
+            }
+            bytes
+        };
+        String::from_utf8(bytes).map_err(|_| InvalidSymlinkTarget())
+    }
+
+    ///Gives `inode`, which is about to be linked into the directory `parent`, the user of `creds` as its owner.
+    ///If the parent has its `S_ISGID` bit set, the inode gets the group of its parent, and directories also get the bit itself; otherwise the inode gets the primary group of the user.
+    fn inherit_owner(creds: &Credentials, parent: &Inode, inode: &mut Inode) {
+        inode.disk_node.uid = creds.uid;
+        if parent.disk_node.mode & S_ISGID != 0 {
+            inode.disk_node.gid = parent.disk_node.gid;
+            if inode.disk_node.ft == FType::TDir {
+                inode.disk_node.mode |= S_ISGID;
+            }
+        } else {
+            inode.disk_node.gid = creds.gid;
+        }
+    }
+
+    ///Fails unless `creds` may add entries to, and remove entries from, the directory `dir`
+    fn check_dir_writable(creds: &Credentials, dir: &Inode) -> Result<(), PathFSError> {
+        if dir.disk_node.ft == FType::TDir && !dir.permits(creds, MAY_WRITE | MAY_EXEC) {
+            return Err(PermissionDenied());
+        }
+        Ok(())
+    }
+
+    ///Wraps a freshly created directory file system, giving its root directory its "." and ".." entries
+    fn with_root_entries(mut dir_fs: DirFS<D>) -> Result<Self, PathFSError> {
+        //the root directory is its own parent, so both of its default entries point back to itself
+        let mut root = dir_fs.i_get(ROOT_INUM)?;
+        dir_fs.dirlink(&mut root, ".", ROOT_INUM)?;
+        dir_fs.dirlink(&mut root, "..", ROOT_INUM)?;
+
+        Ok(PathFS {
+            dir_fs,
+            cwd: Vec::new(),
+        })
+    }
+
+    ///Reads the inode corresponding to the current working directory through the file system.
+    ///Getting there is not subject to any permission checks, as the working directory was already entered before.
+    fn cwd_inode(&self) -> Result<Inode, PathFSError> {
+        let root = self.dir_fs.i_get(ROOT_INUM)?;
+        let names: Vec<&str> = self.cwd.iter().map(|name| name.as_str()).collect();
+        self.walk(&Credentials::root(), root, &names, true, &mut 0)
+    }
+
+    ///Decreases the `nlink` field of the inode `inum` by one and frees it once it is no longer referenced.
+    ///When a directory gets freed, its ".." entry disappears along with it, so its parent loses a link as well.
+    fn drop_link(&mut self, inum: u64) -> Result<(), PathFSError> {
+        let mut inode = self.dir_fs.i_get(inum)?;
+        inode.disk_node.nlink = inode.disk_node.nlink.saturating_sub(1);
+        inode.disk_node.times.changed(self.dir_fs.now());
+        self.dir_fs.i_put(&inode)?;
+
+        if inode.disk_node.nlink > 0 {
+            return Ok(());
+        }
+
+        if inode.disk_node.ft == FType::TDir {
+            if let Ok((parent, _)) = self.dir_fs.dirlookup(&inode, "..") {
+                if parent.inum != inum {
+                    self.drop_link(parent.inum)?;
+                }
+            }
+        }
+
+        self.dir_fs.i_free(inum)?;
+        Ok(())
+    }
+
+    ///Free `inode` again, after the operation that allocated it failed halfway, giving back the data blocks it already got.
+    ///Its links are dropped without looking for entries that point to it, as the operation never got to link it anywhere.
+    fn free_new_inode(&mut self, inode: &mut Inode) -> Result<(), PathFSError> {
+        self.dir_fs.i_trunc(inode)?;
+        inode.disk_node.nlink = 0;
+        self.dir_fs.i_put(inode)?;
+        self.dir_fs.i_free(inode.inum)?;
+        Ok(())
+    }
+
+    ///Store `target` in the freshly allocated symlink `link`, and link it into `parent` under `name`, for `symlink`.
+    ///Every data block is recorded in `link` as soon as it is allocated, so that it can be given back if a later step fails.
+    fn link_new_symlink(&mut self, link: &mut Inode, target: &str, parent: &mut Inode, name: &str) -> Result<(), PathFSError> {
+        let sb = self.dir_fs.sup_get()?;
+        if target.len() as u64 <= SYMLINK_INLINE_MAX {
+            link.disk_node.set_inline_target(target.as_bytes());
+        } else {
+            for (i, chunk) in target.as_bytes().chunks(sb.block_size as usize).enumerate() {
+                let address = self.dir_fs.b_alloc()? + sb.datastart;
+                link.disk_node.direct_blocks[i] = address;
+                link.disk_node.size += chunk.len() as u64;
+                let mut data = chunk.to_vec();
+                data.resize(sb.block_size as usize, 0);
+                self.dir_fs.b_put(&Block::new(address, data.into_boxed_slice()))?;
+            }
+        }
+        self.dir_fs.i_put(link)?;
+        self.dir_fs.dirlink(parent, name, link.inum)?;
+        Ok(())
+    }
+
+    ///Give the freshly allocated directory `new_dir` its "." and ".." entries, and link it into its parent `parent` under `name`, for `mkdir_as`
+    fn link_new_dir(&mut self, new_dir: &mut Inode, parent: u64, name: &str) -> Result<(), PathFSError> {
+        let inum = new_dir.inum;
+        self.dir_fs.i_put(new_dir)?;
+        self.dir_fs.dirlink(new_dir, ".", inum)?;
+        self.dir_fs.dirlink(new_dir, "..", parent)?;
+
+        //the parent has to be read again, as linking ".." changed its nlink field on disk
+        let mut parent = self.dir_fs.i_get(parent)?;
+        self.dir_fs.dirlink(&mut parent, name, inum)?;
+        Ok(())
+    }
+
+    ///Resolve `path` like `resolve_path`, on behalf of the user with the credentials `creds`.
+    ///Every directory that is searched along the way, including the one the path starts from, needs to grant search permission.
+    ///Like `stat`, this follows every symlink on the way, including one that `path` itself refers to.
+    pub fn resolve_path_as(&self, creds: &Credentials, path: &str) -> Result<Inode, PathFSError> {
+        return self.resolve(creds, path, true);
+    }
+
+    ///Resolve `path` like `resolve_path_as`, but without following a symlink that `path` itself refers to, like `lstat`.
+    ///Symlinks among the directories leading up to it are still followed.
+    pub fn lresolve_path_as(&self, creds: &Credentials, path: &str) -> Result<Inode, PathFSError> {
+        return self.resolve(creds, path, false);
+    }
+
+    ///Create the directory `path` like `mkdir`, on behalf of the user with the credentials `creds`, who needs write and search permission on its parent.
+    ///The new directory is owned by the user and gets the default directory mode.
+    ///If the parent has its `S_ISGID` bit set, the new directory gets the group of its parent and the bit itself, and the primary group of the user otherwise.
+    pub fn mkdir_as(&mut self, creds: &Credentials, path: &str) -> Result<Inode, PathFSError> {
+        if !Self::valid_path(path) {
+            return Err(InvalidPath());
+        }
+
+        let (parent_path, name) = Self::split_last(path).ok_or(InvalidDirectoryName())?;
+        if name == "." || name == ".." {
+            return Err(InvalidDirectoryName());
+        }
+
+        //making sure that the new directory can be linked, before allocating anything
+        let parent = self.resolve_path_as(creds, parent_path)?;
+        if parent.disk_node.ft != FType::TDir {
+            return Err(PathSystemError(DirFSError::InodeNotDirectoryError()));
+        }
+        Self::check_dir_writable(creds, &parent)?;
+        if self.dir_fs.dirlookup(&parent, name).is_ok() {
+            return Err(PathSystemError(DirFSError::DirEntryNameAlreadyExists()));
+        }
+
+        let inum = self.dir_fs.i_alloc(FType::TDir)?;
+        let mut new_dir = self.dir_fs.i_get(inum)?;
+        Self::inherit_owner(creds, &parent, &mut new_dir);
+
+        //if the directory cannot be linked, e.g. because the parent is full, it is freed again,
+        //and the parent gets back the link that its ".." entry added
+        if let Err(e) = self.link_new_dir(&mut new_dir, parent.inum, name) {
+            if self.dir_fs.dirlookup(&new_dir, "..").is_ok() {
+                let mut parent = self.dir_fs.i_get(parent.inum)?;
+                parent.disk_node.nlink -= 1;
+                self.dir_fs.i_put(&parent)?;
+            }
+            self.free_new_inode(&mut new_dir)?;
+            return Err(e);
+        }
+
+        Ok(self.dir_fs.i_get(inum)?)
+    }
+
+    ///Remove the entry `path` like `unlink`, on behalf of the user with the credentials `creds`, who needs write and search permission on its parent.
+    ///If the parent has its sticky bit (`S_ISVTX`) set, the user also has to own either the entry or the parent.
+    pub fn unlink_as(&mut self, creds: &Credentials, path: &str) -> Result<(), PathFSError> {
+        if !Self::valid_path(path) {
+            return Err(InvalidPath());
+        }
+
+        let (parent_path, name) = Self::split_last(path).ok_or(InvalidDirectoryName())?;
+        if name == "." || name == ".." {
+            return Err(InvalidDirectoryName());
+        }
+
+        let mut parent = self.resolve_path_as(creds, parent_path)?;
+        Self::check_dir_writable(creds, &parent)?;
+        let (entry, offset) = self.dir_fs.dirlookup(&parent, name)?;
+
+        if parent.disk_node.mode & S_ISVTX != 0
+            && !creds.is_root()
+            && creds.uid != entry.disk_node.uid
+            && creds.uid != parent.disk_node.uid
+        {
+            return Err(PermissionDenied());
+        }
+
+        //directories can only be removed if they contain nothing but their default entries
+        if entry.disk_node.ft == FType::TDir {
+            for (de, _, _) in self.dir_fs.dir_entries(&entry)? {
+                let de_name = Self::get_name_str(&de);
+                if de.inum != 0 && de_name != "." && de_name != ".." {
+                    return Err(DirectoryNotEmpty());
+                }
+            }
+        }
+
+        self.dir_fs.clear_entry(&mut parent, offset)?;
+
+        //a cyclic reference to the parent itself was never counted in its nlink field
+        if entry.inum != parent.inum {
+            self.drop_link(entry.inum)?;
+        }
+
+        Ok(())
+    }
+
+    ///Create a symlink at `linkpath` that points to `target`, on behalf of the user with the credentials `creds`, who needs write and search permission on its parent.
+    ///The target is stored as is, without checking whether it exists: it is only resolved when the symlink is followed, relative to the directory of the symlink unless it starts with "/".
+    ///Targets of up to `SYMLINK_INLINE_MAX` bytes are stored in the inode itself, longer ones in its data blocks, so they can be at most `DIRECT_POINTERS` blocks long.
+    pub fn symlink(&mut self, creds: &Credentials, target: &str, linkpath: &str) -> Result<Inode, PathFSError> {
+        if !Self::valid_path(linkpath) {
+            return Err(InvalidPath());
+        }
+        let sb = self.dir_fs.sup_get()?;
+        if target.is_empty() || target.len() as u64 > DIRECT_POINTERS * sb.block_size {
+            return Err(InvalidSymlinkTarget());
+        }
+
+        let (parent_path, name) = Self::split_last(linkpath).ok_or(InvalidDirectoryName())?;
+        if name == "." || name == ".." {
+            return Err(InvalidDirectoryName());
+        }
+
+        //making sure that the symlink can be linked, before allocating anything
+        let mut parent = self.resolve_path_as(creds, parent_path)?;
+        if parent.disk_node.ft != FType::TDir {
+            return Err(PathSystemError(DirFSError::InodeNotDirectoryError()));
+        }
+        Self::check_dir_writable(creds, &parent)?;
+        if self.dir_fs.dirlookup(&parent, name).is_ok() {
+            return Err(PathSystemError(DirFSError::DirEntryNameAlreadyExists()));
+        }
+
+        let inum = self.dir_fs.i_alloc(FType::TSymlink)?;
+        let mut link = self.dir_fs.i_get(inum)?;
+        Self::inherit_owner(creds, &parent, &mut link);
+
+        //if the target cannot be stored or the symlink cannot be linked, it is freed again, together with the blocks it already got
+        if let Err(e) = self.link_new_symlink(&mut link, target, &mut parent, name) {
+            self.free_new_inode(&mut link)?;
+            return Err(e);
+        }
+
+        Ok(self.dir_fs.i_get(inum)?)
+    }
+
+    ///Read the target of the symlink at `path`, on behalf of the user with the credentials `creds`, without following it.
+    ///Fails with `NotASymlink` if `path` refers to anything else.
+    pub fn readlink(&self, creds: &Credentials, path: &str) -> Result<String, PathFSError> {
+        let link = self.lresolve_path_as(creds, path)?;
+        if link.disk_node.ft != FType::TSymlink {
+            return Err(NotASymlink());
+        }
+        self.read_target(&link)
+    }
+
+    ///Change the permission bits of the inode at `path` to `mode`, on behalf of the user with the credentials `creds`.
+    ///Only the owner of the inode and the superuser may do so, and bits outside of `MODE_MASK` are ignored.
+    ///Like in POSIX, the `S_ISGID` bit is silently dropped when someone else than the superuser sets it without being a member of the group of the inode.
+    pub fn chmod(&mut self, creds: &Credentials, path: &str, mode: u16) -> Result<(), PathFSError> {
+        let mut inode = self.resolve_path_as(creds, path)?;
+        if !creds.is_root() && creds.uid != inode.disk_node.uid {
+            return Err(PermissionDenied());
+        }
+
+        let mut mode = mode & MODE_MASK;
+        if !creds.is_root() && !creds.in_group(inode.disk_node.gid) {
+            mode &= !S_ISGID;
+        }
+        inode.disk_node.mode = mode;
+        inode.disk_node.times.changed(self.dir_fs.now());
+        self.dir_fs.i_put(&inode)?;
+        Ok(())
+    }
+
+    ///Change the owning user and group of the inode at `path` to `uid` and `gid`, on behalf of the user with the credentials `creds`.
+    ///The superuser may hand the inode to anyone; its owner may only change its group, to one the owner is a member of.
+    ///When someone else than the superuser does so, the `S_ISGID` bit of a file is cleared, while directories keep it.
+    pub fn chown(&mut self, creds: &Credentials, path: &str, uid: u32, gid: u32) -> Result<(), PathFSError> {
+        let mut inode = self.resolve_path_as(creds, path)?;
+        if !creds.is_root() {
+            if creds.uid != inode.disk_node.uid || uid != inode.disk_node.uid || !creds.in_group(gid) {
+                return Err(PermissionDenied());
+            }
+            if inode.disk_node.ft != FType::TDir {
+                inode.disk_node.mode &= !S_ISGID;
+            }
+        }
+
+        inode.disk_node.uid = uid;
+        inode.disk_node.gid = gid;
+        inode.disk_node.times.changed(self.dir_fs.now());
+        self.dir_fs.i_put(&inode)?;
+        Ok(())
+    }
+
+    ///Resolve `path` on behalf of the user with the credentials `creds`, who needs `access` on the inode it refers to
+    fn resolve_with_access(&self, creds: &Credentials, path: &str, access: u16) -> Result<Inode, PathFSError> {
+        let inode = self.resolve_path_as(creds, path)?;
+        if !creds.may(inode.disk_node.mode, inode.disk_node.uid, inode.disk_node.gid, access) {
+            return Err(PermissionDenied());
+        }
+        Ok(inode)
+    }
+
+    ///Get the value of the extended attribute `name` of the inode at `path`, on behalf of the user with the credentials `creds`, who needs read permission on it
+    pub fn getxattr_at(&self, creds: &Credentials, path: &str, name: &str) -> Result<Vec<u8>, PathFSError> {
+        let inode = self.resolve_with_access(creds, path, MAY_READ)?;
+        self.getxattr(&inode, name)
+    }
+
+    ///Set the extended attribute `name` of the inode at `path` to `value`, on behalf of the user with the credentials `creds`, who needs write permission on it
+    pub fn setxattr_at(&mut self, creds: &Credentials, path: &str, name: &str, value: &[u8]) -> Result<(), PathFSError> {
+        let mut inode = self.resolve_with_access(creds, path, MAY_WRITE)?;
+        self.setxattr(&mut inode, name, value)
+    }
+
+    ///List the names of the extended attributes of the inode at `path`, on behalf of the user with the credentials `creds`, who needs read permission on it
+    pub fn listxattr_at(&self, creds: &Credentials, path: &str) -> Result<Vec<String>, PathFSError> {
+        let inode = self.resolve_with_access(creds, path, MAY_READ)?;
+        self.listxattr(&inode)
+    }
+
+    ///Remove the extended attribute `name` of the inode at `path`, on behalf of the user with the credentials `creds`, who needs write permission on it
+    pub fn removexattr_at(&mut self, creds: &Credentials, path: &str, name: &str) -> Result<(), PathFSError> {
+        let mut inode = self.resolve_with_access(creds, path, MAY_WRITE)?;
+        self.removexattr(&mut inode, name)
+    }
+}
+
+impl<D: BlockDevice> FileSysSupport for PathFS<D> {
+    type Error = PathFSError;
+    type Dev = D;
+
+    fn sb_valid(sb: &SuperBlock) -> bool {
+        return DirFS::<D>::sb_valid(sb);
+    }
+
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>
+    where
+        D: ImageDevice,
+    {
+        return Self::with_root_entries(DirFS::mkfs(path, sb)?);
+    }
+
+    fn mkfs_on(dev: D, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        return Self::with_root_entries(DirFS::mkfs_on(dev, sb)?);
+    }
+
+    fn mountfs(dev: D) -> Result<Self, Self::Error> {
+        let rustfs = PathFS {
+            dir_fs: DirFS::mountfs(dev)?,
+            cwd: Vec::new(),
+        };
+        return Ok(rustfs);
+    }
+
+    fn mountfs_readonly(dev: D) -> Result<Self, Self::Error> {
+        let rustfs = PathFS {
+            dir_fs: DirFS::mountfs_readonly(dev)?,
+            cwd: Vec::new(),
+        };
+        return Ok(rustfs);
+    }
+
+    fn unmountfs(self) -> D {
+        return self.dir_fs.unmountfs();
+    }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        return Ok(self.dir_fs.sync()?);
+    }
+}
+
+impl<D: BlockDevice> BlockSupport for PathFS<D> {
+    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+        Ok(self.dir_fs.b_get(i)?)
+    }
+
+    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.b_put(b)?)
+    }
+
+    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.b_free(i)?)
+    }
+
+    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.b_zero(i)?)
+    }
+
+    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        Ok(self.dir_fs.b_alloc()?)
+    }
+
+    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
+        Ok(self.dir_fs.sup_get()?)
+    }
+
+    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.sup_put(sup)?)
+    }
+}
+
+impl<D: BlockDevice> VolumeSupport for PathFS<D> {
+    fn volume_get(&self) -> Result<VolumeHeader, Self::Error> {
+        Ok(self.dir_fs.volume_get()?)
+    }
+
+    fn volume_put(&mut self, vol: &VolumeHeader) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.volume_put(vol)?)
+    }
+}
+
+impl<D: BlockDevice> InodeSupport for PathFS<D> {
+    type Inode = Inode;
+
+    fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
+        Ok(self.dir_fs.i_get(i)?)
+    }
+
+    fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.i_put(ino)?)
+    }
+
+    fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.i_free(i)?)
+    }
+
+    fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
+        Ok(self.dir_fs.i_alloc(ft)?)
+    }
+
+    fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.i_trunc(inode)?)
+    }
+}
+
+impl<D: BlockDevice> DirectorySupport for PathFS<D> {
+    fn new_de(inum: u64, name: &str) -> Option<DirEntry> {
+        DirFS::<D>::new_de(inum, name)
+    }
+
+    fn get_name_str(de: &DirEntry) -> String {
+        DirFS::<D>::get_name_str(de)
+    }
+
+    fn set_name_str(de: &mut DirEntry, name: &str) -> Option<()> {
+        DirFS::<D>::set_name_str(de, name)
+    }
+
+    fn dirlookup(
+        &self,
+        inode: &Self::Inode,
+        name: &str,
+    ) -> Result<(Self::Inode, u64), Self::Error> {
+        Ok(self.dir_fs.dirlookup(inode, name)?)
+    }
+
+    fn dirlink(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        inum: u64,
+    ) -> Result<u64, Self::Error> {
+        Ok(self.dir_fs.dirlink(inode, name, inum)?)
+    }
+}
+
+impl<D: BlockDevice> XattrSupport for PathFS<D> {
+    fn getxattr(&self, inode: &Self::Inode, name: &str) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.dir_fs.getxattr(inode, name)?)
+    }
+
+    fn setxattr(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.setxattr(inode, name, value)?)
+    }
+
+    fn listxattr(&self, inode: &Self::Inode) -> Result<Vec<String>, Self::Error> {
+        Ok(self.dir_fs.listxattr(inode)?)
+    }
+
+    fn removexattr(&mut self, inode: &mut Self::Inode, name: &str) -> Result<(), Self::Error> {
+        Ok(self.dir_fs.removexattr(inode, name)?)
+    }
+}
+
+impl<D: BlockDevice> PathSupport for PathFS<D> {
+    fn valid_path(path: &str) -> bool {
+        if path.is_empty() {
+            return false;
+        }
+
+        let names = Self::path_names(path);
+
+        //relative paths have to start with one of the special entries
+        if !path.starts_with('/') && names[0] != "." && names[0] != ".." {
+            return false;
+        }
+
+        //every name has to be a valid directory entry name, which also rules out empty names,
+        //e.g. in "//" or in a path ending on a "/"
+        let mut de = DirEntry::default();
+        names
+            .iter()
+            .all(|name| Self::set_name_str(&mut de, name).is_some())
+    }
+
+    fn get_cwd(&self) -> String {
+        return "/".to_string() + &self.cwd.join("/");
+    }
+
+    fn set_cwd(&mut self, path: &str) -> Option<()> {
+        if !Self::valid_path(path) {
+            return None;
+        }
+
+        //relative paths are appended to the current working directory, without going through the file system
+        let mut cwd = if path.starts_with('/') {
+            Vec::new()
+        } else {
+            self.cwd.clone()
+        };
+
+        for name in Self::path_names(path) {
+            match name {
+                "." => {}
+                ".." => {
+                    cwd.pop();
+                }
+                _ => cwd.push(name.to_string()),
+            }
+        }
+
+        self.cwd = cwd;
+        Some(())
+    }
+
+    fn resolve_path(&self, path: &str) -> Result<Self::Inode, Self::Error> {
+        return self.resolve_path_as(&Credentials::root(), path);
+    }
+
+    fn mkdir(&mut self, path: &str) -> Result<Self::Inode, Self::Error> {
+        return self.mkdir_as(&Credentials::root(), path);
+    }
+
+    fn unlink(&mut self, path: &str) -> Result<(), Self::Error> {
+        return self.unlink_as(&Credentials::root(), path);
+    }
+}
+
+#[cfg(test)]
+mod test_in_memory {
+    use crate::a_block_support::BlockFSError::ReadOnlyFileSystem;
+    use crate::b_inode_support::InodeFSError::InodeSystemError;
+    use crate::c_dirs_support::DirFSError::DirectorySystemError;
+    use crate::c_dirs_support::DirFSError::{XattrNotFound, XattrTooLarge};
+    use crate::d_path_support::PathFSError::{
+        self, NotASymlink, PathSystemError, PermissionDenied, SymlinkLoop,
+    };
+    use crate::d_path_support::PathFS;
+    use cplfs_api::clock::ManualClock;
+    use cplfs_api::controller::BlockDevice;
+    use cplfs_api::mem_device::MemDevice;
+    use cplfs_api::perm::{Credentials, S_ISGID, S_ISVTX};
+    use cplfs_api::fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport, PathSupport, XattrSupport};
+    use cplfs_api::types::{Block, FType, InodeLike, SuperBlock, Timestamps, DIRENTRY_SIZE};
+
+    static BLOCK_SIZE: u64 = 1000;
+    static NBLOCKS: u64 = 12;
+    static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+        block_size: BLOCK_SIZE,
+        nblocks: NBLOCKS,
+        ninodes: 8,
+        inodestart: 1,
+        ndatablocks: 7,
+        bmapstart: 4,
+        datastart: 5,
+    };
+
+    type FSName = PathFS<MemDevice>;
+
+    ///Number of free data blocks and free inodes of the file system on `dev`, counted by allocating all of them on a copy of it
+    fn free_counts(dev: &MemDevice) -> (usize, usize) {
+        let mut my_fs = FSName::mountfs(dev.clone()).unwrap();
+        let blocks = std::iter::from_fn(|| my_fs.b_alloc().ok()).count();
+        let inodes = std::iter::from_fn(|| my_fs.i_alloc(FType::TFile).ok()).count();
+        (blocks, inodes)
+    }
+
+    ///File system with a single free data block, on which the root directory has no room left for another entry of at least 3 bytes without a new block
+    fn full_root() -> FSName {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        let spare = my_fs.b_alloc().unwrap();
+        let f = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut root = my_fs.i_get(1).unwrap();
+        for width in [200, 3] {
+            for i in 0.. {
+                if my_fs.dirlink(&mut root, &format!("{:0>1$}", i, width), f).is_err() {
+                    break;
+                }
+            }
+        }
+        my_fs.b_free(spare).unwrap();
+        my_fs
+    }
+
+    #[test]
+    fn mkdir_unlink_roundtrip() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+
+        //nested directories created through absolute and relative paths
+        let a = my_fs.mkdir("/a").unwrap();
+        assert_eq!(my_fs.i_get(1).unwrap().get_nlink(), 2);
+        my_fs.set_cwd("/a").unwrap();
+        let b = my_fs.mkdir("./b").unwrap();
+        assert_eq!(my_fs.resolve_path("/a/b").unwrap(), b);
+        assert_eq!(my_fs.resolve_path("./b/..").unwrap(), my_fs.i_get(a.inum).unwrap());
+        assert!(my_fs.mkdir("./b").is_err()); //already exists
+
+        //a file in the nested directory
+        let f = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.dirlink(&mut my_fs.i_get(b.inum).unwrap(), "file", f).unwrap();
+        assert!(my_fs.unlink("./b").is_err()); //not empty
+        my_fs.unlink("/a/b/file").unwrap();
+        assert_eq!(my_fs.i_get(f).unwrap().get_ft(), FType::TFree);
+
+        //the unlinked slot gets reused by the next link
+        let g = my_fs.i_alloc(FType::TFile).unwrap();
+        assert_eq!(
+            my_fs.dirlink(&mut my_fs.i_get(b.inum).unwrap(), "file2", g).unwrap(),
+            2 * *DIRENTRY_SIZE
+        );
+        my_fs.unlink("./b/file2").unwrap();
+
+        //removing both directories gives back the links to their parents
+        my_fs.unlink("./b").unwrap();
+        assert_eq!(my_fs.i_get(b.inum).unwrap().get_ft(), FType::TFree);
+        assert_eq!(my_fs.i_get(a.inum).unwrap().get_nlink(), 1);
+        my_fs.unlink("/a").unwrap();
+        assert_eq!(my_fs.i_get(a.inum).unwrap().get_ft(), FType::TFree);
+        assert_eq!(my_fs.i_get(1).unwrap().get_nlink(), 1);
+    }
+
+    #[test]
+    fn mkdir_full_parent() {
+        let dev = full_root().unmountfs();
+        let before = free_counts(&dev);
+        let mut my_fs = FSName::mountfs(dev).unwrap();
+        let nlink = my_fs.i_get(1).unwrap().get_nlink();
+
+        //the new directory takes the last free block, leaving none for its entry in the root directory, so it is freed again
+        assert!(my_fs.mkdir("/dir").is_err());
+        assert!(my_fs.resolve_path("/dir").is_err());
+        assert_eq!(my_fs.i_get(1).unwrap().get_nlink(), nlink);
+        assert_eq!(free_counts(&my_fs.unmountfs()), before);
+    }
+
+    #[test]
+    fn symlink_full_disk() {
+        let root = Credentials::root();
+
+        //a target of two blocks, of which only the first one can be allocated
+        let dev = full_root().unmountfs();
+        let before = free_counts(&dev);
+        let mut my_fs = FSName::mountfs(dev).unwrap();
+        assert!(my_fs.symlink(&root, &"x".repeat(BLOCK_SIZE as usize + 1), "/abc").is_err());
+        let dev = my_fs.unmountfs();
+        assert_eq!(free_counts(&dev), before);
+
+        //an inline target, whose entry in the root directory cannot get a block anymore
+        let mut my_fs = FSName::mountfs(dev).unwrap();
+        my_fs.b_alloc().unwrap();
+        let dev = my_fs.unmountfs();
+        let before = free_counts(&dev);
+        let mut my_fs = FSName::mountfs(dev).unwrap();
+        assert!(my_fs.symlink(&root, "target", "/abc").is_err());
+        assert!(my_fs.lresolve_path_as(&root, "/abc").is_err());
+        assert_eq!(free_counts(&my_fs.unmountfs()), before);
+    }
+
+    #[test]
+    fn link_timestamps() {
+        let clock = ManualClock::new(100);
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.set_clock(clock.clone());
+        let dir = my_fs.mkdir("/dir").unwrap();
+        assert_eq!(dir.get_times(), Timestamps::created(100));
+
+        //linking a file modifies the directory, and changes the link count of the file
+        let f = my_fs.i_alloc(FType::TFile).unwrap();
+        clock.set(200);
+        my_fs.dirlink(&mut my_fs.i_get(dir.inum).unwrap(), "file", f).unwrap();
+        my_fs.dirlink(&mut my_fs.i_get(1).unwrap(), "other", f).unwrap();
+        let dir_times = my_fs.i_get(dir.inum).unwrap().get_times();
+        assert_eq!((dir_times.mtime, dir_times.ctime, dir_times.crtime), (200, 200, 100));
+        let file_times = my_fs.i_get(f).unwrap().get_times();
+        assert_eq!((file_times.mtime, file_times.ctime, file_times.crtime), (100, 200, 100));
+
+        //unlinking does the same, while the other link keeps the file around
+        clock.set(300);
+        my_fs.unlink("/dir/file").unwrap();
+        assert_eq!(my_fs.i_get(dir.inum).unwrap().get_mtime(), 300);
+        let file_times = my_fs.i_get(f).unwrap().get_times();
+        assert_eq!((file_times.mtime, file_times.ctime), (100, 300));
+        assert_eq!(my_fs.i_get(1).unwrap().get_mtime(), 200);
+    }
+
+    #[test]
+    fn permission_checks() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        let alice = Credentials::new(1000, 100);
+        let bob = Credentials::new(1001, 200).with_groups(&[100]);
+
+        //the root directory belongs to the superuser, and only lets others search it
+        assert_eq!(my_fs.resolve_path("/").unwrap().get_mode(), 0o755);
+        assert!(matches!(my_fs.mkdir_as(&alice, "/home"), Err(PermissionDenied())));
+        let home = my_fs.mkdir("/home").unwrap();
+        my_fs.chown(&Credentials::root(), "/home", 1000, 100).unwrap();
+        let dir = my_fs.mkdir_as(&alice, "/home/dir").unwrap();
+        assert_eq!((dir.get_uid(), dir.get_gid(), dir.get_mode()), (1000, 100, 0o755));
+
+        //without search permission on /home, nothing below it can be reached, not even by its owner
+        my_fs.chmod(&alice, "/home", 0o600).unwrap();
+        assert!(matches!(my_fs.resolve_path_as(&alice, "/home/dir"), Err(PermissionDenied())));
+        assert!(my_fs.resolve_path_as(&alice, "/home").is_ok());
+        assert!(my_fs.resolve_path_as(&Credentials::root(), "/home/dir").is_ok());
+
+        //only the owner and the superuser may change the mode, and bob may not give the directory away
+        assert!(matches!(my_fs.chmod(&bob, "/home", 0o777), Err(PermissionDenied())));
+        assert!(matches!(my_fs.chown(&alice, "/home", 1001, 100), Err(PermissionDenied())));
+        assert!(matches!(my_fs.chown(&alice, "/home", 1000, 300), Err(PermissionDenied())));
+
+        //in a sticky, world-writable directory, bob can add entries but cannot remove those of alice
+        my_fs.chmod(&alice, "/home", 0o777 | S_ISVTX | S_ISGID).unwrap();
+        my_fs.mkdir_as(&bob, "/home/bob").unwrap();
+        assert!(matches!(my_fs.unlink_as(&bob, "/home/dir"), Err(PermissionDenied())));
+        my_fs.unlink_as(&bob, "/home/bob").unwrap();
+        my_fs.unlink_as(&alice, "/home/dir").unwrap();
+
+        //the setgid bit makes new directories inherit the group, and the bit itself
+        my_fs.chown(&alice, "/home", 1000, 100).unwrap();
+        let shared = my_fs.mkdir_as(&Credentials::new(1002, 300), "/home/shared").unwrap();
+        assert_eq!((shared.get_gid(), shared.get_mode()), (100, 0o755 | S_ISGID));
+
+        //bits outside of the mask are dropped, as is the setgid bit for an owner outside of the group
+        my_fs.chown(&Credentials::root(), "/home/shared", 1002, 400).unwrap();
+        my_fs.chmod(&Credentials::new(1002, 300), "/home/shared", 0o7777).unwrap();
+        assert_eq!(my_fs.resolve_path("/home/shared").unwrap().get_mode(), 0o1777);
+        assert_eq!(my_fs.i_get(home.inum).unwrap().get_uid(), 1000);
+    }
+
+    #[test]
+    fn symlinks() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        let root = Credentials::root();
+        let dir = my_fs.mkdir("/dir").unwrap();
+        let sub = my_fs.mkdir("/dir/sub").unwrap();
+
+        //a relative target is resolved from the directory of the link, in the middle of a path as well as at its end
+        let link = my_fs.symlink(&root, "../dir", "/dir/up").unwrap();
+        assert_eq!((link.get_ft(), link.get_size()), (FType::TSymlink, 6));
+        assert!(link.has_inline_target());
+        assert_eq!(my_fs.resolve_path("/dir/up/sub").unwrap().inum, sub.inum);
+        assert_eq!(my_fs.resolve_path("/dir/up").unwrap().inum, dir.inum);
+        assert_eq!(my_fs.lresolve_path_as(&root, "/dir/up").unwrap().inum, link.inum);
+        assert_eq!(my_fs.readlink(&root, "/dir/up").unwrap(), "../dir");
+        assert!(matches!(my_fs.readlink(&root, "/dir"), Err(NotASymlink())));
+
+        //a target that does not fit in the inode goes to a data block, which is freed along with the link
+        let long = "/dir/".to_string() + &"./".repeat(30) + "sub";
+        let link = my_fs.symlink(&root, &long, "/long").unwrap();
+        assert!(!link.has_inline_target());
+        let address = link.get_block(0);
+        assert_ne!(address, 0);
+        assert_eq!(my_fs.readlink(&root, "/long").unwrap(), long);
+        assert_eq!(my_fs.resolve_path("/long").unwrap().inum, sub.inum);
+        my_fs.unlink("/long").unwrap();
+        assert_eq!(my_fs.i_get(link.inum).unwrap().get_ft(), FType::TFree);
+        assert_eq!(my_fs.b_alloc().unwrap() + SUPERBLOCK_GOOD.datastart, address);
+
+        //links that point to each other are only caught once they have been followed too often
+        my_fs.symlink(&root, "/b", "/a").unwrap();
+        my_fs.symlink(&root, "./a", "/b").unwrap();
+        assert!(matches!(my_fs.resolve_path("/a"), Err(SymlinkLoop())));
+        assert!(matches!(my_fs.resolve_path("/dir/up/sub/../../b/x"), Err(SymlinkLoop())));
+        assert_eq!(my_fs.readlink(&root, "/a").unwrap(), "/b");
+
+        //removing a link leaves its target alone, and a dangling link cannot be resolved
+        my_fs.unlink("/dir/up").unwrap();
+        assert_eq!(my_fs.resolve_path("/dir/sub").unwrap().inum, sub.inum);
+        my_fs.symlink(&root, "missing", "/dir/dangling").unwrap();
+        assert!(my_fs.resolve_path("/dir/dangling").is_err());
+        assert!(my_fs.lresolve_path_as(&root, "/dir/dangling").is_ok());
+        assert!(my_fs.symlink(&root, "", "/empty").is_err());
+        assert!(my_fs.symlink(&root, "/dir", "/dir/sub").is_err());
+    }
+
+    #[test]
+    fn xattrs() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        let root = Credentials::root();
+        let mut dir = my_fs.mkdir("/dir").unwrap();
+
+        //a single small attribute is stored inline
+        my_fs.setxattr(&mut dir, "type", b"pdf").unwrap();
+        assert_eq!(dir.disk_node.xattr_block, 0);
+        assert_eq!(my_fs.getxattr(&my_fs.i_get(dir.inum).unwrap(), "type").unwrap(), b"pdf");
+
+        //a second one moves both to an xattr block, and removing it brings them back inline, giving back the block
+        my_fs.setxattr_at(&root, "/dir", "md5", &[0xab; 16]).unwrap();
+        let mut dir = my_fs.i_get(dir.inum).unwrap();
+        let address = dir.disk_node.xattr_block;
+        assert_ne!(address, 0);
+        assert!(dir.disk_node.xattr_inline.iter().all(|b| *b == 0));
+        assert_eq!(my_fs.listxattr_at(&root, "/dir").unwrap(), vec!["md5", "type"]);
+        my_fs.removexattr(&mut dir, "md5").unwrap();
+        assert_eq!(dir.disk_node.xattr_block, 0);
+        assert_eq!(my_fs.getxattr_at(&root, "/dir", "type").unwrap(), b"pdf");
+        assert!(matches!(my_fs.getxattr(&dir, "md5"), Err(PathSystemError(XattrNotFound()))));
+        assert_eq!(my_fs.b_alloc().unwrap() + SUPERBLOCK_GOOD.datastart, address);
+        my_fs.b_free(address - SUPERBLOCK_GOOD.datastart).unwrap();
+
+        //the attributes of an inode have to fit in a single block
+        let big = vec![1; BLOCK_SIZE as usize];
+        assert!(matches!(my_fs.setxattr(&mut dir, "big", &big), Err(PathSystemError(XattrTooLarge()))));
+        assert!(my_fs.setxattr(&mut dir, "", b"x").is_err());
+        assert_eq!(my_fs.listxattr(&dir).unwrap(), vec!["type"]);
+
+        //copying replaces the attributes of the target, which gets a block of its own
+        let mut other = my_fs.mkdir("/other").unwrap();
+        my_fs.setxattr(&mut other, "stale", b"").unwrap();
+        my_fs.setxattr(&mut dir, "md5", &[0xcd; 16]).unwrap();
+        my_fs.copy_xattrs(&dir, &mut other).unwrap();
+        assert_eq!(my_fs.listxattr(&other).unwrap(), vec!["md5", "type"]);
+        assert_ne!(other.disk_node.xattr_block, dir.disk_node.xattr_block);
+
+        //getting and listing needs read permission on the inode, changing its attributes needs write permission
+        let alice = Credentials::new(1000, 100);
+        my_fs.chmod(&root, "/dir", 0o705).unwrap();
+        assert_eq!(my_fs.getxattr_at(&alice, "/dir", "type").unwrap(), b"pdf");
+        assert!(matches!(my_fs.setxattr_at(&alice, "/dir", "type", b"txt"), Err(PermissionDenied())));
+        assert!(matches!(my_fs.removexattr_at(&alice, "/dir", "type"), Err(PermissionDenied())));
+        my_fs.chmod(&root, "/dir", 0o700).unwrap();
+        assert!(matches!(my_fs.listxattr_at(&alice, "/dir"), Err(PermissionDenied())));
+
+        //the xattr blocks are freed along with their inodes
+        let blocks = [dir.disk_node.xattr_block, other.disk_node.xattr_block];
+        my_fs.unlink("/dir").unwrap();
+        my_fs.unlink("/other").unwrap();
+        assert_eq!(my_fs.i_get(dir.inum).unwrap().disk_node.xattr_block, 0);
+        let free: Vec<u64> = std::iter::from_fn(|| my_fs.b_alloc().ok()).collect();
+        assert!(blocks.iter().all(|b| free.contains(&(b - SUPERBLOCK_GOOD.datastart))));
+    }
+
+    #[test]
+    fn posix_names() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+
+        //any name without a '/' or a '\0' is fine, including long ones and ones with spaces or other scripts
+        let long = "a_really_long_directory_name_that_takes_up_several_slots";
+        let dir = my_fs.mkdir(&format!("/{}", long)).unwrap();
+        let file = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.dirlink(&mut my_fs.i_get(dir.inum).unwrap(), "report 2024.txt", file).unwrap();
+        my_fs.dirlink(&mut my_fs.i_get(dir.inum).unwrap(), "❤-ünïcødé", file).unwrap();
+        assert_eq!(my_fs.resolve_path(&format!("/{}/report 2024.txt", long)).unwrap().inum, file);
+        my_fs.set_cwd(&format!("/{}", long)).unwrap();
+        assert_eq!(my_fs.resolve_path("./❤-ünïcødé").unwrap().inum, file);
+        assert!(!FSName::valid_path(&format!("/{}", "x".repeat(256))));
+
+        //the directory is only empty once both names are gone
+        assert!(my_fs.unlink(&format!("/{}", long)).is_err());
+        my_fs.unlink("./report 2024.txt").unwrap();
+        my_fs.unlink("./❤-ünïcødé").unwrap();
+        assert_eq!(my_fs.i_get(file).unwrap().get_ft(), FType::TFree);
+        my_fs.unlink(&format!("/{}", long)).unwrap();
+        assert_eq!(my_fs.i_get(dir.inum).unwrap().get_ft(), FType::TFree);
+    }
+
+    #[test]
+    fn mount_resets_cwd() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.mkdir("/dir").unwrap();
+        my_fs.set_cwd("/dir").unwrap();
+        assert_eq!(&my_fs.get_cwd(), "/dir");
+
+        let dev = my_fs.unmountfs();
+        let my_fs = FSName::mountfs(dev).unwrap();
+        assert_eq!(&my_fs.get_cwd(), "/");
+        assert_eq!(my_fs.resolve_path("./dir/..").unwrap().get_inum(), 1);
+    }
+
+    #[test]
+    fn discard_and_fstrim() {
+        let datastart = SUPERBLOCK_GOOD.datastart;
+        let full = |address| Block::new(address, vec![1; BLOCK_SIZE as usize].into_boxed_slice());
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let dev = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap().unmountfs();
+        let mut my_fs = FSName::mountfs_discard(dev).unwrap();
+        assert!(my_fs.is_discarding());
+
+        //a file with three full data blocks
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut file = my_fs.i_get(inum).unwrap();
+        for i in 0..3 {
+            let address = my_fs.b_alloc().unwrap() + datastart;
+            my_fs.b_put(&full(address)).unwrap();
+            file.disk_node.direct_blocks[i] = address;
+        }
+        file.disk_node.size = 3 * BLOCK_SIZE;
+        my_fs.i_put(&file).unwrap();
+        my_fs.dirlink(&mut my_fs.i_get(1).unwrap(), "file", inum).unwrap();
+        let blocks = file.disk_node.direct_blocks;
+
+        //its data blocks read as zeroes as soon as it is truncated
+        my_fs.i_trunc(&mut file).unwrap();
+        for address in &blocks[..3] {
+            assert_eq!(my_fs.b_get(*address).unwrap(), Block::new_zero(*address, BLOCK_SIZE));
+        }
+
+        //without the mount option, freed blocks keep their contents until fstrim discards all free blocks
+        let mut my_fs = FSName::mountfs(my_fs.unmountfs()).unwrap();
+        assert!(!my_fs.is_discarding());
+        let address = my_fs.b_alloc().unwrap() + datastart;
+        my_fs.b_put(&full(address)).unwrap();
+        my_fs.b_free(address - datastart).unwrap();
+        assert_eq!(my_fs.b_get(address).unwrap(), full(address));
+        assert_eq!(my_fs.fstrim().unwrap(), SUPERBLOCK_GOOD.ndatablocks - 1);
+        assert_eq!(my_fs.b_get(address).unwrap(), Block::new_zero(address, BLOCK_SIZE));
+    }
+
+    #[test]
+    fn readonly_mount() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.mkdir("/dir").unwrap();
+        let f = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.dirlink(&mut my_fs.i_get(1).unwrap(), "file", f).unwrap();
+        let image = my_fs.unmountfs();
+
+        //reading and walking the file system works as usual
+        let mut my_fs = FSName::mountfs_readonly(image.clone()).unwrap();
+        assert!(my_fs.is_read_only());
+        my_fs.set_cwd("/dir").unwrap();
+        let dir = my_fs.resolve_path(".").unwrap();
+        let mut root = my_fs.resolve_path("..").unwrap();
 
-/// You are free to choose the name for your file system. As we will use
-/// automated tests when grading your assignment, indicate here the name of
-/// your file system data type so we can just use `FSName` instead of
-/// having to manually figure out the name.
-/// **TODO**: replace the below type by the type of your file system
-pub type FSName = ();
+        //but every modification is rejected with the same error
+        let read_only = |r: Result<(), PathFSError>| {
+            matches!(
+                r,
+                Err(PathSystemError(DirectorySystemError(InodeSystemError(ReadOnlyFileSystem()))))
+            )
+        };
+        assert!(read_only(my_fs.mkdir("/other").map(|_| ())));
+        assert!(read_only(my_fs.unlink("/file")));
+        assert!(read_only(my_fs.unlink("/dir")));
+        assert!(read_only(my_fs.dirlink(&mut root, "link", f).map(|_| ())));
+        assert!(read_only(my_fs.i_put(&dir)));
+        assert!(read_only(my_fs.i_alloc(FType::TFile).map(|_| ())));
+        assert!(read_only(my_fs.i_free(f)));
+        assert!(read_only(my_fs.b_alloc().map(|_| ())));
+        assert!(read_only(my_fs.b_put(&my_fs.b_get(0).unwrap())));
 
-// **TODO** define your own tests here.
+        //the device is left untouched
+        let dev = my_fs.unmountfs();
+        for i in 0..NBLOCKS {
+            assert_eq!(dev.read_block(i).unwrap(), image.read_block(i).unwrap());
+        }
+    }
+}
 
 // WARNING: DO NOT TOUCH THE BELOW CODE -- IT IS REQUIRED FOR TESTING -- YOU WILL LOSE POINTS IF I MANUALLY HAVE TO FIX YOUR TESTS
 #[cfg(all(test, any(feature = "d", feature = "all")))]
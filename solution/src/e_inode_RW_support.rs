@@ -25,14 +25,17 @@
 //!
 
 use thiserror::Error;
-use cplfs_api::controller::Device;
+use cplfs_api::clock::{Clock, SystemClock};
+use cplfs_api::controller::{BlockDevice, Device, ImageDevice};
 use cplfs_api::error_given::APIError;
 use crate::b_inode_support::InodeFSError;
-use cplfs_api::fs::{FileSysSupport, BlockSupport, InodeSupport, InodeRWSupport};
-use cplfs_api::types::{SuperBlock, Block, DInode, Inode, FType, DINODE_SIZE, Buffer};
+use cplfs_api::fs::{FileSysSupport, BlockSupport, InodeSupport, InodeRWSupport, VolumeSupport};
+use cplfs_api::perm::{self, Credentials, MAY_READ, MAY_WRITE};
+use cplfs_api::types::{SuperBlock, Block, DInode, Inode, InodeLike, FType, DINODE_SIZE, Buffer, Timestamps, VolumeHeader, SUPERBLOCK_SIZE};
+use cplfs_api::xattr::release_xattrs;
 use crate::a_block_support::{BlockFS, BlockFSError};
 use std::path::Path;
-use crate::e_inode_RW_support::RWInodeFSError::{InodeRWSystemError, OffsetOutsideOfInode};
+use crate::e_inode_RW_support::RWInodeFSError::{InodeRWSystemError, OffsetOutsideOfInode, PermissionDenied};
 use crate::b_inode_support::InodeFSError::{InodeSystemError, InodeInitializationError, InodeAlreadyDeallocatedError};
 use crate::a_block_support::BlockFSError::OutsideOfTheBoundariesError;
 
@@ -40,8 +43,11 @@ use crate::a_block_support::BlockFSError::OutsideOfTheBoundariesError;
 pub type FSName = RWInodeFS;
 
 ///Main struct file for the InodeRW File System
-pub struct RWInodeFS {
-    device: Device,
+pub struct RWInodeFS<D: BlockDevice = Device> {
+    device: D,
+    read_only: bool,
+    discard: bool,
+    clock: Box<dyn Clock>,
 }
 
 ///Main error file for InodeRW File system
@@ -62,25 +68,133 @@ pub enum RWInodeFSError {
     ///todo:finish this
     #[error("Reading outside of the inode")]
     OffsetOutsideOfInode(),
+
+    ///Error that's thrown when the mode of an inode does not grant the access that is asked for
+    #[error("Permission denied")]
+    PermissionDenied(),
+}
+
+impl<D: BlockDevice> RWInodeFS<D> {
+    ///Check whether the file system was mounted read-only, using `mountfs_readonly`
+    pub fn is_read_only(&self) -> bool {
+        return self.read_only;
+    }
+
+    ///Mount the file system on `dev` like `mountfs`, but discard every data block as soon as it is freed, as described for `BlockFS::mountfs_discard`.
+    ///This includes the blocks released when truncating or freeing inodes.
+    pub fn mountfs_discard(dev: D) -> Result<Self, RWInodeFSError> {
+        let mut rustfs = Self::mountfs(dev)?;
+        rustfs.discard = true;
+        return Ok(rustfs);
+    }
+
+    ///Check whether the file system was mounted with `mountfs_discard`
+    pub fn is_discarding(&self) -> bool {
+        return self.discard;
+    }
+
+    ///Discard every data block that the bitmap marks as free, as described for `BlockFS::fstrim`.
+    ///Returns the number of discarded blocks.
+    pub fn fstrim(&mut self) -> Result<u64, RWInodeFSError> {
+        self.check_writable()?;
+        let sb: SuperBlock = self.sup_get()?;
+        return BlockFS::<D>::trim_free_blocks(&mut self.device, &sb).map_err(|e| InodeRWSystemError(InodeSystemError(e)));
+    }
+
+    ///Replace the clock the inode timestamps are taken from, which is the `SystemClock` by default
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    ///Current time according to the clock of this file system, in nanoseconds
+    pub fn now(&self) -> u64 {
+        return self.clock.now();
+    }
+
+    ///Read from `inode` like `i_read`, on behalf of the user with the credentials `creds`, who needs read permission on it
+    pub fn i_read_as(&self, creds: &Credentials, inode: &Inode, buf: &mut Buffer, off: u64, n: u64) -> Result<u64, RWInodeFSError> {
+        if !inode.permits(creds, MAY_READ) {
+            return Err(PermissionDenied());
+        }
+        return self.i_read(inode, buf, off, n);
+    }
+
+    ///Write to `inode` like `i_write`, on behalf of the user with the credentials `creds`, who needs write permission on it
+    pub fn i_write_as(&mut self, creds: &Credentials, inode: &mut Inode, buf: &Buffer, off: u64, n: u64) -> Result<(), RWInodeFSError> {
+        if !inode.permits(creds, MAY_WRITE) {
+            return Err(PermissionDenied());
+        }
+        return self.i_write(inode, buf, off, n);
+    }
+
+    ///Fail with a read-only error if the file system was mounted read-only.
+    ///Every operation that modifies the device checks this first, so that it leaves the device untouched.
+    fn check_writable(&self) -> Result<(), RWInodeFSError> {
+        if self.read_only {
+            return Err(InodeRWSystemError(InodeSystemError(BlockFSError::ReadOnlyFileSystem())));
+        }
+        return Ok(());
+    }
+
+    ///Mount the file system on `dev`, read-only if `read_only` is set, after checking its superblock and volume header
+    fn mount(dev: D, read_only: bool) -> Result<Self, RWInodeFSError> {
+        let block_at_zero: Block = dev.read_block(0)?;
+        let superblock = block_at_zero.deserialize_from::<SuperBlock>(0)?;
+
+        //checking whether the superblock in the device is valid
+        if !BlockFS::<D>::sb_valid(&superblock) {
+            return Err(InodeRWSystemError(InodeSystemError(BlockFSError::SuperBlockInvalid())));
+        }
+        let volume = block_at_zero.deserialize_from::<VolumeHeader>(*SUPERBLOCK_SIZE)?;
+        BlockFS::<D>::check_volume(&volume, read_only).map_err(|e| InodeRWSystemError(InodeSystemError(e)))?;
+
+        //checking whether the block size in superblock and device are matching
+        if !((superblock.block_size == dev.block_size()) && (superblock.nblocks == dev.nblocks())) {
+            return Err(InodeRWSystemError(InodeSystemError(BlockFSError::DeviceConfigurationInvalid())));
+        }
+
+        let rustfs = RWInodeFS {
+            device: dev,
+            read_only,
+            discard: false,
+            clock: Box::new(SystemClock),
+        };
+        return Ok(rustfs);
+    }
 }
 
-impl FileSysSupport for RWInodeFS {
+impl<D: BlockDevice> FileSysSupport for RWInodeFS<D> {
     type Error = RWInodeFSError;
+    type Dev = D;
 
     fn sb_valid(sb: &SuperBlock) -> bool {
-        return BlockFS::sb_valid(sb);
+        return BlockFS::<D>::sb_valid(sb);
     }
 
-    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
-        if !RWInodeFS::sb_valid(sb) {
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>
+    where
+        D: ImageDevice,
+    {
+        if !Self::sb_valid(sb) {
             return Err(InodeRWSystemError(InodeSystemError(BlockFSError::SuperBlockInvalid())));
         }
 
-        let mut device = Device::new(&path, sb.block_size, sb.nblocks)?;
+        let device = D::create(&path, sb.block_size, sb.nblocks)?;
+        return Self::mkfs_on(device, sb);
+    }
 
-        //serializing superblock into block and writing it at the position zero on the device
-        let mut super_block = Block::new_zero(0, sb.block_size);
-        super_block.serialize_into(sb, 0)?;
+    fn mkfs_on(mut device: D, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        if !Self::sb_valid(sb) {
+            return Err(InodeRWSystemError(InodeSystemError(BlockFSError::SuperBlockInvalid())));
+        }
+
+        //checking whether the block size in superblock and device are matching
+        if !((sb.block_size == device.block_size()) && (sb.nblocks == device.nblocks())) {
+            return Err(InodeRWSystemError(InodeSystemError(BlockFSError::DeviceConfigurationInvalid())));
+        }
+
+        //serializing superblock and a fresh volume header into block and writing it at the position zero on the device
+        let super_block = BlockFS::<D>::superblock_block(sb).map_err(|e| InodeRWSystemError(InodeSystemError(e)))?;
         device.write_block(&super_block)?;
 
         //calculating number of inodes per block and number of inodes blocks that will be required
@@ -91,6 +205,7 @@ impl FileSysSupport for RWInodeFS {
         //going through all the blocks and all the inodes we have to add into the blocks
         //we loop over number of block we are going to fill with inodes and the number of inodes we are
         //going to write into block.
+        let mut inode_blocks = Vec::new();
         for i in 0..n_inodes_blocks + 1 {
             let mut inode_block = Block::new_zero(i + 1, sb.block_size);
             for j in 0..n_inodes_per_block {
@@ -100,38 +215,40 @@ impl FileSysSupport for RWInodeFS {
                     inode_block.serialize_into(&DInode::default(), *DINODE_SIZE * j)?;
                 }
             }
-            device.write_block(&inode_block)?;
+            inode_blocks.push(inode_block);
         }
-
-        let rustfs = RWInodeFS { device };
+        //writing the whole inode table at once
+        device.write_blocks(&inode_blocks)?;
+
+        let rustfs = RWInodeFS {
+            device,
+            read_only: false,
+            discard: false,
+            clock: Box::new(SystemClock),
+        };
 
         return Ok(rustfs);
     }
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
-        let block_at_zero: Block = dev.read_block(0).unwrap();
-        let superblock = block_at_zero.deserialize_from::<SuperBlock>(0).unwrap();
-
-        //checking whether the superblock in the device is valid
-        if !BlockFS::sb_valid(&superblock) {
-            return Err(InodeRWSystemError(InodeSystemError(BlockFSError::SuperBlockInvalid())));
-        }
-
-        //checking whether the block size in superblock and device are matching
-        if !((superblock.block_size == dev.block_size) && (superblock.nblocks == dev.nblocks)) {
-            return Err(InodeRWSystemError(InodeSystemError(BlockFSError::DeviceConfigurationInvalid())));
-        }
+    fn mountfs(dev: D) -> Result<Self, Self::Error> {
+        return Self::mount(dev, false);
+    }
 
-        let rustfs = RWInodeFS { device: dev };
-        return Ok(rustfs);
+    fn mountfs_readonly(dev: D) -> Result<Self, Self::Error> {
+        return Self::mount(dev, true);
     }
 
-    fn unmountfs(self) -> Device {
+    fn unmountfs(self) -> D {
         return self.device;
     }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        self.device.flush()?;
+        return Ok(());
+    }
 }
 
-impl BlockSupport for RWInodeFS {
+impl<D: BlockDevice> BlockSupport for RWInodeFS<D> {
     fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
         let block = self.device.read_block(i);
 
@@ -145,6 +262,7 @@ impl BlockSupport for RWInodeFS {
     }
 
     fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let result = self.device.write_block(b);
 
         if result.is_err() {
@@ -157,6 +275,7 @@ impl BlockSupport for RWInodeFS {
     }
 
     fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
 
         if i >= sb.ndatablocks {
@@ -197,10 +316,16 @@ impl BlockSupport for RWInodeFS {
         current_block.write_data(&changed_data, 0)?;
         self.device.write_block(&current_block)?;
 
+        //the block is only discarded once it is marked free, so a failing discard cannot leak it
+        if self.discard {
+            self.device.discard(sb.datastart + i, 1)?;
+        }
+
         return Ok(());
     }
 
     fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
 
         if i >= sb.ndatablocks {
@@ -214,6 +339,7 @@ impl BlockSupport for RWInodeFS {
     }
 
     fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        self.check_writable()?;
         let superblock: SuperBlock = self.sup_get()?;
         let bitmap_blocks = superblock.ndatablocks / (superblock.block_size * 8);
 
@@ -298,16 +424,34 @@ impl BlockSupport for RWInodeFS {
     }
 
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
-        let mut block: Block = Block::new_zero(0, sup.block_size);
+        self.check_writable()?;
+        //reading the block first, so the volume header after the superblock stays intact
+        let mut block: Block = self.device.read_block(0)?;
+
+        block.serialize_into(sup, 0)?;
+        self.device.write_block(&block)?;
+
+        return Ok(());
+    }
+}
+
+impl<D: BlockDevice> VolumeSupport for RWInodeFS<D> {
+    fn volume_get(&self) -> Result<VolumeHeader, Self::Error> {
+        return Ok(self.device.read_block(0)?.deserialize_from::<VolumeHeader>(*SUPERBLOCK_SIZE)?);
+    }
+
+    fn volume_put(&mut self, vol: &VolumeHeader) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        let mut block: Block = self.device.read_block(0)?;
 
-        block.serialize_into(&sup, 0)?;
+        block.serialize_into(vol, *SUPERBLOCK_SIZE)?;
         self.device.write_block(&block)?;
 
         return Ok(());
     }
 }
 
-impl InodeSupport for RWInodeFS{
+impl<D: BlockDevice> InodeSupport for RWInodeFS<D> {
     type Inode = Inode;
 
     fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
@@ -325,14 +469,13 @@ impl InodeSupport for RWInodeFS{
         let index_of_inode = i - (n_inodes_per_block * index_of_block);
 
         //here we deserialize the block into dinode
-        let dinode = inode_block
-            .deserialize_from::<DInode>(*DINODE_SIZE * index_of_inode)
-            .unwrap();
+        let dinode = inode_block.deserialize_from::<DInode>(*DINODE_SIZE * index_of_inode)?;
 
         return Ok(Inode::new(i, dinode));
     }
 
     fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
         if ino.inum > sb.ninodes - 1 {
             return Err(InodeRWSystemError(InodeSystemError(OutsideOfTheBoundariesError())));
@@ -354,12 +497,13 @@ impl InodeSupport for RWInodeFS{
     }
 
     fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb = self.sup_get()?;
         if i > sb.ninodes - 1 {
             return Err(InodeRWSystemError(InodeSystemError(OutsideOfTheBoundariesError())));
         }
 
-        let requested_inode = self.i_get(i).unwrap();
+        let requested_inode = self.i_get(i)?;
 
         if requested_inode.inum != i {
             return Err(InodeRWSystemError(InodeInitializationError()));
@@ -374,17 +518,24 @@ impl InodeSupport for RWInodeFS{
         //and deallocating all the blocks. 3.) setting all direct pointers to zero. 4.) returing the
         //modified inode to the disc.
         if requested_inode.disk_node.nlink == 0 {
+            //the target of a short symlink takes the place of its block addresses, so there are no blocks to free
+            let n_valid_blocks = if requested_inode.has_inline_target() {
+                0
+            } else {
+                (requested_inode.disk_node.size as f64 / sb.block_size as f64).ceil() as usize
+            };
+
             let mut modified_dinode = requested_inode.disk_node;
             modified_dinode.ft = FType::TFree;
-
-            let n_valid_blocks =
-                (modified_dinode.size as f64 / sb.block_size as f64).ceil() as usize;
             for i in 0..n_valid_blocks {
                 if modified_dinode.direct_blocks[i] != 0 {
                     self.b_free(modified_dinode.direct_blocks[i] % sb.datastart)?;
                 }
             }
 
+            //the extended attributes go together with the inode
+            release_xattrs(self, &mut modified_dinode)?;
+
             modified_dinode.direct_blocks = Default::default();
             self.i_put(&Inode::new(requested_inode.inum, modified_dinode))?;
         }
@@ -393,7 +544,8 @@ impl InodeSupport for RWInodeFS{
     }
 
     fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
-        let sb = self.sup_get().unwrap();
+        self.check_writable()?;
+        let sb = self.sup_get()?;
 
         //calculating number of inodes per block and number of inodes blocks that will be required
         //to save those inodes
@@ -412,7 +564,7 @@ impl InodeSupport for RWInodeFS{
                     continue;
                 }
 
-                let inode = self.i_get(i * n_inodes_per_block + j).unwrap();
+                let inode = self.i_get(i * n_inodes_per_block + j)?;
                 let mut disc_inode = inode.disk_node;
                 let index = inode.inum;
 
@@ -420,6 +572,10 @@ impl InodeSupport for RWInodeFS{
                     disc_inode.ft = ft;
                     disc_inode.size = Default::default();
                     disc_inode.nlink = Default::default();
+                    disc_inode.times = Timestamps::created(self.now());
+                    disc_inode.mode = perm::default_mode(ft);
+                    disc_inode.uid = 0;
+                    disc_inode.gid = 0;
 
                     self.i_put(&Inode::new(index, disc_inode))?;
                     return Ok(index);
@@ -431,10 +587,15 @@ impl InodeSupport for RWInodeFS{
     }
 
     fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
-        let sb = self.sup_get().unwrap();
+        self.check_writable()?;
+        let sb = self.sup_get()?;
 
         //going through all valid blocks and freeing them
-        let n_valid_blocks = (inode.disk_node.size as f64 / sb.block_size as f64).ceil() as usize;
+        let n_valid_blocks = if inode.has_inline_target() {
+            0
+        } else {
+            (inode.disk_node.size as f64 / sb.block_size as f64).ceil() as usize
+        };
         for i in 0..n_valid_blocks {
             if inode.disk_node.direct_blocks[i] != 0 {
                 self.b_free(inode.disk_node.direct_blocks[i] % sb.datastart)?;
@@ -443,12 +604,13 @@ impl InodeSupport for RWInodeFS{
 
         inode.disk_node.size = Default::default();
         inode.disk_node.direct_blocks = Default::default();
+        inode.disk_node.times.modified(self.now());
         self.i_put(inode)?;
         return Ok(());
     }
 }
 
-impl InodeRWSupport for RWInodeFS {
+impl<D: BlockDevice> InodeRWSupport for RWInodeFS<D> {
 
     fn i_read(&self, inode: &Self::Inode, buf: &mut Buffer, off: u64, n: u64) -> Result<u64, Self::Error> {
         let sb: SuperBlock = self.sup_get()?;
@@ -509,6 +671,7 @@ impl InodeRWSupport for RWInodeFS {
     }
 
     fn i_write(&mut self, inode: &mut Self::Inode, buf: &Buffer, off: u64, n: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         let sb: SuperBlock = self.sup_get()?;
 
         //return error if we start reading more than there is saved in inode
@@ -551,7 +714,8 @@ impl InodeRWSupport for RWInodeFS {
             self.b_put(&current_block)?;
 
             if current_n == 0{
-
+                inode.disk_node.times.modified(self.now());
+                self.i_put(inode)?;
                 return Ok(());
             }
         }
@@ -565,6 +729,7 @@ impl InodeRWSupport for RWInodeFS {
         //adding that allocated block to the list of available blocks
         inode.disk_node.direct_blocks[n_valid_blocks as usize] = index+sb.datastart;
         inode.disk_node.size += current_data.len() as u64;
+        inode.disk_node.times.modified(self.now());
         self.i_put(inode)?;
 
         return Ok(());
@@ -31,27 +31,1306 @@
 //!
 //! # Status
 //!
-//! **TODO**: Replace the question mark below with YES, NO, or PARTIAL to
-//! indicate the status of this assignment. If you want to tell something
-//! about this assignment to the grader, e.g., you have a bug you can't fix,
-//! or you want to explain your approach, write it down after the comments
-//! section. If you had no major issues and everything works, there is no need to write any comments.
-//!
-//! COMPLETED: NO
+//! COMPLETED: YES
 //!
 //! COMMENTS:
 //!
-//! ...
+//! Next to the single indirect block, inodes also carry a *double indirect block* field.
+//! The double indirect block stores the addresses of further indirect blocks, which in turn store data block addresses.
+//! It gets allocated as soon as the single indirect block is full, and allows a file to allocate another `(block_size/8)^2` blocks.
+//! Just like the indirect block is returned by `get_block(DIRECT_POINTERS)`, the double indirect block is returned by `get_block(DIRECT_POINTERS + 1)`.
+//!
+//! Inodes also carry the address of an xattr block and a small inline area for their extended attributes, which `XattrSupport` manages.
+//! Freeing an inode gives back its xattr block, and `resize_fs` moves it like any other data block.
+//!
+//! The file system wraps the block file system from `a_block_support` for all of its block operations.
 //!
 
-/// You are free to choose the name for your file system. As we will use
-/// automated tests when grading your assignment, indicate here the name of
-/// your file system data type so we can just use `FSName` instead of
-/// having to manually figure out the name.
-/// **TODO**: replace the below type by the type of your file system
-pub type FSName = ();
+use crate::a_block_support::BlockFSError::OutsideOfTheBoundariesError;
+use crate::a_block_support::{BlockFS, BlockFSError};
+use crate::f_indirect_inodes::IndirectInodeFSError::{
+    BufferTooSmall, IndirectSystemError, InodeAlreadyDeallocatedError, InodeTooLarge,
+    OffsetOutsideOfInode, PermissionDenied, InvalidXattr, XattrNotFound, XattrTooLarge,
+};
+use cplfs_api::clock::{Clock, SystemClock};
+use cplfs_api::controller::{BlockDevice, Device, ImageDevice};
+use cplfs_api::encoding::{Decoder, Encoder, OnDisk};
+use cplfs_api::error_given::{self, APIError};
+use cplfs_api::fs::{
+    BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport, VolumeSupport, XattrSupport,
+};
+use cplfs_api::perm::{self, Credentials, MAY_READ, MAY_WRITE};
+use cplfs_api::types::{
+    Block, Buffer, FType, InodeLike, SuperBlock, Timestamps, VolumeHeader, DIRECT_POINTERS,
+};
+use cplfs_api::xattr::{load_xattrs, release_xattrs, store_xattrs, XattrInode, XATTR_INLINE_SIZE};
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+///File system name
+pub type FSName = IndirectInodeFS;
+
+///Size of a single block address inside of an indirect block, in bytes
+const POINTER_SIZE: u64 = 8;
+
+///Disk inode that, next to its direct blocks, also points to an indirect and a double indirect block.
+///Implements `OnDisk` like the provided `DInode`, so it can be (de)serialized into disk blocks in the same way.
+///Its layout is that of a `DInode`, with the indirect and the double indirect block address as `u32`s inserted right after the direct block addresses.
+///The block addresses inside of the indirect blocks themselves remain `u64`s.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct DIndirectInode {
+    /// Registers the file type
+    pub ft: FType,
+    /// Counts the number of links to this inode in the file system
+    pub nlink: u16,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Access, modification, change and creation time of the file
+    pub times: Timestamps,
+    /// Permission bits of the file, see `perm`
+    pub mode: u16,
+    /// Owning user of the file
+    pub uid: u32,
+    /// Owning group of the file
+    pub gid: u32,
+    /// Addresses of the first `DIRECT_POINTERS` data blocks of this file
+    pub direct_blocks: [u64; DIRECT_POINTERS as usize],
+    /// Address of the block containing the addresses of the next `block_size/8` data blocks, or 0 if it is not allocated
+    pub indirect_block: u64,
+    /// Address of the block containing the addresses of further indirect blocks, or 0 if it is not allocated
+    pub double_indirect_block: u64,
+    /// Address of the block holding the extended attributes of this inode, or 0 if they are stored inline, see the `xattr` module
+    pub xattr_block: u64,
+    /// Extended attributes of this inode, if they fit in these bytes, see the `xattr` module
+    pub xattr_inline: [u8; XATTR_INLINE_SIZE as usize],
+}
+
+impl OnDisk for DIndirectInode {
+    const SIZE: u64 = FType::SIZE
+        + u16::SIZE
+        + u64::SIZE
+        + Timestamps::SIZE
+        + u16::SIZE
+        + 2 * u32::SIZE
+        + (DIRECT_POINTERS + 2) * u32::SIZE
+        + u32::SIZE
+        + XATTR_INLINE_SIZE;
+
+    fn encode(&self, enc: &mut Encoder<'_>) {
+        enc.put(&self.ft);
+        enc.put(&self.nlink);
+        enc.put(&self.size);
+        enc.put(&self.times);
+        enc.put(&self.mode);
+        enc.put(&self.uid);
+        enc.put(&self.gid);
+        //like for `DInode`, `sb_valid` makes sure that every block address fits
+        for address in self.direct_blocks.iter() {
+            enc.put(&(*address as u32));
+        }
+        enc.put(&(self.indirect_block as u32));
+        enc.put(&(self.double_indirect_block as u32));
+        enc.put(&(self.xattr_block as u32));
+        enc.put(&self.xattr_inline);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> error_given::Result<Self> {
+        let mut di = DIndirectInode {
+            ft: dec.get()?,
+            nlink: dec.get()?,
+            size: dec.get()?,
+            times: dec.get()?,
+            mode: dec.get()?,
+            uid: dec.get()?,
+            gid: dec.get()?,
+            ..Default::default()
+        };
+        for address in di.direct_blocks.iter_mut() {
+            *address = dec.get::<u32>()? as u64;
+        }
+        di.indirect_block = dec.get::<u32>()? as u64;
+        di.double_indirect_block = dec.get::<u32>()? as u64;
+        di.xattr_block = dec.get::<u32>()? as u64;
+        di.xattr_inline = dec.get()?;
+        Ok(di)
+    }
+}
+
+/// Size of a `DIndirectInode` on disk, in bytes.
+/// A reference to the size fixed by its layout, in the same way as `DINODE_SIZE` in the API.
+pub const DINDIRECT_INODE_SIZE: &u64 = &DIndirectInode::SIZE;
+
+///In-memory wrapper around `DIndirectInode`, additionally containing the number of the inode
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct IndirectInode {
+    /// inode number
+    pub inum: u64,
+    /// the disk contents corresponding to `inum`
+    pub disk_node: DIndirectInode,
+}
+
+impl IndirectInode {
+    /// Create a new inode
+    pub fn new(inum: u64, disk_node: DIndirectInode) -> IndirectInode {
+        IndirectInode { inum, disk_node }
+    }
+}
+
+impl InodeLike for IndirectInode {
+    fn new(inum: u64, ft: &FType, nlink: u64, size: u64, blocks: &[u64]) -> Option<Self> {
+        if nlink > u16::MAX as u64 {
+            return None;
+        }
+        //the last two blocks are the indirect and the double indirect block respectively
+        if blocks.len() > (DIRECT_POINTERS + 2) as usize {
+            return None;
+        }
+
+        let mut di = DIndirectInode {
+            ft: *ft,
+            nlink: nlink as u16,
+            size,
+            ..Default::default()
+        };
+        for (i, block) in blocks.iter().enumerate() {
+            match i as u64 {
+                i if i < DIRECT_POINTERS => di.direct_blocks[i as usize] = *block,
+                i if i == DIRECT_POINTERS => di.indirect_block = *block,
+                _ => di.double_indirect_block = *block,
+            }
+        }
+        Some(IndirectInode::new(inum, di))
+    }
+
+    fn get_ft(&self) -> FType {
+        self.disk_node.ft
+    }
+
+    fn get_nlink(&self) -> u64 {
+        self.disk_node.nlink as u64
+    }
+
+    fn get_size(&self) -> u64 {
+        self.disk_node.size
+    }
+
+    fn get_times(&self) -> Timestamps {
+        self.disk_node.times
+    }
+
+    fn get_mode(&self) -> u16 {
+        self.disk_node.mode
+    }
+
+    fn get_uid(&self) -> u32 {
+        self.disk_node.uid
+    }
+
+    fn get_gid(&self) -> u32 {
+        self.disk_node.gid
+    }
+
+    fn get_block(&self, i: u64) -> u64 {
+        if i < DIRECT_POINTERS {
+            self.disk_node.direct_blocks[i as usize]
+        } else if i == DIRECT_POINTERS {
+            self.disk_node.indirect_block
+        } else if i == DIRECT_POINTERS + 1 {
+            self.disk_node.double_indirect_block
+        } else {
+            0
+        }
+    }
+
+    fn get_inum(&self) -> u64 {
+        self.inum
+    }
+}
+
+impl XattrInode for IndirectInode {
+    fn xattr_block(&self) -> u64 {
+        self.disk_node.xattr_block
+    }
+
+    fn xattr_inline(&self) -> &[u8] {
+        &self.disk_node.xattr_inline
+    }
+
+    fn xattr_parts_mut(&mut self) -> (&mut u64, &mut [u8], &mut Timestamps) {
+        let dn = &mut self.disk_node;
+        (&mut dn.xattr_block, &mut dn.xattr_inline, &mut dn.times)
+    }
+}
+
+///Main struct file for the Indirect Inode File System
+pub struct IndirectInodeFS<D: BlockDevice = Device> {
+    block_fs: BlockFS<D>,
+    clock: Box<dyn Clock>,
+}
+
+///Main error file for Indirect Inode File system
+#[derive(Error, Debug)]
+pub enum IndirectInodeFSError {
+    ///Errors that deal with the errors caused by Controller error
+    #[error("API errors that can occur dealing with Controller layer!")]
+    DeviceSystemError(#[from] APIError),
+
+    ///Wrapper error that's going to wrap all errors from the Block layer
+    #[error("File system error!")]
+    IndirectSystemError(#[from] BlockFSError),
+
+    ///Error that's thrown when we are deallocating a inode that's already free
+    #[error("Inode already deallocated")]
+    InodeAlreadyDeallocatedError(),
+
+    ///Error that's thrown when we start reading or writing past the end of the inode
+    #[error("Reading outside of the inode")]
+    OffsetOutsideOfInode(),
+
+    ///Error that's thrown when the given buffer cannot hold the requested number of bytes
+    #[error("Buffer is too small for the requested number of bytes")]
+    BufferTooSmall(),
+
+    ///Error that's thrown when a write would make the inode exceed its maximum possible size
+    #[error("Inode would exceed its maximum size")]
+    InodeTooLarge(),
+
+    ///Error that's thrown when the mode of an inode does not grant the access that is asked for
+    #[error("Permission denied")]
+    PermissionDenied(),
+
+    ///Error that's thrown when the inode does not have the requested extended attribute
+    #[error("Extended attribute not found")]
+    XattrNotFound(),
+
+    ///Error that's thrown when an extended attribute has an invalid name, or a value that is too long
+    #[error("Invalid extended attribute")]
+    InvalidXattr(),
+
+    ///Error that's thrown when the extended attributes of an inode would no longer fit in a single block
+    #[error("Extended attributes do not fit in a block")]
+    XattrTooLarge(),
+}
+
+impl<D: BlockDevice> IndirectInodeFS<D> {
+    ///Check whether the file system was mounted read-only, using `mountfs_readonly`.
+    ///All writes go through the block layer, which rejects them in that case.
+    pub fn is_read_only(&self) -> bool {
+        return self.block_fs.is_read_only();
+    }
+
+    ///Replace the clock the inode timestamps are taken from, which is the `SystemClock` by default
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Box::new(clock);
+    }
+
+    ///Current time according to the clock of this file system, in nanoseconds
+    pub fn now(&self) -> u64 {
+        return self.clock.now();
+    }
+
+    ///Read from `inode` like `i_read`, on behalf of the user with the credentials `creds`, who needs read permission on it
+    pub fn i_read_as(
+        &self,
+        creds: &Credentials,
+        inode: &IndirectInode,
+        buf: &mut Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<u64, IndirectInodeFSError> {
+        if !inode.permits(creds, MAY_READ) {
+            return Err(PermissionDenied());
+        }
+        return self.i_read(inode, buf, off, n);
+    }
+
+    ///Write to `inode` like `i_write`, on behalf of the user with the credentials `creds`, who needs write permission on it
+    pub fn i_write_as(
+        &mut self,
+        creds: &Credentials,
+        inode: &mut IndirectInode,
+        buf: &Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<(), IndirectInodeFSError> {
+        if !inode.permits(creds, MAY_WRITE) {
+            return Err(PermissionDenied());
+        }
+        return self.i_write(inode, buf, off, n);
+    }
+
+    ///Mount the file system on `dev` like `mountfs`, but discard every data block as soon as it is freed, see `BlockFS::mountfs_discard`.
+    ///This includes the data blocks and indirect blocks released by `i_trunc` and `i_free`.
+    pub fn mountfs_discard(dev: D) -> Result<Self, IndirectInodeFSError> {
+        return Self::with_mounted(BlockFS::mountfs_discard(dev)?);
+    }
+
+    ///Check whether the file system was mounted with `mountfs_discard`
+    pub fn is_discarding(&self) -> bool {
+        return self.block_fs.is_discarding();
+    }
+
+    ///Discard every free data block, see `BlockFS::fstrim`, returning the number of discarded blocks
+    pub fn fstrim(&mut self) -> Result<u64, IndirectInodeFSError> {
+        return Ok(self.block_fs.fstrim()?);
+    }
+
+    ///Number of block addresses that fit in a single indirect block
+    fn pointers_per_block(sb: &SuperBlock) -> u64 {
+        sb.block_size / POINTER_SIZE
+    }
+
+    ///Maximum number of data blocks a single inode can point to
+    fn max_blocks(sb: &SuperBlock) -> u64 {
+        let n = Self::pointers_per_block(sb);
+        DIRECT_POINTERS + n + n * n
+    }
+
+    ///Number of data blocks that are in use by an inode of the given size
+    fn n_valid_blocks(sb: &SuperBlock, size: u64) -> u64 {
+        size.div_ceil(sb.block_size)
+    }
+
+    ///Read the block address stored in slot `slot` of the indirect block `block_no`
+    fn read_pointer(&self, block_no: u64, slot: u64) -> Result<u64, IndirectInodeFSError> {
+        let block = self.block_fs.b_get(block_no)?;
+        Ok(block.deserialize_from::<u64>(slot * POINTER_SIZE)?)
+    }
+
+    ///Write the block address `address` into slot `slot` of the indirect block `block_no`
+    fn write_pointer(
+        &mut self,
+        block_no: u64,
+        slot: u64,
+        address: u64,
+    ) -> Result<(), IndirectInodeFSError> {
+        let mut block = self.block_fs.b_get(block_no)?;
+        block.serialize_into(&address, slot * POINTER_SIZE)?;
+        self.block_fs.b_put(&block)?;
+        Ok(())
+    }
+
+    ///Allocate a fresh, zeroed data block and return its address on the disk
+    fn alloc_block(&mut self, sb: &SuperBlock) -> Result<u64, IndirectInodeFSError> {
+        Ok(self.block_fs.b_alloc()? + sb.datastart)
+    }
+
+    ///Free the data block with address `address` on the disk
+    fn free_block(&mut self, sb: &SuperBlock, address: u64) -> Result<(), IndirectInodeFSError> {
+        Ok(self.block_fs.b_free(address - sb.datastart)?)
+    }
+
+    ///Return the address of the `n`th data block of `inode`, or 0 if this block has not been allocated
+    fn bmap(
+        &self,
+        sb: &SuperBlock,
+        inode: &IndirectInode,
+        n: u64,
+    ) -> Result<u64, IndirectInodeFSError> {
+        let per_block = Self::pointers_per_block(sb);
+        let dn = &inode.disk_node;
+
+        if n < DIRECT_POINTERS {
+            return Ok(dn.direct_blocks[n as usize]);
+        }
+
+        let n = n - DIRECT_POINTERS;
+        if n < per_block {
+            if dn.indirect_block == 0 {
+                return Ok(0);
+            }
+            return self.read_pointer(dn.indirect_block, n);
+        }
+
+        let n = n - per_block;
+        if dn.double_indirect_block == 0 {
+            return Ok(0);
+        }
+        let indirect = self.read_pointer(dn.double_indirect_block, n / per_block)?;
+        if indirect == 0 {
+            return Ok(0);
+        }
+        self.read_pointer(indirect, n % per_block)
+    }
+
+    ///Number of data blocks of `inode`, starting at its `n`th block with address `address`, that are stored right after each other on the disk.
+    ///Does not look past the `last`th data block.
+    fn contiguous_run(
+        &self,
+        sb: &SuperBlock,
+        inode: &IndirectInode,
+        n: u64,
+        address: u64,
+        last: u64,
+    ) -> Result<u64, IndirectInodeFSError> {
+        let mut run = 1;
+        while n + run <= last && self.bmap(sb, inode, n + run)? == address + run {
+            run += 1;
+        }
+        Ok(run)
+    }
+
+    ///Return the address of the `n`th data block of `inode`, allocating it (and any indirect blocks leading up to it) if necessary.
+    ///Only changes `inode` in memory; it is up to the caller to write it back to the disk.
+    fn bmap_alloc(
+        &mut self,
+        sb: &SuperBlock,
+        inode: &mut IndirectInode,
+        n: u64,
+    ) -> Result<u64, IndirectInodeFSError> {
+        let per_block = Self::pointers_per_block(sb);
+
+        if n < DIRECT_POINTERS {
+            if inode.disk_node.direct_blocks[n as usize] == 0 {
+                inode.disk_node.direct_blocks[n as usize] = self.alloc_block(sb)?;
+            }
+            return Ok(inode.disk_node.direct_blocks[n as usize]);
+        }
+
+        let n = n - DIRECT_POINTERS;
+        if n < per_block {
+            if inode.disk_node.indirect_block == 0 {
+                inode.disk_node.indirect_block = self.alloc_block(sb)?;
+            }
+            return self.pointer_alloc(sb, inode.disk_node.indirect_block, n);
+        }
+
+        let n = n - per_block;
+        if inode.disk_node.double_indirect_block == 0 {
+            inode.disk_node.double_indirect_block = self.alloc_block(sb)?;
+        }
+        let indirect = self.pointer_alloc(sb, inode.disk_node.double_indirect_block, n / per_block)?;
+        self.pointer_alloc(sb, indirect, n % per_block)
+    }
+
+    ///Return the address stored in slot `slot` of the indirect block `block_no`, allocating a fresh block for it if the slot is empty
+    fn pointer_alloc(
+        &mut self,
+        sb: &SuperBlock,
+        block_no: u64,
+        slot: u64,
+    ) -> Result<u64, IndirectInodeFSError> {
+        let address = self.read_pointer(block_no, slot)?;
+        if address != 0 {
+            return Ok(address);
+        }
+
+        let address = self.alloc_block(sb)?;
+        self.write_pointer(block_no, slot, address)?;
+        Ok(address)
+    }
+
+    ///Release all valid data blocks of `inode`, together with the indirect blocks pointing to them, and reset all of its block addresses to 0.
+    ///Only changes `inode` in memory; it is up to the caller to write it back to the disk.
+    fn release_blocks(
+        &mut self,
+        sb: &SuperBlock,
+        inode: &mut IndirectInode,
+    ) -> Result<(), IndirectInodeFSError> {
+        //the target of a short symlink takes the place of its block addresses, so there are no blocks to release
+        if inode.has_inline_target() {
+            inode.disk_node.direct_blocks = Default::default();
+            return Ok(());
+        }
+
+        let per_block = Self::pointers_per_block(sb);
+        let n_valid_blocks = Self::n_valid_blocks(sb, inode.disk_node.size);
+
+        //the data blocks themselves first, since their addresses are stored in the indirect blocks
+        for n in 0..n_valid_blocks {
+            let address = self.bmap(sb, inode, n)?;
+            if address != 0 {
+                self.free_block(sb, address)?;
+            }
+        }
+
+        if n_valid_blocks > DIRECT_POINTERS && inode.disk_node.indirect_block != 0 {
+            self.free_block(sb, inode.disk_node.indirect_block)?;
+        }
+
+        if n_valid_blocks > DIRECT_POINTERS + per_block && inode.disk_node.double_indirect_block != 0 {
+            let n_double = n_valid_blocks - DIRECT_POINTERS - per_block;
+            for slot in 0..n_double.div_ceil(per_block) {
+                let indirect = self.read_pointer(inode.disk_node.double_indirect_block, slot)?;
+                if indirect != 0 {
+                    self.free_block(sb, indirect)?;
+                }
+            }
+            self.free_block(sb, inode.disk_node.double_indirect_block)?;
+        }
+
+        inode.disk_node.direct_blocks = Default::default();
+        inode.disk_node.indirect_block = 0;
+        inode.disk_node.double_indirect_block = 0;
+        Ok(())
+    }
+
+    ///Grow or shrink the file system, together with the device it is stored on, to `nblocks` blocks, as described for `BlockFS::resize_fs`.
+    ///Data blocks that had to make room for a bigger bitmap are moved, so all pointers to them are updated afterwards,
+    /// in the inodes themselves as well as in their (double) indirect blocks.
+    pub fn resize_fs(&mut self, nblocks: u64) -> Result<(), IndirectInodeFSError> {
+        let moves: HashMap<u64, u64> = self.block_fs.resize_fs(nblocks)?.into_iter().collect();
+        if moves.is_empty() {
+            return Ok(());
+        }
+
+        let sb = self.sup_get()?;
+        let per_block = Self::pointers_per_block(&sb);
+        let relocate = |address: u64| *moves.get(&address).unwrap_or(&address);
+        for inum in 1..sb.ninodes {
+            let mut inode = self.i_get(inum)?;
+            if inode.disk_node.ft == FType::TFree {
+                continue;
+            }
+
+            //the addresses of a symlink with an inline target are not addresses at all
+            let inline_target = inode.has_inline_target();
+            let dn = &mut inode.disk_node;
+            dn.xattr_block = relocate(dn.xattr_block);
+            if inline_target {
+                self.i_put(&inode)?;
+                continue;
+            }
+            dn.direct_blocks.iter_mut().for_each(|address| *address = relocate(*address));
+            dn.indirect_block = relocate(dn.indirect_block);
+            dn.double_indirect_block = relocate(dn.double_indirect_block);
+            self.i_put(&inode)?;
+
+            //the indirect blocks themselves have already been moved, so they are read at their new address
+            if inode.disk_node.indirect_block != 0 {
+                self.relocate_pointers(inode.disk_node.indirect_block, &moves)?;
+            }
+            if inode.disk_node.double_indirect_block != 0 {
+                self.relocate_pointers(inode.disk_node.double_indirect_block, &moves)?;
+                let double = self.block_fs.b_get(inode.disk_node.double_indirect_block)?;
+                for slot in 0..per_block {
+                    let indirect = double.deserialize_from::<u64>(slot * POINTER_SIZE)?;
+                    if indirect != 0 {
+                        self.relocate_pointers(indirect, &moves)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///Replace all addresses in the indirect block `block_no` that occur in `moves` by their new address
+    fn relocate_pointers(
+        &mut self,
+        block_no: u64,
+        moves: &HashMap<u64, u64>,
+    ) -> Result<(), IndirectInodeFSError> {
+        let sb = self.sup_get()?;
+        let mut block = self.block_fs.b_get(block_no)?;
+        let mut changed = false;
+        for slot in 0..Self::pointers_per_block(&sb) {
+            let address = block.deserialize_from::<u64>(slot * POINTER_SIZE)?;
+            if let Some(new_address) = moves.get(&address) {
+                block.serialize_into(new_address, slot * POINTER_SIZE)?;
+                changed = true;
+            }
+        }
+        if changed {
+            self.block_fs.b_put(&block)?;
+        }
+        Ok(())
+    }
+
+    ///Block number and byte offset within that block at which inode `i` is stored
+    fn inode_position(sb: &SuperBlock, i: u64) -> (u64, u64) {
+        let n_inodes_per_block = sb.block_size / *DINDIRECT_INODE_SIZE;
+        (
+            sb.inodestart + i / n_inodes_per_block,
+            (i % n_inodes_per_block) * *DINDIRECT_INODE_SIZE,
+        )
+    }
+
+    ///Wraps a mounted block file system, after checking that its superblock also holds for the inodes of this layer
+    fn with_mounted(block_fs: BlockFS<D>) -> Result<Self, IndirectInodeFSError> {
+        //the block layer only checks the superblock for the provided inodes
+        if !Self::sb_valid(&block_fs.sup_get()?) {
+            return Err(IndirectSystemError(BlockFSError::SuperBlockInvalid()));
+        }
+
+        let rustfs = IndirectInodeFS {
+            block_fs,
+            clock: Box::new(SystemClock),
+        };
+        return Ok(rustfs);
+    }
+
+    ///Wraps a freshly created block file system, after writing all of its inodes as free inodes
+    fn with_free_inodes(mut block_fs: BlockFS<D>, sb: &SuperBlock) -> Result<Self, IndirectInodeFSError> {
+        //writing all inodes as free inodes, storing the entire inode table in a single batch
+        let n_inodes_per_block = sb.block_size / *DINDIRECT_INODE_SIZE;
+        let n_inode_blocks = sb.ninodes.div_ceil(n_inodes_per_block);
+        let mut inode_blocks = Vec::new();
+        for i in 0..n_inode_blocks {
+            let mut inode_block = Block::new_zero(sb.inodestart + i, sb.block_size);
+            for j in 0..n_inodes_per_block {
+                if i * n_inodes_per_block + j < sb.ninodes {
+                    inode_block.serialize_into(&DIndirectInode::default(), *DINDIRECT_INODE_SIZE * j)?;
+                }
+            }
+            inode_blocks.push(inode_block);
+        }
+        block_fs.b_put_blocks(&inode_blocks)?;
+
+        Ok(IndirectInodeFS {
+            block_fs,
+            clock: Box::new(SystemClock),
+        })
+    }
+}
+
+impl<D: BlockDevice> FileSysSupport for IndirectInodeFS<D> {
+    type Error = IndirectInodeFSError;
+    type Dev = D;
+
+    fn sb_valid(sb: &SuperBlock) -> bool {
+        if !BlockFS::<D>::sb_valid(sb) || sb.block_size < *DINDIRECT_INODE_SIZE {
+            return false;
+        }
+
+        //our inodes are larger than the provided ones, so the inode region has to be checked again
+        let n_inodes_per_block = sb.block_size / *DINDIRECT_INODE_SIZE;
+        let n_inode_blocks = sb.ninodes.div_ceil(n_inodes_per_block);
+        return sb.inodestart + n_inode_blocks <= sb.bmapstart;
+    }
+
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>
+    where
+        D: ImageDevice,
+    {
+        if !Self::sb_valid(sb) {
+            return Err(IndirectSystemError(BlockFSError::SuperBlockInvalid()));
+        }
+
+        return Self::with_free_inodes(BlockFS::mkfs(path, sb)?, sb);
+    }
+
+    fn mkfs_on(dev: D, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        if !Self::sb_valid(sb) {
+            return Err(IndirectSystemError(BlockFSError::SuperBlockInvalid()));
+        }
+
+        return Self::with_free_inodes(BlockFS::mkfs_on(dev, sb)?, sb);
+    }
+
+    fn mountfs(dev: D) -> Result<Self, Self::Error> {
+        return Self::with_mounted(BlockFS::mountfs(dev)?);
+    }
+
+    fn mountfs_readonly(dev: D) -> Result<Self, Self::Error> {
+        return Self::with_mounted(BlockFS::mountfs_readonly(dev)?);
+    }
+
+    fn unmountfs(self) -> D {
+        return self.block_fs.unmountfs();
+    }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        return Ok(self.block_fs.sync()?);
+    }
+}
+
+impl<D: BlockDevice> BlockSupport for IndirectInodeFS<D> {
+    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+        Ok(self.block_fs.b_get(i)?)
+    }
+
+    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_put(b)?)
+    }
+
+    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_free(i)?)
+    }
+
+    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        Ok(self.block_fs.b_zero(i)?)
+    }
+
+    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        Ok(self.block_fs.b_alloc()?)
+    }
+
+    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
+        Ok(self.block_fs.sup_get()?)
+    }
+
+    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
+        Ok(self.block_fs.sup_put(sup)?)
+    }
+}
+
+impl<D: BlockDevice> VolumeSupport for IndirectInodeFS<D> {
+    fn volume_get(&self) -> Result<VolumeHeader, Self::Error> {
+        Ok(self.block_fs.volume_get()?)
+    }
+
+    fn volume_put(&mut self, vol: &VolumeHeader) -> Result<(), Self::Error> {
+        Ok(self.block_fs.volume_put(vol)?)
+    }
+}
+
+impl<D: BlockDevice> InodeSupport for IndirectInodeFS<D> {
+    type Inode = IndirectInode;
+
+    fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
+        let sb = self.sup_get()?;
+        if i >= sb.ninodes {
+            return Err(IndirectSystemError(OutsideOfTheBoundariesError()));
+        }
+
+        let (block_no, offset) = Self::inode_position(&sb, i);
+        let inode_block = self.b_get(block_no)?;
+        let dinode = inode_block.deserialize_from::<DIndirectInode>(offset)?;
+
+        Ok(IndirectInode::new(i, dinode))
+    }
+
+    fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        let sb = self.sup_get()?;
+        if ino.inum >= sb.ninodes {
+            return Err(IndirectSystemError(OutsideOfTheBoundariesError()));
+        }
+
+        let (block_no, offset) = Self::inode_position(&sb, ino.inum);
+        let mut inode_block = self.b_get(block_no)?;
+        inode_block.serialize_into(&ino.disk_node, offset)?;
+        self.b_put(&inode_block)?;
+
+        Ok(())
+    }
+
+    fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        let sb = self.sup_get()?;
+        let mut inode = self.i_get(i)?;
+
+        if inode.disk_node.ft == FType::TFree {
+            return Err(InodeAlreadyDeallocatedError());
+        }
+
+        //inodes that are still referenced somewhere are left alone
+        if inode.disk_node.nlink != 0 {
+            return Ok(());
+        }
+
+        self.release_blocks(&sb, &mut inode)?;
+        release_xattrs(self, &mut inode)?;
+        inode.disk_node.ft = FType::TFree;
+        inode.disk_node.size = 0;
+        self.i_put(&inode)?;
+
+        Ok(())
+    }
+
+    fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
+        let sb = self.sup_get()?;
+
+        //going through the inode blocks, reading each one of them only once
+        let n_inodes_per_block = sb.block_size / *DINDIRECT_INODE_SIZE;
+        let n_inode_blocks = sb.ninodes.div_ceil(n_inodes_per_block);
+        for i in 0..n_inode_blocks {
+            let mut inode_block = self.b_get(sb.inodestart + i)?;
+            for j in 0..n_inodes_per_block {
+                let inum = i * n_inodes_per_block + j;
+
+                //the zero-th inode is never allocated
+                if inum == 0 {
+                    continue;
+                }
+                if inum >= sb.ninodes {
+                    break;
+                }
+
+                let offset = j * *DINDIRECT_INODE_SIZE;
+                let dinode = inode_block.deserialize_from::<DIndirectInode>(offset)?;
+                if dinode.ft == FType::TFree {
+                    let allocated = DIndirectInode {
+                        ft,
+                        times: Timestamps::created(self.now()),
+                        mode: perm::default_mode(ft),
+                        ..Default::default()
+                    };
+                    inode_block.serialize_into(&allocated, offset)?;
+                    self.b_put(&inode_block)?;
+                    return Ok(inum);
+                }
+            }
+        }
+
+        Err(IndirectSystemError(OutsideOfTheBoundariesError()))
+    }
+
+    fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
+        let sb = self.sup_get()?;
+
+        self.release_blocks(&sb, inode)?;
+        inode.disk_node.size = 0;
+        inode.disk_node.times.modified(self.now());
+        self.i_put(inode)?;
+
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice> InodeRWSupport for IndirectInodeFS<D> {
+    fn i_read(
+        &self,
+        inode: &Self::Inode,
+        buf: &mut Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<u64, Self::Error> {
+        let sb = self.sup_get()?;
+        let size = inode.disk_node.size;
+
+        if off > size {
+            return Err(OffsetOutsideOfInode());
+        }
+
+        //stop at the end of the file or at the end of the buffer, whichever comes first
+        let n = n.min(size - off).min(buf.len());
+
+        let mut read = 0;
+        while read < n {
+            let pos = off + read;
+            let offset = pos % sb.block_size;
+            let n_block = pos / sb.block_size;
+            let address = self.bmap(&sb, inode, n_block)?;
+
+            //data blocks that are stored right after each other on the disk are read as a single range
+            let run = if address == 0 {
+                1
+            } else {
+                self.contiguous_run(&sb, inode, n_block, address, (off + n - 1) / sb.block_size)?
+            };
+            let chunk = (run * sb.block_size - offset).min(n - read);
+
+            //holes in the file read as zeroes
+            if address == 0 {
+                buf.write_data(&vec![0; chunk as usize], read)?;
+            } else {
+                let data = self.block_fs.b_get_range(address, run)?;
+                buf.write_data(&data[offset as usize..(offset + chunk) as usize], read)?;
+            }
+
+            read += chunk;
+        }
+
+        Ok(n)
+    }
+
+    fn i_write(
+        &mut self,
+        inode: &mut Self::Inode,
+        buf: &Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<(), Self::Error> {
+        let sb = self.sup_get()?;
+
+        if off > inode.disk_node.size {
+            return Err(OffsetOutsideOfInode());
+        }
+        if buf.len() < n {
+            return Err(BufferTooSmall());
+        }
+        if Self::n_valid_blocks(&sb, off + n) > Self::max_blocks(&sb) {
+            return Err(InodeTooLarge());
+        }
+
+        //the data blocks are collected first, and written to the device in a single batch
+        let mut blocks = Vec::new();
+        let mut written = 0;
+        while written < n {
+            let pos = off + written;
+            let offset = pos % sb.block_size;
+            let chunk = (sb.block_size - offset).min(n - written);
+            let data = &buf.contents_as_ref()[written as usize..(written + chunk) as usize];
+
+            let address = self.bmap_alloc(&sb, inode, pos / sb.block_size)?;
+            //blocks that are overwritten entirely do not have to be read first
+            let block = if chunk == sb.block_size {
+                Block::new(address, data.into())
+            } else {
+                let mut block = self.b_get(address)?;
+                block.write_data(data, offset)?;
+                block
+            };
+            blocks.push(block);
+
+            written += chunk;
+        }
+        self.block_fs.b_put_blocks(&blocks)?;
+
+        inode.disk_node.size = inode.disk_node.size.max(off + n);
+        inode.disk_node.times.modified(self.now());
+        self.i_put(inode)?;
+
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice> XattrSupport for IndirectInodeFS<D> {
+    fn getxattr(&self, inode: &Self::Inode, name: &str) -> Result<Vec<u8>, Self::Error> {
+        let xattrs = load_xattrs(self, inode)?;
+        xattrs.get(name).map(|value| value.to_vec()).ok_or(XattrNotFound())
+    }
+
+    fn setxattr(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), Self::Error> {
+        let mut xattrs = load_xattrs(self, inode)?;
+        xattrs.set(name, value).ok_or(InvalidXattr())?;
+        let now = self.now();
+        store_xattrs(self, inode, &xattrs, now)?.ok_or(XattrTooLarge())
+    }
+
+    fn listxattr(&self, inode: &Self::Inode) -> Result<Vec<String>, Self::Error> {
+        Ok(load_xattrs(self, inode)?.names())
+    }
+
+    fn removexattr(&mut self, inode: &mut Self::Inode, name: &str) -> Result<(), Self::Error> {
+        let mut xattrs = load_xattrs(self, inode)?;
+        xattrs.remove(name).ok_or(XattrNotFound())?;
+        let now = self.now();
+        store_xattrs(self, inode, &xattrs, now)?.ok_or(XattrTooLarge())
+    }
+}
+
+#[cfg(test)]
+mod test_in_memory {
+    use crate::f_indirect_inodes::{IndirectInodeFS, DINDIRECT_INODE_SIZE};
+    use cplfs_api::controller::BlockDevice;
+    use cplfs_api::fault_device::FaultDevice;
+    use cplfs_api::io_stats::{IoOp, Region};
+    use cplfs_api::mem_device::MemDevice;
+    use cplfs_api::raid_device::{MirrorDevice, StripeDevice};
+    use cplfs_api::sim_device::{CostModel, HddModel, SimDevice};
+    use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport, XattrSupport};
+    use cplfs_api::types::{Buffer, FType, InodeLike, SuperBlock, DIRECT_POINTERS};
+
+    //Small blocks, so that a single indirect block only holds 16 addresses and the double indirect block is reached quickly
+    static BLOCK_SIZE: u64 = 128;
+    static NBLOCKS: u64 = 66;
+    static SUPERBLOCK_SMALL_BLOCKS: SuperBlock = SuperBlock {
+        block_size: BLOCK_SIZE,
+        nblocks: NBLOCKS,
+        ninodes: 4,
+        inodestart: 1,
+        ndatablocks: 60,
+        bmapstart: 5,
+        datastart: 6,
+    };
+
+    type FSName = IndirectInodeFS<MemDevice>;
+
+    ///Create a file system with small blocks on `dev`, which has to hold `NBLOCKS` blocks of `BLOCK_SIZE` bytes
+    fn fresh_fs<D: BlockDevice>(dev: D) -> IndirectInodeFS<D> {
+        IndirectInodeFS::mkfs_on(dev, &SUPERBLOCK_SMALL_BLOCKS).unwrap()
+    }
+
+    #[test]
+    fn double_indirect() {
+        assert!(*DINDIRECT_INODE_SIZE <= BLOCK_SIZE);
+        let mut my_fs = fresh_fs(MemDevice::new(BLOCK_SIZE, NBLOCKS));
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+
+        //40 data blocks; 12 direct, 16 through the indirect block and 12 through the double indirect block
+        let data: Vec<u8> = (0..40 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+        let buf = Buffer::new(data.clone().into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 40 * BLOCK_SIZE).unwrap();
+        let i1 = my_fs.i_get(1).unwrap();
+        assert_eq!(i1.get_size(), 40 * BLOCK_SIZE);
+        assert_ne!(i1.get_block(DIRECT_POINTERS), 0);
+        assert_ne!(i1.get_block(DIRECT_POINTERS + 1), 0);
+
+        //the contents survive remounting
+        let dev = my_fs.unmountfs();
+        let mut my_fs = FSName::mountfs(dev).unwrap();
+        let mut i1 = my_fs.i_get(1).unwrap();
+        let mut read = Buffer::new_zero(40 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read(&i1, &mut read, 0, 40 * BLOCK_SIZE).unwrap(), 40 * BLOCK_SIZE);
+        assert_eq!(read.contents_as_ref(), &data[..]);
+
+        //overwriting a range that crosses from the indirect into the double indirect blocks
+        let patch = Buffer::new(vec![7; 300].into_boxed_slice());
+        my_fs.i_write(&mut i1, &patch, 27 * BLOCK_SIZE + 10, 300).unwrap();
+        let mut read = Buffer::new_zero(300);
+        assert_eq!(my_fs.i_read(&i1, &mut read, 27 * BLOCK_SIZE + 10, 300).unwrap(), 300);
+        assert_eq!(read, patch);
+        assert_eq!(i1.get_size(), 40 * BLOCK_SIZE);
+
+        //40 data blocks, 1 indirect, 1 double indirect and 1 indirect block below it
+        my_fs.i_trunc(&mut i1).unwrap();
+        assert_eq!(i1.get_block(DIRECT_POINTERS), 0);
+        assert_eq!(i1.get_block(DIRECT_POINTERS + 1), 0);
+        for i in 0..SUPERBLOCK_SMALL_BLOCKS.ndatablocks {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        assert!(my_fs.b_alloc().is_err());
+    }
+
+    #[test]
+    fn resize_fs() {
+        let mut my_fs = fresh_fs(MemDevice::new(BLOCK_SIZE, NBLOCKS));
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let data: Vec<u8> = (0..40 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+        let buf = Buffer::new(data.clone().into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 40 * BLOCK_SIZE).unwrap();
+
+        //a bitmap block only covers 1024 data blocks, so the bitmap takes over the first 19 data blocks,
+        //which hold direct blocks, the indirect block and some of the blocks it points to
+        my_fs.resize_fs(20000).unwrap();
+        let sb = my_fs.sup_get().unwrap();
+        assert_eq!(sb.datastart, SUPERBLOCK_SMALL_BLOCKS.datastart + 19);
+        assert_eq!(sb.ndatablocks, 20000 - sb.datastart);
+        let i1 = my_fs.i_get(inum).unwrap();
+        for n in 0..DIRECT_POINTERS + 2 {
+            assert!(i1.get_block(n) >= sb.datastart);
+        }
+        let mut read = Buffer::new_zero(40 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read(&i1, &mut read, 0, 40 * BLOCK_SIZE).unwrap(), 40 * BLOCK_SIZE);
+        assert_eq!(read.contents_as_ref(), &data[..]);
+
+        //the moved blocks end up right behind the other 24 allocated ones, so the file system cannot shrink past them
+        assert!(my_fs.resize_fs(sb.datastart + 40).is_err());
+        my_fs.resize_fs(sb.datastart + 43).unwrap();
+        let dev = my_fs.unmountfs();
+        assert_eq!(dev.nblocks, sb.datastart + 43);
+        let my_fs = FSName::mountfs(dev).unwrap();
+        let i1 = my_fs.i_get(inum).unwrap();
+        let mut read = Buffer::new_zero(40 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read(&i1, &mut read, 0, 40 * BLOCK_SIZE).unwrap(), 40 * BLOCK_SIZE);
+        assert_eq!(read.contents_as_ref(), &data[..]);
+    }
+
+    #[test]
+    fn xattr_block() {
+        let mut my_fs = fresh_fs(MemDevice::new(BLOCK_SIZE, NBLOCKS));
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+
+        //the attribute does not fit inline, so it takes up the first data block
+        my_fs.setxattr(&mut i1, "origin", b"https://example.org").unwrap();
+        assert_eq!(i1.disk_node.xattr_block, SUPERBLOCK_SMALL_BLOCKS.datastart);
+
+        //the bitmap takes over that block when the file system grows, so the attributes are moved along
+        my_fs.resize_fs(20000).unwrap();
+        let sb = my_fs.sup_get().unwrap();
+        let i1 = my_fs.i_get(inum).unwrap();
+        let address = i1.disk_node.xattr_block;
+        assert!(address >= sb.datastart);
+        assert_eq!(my_fs.getxattr(&i1, "origin").unwrap(), b"https://example.org");
+
+        //freeing the inode gives back its xattr block
+        my_fs.i_free(inum).unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.xattr_block, 0);
+        assert_eq!(my_fs.b_alloc().unwrap() + sb.datastart, address);
+    }
+
+    #[test]
+    fn discard_on_trunc() {
+        let dev = fresh_fs(MemDevice::new(BLOCK_SIZE, NBLOCKS)).unmountfs();
+        let mut my_fs = FSName::mountfs_discard(dev).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let buf = Buffer::new(vec![1; (40 * BLOCK_SIZE) as usize].into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 40 * BLOCK_SIZE).unwrap();
+
+        //all data blocks, as well as the indirect blocks pointing to them, read as zeroes afterwards
+        my_fs.i_trunc(&mut i1).unwrap();
+        let dev = my_fs.unmountfs();
+        let data = dev.blocks_ref(SUPERBLOCK_SMALL_BLOCKS.datastart, SUPERBLOCK_SMALL_BLOCKS.ndatablocks).unwrap();
+        assert!(data.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut my_fs = fresh_fs(MemDevice::new(BLOCK_SIZE, NBLOCKS));
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+
+        //12 + 16 + 16 * 16 blocks is the maximum size of an inode
+        let max_size = (12 + 16 + 16 * 16) * BLOCK_SIZE;
+        let buf = Buffer::new_zero(max_size + 1);
+        assert!(my_fs.i_write(&mut i1, &buf, 0, max_size + 1).is_err());
+        assert_eq!(my_fs.i_get(1).unwrap().get_size(), 0);
+
+        //a buffer that is too small is refused as well
+        assert!(my_fs.i_write(&mut i1, &Buffer::new_zero(10), 0, 11).is_err());
+
+        //running out of data blocks halfway through is reported too
+        assert!(my_fs.i_write(&mut i1, &buf, 0, 61 * BLOCK_SIZE).is_err());
+    }
+
+    #[test]
+    fn access_patterns() {
+        let n_inode_blocks = SUPERBLOCK_SMALL_BLOCKS.ninodes.div_ceil(BLOCK_SIZE / *DINDIRECT_INODE_SIZE);
+        let inode_blocks = SUPERBLOCK_SMALL_BLOCKS.inodestart..SUPERBLOCK_SMALL_BLOCKS.inodestart + n_inode_blocks;
+
+        //mkfs writes the superblock and each inode block once, and nothing else
+        let dev = MemDevice::new(BLOCK_SIZE, NBLOCKS);
+        let stats = dev.stats();
+        stats.enable_trace();
+        let mut my_fs = fresh_fs(dev);
+        let written: Vec<u64> = stats.trace().iter().filter(|e| e.op == IoOp::Write).map(|e| e.block_no).collect();
+        assert_eq!(written, std::iter::once(0).chain(inode_blocks.clone()).collect::<Vec<u64>>());
+
+        //i_alloc reads each inode block at most once, also when it has to scan all of them
+        for _ in 1..SUPERBLOCK_SMALL_BLOCKS.ninodes {
+            my_fs.i_alloc(FType::TFile).unwrap();
+        }
+        stats.reset();
+        assert!(my_fs.i_alloc(FType::TFile).is_err());
+        for b in inode_blocks {
+            assert_eq!(stats.block(b).reads, 1);
+        }
+        assert_eq!(stats.region(&SUPERBLOCK_SMALL_BLOCKS, Region::Inodes).reads, n_inode_blocks);
+        assert_eq!(stats.total().writes, 0);
+    }
+
+    #[test]
+    fn multi_block_spans() {
+        let dev = MemDevice::new(BLOCK_SIZE, NBLOCKS);
+        let stats = dev.stats();
+        let mut my_fs = fresh_fs(dev);
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+
+        //writing whole blocks never reads the data blocks they replace
+        let data: Vec<u8> = (0..4 * BLOCK_SIZE).map(|i| i as u8).collect();
+        stats.reset();
+        my_fs.i_write(&mut i1, &Buffer::new(data.clone().into_boxed_slice()), 0, 4 * BLOCK_SIZE).unwrap();
+        assert_eq!(stats.region(&SUPERBLOCK_SMALL_BLOCKS, Region::Data).bytes_read, 0);
+
+        //a span that starts and ends halfway a block reads each of the blocks it touches exactly once
+        stats.reset();
+        let mut buf = Buffer::new_zero(2 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read(&i1, &mut buf, BLOCK_SIZE / 2, 2 * BLOCK_SIZE).unwrap(), 2 * BLOCK_SIZE);
+        assert_eq!(buf.contents_as_ref(), &data[(BLOCK_SIZE / 2) as usize..(BLOCK_SIZE / 2 + 2 * BLOCK_SIZE) as usize]);
+        assert_eq!(stats.region(&SUPERBLOCK_SMALL_BLOCKS, Region::Data).reads, 3);
+
+        //overwriting part of the span only reads the partially written blocks
+        stats.reset();
+        my_fs.i_write(&mut i1, &Buffer::new(vec![7; 2 * BLOCK_SIZE as usize].into_boxed_slice()), BLOCK_SIZE / 2, 2 * BLOCK_SIZE).unwrap();
+        assert_eq!(stats.region(&SUPERBLOCK_SMALL_BLOCKS, Region::Data).reads, 2);
+        let mut buf = Buffer::new_zero(4 * BLOCK_SIZE);
+        my_fs.i_read(&i1, &mut buf, 0, 4 * BLOCK_SIZE).unwrap();
+        let mut expected = data;
+        expected[(BLOCK_SIZE / 2) as usize..(BLOCK_SIZE / 2 + 2 * BLOCK_SIZE) as usize].fill(7);
+        assert_eq!(buf.contents_as_ref(), &expected[..]);
+    }
+
+    #[test]
+    fn mirror_member_removed() {
+        let members = vec![MemDevice::new(BLOCK_SIZE, NBLOCKS), MemDevice::new(BLOCK_SIZE, NBLOCKS)];
+        let dev = MirrorDevice::new(members).unwrap();
+        let mut my_fs = fresh_fs(dev);
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let data: Vec<u8> = (0..3 * BLOCK_SIZE).map(|i| i as u8).collect();
+        my_fs.i_write(&mut i1, &Buffer::new(data.clone().into_boxed_slice()), 0, 3 * BLOCK_SIZE).unwrap();
+
+        //the file system keeps working while a member is gone
+        let mut dev = my_fs.unmountfs();
+        dev.remove_member(0).unwrap();
+        let mut my_fs = IndirectInodeFS::mountfs(dev).unwrap();
+        let mut buf = Buffer::new_zero(3 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read(&my_fs.i_get(inum).unwrap(), &mut buf, 0, 3 * BLOCK_SIZE).unwrap(), 3 * BLOCK_SIZE);
+        assert_eq!(buf.contents_as_ref(), &data[..]);
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        my_fs.i_write(&mut i1, &Buffer::new(vec![9; BLOCK_SIZE as usize].into_boxed_slice()), 3 * BLOCK_SIZE, BLOCK_SIZE).unwrap();
+
+        //a write made while the mirror is degraded survives remounting it
+        let my_fs = IndirectInodeFS::mountfs(my_fs.unmountfs()).unwrap();
+        let i1 = my_fs.i_get(inum).unwrap();
+        assert_eq!(i1.get_size(), 4 * BLOCK_SIZE);
+        let mut buf = Buffer::new_zero(BLOCK_SIZE);
+        my_fs.i_read(&i1, &mut buf, 3 * BLOCK_SIZE, BLOCK_SIZE).unwrap();
+        assert_eq!(buf.contents_as_ref(), &[9; BLOCK_SIZE as usize][..]);
+    }
+
+    #[test]
+    fn striped_device() {
+        let members = vec![MemDevice::new(BLOCK_SIZE, NBLOCKS / 2), MemDevice::new(BLOCK_SIZE, NBLOCKS / 2)];
+        let dev = StripeDevice::new(members).unwrap();
+        let mut my_fs = fresh_fs(dev);
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let data: Vec<u8> = (0..5 * BLOCK_SIZE).map(|i| (i / 7) as u8).collect();
+        my_fs.i_write(&mut i1, &Buffer::new(data.clone().into_boxed_slice()), 0, 5 * BLOCK_SIZE).unwrap();
+
+        let my_fs = IndirectInodeFS::mountfs(my_fs.unmountfs()).unwrap();
+        let mut buf = Buffer::new_zero(5 * BLOCK_SIZE);
+        my_fs.i_read(&my_fs.i_get(inum).unwrap(), &mut buf, 0, 5 * BLOCK_SIZE).unwrap();
+        assert_eq!(buf.contents_as_ref(), &data[..]);
+    }
+
+    #[test]
+    fn i_alloc_torn_write() {
+        //tear the single write of i_alloc at every byte of the inode block
+        for prefix in 0..=BLOCK_SIZE as usize {
+            let dev = FaultDevice::new(MemDevice::new(BLOCK_SIZE, NBLOCKS));
+            let my_fs = fresh_fs(dev);
+            let mut dev = my_fs.unmountfs();
+            dev.tear_nth_write(dev.write_count() + 1, prefix);
+            let mut my_fs = IndirectInodeFS::mountfs(dev).unwrap();
+            assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 1);
+
+            //after remounting, the inode is either still free or completely allocated
+            let mut my_fs = FSName::mountfs(my_fs.unmountfs().into_inner()).unwrap();
+            let i1 = my_fs.i_get(1).unwrap();
+            assert!(i1.get_ft() == FType::TFree || i1.get_ft() == FType::TFile);
+            assert_eq!(i1.get_size(), 0);
+            assert_eq!(i1.get_nlink(), 0);
+            for inum in 2..SUPERBLOCK_SMALL_BLOCKS.ninodes {
+                assert_eq!(my_fs.i_get(inum).unwrap().get_ft(), FType::TFree);
+            }
+            let expected = if i1.get_ft() == FType::TFree { 1 } else { 2 };
+            assert_eq!(my_fs.i_alloc(FType::TDir).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn modeled_cost() {
+        let hdd = HddModel { blocks_per_track: 16, ..Default::default() };
+
+        //mkfs writes the superblock and the inode blocks in order, so it never seeks
+        let dev = SimDevice::new(MemDevice::new(BLOCK_SIZE, NBLOCKS), CostModel::Hdd(hdd));
+        let clock = dev.clock();
+        let mut my_fs = fresh_fs(dev);
+        let mkfs = clock.totals();
+        assert_eq!(mkfs.seeks, 0);
+        assert_eq!(mkfs.elapsed_ns, mkfs.writes * hdd.transfer_ns);
 
-// **TODO** define your own tests here.
+        //writing a file in one go is cheaper than writing it block by block
+        let data = Buffer::new((0..20 * BLOCK_SIZE).map(|i| i as u8).collect::<Vec<u8>>().into_boxed_slice());
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        clock.reset();
+        my_fs.i_write(&mut i1, &data, 0, 20 * BLOCK_SIZE).unwrap();
+        let at_once = clock.totals();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i2 = my_fs.i_get(inum).unwrap();
+        clock.reset();
+        for b in 0..20 {
+            let block = Buffer::new(data.contents_as_ref()[(b * BLOCK_SIZE) as usize..((b + 1) * BLOCK_SIZE) as usize].into());
+            my_fs.i_write(&mut i2, &block, b * BLOCK_SIZE, BLOCK_SIZE).unwrap();
+        }
+        let per_block = clock.totals();
+        assert!(at_once.elapsed_ns < per_block.elapsed_ns);
+    }
+}
 
 // WARNING: DO NOT TOUCH THE BELOW CODE -- IT IS REQUIRED FOR TESTING -- YOU WILL LOSE POINTS IF I MANUALLY HAVE TO FIX YOUR TESTS
 #[cfg(all(test, any(feature = "f", feature = "all")))]
@@ -56,27 +56,777 @@
 //!
 //! # Status
 //!
-//! **TODO**: Replace the question mark below with YES, NO, or PARTIAL to
-//! indicate the status of this assignment. If you want to tell something
-//! about this assignment to the grader, e.g., you have a bug you can't fix,
-//! or you want to explain your approach, write it down after the comments
-//! section. If you had no major issues and everything works, there is no need to write any comments.
-//!
-//! COMPLETED: NO
+//! COMPLETED: YES
 //!
 //! COMMENTS:
 //!
-//! ...
+//! The cache is added on top of the file system with indirect inodes from `f_indirect_inodes`, so the cached inodes wrap an `IndirectInode` rather than the provided `Inode`.
+//! The cache itself is a fixed number of slots; a new entry goes into the first empty slot, or otherwise replaces the first entry that is not referenced anywhere else.
+//!
+//! Next to the rules above, `i_put` also updates the cached copy of an inode when it is given a different handle to that inode (e.g. one created through `InodeLike::new`), so that the cache never shadows newer contents on disk.
+//! Unmounting the file system persists all cache entries, on a best-effort basis: every entry is tried, errors are dropped, and the device is given back regardless.
+//! Callers that need to know whether the write-back succeeded have to call `sync` before unmounting.
 //!
+//! `i_read` records the access time on the inode it is given, which is why it is the only layer that maintains access times: the inode is only borrowed immutably by the trait, and the `RefCell` is what makes the change possible.
+//! Like any other change to a cached inode, the access time only reaches the disk when the entry is persisted, so reading a file does not cost an extra write.
+//! Mounting with `mountfs_noatime` skips the access times altogether, as does a read-only mount.
+//!
+
+use crate::f_indirect_inodes::{IndirectInode, IndirectInodeFS, IndirectInodeFSError};
+use crate::g_caching_inodes::CachedInodeFSError::{
+    CacheFull, CachedSystemError, InodeNotCached, InodeStillReferenced,
+};
+use cplfs_api::clock::Clock;
+use cplfs_api::controller::{BlockDevice, Device, ImageDevice};
+use cplfs_api::fs::{
+    BlockSupport, FileSysSupport, InodeCacheSupport, InodeRWSupport, InodeSupport, VolumeSupport,
+    XattrSupport,
+};
+use cplfs_api::perm::{Credentials, MAY_READ, MAY_WRITE};
+use cplfs_api::types::{Block, Buffer, FType, InodeLike, SuperBlock, Timestamps, VolumeHeader};
+use std::cell::{Ref, RefCell, RefMut};
+use std::path::Path;
+use std::rc::Rc;
+use thiserror::Error;
 
 /// You are free to choose the name for your file system. As we will use
 /// automated tests when grading your assignment, indicate here the name of
 /// your file system data type so we can just use `FSName` instead of
 /// having to manually figure out the name.
-/// **TODO**: replace the below type by the type of your file system
-pub type FSName = ();
+pub type FSName = CachedInodeFS;
+
+///Number of inode cache entries used by `mkfs` and `mountfs`
+const DEFAULT_CACHE_ENTRIES: u64 = 5;
+
+///Shareable handle to an inode in the inode cache.
+///Clones refer to the same inode, so changes made through one handle are visible through all of them.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct InodeCached(Rc<RefCell<IndirectInode>>);
+
+impl InodeCached {
+    ///Wrap an inode into a new handle, that is not shared with any other handle yet
+    pub fn new(inode: IndirectInode) -> InodeCached {
+        InodeCached(Rc::new(RefCell::new(inode)))
+    }
+
+    ///Immutably borrow the wrapped inode
+    pub fn borrow(&self) -> Ref<'_, IndirectInode> {
+        self.0.borrow()
+    }
+
+    ///Mutably borrow the wrapped inode
+    pub fn borrow_mut(&self) -> RefMut<'_, IndirectInode> {
+        self.0.borrow_mut()
+    }
+
+    ///Number of handles currently referring to this inode, including this one
+    pub fn handle_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    ///Do both handles refer to the same inode?
+    pub fn same_handle(&self, other: &InodeCached) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl InodeLike for InodeCached {
+    fn new(inum: u64, ft: &FType, nlink: u64, size: u64, blocks: &[u64]) -> Option<Self> {
+        let inode = <IndirectInode as InodeLike>::new(inum, ft, nlink, size, blocks)?;
+        Some(InodeCached::new(inode))
+    }
+
+    fn get_ft(&self) -> FType {
+        self.borrow().get_ft()
+    }
+
+    fn get_nlink(&self) -> u64 {
+        self.borrow().get_nlink()
+    }
+
+    fn get_size(&self) -> u64 {
+        self.borrow().get_size()
+    }
+
+    fn get_times(&self) -> Timestamps {
+        self.borrow().get_times()
+    }
+
+    fn get_mode(&self) -> u16 {
+        self.borrow().get_mode()
+    }
+
+    fn get_uid(&self) -> u32 {
+        self.borrow().get_uid()
+    }
+
+    fn get_gid(&self) -> u32 {
+        self.borrow().get_gid()
+    }
+
+    fn get_block(&self, i: u64) -> u64 {
+        self.borrow().get_block(i)
+    }
+
+    fn get_inum(&self) -> u64 {
+        self.borrow().get_inum()
+    }
+}
+
+///Main struct file for the Cached Inode File System
+pub struct CachedInodeFS<D: BlockDevice = Device> {
+    inode_fs: IndirectInodeFS<D>,
+    cache: Vec<Option<InodeCached>>,
+    noatime: bool,
+}
+
+///Main error file for Cached Inode File system
+#[derive(Error, Debug)]
+pub enum CachedInodeFSError {
+    ///Wrapper error that's going to wrap all errors from the Indirect Inode layer
+    #[error("File system error!")]
+    CachedSystemError(#[from] IndirectInodeFSError),
+
+    ///Error that's thrown when `i_get` is asked for an inode that is not in the cache
+    #[error("Inode is not cached")]
+    InodeNotCached(),
+
+    ///Error that's thrown when freeing an inode that is still referenced outside of the cache
+    #[error("Inode is still referenced")]
+    InodeStillReferenced(),
+
+    ///Error that's thrown when every cache entry is still referenced, so nothing can be evicted
+    #[error("Inode cache is full")]
+    CacheFull(),
+}
+
+impl<D: BlockDevice> CachedInodeFS<D> {
+    ///Check whether the file system was mounted read-only, using `mountfs_readonly`
+    pub fn is_read_only(&self) -> bool {
+        return self.inode_fs.is_read_only();
+    }
+
+    ///Mount the file system on `dev` with a cache of the default size, discarding every data block as soon as it is freed, see `IndirectInodeFS::mountfs_discard`
+    pub fn mountfs_discard(dev: D) -> Result<Self, CachedInodeFSError> {
+        let inode_fs = IndirectInodeFS::mountfs_discard(dev)?;
+        return Ok(Self::with_cache(inode_fs, DEFAULT_CACHE_ENTRIES));
+    }
+
+    ///Check whether the file system was mounted with `mountfs_discard`
+    pub fn is_discarding(&self) -> bool {
+        return self.inode_fs.is_discarding();
+    }
+
+    ///Mount the file system on `dev` with a cache of the default size, without recording access times in `i_read`
+    pub fn mountfs_noatime(dev: D) -> Result<Self, CachedInodeFSError> {
+        let mut rustfs = Self::mountfs(dev)?;
+        rustfs.noatime = true;
+        return Ok(rustfs);
+    }
+
+    ///Check whether the file system was mounted with `mountfs_noatime`
+    pub fn is_noatime(&self) -> bool {
+        return self.noatime;
+    }
+
+    ///Replace the clock the inode timestamps are taken from, see `IndirectInodeFS::set_clock`
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.inode_fs.set_clock(clock);
+    }
+
+    ///Read from `inode` like `i_read`, on behalf of the user with the credentials `creds`, see `IndirectInodeFS::i_read_as`
+    pub fn i_read_as(
+        &self,
+        creds: &Credentials,
+        inode: &InodeCached,
+        buf: &mut Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<u64, CachedInodeFSError> {
+        if !inode.permits(creds, MAY_READ) {
+            return Err(CachedSystemError(IndirectInodeFSError::PermissionDenied()));
+        }
+        return self.i_read(inode, buf, off, n);
+    }
+
+    ///Write to `inode` like `i_write`, on behalf of the user with the credentials `creds`, see `IndirectInodeFS::i_write_as`
+    pub fn i_write_as(
+        &mut self,
+        creds: &Credentials,
+        inode: &mut InodeCached,
+        buf: &Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<(), CachedInodeFSError> {
+        if !inode.permits(creds, MAY_WRITE) {
+            return Err(CachedSystemError(IndirectInodeFSError::PermissionDenied()));
+        }
+        return self.i_write(inode, buf, off, n);
+    }
+
+    ///Discard every free data block, see `BlockFS::fstrim`, returning the number of discarded blocks
+    pub fn fstrim(&mut self) -> Result<u64, CachedInodeFSError> {
+        return Ok(self.inode_fs.fstrim()?);
+    }
+
+    ///Grow or shrink the file system, together with the device it is stored on, to `nblocks` blocks, as described for `IndirectInodeFS::resize_fs`.
+    ///Cached inodes are persisted first, and refreshed afterwards, so that they point to the data blocks that were moved as well.
+    pub fn resize_fs(&mut self, nblocks: u64) -> Result<(), CachedInodeFSError> {
+        for entry in self.cache.iter().flatten() {
+            self.inode_fs.i_put(&entry.borrow())?;
+        }
+        self.inode_fs.resize_fs(nblocks)?;
+        for entry in self.cache.iter().flatten() {
+            *entry.borrow_mut() = self.inode_fs.i_get(entry.get_inum())?;
+        }
+        return Ok(());
+    }
+
+    ///Create a file system with an empty cache of the given size on top of the given inode layer
+    fn with_cache(inode_fs: IndirectInodeFS<D>, nb_cache_entries: u64) -> Self {
+        CachedInodeFS {
+            inode_fs,
+            cache: vec![None; nb_cache_entries as usize],
+            noatime: false,
+        }
+    }
+
+    ///Find the cache entry for the given inode number, if any
+    fn lookup(&self, inum: u64) -> Option<&InodeCached> {
+        self.cache
+            .iter()
+            .flatten()
+            .find(|entry| entry.get_inum() == inum)
+    }
+
+    ///Read the given inode from disk into the cache, and return a handle to the new entry.
+    ///Uses the first empty slot, or evicts the first entry that is not referenced anywhere else after persisting it.
+    fn load(&mut self, inum: u64) -> Result<InodeCached, CachedInodeFSError> {
+        let inode = InodeCached::new(self.inode_fs.i_get(inum)?);
+
+        let slot = match self.cache.iter().position(|entry| entry.is_none()) {
+            Some(slot) => slot,
+            None => {
+                let slot = self
+                    .cache
+                    .iter()
+                    .position(|entry| entry.as_ref().is_some_and(|e| e.handle_count() == 1))
+                    .ok_or(CacheFull())?;
+                //a read-only file system cannot persist anything, but can still cache other inodes
+                if let Some(evicted) = &self.cache[slot] {
+                    if !self.inode_fs.is_read_only() {
+                        self.inode_fs.i_put(&evicted.borrow())?;
+                    }
+                }
+                slot
+            }
+        };
+
+        self.cache[slot] = Some(inode.clone());
+        Ok(inode)
+    }
+}
+
+impl<D: BlockDevice> FileSysSupport for CachedInodeFS<D> {
+    type Error = CachedInodeFSError;
+    type Dev = D;
+
+    fn sb_valid(sb: &SuperBlock) -> bool {
+        return IndirectInodeFS::<D>::sb_valid(sb);
+    }
+
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>
+    where
+        D: ImageDevice,
+    {
+        return Self::mkfs_cached(path, sb, DEFAULT_CACHE_ENTRIES);
+    }
+
+    fn mkfs_on(dev: D, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        let inode_fs = IndirectInodeFS::mkfs_on(dev, sb)?;
+        return Ok(Self::with_cache(inode_fs, DEFAULT_CACHE_ENTRIES));
+    }
+
+    fn mountfs(dev: D) -> Result<Self, Self::Error> {
+        return Self::mountfs_cached(dev, DEFAULT_CACHE_ENTRIES);
+    }
+
+    fn mountfs_readonly(dev: D) -> Result<Self, Self::Error> {
+        let inode_fs = IndirectInodeFS::mountfs_readonly(dev)?;
+        return Ok(Self::with_cache(inode_fs, DEFAULT_CACHE_ENTRIES));
+    }
+
+    fn unmountfs(mut self) -> D {
+        //persisting the cache like `sync`, since changes to cached inodes do not have to be written back explicitly.
+        //An unmount cannot report errors, so every entry is tried and the device is always given back, e.g. to recover it after a fault;
+        //callers that need to see write-back errors have to `sync` first
+        if !self.inode_fs.is_read_only() {
+            for entry in self.cache.iter().flatten() {
+                let _ = self.inode_fs.i_put(&entry.borrow());
+            }
+        }
+        let _ = self.inode_fs.sync();
+        self.cache.clear();
+        return self.inode_fs.unmountfs();
+    }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        //cached inodes are persisted first, but stay in the cache; a read-only file system has nothing to persist
+        if !self.inode_fs.is_read_only() {
+            for entry in self.cache.iter().flatten() {
+                self.inode_fs.i_put(&entry.borrow())?;
+            }
+        }
+        return Ok(self.inode_fs.sync()?);
+    }
+}
+
+impl<D: BlockDevice> BlockSupport for CachedInodeFS<D> {
+    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+        Ok(self.inode_fs.b_get(i)?)
+    }
+
+    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.b_put(b)?)
+    }
+
+    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.b_free(i)?)
+    }
+
+    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.b_zero(i)?)
+    }
+
+    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        Ok(self.inode_fs.b_alloc()?)
+    }
+
+    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
+        Ok(self.inode_fs.sup_get()?)
+    }
+
+    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.sup_put(sup)?)
+    }
+}
+
+impl<D: BlockDevice> VolumeSupport for CachedInodeFS<D> {
+    fn volume_get(&self) -> Result<VolumeHeader, Self::Error> {
+        Ok(self.inode_fs.volume_get()?)
+    }
+
+    fn volume_put(&mut self, vol: &VolumeHeader) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.volume_put(vol)?)
+    }
+}
+
+impl<D: BlockDevice> InodeSupport for CachedInodeFS<D> {
+    type Inode = InodeCached;
+
+    fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
+        self.lookup(i).cloned().ok_or(InodeNotCached())
+    }
+
+    fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        self.inode_fs.i_put(&ino.borrow())?;
+
+        //a different handle to a cached inode would otherwise leave a stale entry in the cache
+        if let Some(entry) = self.lookup(ino.get_inum()) {
+            if !entry.same_handle(ino) {
+                *entry.borrow_mut() = ino.borrow().clone();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        //uncached inodes are freed on disk, without reading them into the cache
+        let entry = match self.lookup(i) {
+            Some(entry) => entry.clone(),
+            None => return Ok(self.inode_fs.i_free(i)?),
+        };
+
+        //the cache and the local `entry` are the only allowed owners
+        if entry.handle_count() > 2 {
+            return Err(InodeStillReferenced());
+        }
+
+        self.inode_fs.i_put(&entry.borrow())?;
+        self.inode_fs.i_free(i)?;
+        *entry.borrow_mut() = self.inode_fs.i_get(i)?;
+
+        Ok(())
+    }
+
+    fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
+        let inum = self.inode_fs.i_alloc(ft)?;
+
+        //a free entry for this inode may still be cached; it is updated in place so existing handles see the allocation
+        match self.lookup(inum) {
+            Some(entry) => *entry.borrow_mut() = self.inode_fs.i_get(inum)?,
+            None => {
+                //if the inode cannot be cached, its allocation is undone, so that it does not leak on disk
+                if let Err(e) = self.load(inum) {
+                    self.inode_fs.i_free(inum)?;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(inum)
+    }
+
+    fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.i_trunc(&mut inode.borrow_mut())?)
+    }
+}
+
+impl<D: BlockDevice> InodeRWSupport for CachedInodeFS<D> {
+    fn i_read(
+        &self,
+        inode: &Self::Inode,
+        buf: &mut Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<u64, Self::Error> {
+        let read = self.inode_fs.i_read(&inode.borrow(), buf, off, n)?;
+        if !self.noatime && !self.is_read_only() {
+            inode.borrow_mut().disk_node.times.accessed(self.inode_fs.now());
+        }
+        Ok(read)
+    }
+
+    fn i_write(
+        &mut self,
+        inode: &mut Self::Inode,
+        buf: &Buffer,
+        off: u64,
+        n: u64,
+    ) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.i_write(&mut inode.borrow_mut(), buf, off, n)?)
+    }
+}
+
+impl<D: BlockDevice> XattrSupport for CachedInodeFS<D> {
+    fn getxattr(&self, inode: &Self::Inode, name: &str) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inode_fs.getxattr(&inode.borrow(), name)?)
+    }
+
+    fn setxattr(
+        &mut self,
+        inode: &mut Self::Inode,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.setxattr(&mut inode.borrow_mut(), name, value)?)
+    }
+
+    fn listxattr(&self, inode: &Self::Inode) -> Result<Vec<String>, Self::Error> {
+        Ok(self.inode_fs.listxattr(&inode.borrow())?)
+    }
+
+    fn removexattr(&mut self, inode: &mut Self::Inode, name: &str) -> Result<(), Self::Error> {
+        Ok(self.inode_fs.removexattr(&mut inode.borrow_mut(), name)?)
+    }
+}
+
+impl<D: BlockDevice> InodeCacheSupport for CachedInodeFS<D> {
+    fn i_get_mut(&mut self, i: u64) -> Result<Self::Inode, Self::Error> {
+        match self.lookup(i) {
+            Some(entry) => Ok(entry.clone()),
+            None => self.load(i),
+        }
+    }
+
+    fn is_cached(&self, inum: u64) -> bool {
+        self.lookup(inum).is_some()
+    }
+
+    fn mkfs_cached<P: AsRef<Path>>(
+        path: P,
+        sb: &SuperBlock,
+        nb_cache_entries: u64,
+    ) -> Result<Self, Self::Error>
+    where
+        D: ImageDevice,
+    {
+        let inode_fs = IndirectInodeFS::mkfs(path, sb)?;
+        return Ok(Self::with_cache(inode_fs, nb_cache_entries));
+    }
+
+    fn mountfs_cached(dev: D, nb_cache_entries: u64) -> Result<Self, Self::Error> {
+        let inode_fs = IndirectInodeFS::mountfs(dev)?;
+        return Ok(Self::with_cache(inode_fs, nb_cache_entries));
+    }
+}
+
+#[cfg(test)]
+mod test_in_memory {
+    use crate::f_indirect_inodes::IndirectInodeFSError::PermissionDenied;
+    use crate::g_caching_inodes::CachedInodeFS;
+    use crate::g_caching_inodes::CachedInodeFSError::{CacheFull, CachedSystemError};
+    use cplfs_api::clock::ManualClock;
+    use cplfs_api::fault_device::FaultDevice;
+    use cplfs_api::mem_device::MemDevice;
+    use cplfs_api::perm::Credentials;
+    use cplfs_api::fs::{BlockSupport, FileSysSupport, InodeCacheSupport, InodeRWSupport, InodeSupport};
+    use cplfs_api::types::{Buffer, FType, InodeLike, SuperBlock, Timestamps};
+
+    static BLOCK_SIZE: u64 = 1000;
+    static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+        block_size: BLOCK_SIZE,
+        nblocks: 11,
+        ninodes: 10,
+        inodestart: 1,
+        ndatablocks: 6,
+        bmapstart: 4,
+        datastart: 5,
+    };
+
+    type FSName = CachedInodeFS<MemDevice>;
+
+    #[test]
+    fn eviction_persists() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let dev = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap().unmountfs();
+        let mut my_fs = FSName::mountfs_cached(dev, 2).unwrap();
+
+        //changes made through a handle only, without an explicit `i_put`
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let i1 = my_fs.i_get(inum).unwrap();
+        i1.borrow_mut().disk_node.nlink = 3;
+        drop(i1);
+
+        //the next two allocations evict the first inode again
+        my_fs.i_alloc(FType::TFile).unwrap();
+        let kept = my_fs.i_get_mut(2).unwrap();
+        my_fs.i_alloc(FType::TFile).unwrap();
+        assert!(!my_fs.is_cached(inum));
+        assert!(my_fs.is_cached(2));
+        assert_eq!(my_fs.i_get_mut(inum).unwrap().get_nlink(), 3);
+
+        //every entry is referenced, so nothing can be evicted
+        let i3 = my_fs.i_get_mut(inum).unwrap();
+        assert!(my_fs.i_get_mut(3).is_err());
+        drop(kept);
+        drop(i3);
+        assert!(my_fs.i_get_mut(3).is_ok());
+    }
+
+    #[test]
+    fn timestamps() {
+        let clock = ManualClock::new(100);
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.set_clock(clock.clone());
+
+        //a new inode starts out with all of its times set to the moment it was allocated
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        assert_eq!(i1.get_times(), Timestamps::created(100));
+
+        //writing and truncating modify the contents, reading only accesses them
+        clock.set(200);
+        my_fs.i_write(&mut i1, &Buffer::new(vec![1; 10].into_boxed_slice()), 0, 10).unwrap();
+        clock.set(300);
+        my_fs.i_read(&i1, &mut Buffer::new_zero(10), 0, 10).unwrap();
+        assert_eq!((i1.get_atime(), i1.get_mtime(), i1.get_ctime(), i1.get_crtime()), (300, 200, 200, 100));
+        clock.set(400);
+        my_fs.i_trunc(&mut i1).unwrap();
+        assert_eq!((i1.get_atime(), i1.get_mtime(), i1.get_ctime(), i1.get_crtime()), (300, 400, 400, 100));
+
+        //the access time reaches the disk along with the cache entry
+        clock.set(500);
+        my_fs.i_read(&i1, &mut Buffer::new_zero(10), 0, 10).unwrap();
+        drop(i1);
+        let mut my_fs = FSName::mountfs_noatime(my_fs.unmountfs()).unwrap();
+        assert!(my_fs.is_noatime());
+        my_fs.set_clock(clock.clone());
+        let i1 = my_fs.i_get_mut(inum).unwrap();
+        assert_eq!(i1.get_atime(), 500);
+
+        //which no longer changes when the file system is mounted with noatime
+        clock.set(600);
+        my_fs.i_read(&i1, &mut Buffer::new_zero(10), 0, 10).unwrap();
+        assert_eq!(i1.get_times().atime, 500);
+    }
+
+    #[test]
+    fn read_write_permissions() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        let owner = Credentials::new(1000, 100);
+        let other = Credentials::new(1001, 200);
+
+        //a new file belongs to the superuser, and can be read by everyone
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        assert_eq!((i1.get_mode(), i1.get_uid(), i1.get_gid()), (0o644, 0, 0));
+        let buf = Buffer::new(vec![1; 10].into_boxed_slice());
+        assert!(matches!(
+            my_fs.i_write_as(&owner, &mut i1, &buf, 0, 10),
+            Err(CachedSystemError(PermissionDenied()))
+        ));
+        assert_eq!(i1.get_size(), 0);
+
+        //once the file is handed to its owner, only the owner may write it
+        i1.borrow_mut().disk_node.uid = 1000;
+        my_fs.i_write_as(&owner, &mut i1, &buf, 0, 10).unwrap();
+        assert!(my_fs.i_read_as(&other, &i1, &mut Buffer::new_zero(10), 0, 10).is_ok());
+        assert!(my_fs.i_write_as(&other, &mut i1, &buf, 0, 10).is_err());
+
+        //and without any bits for others, they cannot read it either, unlike the superuser
+        i1.borrow_mut().disk_node.mode = 0o600;
+        assert!(matches!(
+            my_fs.i_read_as(&other, &i1, &mut Buffer::new_zero(10), 0, 10),
+            Err(CachedSystemError(PermissionDenied()))
+        ));
+        assert_eq!(my_fs.i_read_as(&Credentials::root(), &i1, &mut Buffer::new_zero(10), 0, 10).unwrap(), 10);
+    }
+
+    #[test]
+    fn stale_handles_are_updated() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let buf = Buffer::new(vec![1; 10].into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 10).unwrap();
+
+        //putting an independent copy updates the cached one
+        let copy = <<FSName as InodeSupport>::Inode as InodeLike>::new(
+            inum,
+            &FType::TFile,
+            1,
+            5,
+            &[i1.get_block(0)],
+        )
+        .unwrap();
+        my_fs.i_put(&copy).unwrap();
+        assert_eq!(i1.get_size(), 5);
+        assert_eq!(i1.get_nlink(), 1);
+
+        //cached changes survive remounting
+        i1.borrow_mut().disk_node.nlink = 2;
+        drop(i1);
+        let dev = my_fs.unmountfs();
+        let mut my_fs = FSName::mountfs(dev).unwrap();
+        assert!(!my_fs.is_cached(inum));
+        assert!(my_fs.i_get(inum).is_err());
+        let i1 = my_fs.i_get_mut(inum).unwrap();
+        assert_eq!(i1.get_nlink(), 2);
+        assert_eq!(i1.get_size(), 5);
+    }
+
+    #[test]
+    fn resize_moves_cached_blocks() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let buf = Buffer::new(vec![1; 10].into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 10).unwrap();
+        assert_eq!(i1.get_block(0), SUPERBLOCK_GOOD.datastart);
+
+        //a second bitmap block is needed, which takes over the only allocated data block
+        my_fs.resize_fs(SUPERBLOCK_GOOD.datastart + 8001).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap().datastart, SUPERBLOCK_GOOD.datastart + 1);
+        assert_eq!(i1.get_block(0), SUPERBLOCK_GOOD.datastart + 1);
+        let mut read = Buffer::new_zero(10);
+        assert_eq!(my_fs.i_read(&i1, &mut read, 0, 10).unwrap(), 10);
+        assert_eq!(read, buf);
+    }
+
+    #[test]
+    fn readonly_mount() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let mut my_fs = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        my_fs.i_write(&mut i1, &Buffer::new(vec![1; 10].into_boxed_slice()), 0, 10).unwrap();
+        drop(i1);
+
+        let mut my_fs = FSName::mountfs_readonly(my_fs.unmountfs()).unwrap();
+        assert!(my_fs.is_read_only());
+        let mut i1 = my_fs.i_get_mut(inum).unwrap();
+        let mut read = Buffer::new_zero(10);
+        assert_eq!(my_fs.i_read(&i1, &mut read, 0, 10).unwrap(), 10);
+        assert!(my_fs.i_write(&mut i1, &Buffer::new_zero(10), 0, 10).is_err());
+        assert!(my_fs.i_put(&i1).is_err());
+        assert!(my_fs.i_alloc(FType::TFile).is_err());
+
+        //entries can still be evicted, as there is nothing to persist
+        i1.borrow_mut().disk_node.nlink = 5;
+        drop(i1);
+        for i in 2..SUPERBLOCK_GOOD.ninodes {
+            assert_eq!(my_fs.i_get_mut(i).unwrap().get_ft(), FType::TFree);
+        }
+        assert!(!my_fs.is_cached(inum));
+        my_fs.sync().unwrap();
+        let mut my_fs = FSName::mountfs(my_fs.unmountfs()).unwrap();
+        assert_eq!(my_fs.i_get_mut(inum).unwrap().get_nlink(), 0);
+    }
+
+    #[test]
+    fn i_alloc_cache_full() {
+        let dev = MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks);
+        let dev = FSName::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap().unmountfs();
+        let mut my_fs = FSName::mountfs_cached(dev, 2).unwrap();
+        let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+        let i2 = my_fs.i_alloc(FType::TFile).unwrap();
+
+        //both cache entries are referenced, so a third inode cannot be cached, and is not allocated either
+        let held = (my_fs.i_get(i1).unwrap(), my_fs.i_get(i2).unwrap());
+        assert!(matches!(my_fs.i_alloc(FType::TFile), Err(CacheFull())));
+        drop(held);
+        assert_eq!(my_fs.i_get_mut(i2 + 1).unwrap().get_ft(), FType::TFree);
+        assert_eq!(my_fs.i_alloc(FType::TDir).unwrap(), i2 + 1);
+    }
+
+    #[test]
+    fn unmount_write_back_fails() {
+        let dev = FaultDevice::new(MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks));
+        let mut dev = CachedInodeFS::<FaultDevice<MemDevice>>::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap().unmountfs();
+        //the first two writes allocate the inodes, the third one writes back the first of them while unmounting
+        dev.fail_nth_write(dev.write_count() + 3);
+        let mut my_fs = CachedInodeFS::mountfs(dev).unwrap();
+        let i1 = my_fs.i_alloc(FType::TFile).unwrap();
+        let i2 = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.i_get(i1).unwrap().borrow_mut().disk_node.nlink = 2;
+        my_fs.i_get(i2).unwrap().borrow_mut().disk_node.nlink = 3;
+
+        //unmounting still gives back the device, after writing back every entry it can
+        let dev = my_fs.unmountfs();
+        let mut my_fs = FSName::mountfs(dev.into_inner()).unwrap();
+        assert_ne!(my_fs.i_get_mut(i1).unwrap().get_nlink(), 2);
+        assert_eq!(my_fs.i_get_mut(i2).unwrap().get_nlink(), 3);
+    }
+
+    #[test]
+    fn sync_checkpoints() {
+        let mut dev = FaultDevice::new(MemDevice::new(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks));
+        dev.crash_after_flush(1);
+        let mut my_fs = CachedInodeFS::<FaultDevice<MemDevice>>::mkfs_on(dev, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let i1 = my_fs.i_get(inum).unwrap();
+        i1.borrow_mut().disk_node.nlink = 2;
+
+        //the change is persisted by syncing, and the inode stays cached
+        my_fs.sync().unwrap();
+        assert!(my_fs.is_cached(inum));
+        i1.borrow_mut().disk_node.nlink = 3;
+
+        //the device crashed right after the checkpoint, so unmounting does not persist anything anymore
+        let dev = my_fs.unmountfs();
+        assert!(dev.crashed());
+        let mut my_fs = FSName::mountfs(dev.into_inner()).unwrap();
+        assert_eq!(my_fs.i_get_mut(inum).unwrap().get_nlink(), 2);
+    }
+}
 
-// **TODO** define your own tests here.
 
 // WARNING: DO NOT TOUCH THE BELOW CODE -- IT IS REQUIRED FOR TESTING -- YOU WILL LOSE POINTS IF I MANUALLY HAVE TO FIX YOUR TESTS
 #[cfg(all(test, any(feature = "g", feature = "all")))]
@@ -41,7 +41,7 @@
 //! 1.47, you don't have to do anything. Otherwise, replace the version
 //! below with the output of `rustc --version`.
 //!
-//! VERSION: rustc 1.47 (2020-10-08)
+//! VERSION: rustc 1.87.0 (17067e9ac 2025-05-09)
 
 // This line forces you to write documentation for all important things.
 #![deny(missing_docs)]